@@ -0,0 +1,288 @@
+//! A self-describing archive of a trace that bundles the
+//! [`TraceMetadata`], the decoded [`TraceData`], the resolved
+//! [`api::EventChunk`]s, and the raw ITM byte stream (if available)
+//! into a single file. A trace can thus be re-resolved, replayed, and
+//! re-decoded from the archive alone, e.g. when attached to a bug
+//! report. Archives that only contain events can still be re-resolved
+//! as far as their unmappable events go; see [`reresolve_file`].
+//!
+//! An archive starts with [`MAGIC`] and the format [`VERSION`] (`u32`,
+//! little-endian), followed by records of a one-byte [`Stream`] tag, the
+//! length of the payload (`u32`, little-endian), and the payload.
+//! Metadata, trace data, event chunks, application tokens, and
+//! manifest properties are JSON-serialized; raw bytes are stored
+//! verbatim. Records of unknown streams are skipped.
+//!
+//! The application tokens and manifest properties are only bundled in
+//! reproductions; see [`crate::repro`].
+use crate::manifest::ManifestProperties;
+use crate::recovery::TraceMetadata;
+use crate::sources::SourceError;
+use crate::{RTICScopeError, TraceData};
+
+use std::convert::TryInto;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+use anyhow::Context;
+use rtic_scope_api as api;
+
+pub const MAGIC: &[u8; 8] = b"RTICSARC";
+pub const VERSION: u32 = 1;
+
+/// Size of the records the raw byte stream is split into.
+pub const RAW_RECORD_SIZE: usize = 64 * 1024;
+
+/// The stream a record belongs to.
+#[derive(Clone, Copy)]
+pub enum Stream {
+    Metadata = 0,
+    Trace = 1,
+    Events = 2,
+    Raw = 3,
+    App = 4,
+    Manifest = 5,
+}
+
+pub fn write_header(w: &mut impl Write) -> io::Result<()> {
+    w.write_all(MAGIC)?;
+    w.write_all(&VERSION.to_le_bytes())
+}
+
+pub fn write_record(w: &mut impl Write, stream: Stream, payload: &[u8]) -> io::Result<()> {
+    let len: u32 = payload
+        .len()
+        .try_into()
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "record too large"))?;
+    w.write_all(&[stream as u8])?;
+    w.write_all(&len.to_le_bytes())?;
+    w.write_all(payload)
+}
+
+/// Whether the file at `path` is an archive.
+pub fn is_archive(path: &Path) -> io::Result<bool> {
+    let mut magic = [0; MAGIC.len()];
+    match fs::File::open(path)?.read_exact(&mut magic) {
+        Ok(()) => Ok(&magic == MAGIC),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// The streams of a read archive.
+pub struct Archive {
+    pub metadata: TraceMetadata,
+    pub trace: Vec<TraceData>,
+    pub events: Vec<api::EventChunk>,
+    pub raw: Vec<u8>,
+    /// Tokens of the source of the traced application.
+    pub app: Option<String>,
+    pub manifest: Option<ManifestProperties>,
+}
+
+impl Archive {
+    pub fn read(mut reader: impl Read) -> Result<Self, SourceError> {
+        let mut header = [0; MAGIC.len() + 4];
+        reader
+            .read_exact(&mut header)
+            .map_err(SourceError::SetupIOError)?;
+        if &header[..MAGIC.len()] != MAGIC {
+            return Err(SourceError::SetupError(
+                "Not an RTIC Scope archive".to_string(),
+            ));
+        }
+        let version = u32::from_le_bytes(header[MAGIC.len()..].try_into().unwrap());
+        if version > VERSION {
+            return Err(SourceError::SetupError(format!(
+                "Archive format version {} is newer than the supported version {}",
+                version, VERSION
+            )));
+        }
+
+        let mut metadata = None;
+        let mut trace = vec![];
+        let mut events = vec![];
+        let mut raw = vec![];
+        let mut app = None;
+        let mut manifest = None;
+        loop {
+            let mut head = [0; 5];
+            match reader.read_exact(&mut head) {
+                Ok(()) => (),
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(SourceError::IterIOError(e)),
+            }
+            let len = u32::from_le_bytes(head[1..].try_into().unwrap());
+            let mut payload = vec![0; len as usize];
+            reader
+                .read_exact(&mut payload)
+                .map_err(SourceError::IterIOError)?;
+
+            match head[0] {
+                t if t == Stream::Metadata as u8 => {
+                    metadata = Some(TraceMetadata::from_header(serde_json::from_slice(
+                        &payload,
+                    )?)?)
+                }
+                t if t == Stream::Trace as u8 => trace.push(serde_json::from_slice(&payload)?),
+                t if t == Stream::Events as u8 => events.push(serde_json::from_slice(&payload)?),
+                t if t == Stream::Raw as u8 => raw.append(&mut payload),
+                t if t == Stream::App as u8 => app = Some(serde_json::from_slice(&payload)?),
+                t if t == Stream::Manifest as u8 => {
+                    manifest = Some(serde_json::from_slice(&payload)?)
+                }
+                _ => (),
+            }
+        }
+
+        Ok(Self {
+            metadata: metadata.ok_or_else(|| {
+                SourceError::SetupError("Archive does not contain metadata".to_string())
+            })?,
+            trace,
+            events,
+            raw,
+            app,
+            manifest,
+        })
+    }
+
+    /// Writes the archive. Trace data and events are interleaved as
+    /// recorded.
+    pub fn write(&self, w: &mut impl Write) -> io::Result<()> {
+        write_header(w)?;
+        write_record(w, Stream::Metadata, &serde_json::to_vec(&self.metadata)?)?;
+        if let Some(app) = &self.app {
+            write_record(w, Stream::App, &serde_json::to_vec(app)?)?;
+        }
+        if let Some(manifest) = &self.manifest {
+            write_record(w, Stream::Manifest, &serde_json::to_vec(manifest)?)?;
+        }
+        for raw in self.raw.chunks(RAW_RECORD_SIZE) {
+            write_record(w, Stream::Raw, raw)?;
+        }
+        for i in 0..self.trace.len().max(self.events.len()) {
+            if let Some(data) = self.trace.get(i) {
+                write_record(w, Stream::Trace, &serde_json::to_vec(data)?)?;
+            }
+            if let Some(chunk) = self.events.get(i) {
+                write_record(w, Stream::Events, &serde_json::to_vec(chunk)?)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Writes the archive at `path` to `output` with the lookup maps of
+/// `maps`, and re-resolves the unmappable events it contains against
+/// them. Returns the number of resolved events, and the number of
+/// events that remain unmappable.
+///
+/// If the archive contains the trace data of its events, the trace is
+/// decoded anew in order, such that the events are resolved as if the
+/// trace had been recorded with `maps`.
+pub fn reresolve_file(
+    path: &Path,
+    output: &Path,
+    maps: &TraceMetadata,
+) -> Result<(usize, usize), RTICScopeError> {
+    let mut archive = Archive::read(fs::File::open(path)?)?;
+    archive.metadata.replace_maps(maps);
+
+    let mut resolved = 0;
+    if archive.trace.len() == archive.events.len() {
+        for (data, chunk) in archive.trace.iter().zip(archive.events.iter_mut()) {
+            resolved += archive.metadata.reresolve(chunk, Some(data));
+        }
+    } else {
+        for chunk in archive.events.iter_mut() {
+            resolved += archive.metadata.reresolve(chunk, None);
+        }
+    }
+    let unmappable = archive
+        .events
+        .iter()
+        .flat_map(|chunk| chunk.events.iter())
+        .filter(|e| {
+            matches!(
+                e,
+                api::EventType::Unmappable(_, _) | api::EventType::Unknown(_)
+            )
+        })
+        .count();
+
+    let mut file = io::BufWriter::new(
+        fs::File::create(output)
+            .with_context(|| format!("Failed to create {}", output.display()))?,
+    );
+    archive.write(&mut file)?;
+    file.flush()?;
+
+    Ok((resolved, unmappable))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::sinks::{ArchiveSink, Sink};
+
+    use std::time::Duration;
+
+    use itm::{Timestamp, TracePacket};
+
+    /// Ensure that all streams round-trip losslessly.
+    #[test]
+    fn round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let raw: Vec<u8> = (0..=255).cycle().take(200_000).collect();
+        fs::write(dir.path().join("raw"), &raw).unwrap();
+
+        let metadata = crate::recovery::test::metadata();
+        let mut sink = ArchiveSink::new(
+            &dir.path().join("trace.rsa"),
+            &metadata,
+            Some(&dir.path().join("raw")),
+        )
+        .unwrap();
+        let trace: Vec<TraceData> = (0..3)
+            .map(|i| TraceData {
+                timestamp: Timestamp::Sync(Duration::from_nanos(i)),
+                packets: vec![TracePacket::Overflow],
+                malformed_packets: vec![],
+                consumed_packets: 1,
+            })
+            .collect();
+        let events: Vec<api::EventChunk> = (0..3)
+            .map(|i| {
+                api::ChunkBuilder::at(Duration::from_nanos(i))
+                    .entered("app::foo")
+                    .overflow()
+                    .build()
+            })
+            .collect();
+        for (data, chunk) in trace.iter().zip(events.iter()) {
+            sink.drain(data.clone(), chunk.clone()).unwrap();
+        }
+        sink.finalize().unwrap();
+
+        let path = dir.path().join("trace.rsa");
+        assert!(is_archive(&path).unwrap());
+        assert!(!is_archive(&dir.path().join("raw")).unwrap());
+
+        let archive = Archive::read(fs::File::open(&path).unwrap()).unwrap();
+        assert_eq!(
+            serde_json::to_string(&archive.metadata).unwrap(),
+            serde_json::to_string(&metadata).unwrap()
+        );
+        assert_eq!(
+            serde_json::to_string(&archive.trace).unwrap(),
+            serde_json::to_string(&trace).unwrap()
+        );
+        assert_eq!(
+            serde_json::to_string(&archive.events).unwrap(),
+            serde_json::to_string(&events).unwrap()
+        );
+        assert_eq!(archive.raw, raw);
+    }
+}
@@ -0,0 +1,117 @@
+//! Labeled timestamps of interest that are persisted in the metadata
+//! of a recorded trace, e.g. to mark a glitch for a later session.
+//! Bookmarks are added to and removed from a copy of a trace file or
+//! archive.
+use crate::archive::{self, Archive};
+use crate::log;
+use crate::recovery::TraceMetadata;
+use crate::sinks::{FileSink, Sink};
+use crate::sources::FileSource;
+use crate::RTICScopeError;
+
+use std::fs;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::Context;
+use rtic_scope_api as api;
+
+/// A bookmark given on the command line as `<offset>=<label>`, where
+/// `<offset>` is in nanoseconds since target reset.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bookmark {
+    pub offset: Duration,
+    pub label: String,
+}
+
+impl std::str::FromStr for Bookmark {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (offset, label) = s
+            .split_once('=')
+            .ok_or_else(|| format!("expected <offset>=<label>, got {}", s))?;
+        let offset = offset
+            .trim()
+            .parse()
+            .map_err(|e| format!("invalid offset {}: {}", offset, e))?;
+
+        Ok(Self {
+            offset: Duration::from_nanos(offset),
+            label: label.to_string(),
+        })
+    }
+}
+
+/// Writes the trace file or archive at `path` to `output` with the
+/// bookmarks `add` added, and all bookmarks labeled as any of `remove`
+/// removed. Returns the edited metadata.
+pub fn edit_file(
+    path: &Path,
+    output: &Path,
+    add: &[Bookmark],
+    remove: &[String],
+) -> Result<TraceMetadata, RTICScopeError> {
+    let edit = |metadata: &mut TraceMetadata| {
+        for label in remove {
+            if !metadata.remove_bookmark(label) {
+                log::warn(format!("no bookmark labeled {} to remove", label));
+            }
+        }
+        for Bookmark { offset, label } in add {
+            metadata.add_bookmark(api::Timestamp::Sync(*offset), label.clone());
+        }
+    };
+
+    let metadata = if archive::is_archive(path)? {
+        let mut archive = Archive::read(fs::File::open(path)?)?;
+        edit(&mut archive.metadata);
+        let mut file = BufWriter::new(
+            fs::File::create(output)
+                .with_context(|| format!("Failed to create {}", output.display()))?,
+        );
+        archive.write(&mut file)?;
+        file.flush()?;
+        archive.metadata
+    } else {
+        let src = FileSource::new(fs::OpenOptions::new().read(true).open(path)?)?;
+        let mut metadata = src.metadata();
+        edit(&mut metadata);
+        let mut sink = FileSink::create(output)?;
+        sink.drain_metadata(&metadata)?;
+        for data in src {
+            let data = data?;
+            let chunk = api::EventChunk {
+                timestamp: data.timestamp.clone(),
+                events: vec![],
+                raw: None,
+                monotonic_ticks: None,
+            };
+            sink.drain(data, chunk)?;
+        }
+        sink.finalize()?;
+        metadata
+    };
+
+    Ok(metadata)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Ensure that bookmarks are parsed from the command line.
+    #[test]
+    fn parse_bookmark() {
+        assert_eq!(
+            "1500=glitch = here".parse(),
+            Ok(Bookmark {
+                offset: Duration::from_nanos(1500),
+                label: "glitch = here".to_string(),
+            })
+        );
+        assert!("glitch".parse::<Bookmark>().is_err());
+        assert!("1.5=glitch".parse::<Bookmark>().is_err());
+    }
+}
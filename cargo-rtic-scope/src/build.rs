@@ -5,6 +5,8 @@ use std::env;
 use std::io::BufReader;
 use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
 
 pub use cargo_metadata::Artifact;
 use cargo_metadata::Message;
@@ -13,6 +15,7 @@ use thiserror::Error;
 pub struct CargoWrapper {
     target_dir: Option<PathBuf>,
     app_metadata: Option<cargo_metadata::Metadata>,
+    target: Option<String>,
 }
 
 #[derive(Debug, Error)]
@@ -37,6 +40,8 @@ pub enum CargoError {
     StdoutError(#[source] std::io::Error),
     #[error("Failed to resolve the current directory: {0}")]
     CurrentDirError(#[source] std::io::Error),
+    #[error("`cargo build {}` did not finish within {0:?} and was killed", Self::maybe_opts_to_str(.1))]
+    BuildTimeout(Duration, Option<Vec<String>>),
 }
 
 impl CargoError {
@@ -46,6 +51,22 @@ impl CargoError {
 }
 
 impl diag::DiagnosableError for CargoError {
+    fn kind(&self) -> &'static str {
+        match self {
+            Self::CannotFindManifest(..) => "CannotFindManifest",
+            Self::MultipleSuitableArtifacts(..) => "MultipleSuitableArtifacts",
+            Self::NoSuitableArtifact(..) => "NoSuitableArtifact",
+            Self::CargoBuildExecFailed(..) => "CargoBuildExecFailed",
+            Self::CargoMetadataExecFailed(..) => "CargoMetadataExecFailed",
+            Self::CannotFindRootPackage => "CannotFindRootPackage",
+            Self::CannotCanonicalize(..) => "CannotCanonicalize",
+            Self::CargoBuildSpawnWaitError(..) => "CargoBuildSpawnWaitError",
+            Self::StdoutError(..) => "StdoutError",
+            Self::CurrentDirError(..) => "CurrentDirError",
+            Self::BuildTimeout(..) => "BuildTimeout",
+        }
+    }
+
     fn diagnose(&self) -> Vec<String> {
         match self {
             CargoError::MultipleSuitableArtifacts(kind, _opts)
@@ -60,6 +81,15 @@ impl diag::DiagnosableError for CargoError {
     }
 }
 
+/// The trimmed stdout of `cmd -V`, if it succeeds.
+fn version(mut cmd: Command) -> Option<String> {
+    let output = cmd.arg("-V").stderr(Stdio::null()).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8(output.stdout).ok()?.trim().to_string())
+}
+
 /// A functionality wrapper around subproccess calls to cargo in `PATH`.
 impl CargoWrapper {
     fn cmd() -> Command {
@@ -70,6 +100,7 @@ impl CargoWrapper {
         CargoWrapper {
             target_dir: None,
             app_metadata: None,
+            target: None,
         }
     }
 
@@ -97,11 +128,31 @@ impl CargoWrapper {
                     |e| CargoError::CannotCanonicalize(metadata.target_directory.clone().into(), e),
                 )?),
                 app_metadata: Some(metadata),
+                target: build_target(crate_root, &opts),
             },
             artifact,
         ))
     }
 
+    /// The output of `cargo -V`, if cargo could be executed.
+    pub fn cargo_version(&self) -> Option<String> {
+        version(Self::cmd())
+    }
+
+    /// The output of `rustc -V`, if rustc could be executed. Can be
+    /// overridden via the `RUSTC` environment variable, as by cargo.
+    pub fn rustc_version(&self) -> Option<String> {
+        version(Command::new(
+            env::var_os("RUSTC").unwrap_or_else(|| "rustc".into()),
+        ))
+    }
+
+    /// The target triple the application is built for, unless it is
+    /// built for the host.
+    pub fn target(&self) -> Option<&str> {
+        self.target.as_deref()
+    }
+
     pub fn target_dir(&self) -> &PathBuf {
         self.target_dir.as_ref().unwrap()
     }
@@ -125,6 +176,18 @@ impl CargoWrapper {
         crate_root: &Path,
         opts: Option<Vec<String>>,
         expected_artifact_kind: &str,
+    ) -> Result<Artifact, CargoError> {
+        self.build_with_timeout(crate_root, opts, expected_artifact_kind, None)
+    }
+
+    /// Like [`CargoWrapper::build`], but kills the build if it does not
+    /// finish within `timeout`.
+    pub fn build_with_timeout(
+        &self,
+        crate_root: &Path,
+        opts: Option<Vec<String>>,
+        expected_artifact_kind: &str,
+        timeout: Option<Duration>,
     ) -> Result<Artifact, CargoError> {
         let mut cargo = Self::cmd();
         cargo.arg("build");
@@ -139,8 +202,6 @@ impl CargoWrapper {
         }
 
         cargo.arg("--message-format=json-diagnostic-rendered-ansi");
-        cargo.stdout(Stdio::piped());
-        cargo.stderr(Stdio::piped());
 
         // Dirty fix for evading any eventual .cargo/config in the working
         // directory. We obviously need it when we build the target
@@ -162,51 +223,132 @@ impl CargoWrapper {
             cargo.current_dir(crate_root);
         }
 
+        Self::run(cargo, opts, expected_artifact_kind, timeout)
+    }
+
+    /// Executes the prepared `cargo` build command and collects its
+    /// messages.
+    fn run(
+        mut cargo: Command,
+        opts: Option<Vec<String>>,
+        expected_artifact_kind: &str,
+        timeout: Option<Duration>,
+    ) -> Result<Artifact, CargoError> {
+        cargo.stdout(Stdio::piped());
+        cargo.stderr(Stdio::piped());
+
         let mut child = cargo
             .spawn()
             .map_err(CargoError::CargoBuildSpawnWaitError)?;
         let stdout = BufReader::new(child.stdout.take().expect("Pipe to cargo process failed"));
         let stderr = BufReader::new(child.stderr.take().expect("Pipe to cargo process failed"));
 
-        let messages = Message::parse_stream(stdout).chain(Message::parse_stream(stderr));
-
-        let mut target_artifact: Option<Artifact> = None;
-        for message in messages {
-            match message.map_err(CargoError::StdoutError)? {
-                Message::CompilerArtifact(artifact)
-                    if artifact.target.kind == [expected_artifact_kind] =>
-                {
-                    if target_artifact.is_some() {
-                        return Err(CargoError::MultipleSuitableArtifacts(
-                            expected_artifact_kind.to_string(),
-                            opts,
-                        ));
+        // Messages are collected on their own thread so that the build
+        // can be killed if it does not finish in time.
+        let parser = {
+            let opts = opts.clone();
+            let kind = expected_artifact_kind.to_string();
+            thread::spawn(move || -> Result<Option<Artifact>, CargoError> {
+                let messages = Message::parse_stream(stdout).chain(Message::parse_stream(stderr));
+
+                let mut target_artifact: Option<Artifact> = None;
+                for message in messages {
+                    match message.map_err(CargoError::StdoutError)? {
+                        Message::CompilerArtifact(artifact)
+                            if artifact.target.kind == [kind.as_str()] =>
+                        {
+                            if target_artifact.is_some() {
+                                return Err(CargoError::MultipleSuitableArtifacts(kind, opts));
+                            }
+                            target_artifact = Some(artifact);
+                        }
+                        Message::CompilerMessage(msg) => {
+                            if let Some(rendered) = msg.message.rendered {
+                                eprint!("{}", rendered);
+                            }
+                        }
+                        _ => (),
                     }
-                    target_artifact = Some(artifact);
                 }
-                Message::CompilerMessage(msg) => {
-                    if let Some(rendered) = msg.message.rendered {
-                        eprint!("{}", rendered);
-                    }
+
+                Ok(target_artifact)
+            })
+        };
+
+        if let Some(timeout) = timeout {
+            let deadline = Instant::now() + timeout;
+            while child
+                .try_wait()
+                .map_err(CargoError::CargoBuildSpawnWaitError)?
+                .is_none()
+            {
+                if Instant::now() >= deadline {
+                    // NOTE(ignore) the build has failed either way.
+                    // Compiler processes spawned by cargo are orphaned
+                    // and eventually fail on the closed pipes.
+                    let _ = child.kill();
+                    let _ = child.wait();
+                    return Err(CargoError::BuildTimeout(timeout, opts));
                 }
-                _ => (),
+                thread::sleep(Duration::from_millis(50));
             }
         }
 
+        let target_artifact = parser.join().expect("cargo message parser panicked")?;
         let status = child.wait().map_err(CargoError::CargoBuildSpawnWaitError)?;
 
         if !status.success() {
             return Err(CargoError::CargoBuildExecFailed(status, opts));
         }
 
-        if target_artifact.is_none() {
-            return Err(CargoError::NoSuitableArtifact(
-                expected_artifact_kind.to_string(),
-                opts,
-            ));
-        }
+        target_artifact
+            .ok_or_else(|| CargoError::NoSuitableArtifact(expected_artifact_kind.to_string(), opts))
+    }
+}
+
+/// The target triple that `cargo build` with `opts` builds for in
+/// `cwd`, as configured by `--target`, `CARGO_BUILD_TARGET`, or the
+/// `build.target` of the cargo configuration files, in that order of
+/// precedence. `None` if built for the host, or for several targets.
+fn build_target(cwd: &Path, opts: &[String]) -> Option<String> {
+    let configured = opts
+        .iter()
+        .enumerate()
+        .find_map(|(i, opt)| match opt.strip_prefix("--target") {
+            Some("") => opts.get(i + 1).cloned(),
+            Some(target) => target.strip_prefix('=').map(str::to_string),
+            None => None,
+        })
+        .or_else(|| env::var("CARGO_BUILD_TARGET").ok())
+        .or_else(|| {
+            let home = env::var_os("CARGO_HOME")
+                .map(PathBuf::from)
+                .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".cargo")));
+            cwd.ancestors()
+                .map(|dir| dir.join(".cargo"))
+                .chain(home)
+                .flat_map(|dir| [dir.join("config.toml"), dir.join("config")])
+                .find_map(|config| config_target(&config))
+        })?;
 
-        Ok(target_artifact.unwrap())
+    // A custom target is built into a directory named by the stem of
+    // its specification.
+    match configured.strip_suffix(".json") {
+        Some(_) => Some(Path::new(&configured).file_stem()?.to_str()?.to_string()),
+        None => Some(configured),
+    }
+}
+
+/// The `build.target` of the cargo configuration file at `path`, if
+/// any, and if it is a single target.
+fn config_target(path: &Path) -> Option<String> {
+    let config: toml::Value = std::fs::read_to_string(path).ok()?.parse().ok()?;
+    match config.get("build")?.get("target")? {
+        toml::Value::String(target) => Some(target.clone()),
+        toml::Value::Array(targets) if targets.len() == 1 => {
+            targets[0].as_str().map(str::to_string)
+        }
+        _ => None,
     }
 }
 
@@ -236,3 +378,65 @@ fn find_manifest_path(artifact: &cargo_metadata::Artifact) -> Result<PathBuf, Ca
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Ensure that a build that does not finish in time is killed.
+    #[test]
+    #[cfg(unix)]
+    fn build_timeout() {
+        // A stub build that never emits any messages.
+        let mut stub = Command::new("sleep");
+        stub.arg("10");
+
+        let start = Instant::now();
+        let res = CargoWrapper::run(stub, None, "cdylib", Some(Duration::from_millis(200)));
+        assert!(matches!(
+            res,
+            Err(CargoError::BuildTimeout(timeout, None)) if timeout == Duration::from_millis(200)
+        ));
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    /// Ensure that the build target is taken from the build options
+    /// before the configuration files, whatever the layout of the
+    /// target directory.
+    #[test]
+    fn build_targets() {
+        let dir = tempfile::tempdir().unwrap();
+        let opts = |opts: &[&str]| -> Vec<String> { opts.iter().map(|o| o.to_string()).collect() };
+        assert_eq!(
+            build_target(
+                dir.path(),
+                &opts(&["--example", "app", "--target", "thumbv7em-none-eabihf"])
+            )
+            .as_deref(),
+            Some("thumbv7em-none-eabihf")
+        );
+        assert_eq!(
+            build_target(dir.path(), &opts(&["--target=specs/cortex-m85.json"])).as_deref(),
+            Some("cortex-m85")
+        );
+
+        std::fs::create_dir(dir.path().join(".cargo")).unwrap();
+        std::fs::write(
+            dir.path().join(".cargo/config.toml"),
+            "[build]\ntarget = \"thumbv6m-none-eabi\"\n",
+        )
+        .unwrap();
+        let nested = dir.path().join("examples");
+        std::fs::create_dir(&nested).unwrap();
+        if env::var_os("CARGO_BUILD_TARGET").is_none() {
+            assert_eq!(
+                build_target(&nested, &opts(&["--release"])).as_deref(),
+                Some("thumbv6m-none-eabi")
+            );
+        }
+        assert_eq!(
+            build_target(&nested, &opts(&["--target", "thumbv7m-none-eabi"])).as_deref(),
+            Some("thumbv7m-none-eabi")
+        );
+    }
+}
@@ -0,0 +1,304 @@
+//! Comparison of the task timing of two recorded traces, e.g. before
+//! and after an optimization.
+//!
+//! The executions of both traces are aligned by their sequence of
+//! tasks, as a sequence diff would align two texts by their lines, such
+//! that executions are compared despite differences in timing and
+//! tasks that only execute in one of the traces.
+use crate::intervals::OverflowPolicy;
+use crate::latency::{Distribution, Execution, Executions};
+use crate::sources::FileSource;
+use crate::RTICScopeError;
+
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+use indexmap::IndexMap;
+
+/// Reads the trace file at `path` and returns its task executions.
+/// Executions in progress on overflow are handled as per `policy`.
+pub fn executions(path: &Path, policy: OverflowPolicy) -> Result<Vec<Execution>, RTICScopeError> {
+    let src = FileSource::new(fs::OpenOptions::new().read(true).open(path)?)?;
+    let metadata = src.metadata();
+    let mut executions = Executions::new(policy);
+    for data in src {
+        executions.update(&metadata.build_event_chunk(data?));
+    }
+
+    Ok(executions.finish())
+}
+
+/// Aligns the sequences `a` and `b` via Myers' difference algorithm
+/// and returns the indices of the matching elements. Runs in
+/// O((N + M) D) time for a difference D between the sequences, and in
+/// linear space by dividing the sequences at the middle of an optimal
+/// path (as per the linear space refinement of Myers' paper).
+fn align<T: PartialEq>(a: &[T], b: &[T]) -> Vec<(usize, usize)> {
+    let max = (a.len() + b.len() + 1) / 2 + 1;
+    let mut vf = vec![0; 2 * max + 1];
+    let mut vb = vec![0; 2 * max + 1];
+    let mut pairs = vec![];
+    conquer(a, b, (0, 0), &mut vf, &mut vb, &mut pairs);
+
+    pairs
+}
+
+/// Appends the matching elements of `a` and `b`, offset by `offset`,
+/// to `pairs`.
+fn conquer<T: PartialEq>(
+    mut a: &[T],
+    mut b: &[T],
+    (mut i, mut j): (usize, usize),
+    vf: &mut [usize],
+    vb: &mut [usize],
+    pairs: &mut Vec<(usize, usize)>,
+) {
+    // Strip the common prefix and suffix: what remains differs in at
+    // least two elements, unless either sequence is empty.
+    let prefix = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    pairs.extend((0..prefix).map(|p| (i + p, j + p)));
+    a = &a[prefix..];
+    b = &b[prefix..];
+    i += prefix;
+    j += prefix;
+    let suffix = a
+        .iter()
+        .rev()
+        .zip(b.iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+    a = &a[..a.len() - suffix];
+    b = &b[..b.len() - suffix];
+
+    if !a.is_empty() && !b.is_empty() {
+        let (x, y) = middle(a, b, vf, vb);
+        conquer(&a[..x], &b[..y], (i, j), vf, vb, pairs);
+        conquer(&a[x..], &b[y..], (i + x, j + y), vf, vb, pairs);
+    }
+    pairs.extend((0..suffix).map(|s| (i + a.len() + s, j + b.len() + s)));
+}
+
+/// Finds a point (x, y) halfway along an optimal path through `a` and
+/// `b` by searching from both ends until the searches meet.
+fn middle<T: PartialEq>(a: &[T], b: &[T], vf: &mut [usize], vb: &mut [usize]) -> (usize, usize) {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let delta = n - m;
+    let odd = delta % 2 != 0;
+    let max = (n + m + 1) / 2 + 1;
+    let idx = |k: isize| (k + max) as usize;
+
+    // Furthest reaching x on each diagonal k = x - y, from the start
+    // (vf) and from the end (vb) of the sequences.
+    vf[idx(1)] = 0;
+    vb[idx(1)] = 0;
+    for d in 0..max {
+        for k in (-d..=d).rev().step_by(2) {
+            let mut x = if k == -d || (k != d && vf[idx(k - 1)] < vf[idx(k + 1)]) {
+                vf[idx(k + 1)]
+            } else {
+                vf[idx(k - 1)] + 1
+            } as isize;
+            let (x0, y0) = (x, x - k);
+            let mut y = y0;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            vf[idx(k)] = x as usize;
+            if odd && (k - delta).abs() < d && x + vb[idx(delta - k)] as isize >= n {
+                return (x0 as usize, y0 as usize);
+            }
+        }
+        for k in (-d..=d).rev().step_by(2) {
+            let mut x = if k == -d || (k != d && vb[idx(k - 1)] < vb[idx(k + 1)]) {
+                vb[idx(k + 1)]
+            } else {
+                vb[idx(k - 1)] + 1
+            } as isize;
+            let mut y = x - k;
+            while x < n && y < m && a[(n - x - 1) as usize] == b[(m - y - 1) as usize] {
+                x += 1;
+                y += 1;
+            }
+            vb[idx(k)] = x as usize;
+            if !odd && (k - delta).abs() <= d && x + vf[idx(delta - k)] as isize >= n {
+                return ((n - x) as usize, (m - y) as usize);
+            }
+        }
+    }
+    unreachable!("the searches meet after at most {} differences", max)
+}
+
+/// Execution times of the tasks of two traces over the executions
+/// that align.
+pub struct Comparison {
+    /// Execution times per task in the base and the other trace.
+    tasks: IndexMap<String, (Distribution, Distribution)>,
+    /// Executions only present in the base and the other trace,
+    /// respectively.
+    unmatched: (usize, usize),
+    /// Executions that span an overflow in the base and the other
+    /// trace, respectively.
+    gaps: (usize, usize),
+}
+
+impl Comparison {
+    pub fn new(base: &[Execution], other: &[Execution]) -> Self {
+        let tasks = |execs: &[Execution]| execs.iter().map(|e| e.task.clone()).collect::<Vec<_>>();
+        let gaps = |execs: &[Execution]| execs.iter().filter(|e| e.gap).count();
+        let pairs = align(&tasks(base), &tasks(other));
+
+        let mut comparison = Self {
+            tasks: IndexMap::new(),
+            unmatched: (base.len() - pairs.len(), other.len() - pairs.len()),
+            gaps: (gaps(base), gaps(other)),
+        };
+        for (i, j) in pairs {
+            let (b, o) = comparison.tasks.entry(base[i].task.clone()).or_default();
+            b.add(base[i].duration);
+            o.add(other[j].duration);
+        }
+
+        comparison
+    }
+
+    /// Difference in mean execution time of `task` in the other trace
+    /// relative to the base trace, in nanoseconds. Positive if the
+    /// task got slower.
+    pub fn delta(&self, task: &str) -> Option<i128> {
+        self.tasks
+            .get(task)
+            .map(|(b, o)| o.mean().as_nanos() as i128 - b.mean().as_nanos() as i128)
+    }
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (task, (base, other)) in self.tasks.iter() {
+            let delta = self.delta(task).unwrap();
+            writeln!(
+                f,
+                "{}: {:?} -> {:?} ({}{:?}, {}) over {} executions",
+                task,
+                base.mean(),
+                other.mean(),
+                if delta < 0 { "-" } else { "+" },
+                Duration::from_nanos(delta.unsigned_abs() as u64),
+                match delta {
+                    d if d > 0 => "slower",
+                    d if d < 0 => "faster",
+                    _ => "unchanged",
+                },
+                base.count(),
+            )?;
+        }
+        write!(
+            f,
+            "{} executions only in the base trace, {} only in the other",
+            self.unmatched.0, self.unmatched.1
+        )?;
+        if self.gaps != (0, 0) {
+            write!(
+                f,
+                "\n{} executions in the base trace and {} in the other span an overflow",
+                self.gaps.0, self.gaps.1
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn exec(task: &str, start: u64, duration: u64) -> Execution {
+        Execution {
+            task: format!("app::{}", task),
+            start,
+            duration,
+            gap: false,
+        }
+    }
+
+    /// Ensure that the sequences are aligned as a diff would.
+    #[test]
+    fn align_sequences() {
+        let a = ['a', 'b', 'c', 'a', 'b', 'b', 'a'];
+        let b = ['c', 'b', 'a', 'b', 'a', 'c'];
+        let pairs = align(&a, &b);
+        assert_eq!(pairs.len(), 4);
+        assert!(pairs.iter().all(|(i, j)| a[*i] == b[*j]));
+        assert!(pairs.windows(2).all(|w| w[0].0 < w[1].0 && w[0].1 < w[1].1));
+
+        assert!(align::<char>(&[], &[]).is_empty());
+        assert_eq!(align(&a, &a).len(), a.len());
+    }
+
+    /// Ensure that the alignment is as long as the longest common
+    /// subsequence, as found by dynamic programming.
+    #[test]
+    fn align_optimally() {
+        let lcs = |a: &[u8], b: &[u8]| {
+            let mut t = vec![vec![0; b.len() + 1]; a.len() + 1];
+            for i in 0..a.len() {
+                for j in 0..b.len() {
+                    t[i + 1][j + 1] = if a[i] == b[j] {
+                        t[i][j] + 1
+                    } else {
+                        t[i][j + 1].max(t[i + 1][j])
+                    };
+                }
+            }
+            t[a.len()][b.len()]
+        };
+
+        let mut state = 1u32;
+        let mut seq = |len: usize| {
+            (0..len)
+                .map(|_| {
+                    state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                    (state >> 16) as u8 % 4
+                })
+                .collect::<Vec<_>>()
+        };
+        for len in 1..64 {
+            let (a, b) = (seq(len), seq(64 - len));
+            let pairs = align(&a, &b);
+            assert_eq!(pairs.len(), lcs(&a, &b));
+            assert!(pairs.iter().all(|(i, j)| a[*i] == b[*j]));
+            assert!(pairs.windows(2).all(|w| w[0].0 < w[1].0 && w[0].1 < w[1].1));
+        }
+    }
+
+    /// Ensure that a task that got slower is reported as such, despite
+    /// an execution only present in the other trace.
+    #[test]
+    fn slower_task() {
+        let base = [
+            exec("foo", 0, 100),
+            exec("bar", 200, 50),
+            exec("foo", 1000, 100),
+            exec("bar", 1200, 50),
+        ];
+        let other = [
+            exec("foo", 0, 150),
+            exec("bar", 300, 50),
+            exec("baz", 400, 10),
+            exec("foo", 2000, 170),
+            exec("bar", 2300, 50),
+        ];
+
+        let comparison = Comparison::new(&base, &other);
+        assert_eq!(comparison.delta("app::foo"), Some(60));
+        assert_eq!(comparison.delta("app::bar"), Some(0));
+        assert_eq!(comparison.delta("app::baz"), None);
+        assert_eq!(comparison.unmatched, (0, 1));
+        assert!(comparison
+            .to_string()
+            .starts_with("app::foo: 100ns -> 160ns (+60ns, slower) over 2 executions"));
+    }
+}
@@ -1,5 +1,128 @@
+use crate::log;
+
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, Ordering};
+
 pub trait DiagnosableError: std::error::Error {
+    /// Name of the error variant, e.g. `RTICArgumentsMissing`.
+    fn kind(&self) -> &'static str;
+
     fn diagnose(&self) -> Vec<String> {
         vec![]
     }
 }
+
+/// Format in which errors and their hints are emitted, selected via
+/// `--message-format`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MessageFormat {
+    /// Colored messages on stderr.
+    Human,
+    /// A [`Diagnostic`] per line on stderr, e.g. for editor or CI
+    /// integration.
+    Json,
+}
+
+static JSON: AtomicBool = AtomicBool::new(false);
+
+/// Selects the format of all subsequent errors, warnings, and hints.
+pub fn set_format(format: MessageFormat) {
+    JSON.store(format == MessageFormat::Json, Ordering::Relaxed);
+}
+
+pub fn format() -> MessageFormat {
+    if JSON.load(Ordering::Relaxed) {
+        MessageFormat::Json
+    } else {
+        MessageFormat::Human
+    }
+}
+
+impl std::str::FromStr for MessageFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "human" => Ok(Self::Human),
+            "json" => Ok(Self::Json),
+            _ => Err(format!(
+                "unknown message format {}; expected one of: human, json",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    Error,
+    Warning,
+    Hint,
+}
+
+/// Machine-readable representation of an error, warning, or hint.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Diagnostic {
+    pub level: Level,
+    /// Path of the error variant, e.g.
+    /// `MetadataError::RTICArgumentsMissing`, if the diagnostic stems
+    /// from one.
+    pub kind: Option<String>,
+    pub message: String,
+    pub suggestions: Vec<String>,
+}
+
+impl Diagnostic {
+    /// Writes the diagnostic as a line of JSON to stderr.
+    pub fn emit(&self) {
+        eprintln!(
+            "{}",
+            serde_json::to_string(self).expect("diagnostic is serializable")
+        );
+    }
+}
+
+/// Warns about `e`, with its hints.
+pub fn warn(e: &dyn DiagnosableError) {
+    match format() {
+        MessageFormat::Human => {
+            log::warn(e.to_string());
+            for hint in e.diagnose() {
+                log::hint(hint);
+            }
+        }
+        MessageFormat::Json => Diagnostic {
+            level: Level::Warning,
+            kind: Some(e.kind().to_string()),
+            message: e.to_string(),
+            suggestions: e.diagnose(),
+        }
+        .emit(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Ensure that diagnostics serialize as documented.
+    #[test]
+    fn serialize() {
+        let diagnostic = Diagnostic {
+            level: Level::Warning,
+            kind: Some("MetadataError::StaleArtifact".to_string()),
+            message: "stale".to_string(),
+            suggestions: vec![],
+        };
+        assert_eq!(
+            serde_json::to_value(&diagnostic).unwrap(),
+            serde_json::json!({
+                "level": "warning",
+                "kind": "MetadataError::StaleArtifact",
+                "message": "stale",
+                "suggestions": [],
+            })
+        );
+    }
+}
@@ -0,0 +1,165 @@
+//! Generation of a C header or Rust module of the identifiers over
+//! which the tasks of an application are traced, for host-side tools
+//! that correlate ITM packets with task names on their own.
+use crate::recovery::{TaskId, TraceLookupMaps};
+
+use std::fmt::Write;
+use std::path::Path;
+
+use itm::VectActive;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Language {
+    C,
+    Rust,
+}
+
+impl Language {
+    /// Rust for `*.rs` files, otherwise C.
+    pub fn of(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("rs") => Self::Rust,
+            _ => Self::C,
+        }
+    }
+}
+
+/// The exception number of `veca`, i.e. its position in the vector
+/// table, as reported in exception trace packets.
+fn exception_number(veca: &VectActive) -> Option<u16> {
+    match veca {
+        VectActive::ThreadMode => None,
+        VectActive::Exception(exception) => Some((exception.irqn() as i16 + 16) as u16),
+        VectActive::Interrupt { irqn } => Some(*irqn as u16 + 16),
+    }
+}
+
+/// Generates the identifiers of the tasks in `maps` of the application
+/// `program`: hardware tasks by exception number and software tasks
+/// by ID.
+pub fn generate(maps: &TraceLookupMaps, program: &str, lang: Language) -> String {
+    let (mut hardware, mut software) = (vec![], vec![]);
+    for (id, name) in maps.tasks() {
+        match id {
+            TaskId::Hardware(veca) => {
+                if let Some(nr) = exception_number(&veca) {
+                    hardware.push((nr as usize, name));
+                }
+            }
+            TaskId::Software(id) => software.push((id, name)),
+        }
+    }
+
+    let mut out = String::new();
+    // NOTE(unwrap) writing to a String cannot fail
+    match lang {
+        Language::C => {
+            let guard = format!(
+                "RTIC_SCOPE_{}_H",
+                program
+                    .chars()
+                    .map(|c| if c.is_ascii_alphanumeric() {
+                        c.to_ascii_uppercase()
+                    } else {
+                        '_'
+                    })
+                    .collect::<String>()
+            );
+            writeln!(
+                out,
+                "/* Generated by cargo-rtic-scope for {}. Do not edit. */\n\n#ifndef {guard}\n#define {guard}\n\n#include <stdint.h>\n\nstruct rtic_scope_task {{\n    uint16_t number;\n    const char *name;\n}};",
+                program,
+                guard = guard
+            )
+            .unwrap();
+            for (array, doc, tasks) in [
+                (
+                    "rtic_scope_hardware_tasks",
+                    "Hardware tasks by exception number",
+                    &hardware,
+                ),
+                (
+                    "rtic_scope_software_tasks",
+                    "Software tasks by ID",
+                    &software,
+                ),
+            ] {
+                writeln!(
+                    out,
+                    "\n/* {}, terminated by an entry without a name. */\nstatic const struct rtic_scope_task {}[] = {{",
+                    doc, array
+                )
+                .unwrap();
+                for (nr, name) in tasks {
+                    writeln!(out, "    {{{}, {:?}}},", nr, name).unwrap();
+                }
+                writeln!(out, "    {{0, 0}},\n}};").unwrap();
+            }
+            writeln!(out, "\n#endif /* {} */", guard).unwrap();
+        }
+        Language::Rust => {
+            writeln!(
+                out,
+                "//! Generated by cargo-rtic-scope for {}. Do not edit.",
+                program
+            )
+            .unwrap();
+            for (array, doc, tasks) in [
+                (
+                    "HARDWARE_TASKS",
+                    "Hardware tasks by exception number.",
+                    &hardware,
+                ),
+                ("SOFTWARE_TASKS", "Software tasks by ID.", &software),
+            ] {
+                writeln!(
+                    out,
+                    "\n/// {}\npub const {}: &[(u16, &str)] = &[",
+                    doc, array
+                )
+                .unwrap();
+                for (nr, name) in tasks {
+                    writeln!(out, "    ({}, {:?}),", nr, name).unwrap();
+                }
+                writeln!(out, "];").unwrap();
+            }
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::recovery::test::maps;
+
+    /// Ensure that the generated header and module contain an entry
+    /// per task.
+    #[test]
+    fn entry_per_task() {
+        let header = generate(&maps(), "blinky-2", Language::C);
+        assert!(header.contains("#ifndef RTIC_SCOPE_BLINKY_2_H"));
+        for entry in [
+            "{15, \"app::low\"},",
+            "{14, \"app::high\"},",
+            "{0, \"app::foo\"},",
+            "{1, \"app::bar\"},",
+        ] {
+            assert!(header.contains(entry), "{} not in {}", entry, header);
+        }
+        assert_eq!(header.matches("{0, 0},").count(), 2);
+
+        let module = generate(&maps(), "blinky-2", Language::Rust);
+        for entry in [
+            "(15, \"app::low\"),",
+            "(14, \"app::high\"),",
+            "(0, \"app::foo\"),",
+            "(1, \"app::bar\"),",
+        ] {
+            assert!(module.contains(entry), "{} not in {}", entry, module);
+        }
+        assert_eq!(Language::of(Path::new("tasks.rs")), Language::Rust);
+        assert_eq!(Language::of(Path::new("tasks.h")), Language::C);
+    }
+}
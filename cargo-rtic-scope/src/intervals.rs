@@ -0,0 +1,268 @@
+//! Pairing of the entries and exits of tasks into the intervals during
+//! which the tasks are active. Shared by all analyses of task timing,
+//! such that lost exits and overflows are handled alike.
+//!
+//! A task is active from its entry until its exit, also while it is
+//! preempted. The exit of, or return to, a task ends any task entered
+//! after it: their exits were lost, e.g. on overflow, or on
+//! tail-chaining, where an exception is entered directly after another
+//! exits without returning to the preempted task in between.
+use rtic_scope_api as api;
+
+/// What an overflow does to the executions in progress when it occurs.
+/// Packets may have been dropped on overflow, so the timing of these
+/// executions cannot be trusted.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OverflowPolicy {
+    /// Discard the executions in progress.
+    DropOpenIntervals,
+    /// Keep the executions in progress, but flag them as spanning a
+    /// gap.
+    KeepAndFlag,
+}
+
+impl Default for OverflowPolicy {
+    fn default() -> Self {
+        Self::KeepAndFlag
+    }
+}
+
+impl std::str::FromStr for OverflowPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "drop-open-intervals" => Ok(Self::DropOpenIntervals),
+            "keep-and-flag" => Ok(Self::KeepAndFlag),
+            _ => Err(format!(
+                "unknown overflow policy {}; expected one of: drop-open-intervals, keep-and-flag",
+                s
+            )),
+        }
+    }
+}
+
+/// A task that has been entered but not yet exited.
+#[derive(Debug, Clone)]
+pub struct Open {
+    /// Full path of the task.
+    pub task: String,
+    pub priority: Option<u8>,
+    /// Offset since target reset at which the task was entered, in
+    /// nanoseconds.
+    pub start: u64,
+    /// Time the task executed up until it was last preempted.
+    executed: u64,
+    /// When the task last resumed.
+    resumed: u64,
+    /// Whether an overflow occurred since the task was entered.
+    pub gap: bool,
+}
+
+/// A task from its entry until its exit.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Interval {
+    /// Full path of the task.
+    pub task: String,
+    /// Offsets since target reset at which the task was entered and
+    /// exited, in nanoseconds.
+    pub start: u64,
+    pub end: u64,
+    /// Time the task executed between its entry and exit, excluding
+    /// the time it was preempted.
+    pub executed: u64,
+    /// Whether an overflow occurred during the interval, such that its
+    /// timing may be inaccurate.
+    pub gap: bool,
+}
+
+impl Interval {
+    /// Time between the entry and exit of the task, including the time
+    /// it was preempted.
+    pub fn duration(&self) -> u64 {
+        self.end.saturating_sub(self.start)
+    }
+}
+
+/// The stack of active tasks, in the order they were entered.
+#[derive(Debug, Clone, Default)]
+pub struct Intervals {
+    policy: OverflowPolicy,
+    open: Vec<Open>,
+}
+
+impl Intervals {
+    pub fn new(policy: OverflowPolicy) -> Self {
+        Self {
+            policy,
+            open: vec![],
+        }
+    }
+
+    /// The active tasks, in the order they were entered: a task that
+    /// preempts another is after it.
+    pub fn open(&self) -> &[Open] {
+        &self.open
+    }
+
+    /// The number of active tasks.
+    pub fn depth(&self) -> usize {
+        self.open.len()
+    }
+
+    /// Enters `task` at `now`, preempting the executing task, if any.
+    /// Returns the depth of the task.
+    pub fn enter(&mut self, task: &str, priority: Option<u8>, now: u64) -> usize {
+        if let Some(top) = self.open.last_mut() {
+            top.executed += now.saturating_sub(top.resumed);
+        }
+        self.open.push(Open {
+            task: task.to_string(),
+            priority,
+            start: now,
+            executed: 0,
+            resumed: now,
+            gap: false,
+        });
+
+        self.open.len()
+    }
+
+    /// Exits `task` at `now`. Returns the depth and the interval of the
+    /// task, or `None` if it is not active, e.g. if it was entered
+    /// before the trace started or discarded on overflow.
+    pub fn exit(&mut self, task: &str, now: u64) -> Option<(usize, Interval)> {
+        let i = self.open.iter().rposition(|o| o.task == task)?;
+        // NOTE(truncate) the time the task executed since its lost
+        // preemptions is lost with them.
+        let top = i + 1 == self.open.len();
+        self.open.truncate(i + 1);
+        let mut open = self.open.pop().unwrap();
+        if top {
+            open.executed += now.saturating_sub(open.resumed);
+        }
+        if let Some(top) = self.open.last_mut() {
+            top.resumed = now;
+        }
+
+        Some((
+            i + 1,
+            Interval {
+                task: open.task,
+                start: open.start,
+                end: now,
+                executed: open.executed,
+                gap: open.gap,
+            },
+        ))
+    }
+
+    /// Returns to `task` at `now`, which resumes it. Returns the depth
+    /// of the task, or `None` if it is not active.
+    pub fn resume(&mut self, task: &str, now: u64) -> Option<usize> {
+        let i = self.open.iter().rposition(|o| o.task == task)?;
+        if i + 1 < self.open.len() {
+            self.open.truncate(i + 1);
+            self.open[i].resumed = now;
+        }
+
+        Some(i + 1)
+    }
+
+    /// Discards or flags the active tasks as per the overflow policy.
+    pub fn overflow(&mut self) {
+        match self.policy {
+            OverflowPolicy::DropOpenIntervals => self.open.clear(),
+            OverflowPolicy::KeepAndFlag => self.open.iter_mut().for_each(|o| o.gap = true),
+        }
+    }
+
+    /// Forgets all active tasks, e.g. when which tasks are executing
+    /// is unknown.
+    pub fn clear(&mut self) {
+        self.open.clear();
+    }
+
+    /// Accounts for the task events and overflows of `chunk`. Returns
+    /// the intervals it completes, in order of exit.
+    pub fn update(&mut self, chunk: &api::EventChunk) -> Vec<Interval> {
+        let now = api::timestamp_offset(&chunk.timestamp).as_nanos() as u64;
+        let mut done = vec![];
+
+        for event in chunk.events.iter() {
+            match event {
+                api::EventType::Task {
+                    name,
+                    action,
+                    priority,
+                    ..
+                } => match action {
+                    api::TaskAction::Entered => {
+                        self.enter(name, *priority, now);
+                    }
+                    api::TaskAction::Exited => {
+                        done.extend(self.exit(name, now).map(|(_, interval)| interval));
+                    }
+                    api::TaskAction::Returned => {
+                        self.resume(name, now);
+                    }
+                    // NOTE(noop) the last poll of the task has exited
+                    // already.
+                    api::TaskAction::Completed => (),
+                },
+                api::EventType::Overflow => self.overflow(),
+                _ => (),
+            }
+        }
+
+        done
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Ensure that the exit of, or return to, a task ends the tasks
+    /// entered after it, and that preempted time is excluded from the
+    /// executed time of a task.
+    #[test]
+    fn lost_exits() {
+        let mut intervals = Intervals::default();
+        assert_eq!(intervals.enter("app::low", None, 0), 1);
+        assert_eq!(intervals.enter("app::mid", None, 100), 2);
+        assert_eq!(intervals.enter("app::high", None, 150), 3);
+        // The exit of app::high is lost.
+        let (depth, mid) = intervals.exit("app::mid", 200).unwrap();
+        assert_eq!(depth, 2);
+        assert_eq!((mid.duration(), mid.executed), (100, 50));
+        assert_eq!(intervals.depth(), 1);
+        assert!(intervals.exit("app::high", 210).is_none());
+
+        // The exit of app::high is lost again, but app::low is returned
+        // to.
+        intervals.enter("app::high", None, 300);
+        assert_eq!(intervals.resume("app::low", 350), Some(1));
+        let (_, low) = intervals.exit("app::low", 400).unwrap();
+        assert_eq!((low.duration(), low.executed), (400, 100 + 100 + 50));
+        assert_eq!(intervals.depth(), 0);
+    }
+
+    /// Ensure that intervals in progress on overflow are dropped or
+    /// flagged as per the policy.
+    #[test]
+    fn overflow_policies() {
+        for (policy, expected) in [
+            (OverflowPolicy::DropOpenIntervals, None),
+            (OverflowPolicy::KeepAndFlag, Some(true)),
+        ] {
+            let mut intervals = Intervals::new(policy);
+            intervals.enter("app::foo", None, 0);
+            intervals.overflow();
+            assert_eq!(
+                intervals.exit("app::foo", 100).map(|(_, i)| i.gap),
+                expected
+            );
+        }
+    }
+}
@@ -0,0 +1,586 @@
+//! Timing analysis of the resolved events of a trace, reported at the
+//! end of a trace or replay.
+use crate::intervals::{Interval, Intervals, OverflowPolicy};
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::Duration;
+
+use indexmap::{IndexMap, IndexSet};
+use rtic_scope_api as api;
+use serde::Serialize;
+
+/// Running statistics of a series of durations.
+#[derive(Debug, Clone, Default)]
+pub struct Distribution {
+    count: usize,
+    min: u64,
+    max: u64,
+    sum: u64,
+    mean: f64,
+    /// Sum of squared deviations from the mean; see Welford's online
+    /// algorithm.
+    m2: f64,
+}
+
+impl Distribution {
+    pub fn add(&mut self, nanos: u64) {
+        if self.count == 0 {
+            self.min = nanos;
+            self.max = nanos;
+        } else {
+            self.min = self.min.min(nanos);
+            self.max = self.max.max(nanos);
+        }
+
+        self.count += 1;
+        self.sum += nanos;
+        let x = nanos as f64;
+        let delta = x - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (x - self.mean);
+    }
+
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    pub fn min(&self) -> Duration {
+        Duration::from_nanos(self.min)
+    }
+
+    pub fn max(&self) -> Duration {
+        Duration::from_nanos(self.max)
+    }
+
+    pub fn total(&self) -> Duration {
+        Duration::from_nanos(self.sum)
+    }
+
+    pub fn mean(&self) -> Duration {
+        Duration::from_nanos(self.mean.round() as u64)
+    }
+
+    /// Population standard deviation.
+    pub fn stddev(&self) -> Duration {
+        if self.count == 0 {
+            return Duration::ZERO;
+        }
+        Duration::from_nanos((self.m2 / self.count as f64).sqrt().round() as u64)
+    }
+}
+
+impl fmt::Display for Distribution {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "mean {:?} (min {:?}, max {:?}, stddev {:?}) over {} samples",
+            self.mean(),
+            self.min(),
+            self.max(),
+            self.stddev(),
+            self.count()
+        )
+    }
+}
+
+/// Histogram of a series of durations in buckets of a fixed width.
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct Histogram {
+    bucket_width_ns: u64,
+    /// Number of durations in each non-empty bucket, by the start of
+    /// the bucket in nanoseconds.
+    buckets: BTreeMap<u64, usize>,
+}
+
+impl Histogram {
+    pub fn new(bucket_width: Duration) -> Self {
+        Self {
+            bucket_width_ns: (bucket_width.as_nanos() as u64).max(1),
+            buckets: BTreeMap::new(),
+        }
+    }
+
+    pub fn add(&mut self, nanos: u64) {
+        let start = nanos - nanos % self.bucket_width_ns;
+        *self.buckets.entry(start).or_default() += 1;
+    }
+}
+
+/// Histograms of the timing of a hardware task.
+#[derive(Debug, Clone, Serialize)]
+pub struct TaskHistograms {
+    /// Time between successive arrivals.
+    pub period: Histogram,
+    /// Time between entry and exit, including the time the task was
+    /// preempted.
+    pub response: Histogram,
+}
+
+/// Timing of the hardware tasks of a trace.
+///
+/// Exceptions are traced on entry, not when they are pended: the time
+/// between the triggering event and the entry of a task is not
+/// observable, and arrivals are thus entries.
+pub struct LatencyReport {
+    hardware_tasks: IndexSet<String>,
+    policy: OverflowPolicy,
+    /// Offset since target reset of the latest arrival of each
+    /// hardware task, in nanoseconds.
+    last_arrival: IndexMap<String, u64>,
+    /// Time between successive arrivals of each hardware task. The
+    /// variation of which is the jitter of the task.
+    inter_arrival: IndexMap<String, Distribution>,
+    intervals: Intervals,
+    /// Width of the buckets of [`Self::histograms`], if collected.
+    bucket_width: Option<Duration>,
+    histograms: IndexMap<String, TaskHistograms>,
+}
+
+impl LatencyReport {
+    /// Creates a report over the given hardware tasks, by full path.
+    /// Responses in progress on overflow are handled as per `policy`,
+    /// which also discards the arrivals before an overflow if open
+    /// intervals are dropped.
+    pub fn new(hardware_tasks: IndexSet<String>, policy: OverflowPolicy) -> Self {
+        Self {
+            hardware_tasks,
+            policy,
+            last_arrival: IndexMap::new(),
+            inter_arrival: IndexMap::new(),
+            intervals: Intervals::new(policy),
+            bucket_width: None,
+            histograms: IndexMap::new(),
+        }
+    }
+
+    /// Additionally collect histograms of the period and response time
+    /// of each hardware task in buckets of `bucket_width`.
+    pub fn with_histograms(mut self, bucket_width: Duration) -> Self {
+        self.bucket_width = Some(bucket_width);
+        self
+    }
+
+    pub fn update(&mut self, chunk: &api::EventChunk) {
+        let now = api::timestamp_offset(&chunk.timestamp).as_nanos() as u64;
+
+        for event in chunk.events.iter() {
+            match event {
+                api::EventType::Task {
+                    name,
+                    action: api::TaskAction::Entered,
+                    ..
+                } if self.hardware_tasks.contains(name) => {
+                    if let Some(last) = self.last_arrival.insert(name.clone(), now) {
+                        let period = now.saturating_sub(last);
+                        self.inter_arrival
+                            .entry(name.clone())
+                            .or_default()
+                            .add(period);
+                        if let Some(histograms) = self.histograms_of(name) {
+                            histograms.period.add(period);
+                        }
+                    }
+                }
+                // Packets may have been dropped: arrivals may have been
+                // lost.
+                api::EventType::Overflow if self.policy == OverflowPolicy::DropOpenIntervals => {
+                    self.last_arrival.clear()
+                }
+                _ => (),
+            }
+        }
+
+        for interval in self.intervals.update(chunk) {
+            if !self.hardware_tasks.contains(&interval.task) {
+                continue;
+            }
+            if let Some(histograms) = self.histograms_of(&interval.task) {
+                histograms.response.add(interval.duration());
+            }
+        }
+    }
+
+    fn histograms_of(&mut self, task: &str) -> Option<&mut TaskHistograms> {
+        let bucket_width = self.bucket_width?;
+        Some(
+            self.histograms
+                .entry(task.to_string())
+                .or_insert_with(|| TaskHistograms {
+                    period: Histogram::new(bucket_width),
+                    response: Histogram::new(bucket_width),
+                }),
+        )
+    }
+
+    /// Histograms of the timing of each hardware task, if collected.
+    /// Serializes to JSON, e.g. to plot them.
+    pub fn histograms(&self) -> &IndexMap<String, TaskHistograms> {
+        &self.histograms
+    }
+
+    /// Time between successive arrivals of the given hardware task.
+    pub fn inter_arrival(&self, task: &str) -> Option<&Distribution> {
+        self.inter_arrival.get(task)
+    }
+}
+
+impl fmt::Display for LatencyReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, task) in self.hardware_tasks.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            match self.inter_arrival(task) {
+                Some(dist) => write!(f, "{}: inter-arrival {}", task, dist)?,
+                None => write!(f, "{}: less than two arrivals", task)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The analyses selected for a trace, updated with each chunk and
+/// reported at the end of the trace or replay.
+#[derive(Default)]
+pub struct Analyses {
+    pub latency: Option<LatencyReport>,
+    pub summary: Option<TaskSummary>,
+}
+
+impl Analyses {
+    pub fn update(&mut self, chunk: &api::EventChunk) {
+        if let Some(latency) = &mut self.latency {
+            latency.update(chunk);
+        }
+        if let Some(summary) = &mut self.summary {
+            summary.update(chunk);
+        }
+    }
+}
+
+/// Durations of the executions of each task of a trace, reported at the
+/// end of a trace or replay.
+pub struct TaskSummary {
+    tasks: IndexSet<String>,
+    intervals: Intervals,
+    /// Time between the entry and exit of each task, including the
+    /// time it was preempted.
+    wall: IndexMap<String, Distribution>,
+    /// Time each task executed between its entry and exit, excluding
+    /// the time it was preempted.
+    exclusive: IndexMap<String, Distribution>,
+}
+
+impl TaskSummary {
+    /// Creates a summary over the given tasks, by full path. Tasks not
+    /// given are summarized after them. Executions in progress on
+    /// overflow are handled as per `policy`.
+    pub fn new(tasks: IndexSet<String>, policy: OverflowPolicy) -> Self {
+        Self {
+            tasks,
+            intervals: Intervals::new(policy),
+            wall: IndexMap::new(),
+            exclusive: IndexMap::new(),
+        }
+    }
+
+    pub fn update(&mut self, chunk: &api::EventChunk) {
+        for interval in self.intervals.update(chunk) {
+            self.wall
+                .entry(interval.task.clone())
+                .or_default()
+                .add(interval.duration());
+            self.exclusive
+                .entry(interval.task)
+                .or_default()
+                .add(interval.executed);
+        }
+    }
+
+    /// Time between the entry and exit of the given task, including the
+    /// time it was preempted.
+    pub fn wall(&self, task: &str) -> Option<&Distribution> {
+        self.wall.get(task)
+    }
+
+    /// Time the given task executed between its entry and exit,
+    /// excluding the time it was preempted.
+    pub fn exclusive(&self, task: &str) -> Option<&Distribution> {
+        self.exclusive.get(task)
+    }
+}
+
+impl fmt::Display for TaskSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let tasks: IndexSet<&String> = self.tasks.iter().chain(self.wall.keys()).collect();
+        let width = tasks.iter().map(|t| t.len()).max().unwrap_or(0).max(4);
+
+        write!(
+            f,
+            "{:<width$} {:>7} {:>12} {:>12} {:>12} {:>12} {:>12} {:>12}",
+            "task",
+            "count",
+            "total",
+            "min",
+            "max",
+            "mean",
+            "excl. total",
+            "excl. mean",
+            width = width
+        )?;
+        for task in tasks {
+            writeln!(f)?;
+            match (self.wall(task), self.exclusive(task)) {
+                (Some(wall), Some(exclusive)) => write!(
+                    f,
+                    "{:<width$} {:>7} {:>12} {:>12} {:>12} {:>12} {:>12} {:>12}",
+                    task,
+                    wall.count(),
+                    format!("{:?}", wall.total()),
+                    format!("{:?}", wall.min()),
+                    format!("{:?}", wall.max()),
+                    format!("{:?}", wall.mean()),
+                    format!("{:?}", exclusive.total()),
+                    format!("{:?}", exclusive.mean()),
+                    width = width
+                )?,
+                _ => write!(f, "{:<width$} {:>7}", task, 0, width = width)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// A completed execution of a task.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Execution {
+    /// Full path of the task.
+    pub task: String,
+    /// Offset since target reset at which the task was entered, in
+    /// nanoseconds.
+    pub start: u64,
+    /// Time between the entry and exit of the task, in nanoseconds.
+    pub duration: u64,
+    /// Whether an overflow occurred during the execution, such that its
+    /// duration may be inaccurate.
+    pub gap: bool,
+}
+
+impl From<Interval> for Execution {
+    fn from(interval: Interval) -> Self {
+        Self {
+            duration: interval.duration(),
+            task: interval.task,
+            start: interval.start,
+            gap: interval.gap,
+        }
+    }
+}
+
+/// Pairs the entries and exits of tasks into [`Execution`]s.
+pub struct Executions {
+    intervals: Intervals,
+    done: Vec<Execution>,
+}
+
+impl Executions {
+    pub fn new(policy: OverflowPolicy) -> Self {
+        Self {
+            intervals: Intervals::new(policy),
+            done: vec![],
+        }
+    }
+
+    pub fn update(&mut self, chunk: &api::EventChunk) {
+        self.done.extend(
+            self.intervals
+                .update(chunk)
+                .into_iter()
+                .map(Execution::from),
+        );
+    }
+
+    /// Takes the executions completed since the last call, in order of
+    /// exit, e.g. to export them as the trace progresses.
+    pub fn take(&mut self) -> Vec<Execution> {
+        std::mem::take(&mut self.done)
+    }
+
+    /// The completed executions in order of entry. Executions that
+    /// have not exited are discarded.
+    pub fn finish(mut self) -> Vec<Execution> {
+        self.done.sort_by_key(|e| e.start);
+        self.done
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Ensure that a late arrival of a periodic task is captured as
+    /// jitter.
+    #[test]
+    fn periodic_jitter() {
+        let mut report = LatencyReport::new(
+            IndexSet::from_iter(["app::tick".to_string(), "app::never".to_string()]),
+            OverflowPolicy::KeepAndFlag,
+        );
+        // Nominal period of 1 ms; the fourth arrival is 300 µs late.
+        for us in [0, 1000, 2000, 3300, 4000, 5000] {
+            let chunk = api::ChunkBuilder::at(Duration::from_micros(us))
+                .entered("app::tick")
+                .entered("app::software")
+                .build();
+            report.update(&chunk);
+        }
+
+        let dist = report.inter_arrival("app::tick").unwrap();
+        assert_eq!(dist.count(), 5);
+        assert_eq!(dist.min(), Duration::from_micros(700));
+        assert_eq!(dist.max(), Duration::from_micros(1300));
+        assert_eq!(dist.mean(), Duration::from_micros(1000));
+        // sqrt((300² + 300²) / 5) µs
+        assert_eq!(dist.stddev(), Duration::from_nanos(189_737));
+        assert!(report.inter_arrival("app::software").is_none());
+        assert!(report.inter_arrival("app::never").is_none());
+    }
+
+    /// Ensure that periods and response times are bucketed, and that a
+    /// period only spans an overflow if open intervals are kept.
+    #[test]
+    fn histograms() {
+        let replay = |policy| {
+            let mut report =
+                LatencyReport::new(IndexSet::from_iter(["app::tick".to_string()]), policy)
+                    .with_histograms(Duration::from_micros(100));
+            let at = |us| api::ChunkBuilder::at(Duration::from_micros(us));
+            for chunk in [
+                at(0).entered("app::tick"),
+                at(10).exited("app::tick"),
+                at(1000).entered("app::tick"),
+                at(1150).exited("app::tick"),
+                at(1500).overflow(),
+                at(2050).entered("app::tick"),
+                at(2060).exited("app::tick"),
+            ] {
+                report.update(&chunk.build());
+            }
+            report
+        };
+
+        let report = replay(OverflowPolicy::DropOpenIntervals);
+        let histograms = &report.histograms()["app::tick"];
+        assert_eq!(
+            serde_json::to_value(&histograms.period).unwrap(),
+            serde_json::json!({
+                "bucket_width_ns": 100_000,
+                "buckets": { "1000000": 1 },
+            })
+        );
+        assert_eq!(
+            histograms.response.buckets,
+            BTreeMap::from_iter([(0, 2), (100_000, 1)])
+        );
+
+        let report = replay(OverflowPolicy::KeepAndFlag);
+        assert_eq!(
+            report.histograms()["app::tick"].period.buckets,
+            BTreeMap::from_iter([(1_000_000, 2)])
+        );
+        assert_eq!(report.inter_arrival("app::tick").unwrap().count(), 2);
+    }
+
+    /// Ensure that the time a task is preempted is included in its wall
+    /// duration, but not in its exclusive duration.
+    #[test]
+    fn preempted_summary() {
+        let mut summary = TaskSummary::new(
+            IndexSet::from_iter(["app::low".to_string(), "app::high".to_string()]),
+            OverflowPolicy::KeepAndFlag,
+        );
+        let at = |ns| api::ChunkBuilder::at(Duration::from_nanos(ns));
+        for chunk in [
+            at(0).entered("app::low"),
+            at(100).entered("app::high"),
+            at(250).exited("app::high").returned("app::low"),
+            at(300).exited("app::low"),
+            at(400).entered("app::high"),
+            at(450).exited("app::high"),
+        ] {
+            summary.update(&chunk.build());
+        }
+
+        let low = summary.wall("app::low").unwrap();
+        assert_eq!((low.count(), low.total()), (1, Duration::from_nanos(300)));
+        let low = summary.exclusive("app::low").unwrap();
+        assert_eq!(low.total(), Duration::from_nanos(150));
+
+        let high = summary.wall("app::high").unwrap();
+        assert_eq!(high.count(), 2);
+        assert_eq!(high.total(), Duration::from_nanos(200));
+        assert_eq!(high.min(), Duration::from_nanos(50));
+        assert_eq!(high.max(), Duration::from_nanos(150));
+        assert_eq!(
+            summary.exclusive("app::high").unwrap().total(),
+            high.total()
+        );
+
+        let table = summary.to_string();
+        assert!(table.lines().next().unwrap().starts_with("task"));
+        assert_eq!(table.lines().count(), 3);
+    }
+
+    /// Ensure that executions in progress on overflow are dropped or
+    /// flagged as per the policy.
+    #[test]
+    fn overflow_policies() {
+        let chunks = [
+            api::ChunkBuilder::at(Duration::from_nanos(0))
+                .entered("app::low")
+                .build(),
+            api::ChunkBuilder::at(Duration::from_nanos(100))
+                .entered("app::high")
+                .exited("app::high")
+                .build(),
+            api::ChunkBuilder::at(Duration::from_nanos(200))
+                .overflow()
+                .build(),
+            api::ChunkBuilder::at(Duration::from_nanos(300))
+                .exited("app::low")
+                .entered("app::high")
+                .build(),
+            api::ChunkBuilder::at(Duration::from_nanos(400))
+                .exited("app::high")
+                .build(),
+        ];
+        let executions = |policy| {
+            let mut executions = Executions::new(policy);
+            chunks.iter().for_each(|chunk| executions.update(chunk));
+            executions
+                .finish()
+                .into_iter()
+                .map(|e| (e.task, e.start, e.duration, e.gap))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            executions(OverflowPolicy::DropOpenIntervals),
+            [
+                ("app::high".to_string(), 100, 0, false),
+                ("app::high".to_string(), 300, 100, false),
+            ]
+        );
+        assert_eq!(
+            executions(OverflowPolicy::KeepAndFlag),
+            [
+                ("app::low".to_string(), 0, 300, true),
+                ("app::high".to_string(), 100, 0, false),
+                ("app::high".to_string(), 300, 100, false),
+            ]
+        );
+    }
+}
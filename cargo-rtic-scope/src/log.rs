@@ -1,4 +1,6 @@
 //! Auxilliary functions for logging information to `stdout`.
+use crate::diag;
+
 use colored::Colorize;
 use crossterm::{
     cursor,
@@ -32,12 +34,32 @@ pub fn status(header: &str, msg: String) {
     indent_with(header.green().bold(), msg);
 }
 
+/// Writes `msg` as a diagnostic of `level` if JSON messages are
+/// requested. Returns whether it did.
+fn diagnostic(level: diag::Level, msg: &str) -> bool {
+    if diag::format() != diag::MessageFormat::Json {
+        return false;
+    }
+    diag::Diagnostic {
+        level,
+        kind: None,
+        message: msg.to_string(),
+        suggestions: vec![],
+    }
+    .emit();
+    true
+}
+
 pub fn warn(msg: String) {
-    indent_with("Warning".yellow().bold(), msg);
+    if !diagnostic(diag::Level::Warning, &msg) {
+        indent_with("Warning".yellow().bold(), msg);
+    }
 }
 
 pub fn err(msg: String) {
-    indent_with("Error".red().bold(), msg);
+    if !diagnostic(diag::Level::Error, &msg) {
+        indent_with("Error".red().bold(), msg);
+    }
 }
 
 pub fn frontend(msg: String) {
@@ -45,5 +67,11 @@ pub fn frontend(msg: String) {
 }
 
 pub fn hint(msg: String) {
-    indent_with("Hint".blue().bold(), msg);
+    if !diagnostic(diag::Level::Hint, &msg) {
+        indent_with("Hint".blue().bold(), msg);
+    }
+}
+
+pub fn debug(msg: String) {
+    indent_with("Debug".magenta().bold(), msg);
 }
@@ -8,7 +8,6 @@ use std::path::PathBuf;
 use anyhow::{bail, Context};
 use async_std::{prelude::*, process};
 use cargo_metadata::Artifact;
-use chrono::Local;
 use crossbeam_channel as channel;
 use futures::executor::block_on;
 use probe_rs_cli_util::{
@@ -19,16 +18,33 @@ use rtic_scope_api as api;
 use structopt::StructOpt;
 use thiserror::Error;
 
+mod archive;
+mod bookmarks;
 mod build;
+mod compare;
 mod diag;
+mod header;
+mod intervals;
+mod latency;
 mod log;
 mod manifest;
 mod recovery;
+mod repro;
+mod retime;
 mod sinks;
 mod sources;
+mod stats;
+mod symbols;
+mod task_filter;
+mod virtual_tasks;
 
 use build::{CargoError, CargoWrapper};
+use diag::DiagnosableError;
+use latency::{Analyses, LatencyReport, TaskSummary};
 use recovery::TraceMetadata;
+use stats::{Stats, StatusLine};
+use task_filter::TaskFilter;
+use virtual_tasks::VirtualTasks;
 
 pub type TraceData = itm::TimestampedTracePackets;
 
@@ -39,10 +55,189 @@ struct Opts {
     #[structopt(long = "frontend", short = "-F", default_value = "dummy")]
     frontends: Vec<String>,
 
+    /// Export the recorded/replayed trace via the given exporter (ctf,
+    /// utilization, influx, chrome, vcd, flamegraph) to <output> instead of
+    /// forwarding it to frontends, e.g. to convert recorded traces in
+    /// batch.
+    #[structopt(long = "export", requires("output"))]
+    export: Option<sinks::Exporter>,
+
+    /// Output of the exporter selected via --export.
+    #[structopt(
+        name = "output",
+        long = "output",
+        short = "o",
+        parse(from_os_str),
+        requires("export")
+    )]
+    output: Option<PathBuf>,
+
+    /// Additionally export the recorded/replayed trace in the Common
+    /// Trace Format (CTF) to the given directory. Shorthand for the ctf
+    /// exporter of --export that keeps forwarding to frontends.
+    #[structopt(long = "ctf", parse(from_os_str))]
+    ctf: Option<PathBuf>,
+
+    /// Additionally export the CPU utilization per task priority of the
+    /// recorded/replayed trace as a CSV time series to the given file.
+    /// Shorthand for the utilization exporter of --export that keeps
+    /// forwarding to frontends.
+    #[structopt(long = "utilization", parse(from_os_str))]
+    utilization: Option<PathBuf>,
+
+    /// Size of the windows of the exported CPU utilization, in
+    /// microseconds.
+    #[structopt(long = "utilization-window", default_value = "1000")]
+    utilization_window: u64,
+
+    /// Additionally record the recorded/replayed trace into a
+    /// self-describing archive at the given path, bundling the trace
+    /// metadata, the trace data, the resolved events, and the raw trace
+    /// stream when replaying a raw file. Archives are replayed via
+    /// `replay --trace-file`.
+    #[structopt(long = "archive", parse(from_os_str))]
+    archive: Option<PathBuf>,
+
+    /// Do not forward the events of the given task to frontends and
+    /// exporters, by software task ID or by a glob (`*` and `?`
+    /// wildcards) over its full path, e.g. `app::some_task`. Takes
+    /// precedence over --include. Recorded traces are not affected.
+    #[structopt(long = "mask")]
+    mask: Vec<String>,
+
+    /// Present tasks under another name, given as `<path>=<name>`,
+    /// e.g. `app::sensors::i2c::poll=i2c_poll`. A module path renames
+    /// all tasks within it. Recorded traces are not affected.
+    #[structopt(long = "rename")]
+    rename: Vec<String>,
+
+    /// Only forward the events of tasks whose full path matches the
+    /// given glob (`*` and `?` wildcards), e.g. `app::sensor_*`, to
+    /// frontends and exporters. Other events are selected via
+    /// --event-kinds. Recorded traces are not affected.
+    #[structopt(long = "include")]
+    include: Vec<String>,
+
+    /// Aggregate tasks into a virtual task, given as
+    /// `<name>=<path>,<path>,...`, e.g.
+    /// `transfer=app::dma1,app::dma2`. The virtual task is entered when
+    /// any of the tasks is entered and exited when all have exited.
+    /// Recorded traces are not affected.
+    #[structopt(long = "virtual-task")]
+    virtual_tasks: Vec<String>,
+
+    /// Only forward events of the given kinds (overflow, task,
+    /// data_trace, unknown, unmappable, invalid, host_dropped,
+    /// pc_sample, resynced, log) to frontends and exporters. Recorded
+    /// traces are not affected.
+    #[structopt(long = "event-kinds", use_delimiter = true)]
+    event_kinds: Vec<api::EventKind>,
+
+    /// Attach the raw ITM bytes each chunk of events was decoded from to
+    /// the chunk forwarded to frontends and exporters, e.g. to present
+    /// the bytes behind an event. Requires a source that decodes a raw
+    /// ITM byte stream.
+    #[structopt(long = "raw-bytes")]
+    raw_bytes: bool,
+
+    /// Split chunks of more than the given number of events before
+    /// forwarding them to frontends, e.g. to bound the latency of a
+    /// frontend under bursts. Tasks entered and exited within a chunk
+    /// are not split from their exits.
+    #[structopt(long = "max-chunk-events")]
+    max_chunk_events: Option<usize>,
+
+    /// Pause reading from the source while a frontend cannot keep up
+    /// instead of dropping chunks for it. Chunks that are dropped
+    /// otherwise are reported to the frontend as host-side drops.
+    /// Recordings and exports never drop chunks. When tracing, the
+    /// target may overflow instead.
+    #[structopt(long = "backpressure")]
+    backpressure: bool,
+
+    /// Present the entry and exit of the interrupts from which RTIC
+    /// dispatches software tasks as tasks named
+    /// `app::dispatcher::<IRQ>` instead of hiding them.
+    #[structopt(long = "dispatchers")]
+    dispatchers: bool,
+
+    /// Report the timing of hardware tasks, e.g. the jitter of their
+    /// arrivals, at the end of the trace/replay.
+    #[structopt(long = "latency")]
+    latency: bool,
+
+    /// Additionally write histograms of the period and response time
+    /// of each hardware task to the given file as JSON at the end of
+    /// the trace/replay. Implies --latency.
+    #[structopt(long = "latency-histograms", parse(from_os_str))]
+    latency_histograms: Option<PathBuf>,
+
+    /// Width of the buckets of --latency-histograms, in nanoseconds.
+    #[structopt(long = "histogram-bucket-width", default_value = "1000")]
+    histogram_bucket_width: u64,
+
+    /// What an overflow does to the task executions in progress, which
+    /// may have lost packets, when analyzed via --latency, --summary, or
+    /// `replay --compare`: discard them (drop-open-intervals), or keep them but
+    /// flag them as spanning an overflow (keep-and-flag). Discarding
+    /// them also discards the arrivals before the overflow, such that
+    /// no inter-arrival time spans it.
+    #[structopt(long = "overflow-policy", default_value = "keep-and-flag")]
+    overflow_policy: intervals::OverflowPolicy,
+
+    /// Summarize the number of executions of each task and their
+    /// durations, with and without the time the task was preempted, at
+    /// the end of the trace/replay.
+    #[structopt(long = "summary")]
+    summary: bool,
+
+    /// Swap the roles of the DWT comparators for entering and exiting
+    /// software tasks, i.e. `dwt_enter_id` and `dwt_exit_id`.
+    #[structopt(long = "swap-comparators")]
+    swap_comparators: bool,
+
+    /// Drop all packets after the given number of consecutive malformed
+    /// packets until the next synchronization packet, e.g. when the
+    /// SWO link is noisy, and report the number of dropped packets
+    /// instead of each malformed packet. Recorded traces are not
+    /// affected.
+    #[structopt(long = "resync-after")]
+    resync_after: Option<usize>,
+
+    /// Log each decoded packet alongside the events it is mapped to,
+    /// including the reason it could not be mapped, if any. E.g. to
+    /// diagnose a misconfigured `dwt_enter_id` or `dwt_exit_id`.
+    #[structopt(long = "debug-packets")]
+    debug_packets: bool,
+
+    /// Format of emitted errors, warnings, and hints (human, json).
+    /// With json, each is printed to stderr as a JSON object of its
+    /// level, kind, message, and suggestions.
+    #[structopt(long = "message-format", default_value = "human", global = true)]
+    message_format: diag::MessageFormat,
+
     #[structopt(subcommand)]
     cmd: Command,
 }
 
+impl Opts {
+    /// The exporters to export the trace via and their outputs, as
+    /// selected via --export, --ctf, and --utilization.
+    fn exports(&self) -> Vec<(sinks::Exporter, &std::path::Path)> {
+        [
+            (self.export, self.output.as_deref()),
+            (Some(sinks::Exporter::Ctf), self.ctf.as_deref()),
+            (
+                Some(sinks::Exporter::Utilization),
+                self.utilization.as_deref(),
+            ),
+        ]
+        .into_iter()
+        .filter_map(|(exporter, output)| Some((exporter?, output?)))
+        .collect()
+    }
+}
+
 /// Execute and trace a chosen application on a target device and record
 /// the trace stream to file.
 #[derive(StructOpt, Debug)]
@@ -52,6 +247,30 @@ struct TraceOptions {
     #[structopt(name = "serial", long = "serial")]
     serial: Option<String>,
 
+    /// Read the raw trace stream from stdin, e.g. when piped from an
+    /// external SWO tool, instead of a CMSIS-DAP device. The target is
+    /// not touched.
+    #[structopt(name = "stdin", long = "stdin", conflicts_with = "serial")]
+    stdin: bool,
+
+    /// Read the raw trace stream from the given source instead of a
+    /// CMSIS-DAP device. Supported is `tcp://host:port`, e.g. the trace
+    /// server of openocd on a remote debug host. The target is not
+    /// touched.
+    #[structopt(name = "source", long = "source", conflicts_with_all(&["serial", "stdin"]))]
+    source: Option<SourceUri>,
+
+    /// Read software task events from an RTT up-channel of the target
+    /// via the probe, instead of the SWO output. For targets that do
+    /// not route SWO: hardware tasks are not traced. Requires the `rtt`
+    /// feature of `cortex-m-rtic-trace`.
+    #[structopt(name = "rtt", long = "rtt", conflicts_with_all(&["serial", "stdin", "source"]))]
+    rtt: bool,
+
+    /// Number of the RTT up-channel to read from.
+    #[structopt(long = "rtt-channel", default_value = "0", requires("rtt"))]
+    rtt_channel: usize,
+
     /// Output directory for recorded trace streams. By default, the
     /// build chache of <bin> is used (usually ./target/).
     #[structopt(long = "trace-dir", parse(from_os_str))]
@@ -66,14 +285,36 @@ struct TraceOptions {
     remove_prev_traces: bool,
 
     /// Only resolve the translation maps; do not program or trace the target.
-    #[structopt(long = "resolve-only")]
+    #[structopt(name = "resolve-only", long = "resolve-only")]
     resolve_only: bool,
 
+    /// Print the identifiers over which the task with the given full
+    /// path (e.g. `app::some_task`) is traced.
+    #[structopt(long = "lookup", requires("resolve-only"))]
+    lookup: Vec<String>,
+
+    /// Write the identifiers over which each task is traced to the
+    /// given file: a Rust module if it ends with `.rs`, otherwise a C
+    /// header.
+    #[structopt(long = "emit-header", parse(from_os_str), requires("resolve-only"))]
+    emit_header: Option<PathBuf>,
+
     /// Do not attempt to flash, configure and/or reset the target:
     /// start tracing immediately.
     #[structopt(long = "dont-touch-target", requires("serial"))]
     dont_touch_target: bool,
 
+    /// Stop tracing once the trace timeline reaches the given number
+    /// of seconds since target reset, as if interrupted. The timeline is
+    /// that of the recorded timestamps, not of the host.
+    #[structopt(long = "duration", parse(try_from_str = parse_seconds))]
+    duration: Option<std::time::Duration>,
+
+    /// Stop tracing once the given number of ITM packets have been
+    /// received, as if interrupted.
+    #[structopt(long = "max-packets")]
+    max_packets: Option<usize>,
+
     #[structopt(flatten)]
     pac: ManifestOptions,
 
@@ -81,6 +322,43 @@ struct TraceOptions {
     flash_options: FlashOptions,
 }
 
+impl TraceOptions {
+    /// Whether the target should be flashed, configured, and reset.
+    fn touch_target(&self) -> bool {
+        !(self.dont_touch_target || self.stdin || self.source.is_some())
+    }
+}
+
+/// A trace source given by URI via --source.
+#[derive(Debug)]
+enum SourceUri {
+    /// A TCP socket at `host:port`.
+    Tcp(String),
+}
+
+/// Parses a non-negative, finite number of seconds.
+fn parse_seconds(s: &str) -> Result<std::time::Duration, String> {
+    let secs: f64 = s
+        .parse()
+        .map_err(|e| format!("invalid number of seconds {:?}: {}", s, e))?;
+    std::time::Duration::try_from_secs_f64(secs)
+        .map_err(|_| format!("{} is not a valid duration in seconds", s))
+}
+
+impl std::str::FromStr for SourceUri {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once("://") {
+            Some(("tcp", addr)) if !addr.is_empty() => Ok(Self::Tcp(addr.to_string())),
+            _ => Err(format!(
+                "unsupported source {:?}: expected tcp://host:port",
+                s
+            )),
+        }
+    }
+}
+
 #[derive(StructOpt, Debug)]
 pub struct ManifestOptions {
     /// Name of the PAC used in traced application.
@@ -107,6 +385,31 @@ pub struct ManifestOptions {
     /// Baud rate of the communication from the target TPIU.
     #[structopt(long = "tpiu-baud")]
     tpiu_baud: Option<u32>,
+
+    /// TPIU trace source ID of the ITM, if the trace stream is
+    /// formatted by the TPIU. Only the data of this source is decoded.
+    #[structopt(long = "tpiu-source-id")]
+    tpiu_source_id: Option<u8>,
+
+    /// Speed in Hz of the clock that local timestamps count, if other
+    /// than the TPIU trace clock, e.g. the processor clock.
+    #[structopt(long = "timestamp-freq")]
+    timestamp_freq: Option<u32>,
+
+    /// Path to a JSON file of software task ID overrides that are
+    /// applied over the recovered software task associations.
+    #[structopt(long = "task-overrides", parse(from_os_str))]
+    task_overrides: Option<PathBuf>,
+
+    /// Path to the SVD file of the target from which the IRQ numbers
+    /// of bound interrupts are read instead of building the PAC.
+    #[structopt(long = "svd", parse(from_os_str))]
+    svd: Option<PathBuf>,
+
+    /// Seconds after which the build of the intermediate crate that
+    /// resolves interrupts via the PAC is killed. Defaults to 600.
+    #[structopt(long = "adhoc-build-timeout")]
+    adhoc_build_timeout: Option<u64>,
 }
 
 /// Replay a previously recorded trace stream for post-mortem analysis.
@@ -115,16 +418,106 @@ struct ReplayOptions {
     #[structopt(name = "list", long = "list", short = "l")]
     list: bool,
 
+    /// Print a summary of the metadata of the trace instead of
+    /// replaying it.
+    #[structopt(
+        name = "info",
+        long = "info",
+        conflicts_with_all(&["list", "raw-file"])
+    )]
+    info: bool,
+
+    /// Correct the timestamps of the trace for the given actual TPIU
+    /// frequency (in Hz) and write it to --output instead of replaying
+    /// it. Useful if the TPIU clock was discovered to have drifted.
+    #[structopt(long = "retime", conflicts_with_all(&["list", "raw-file", "info"]))]
+    retime: Option<u32>,
+
+    /// Compare the task execution times of the trace against those of
+    /// the given trace file instead of replaying it. Executions are
+    /// aligned by task sequence, e.g. to assess an optimization.
+    #[structopt(
+        long = "compare",
+        parse(from_os_str),
+        conflicts_with_all(&["list", "raw-file", "info", "retime"])
+    )]
+    compare: Option<PathBuf>,
+
+    /// Re-resolve the unmappable events of the archive against the
+    /// lookup maps of the given trace file or archive and write it to
+    /// --output instead of replaying it, e.g. after the application has
+    /// been rebuilt with the missing tasks. The events are resolved
+    /// anew from the trace data of the archive, if any; otherwise,
+    /// resolved events are left as-is.
+    #[structopt(
+        long = "reresolve",
+        parse(from_os_str),
+        conflicts_with_all(&["list", "raw-file", "info", "retime", "compare"])
+    )]
+    reresolve: Option<PathBuf>,
+
+    /// Bookmark the trace at the given offset since target reset (in
+    /// nanoseconds) and write it to --output instead of replaying it,
+    /// given as `<offset>=<label>`. Bookmarks are marked by the CTF
+    /// exporter.
+    #[structopt(
+        long = "bookmark",
+        conflicts_with_all(&["list", "raw-file", "info", "retime", "compare", "reresolve"])
+    )]
+    bookmarks: Vec<bookmarks::Bookmark>,
+
+    /// Remove all bookmarks with the given label from the trace and
+    /// write it to --output instead of replaying it.
+    #[structopt(
+        long = "remove-bookmark",
+        conflicts_with_all(&["list", "raw-file", "info", "retime", "compare", "reresolve"])
+    )]
+    remove_bookmarks: Vec<String>,
+
+    /// Where the trace edited via --retime, --reresolve, --bookmark, or
+    /// --remove-bookmark is written. The recorded trace itself is never
+    /// modified.
+    #[structopt(long = "output", parse(from_os_str))]
+    output: Option<PathBuf>,
+
+    /// Bundle the trace, restricted via --from and --until, with the
+    /// source and manifest properties of the application into an
+    /// anonymized archive at the given path instead of replaying it.
+    /// Attach the archive to bug reports against RTIC Scope.
+    #[structopt(
+        long = "repro",
+        parse(from_os_str),
+        conflicts_with_all(&["list", "raw-file", "info", "retime", "compare", "reresolve"])
+    )]
+    repro: Option<PathBuf>,
+
     /// Relative path to trace file to replay.
     #[structopt(name = "trace-file", long = "trace-file")]
     trace_file: Option<PathBuf>,
 
-    #[structopt(required_unless_one(&["list", "raw-file", "trace-file"]))]
-    index: Option<usize>,
+    /// The recorded trace to replay: its index in <trace-dir> (see
+    /// --list), or the path to a trace file or archive. Prefix paths
+    /// that are numbers with `./`. The target need not be connected.
+    #[structopt(
+        name = "recording",
+        required_unless_one(&["list", "raw-file", "trace-file"]),
+        conflicts_with = "trace-file"
+    )]
+    recording: Option<Recording>,
 
     #[structopt(flatten)]
     raw_options: RawFileOptions,
 
+    /// Only replay trace data from this offset (in nanoseconds) since
+    /// target reset.
+    #[structopt(long = "from", conflicts_with_all(&["list", "raw-file"]))]
+    from: Option<u64>,
+
+    /// Only replay trace data until this offset (in nanoseconds) since
+    /// target reset.
+    #[structopt(long = "until", conflicts_with_all(&["list", "raw-file"]))]
+    until: Option<u64>,
+
     /// Directory where previously recorded trace streams. By default,
     /// the build cache of <bin> is used (usually ./target/).
     #[structopt(name = "trace-dir", long = "trace-dir", parse(from_os_str))]
@@ -134,11 +527,90 @@ struct ReplayOptions {
     cargo_options: CargoOptions,
 }
 
+/// A recorded trace, by index in the trace directory or by path.
+#[derive(Debug)]
+enum Recording {
+    Index(usize),
+    File(PathBuf),
+}
+
+impl std::str::FromStr for Recording {
+    type Err = std::convert::Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.parse() {
+            Ok(index) => Self::Index(index),
+            Err(_) => Self::File(PathBuf::from(s)),
+        })
+    }
+}
+
+impl ReplayOptions {
+    /// Replays a recording given by index or path as if its path was
+    /// given via --trace-file.
+    fn resolve_recording(&mut self) -> Result<(), RTICScopeError> {
+        match self.recording.take() {
+            Some(Recording::File(file)) => self.trace_file = Some(file),
+            Some(Recording::Index(idx)) => {
+                self.trace_file = Some(
+                    self.trace_files()?
+                        .nth(idx)
+                        .with_context(|| format!("No trace with index {}", idx))?,
+                );
+            }
+            None => (),
+        }
+
+        Ok(())
+    }
+
+    /// The trace files in <trace-dir>, in the order they are indexed.
+    fn trace_files(&self) -> Result<impl Iterator<Item = PathBuf>, RTICScopeError> {
+        Ok(sinks::file::find_trace_files(
+            self.trace_dir.clone().unwrap_or(
+                cargo_metadata::MetadataCommand::new()
+                    .exec()
+                    .context("cargo metadata command failed")?
+                    .target_directory
+                    .join("rtic-traces")
+                    .into(),
+            ),
+        )?)
+    }
+
+    /// Whether bookmarks are added to or removed from the trace.
+    fn edits_bookmarks(&self) -> bool {
+        !(self.bookmarks.is_empty() && self.remove_bookmarks.is_empty())
+    }
+
+    /// The path given by --output to which the recording is written
+    /// once edited via `edit`. Recordings are never edited in place.
+    fn edit_output(
+        &self,
+        recording: &std::path::Path,
+        edit: &str,
+    ) -> Result<&std::path::Path, anyhow::Error> {
+        let output = self
+            .output
+            .as_deref()
+            .with_context(|| format!("{} requires --output <path>", edit))?;
+        if output.exists() && fs::canonicalize(output)? == fs::canonicalize(recording)? {
+            bail!(
+                "--output {} is the recording itself, which is never edited in place",
+                output.display()
+            );
+        }
+        Ok(output)
+    }
+}
+
 #[derive(StructOpt, Debug)]
 struct RawFileOptions {
     /// Path to the file containing raw trace data that should be
-    /// replayed.
-    #[structopt(name = "raw-file", long = "raw-file", requires("virtual-freq"))]
+    /// replayed, e.g. a dump of a serial device. The TPIU frequency is
+    /// read from the manifest unless given via --tpiu-freq or
+    /// --timestamp-freq.
+    #[structopt(name = "raw-file", long = "raw-file")]
     file: Option<PathBuf>,
 
     #[structopt(long = "comment", short = "c", hidden = true)]
@@ -147,10 +619,60 @@ struct RawFileOptions {
     pac: ManifestOptions,
 }
 
+/// Resolve the task maps of the application and print them to stdout
+/// without tracing the target, e.g. for external analysis scripts, or
+/// to check that all tasks are picked up before recording.
+#[derive(StructOpt, Debug)]
+struct DumpMapsOptions {
+    /// Format of the printed maps: json, or text for one "<task>: <id>"
+    /// line per task as printed by `list-tasks`.
+    #[structopt(long = "format", default_value = "json", possible_values = &["json", "text"])]
+    format: String,
+
+    #[structopt(flatten)]
+    pac: ManifestOptions,
+
+    #[structopt(flatten)]
+    cargo_options: CargoOptions,
+}
+
+/// Resolve the tasks of the application and print them to stdout
+/// without tracing the target, e.g. to check that all tasks are picked
+/// up before recording. Exits with an error if no tasks were resolved.
+#[derive(StructOpt, Debug)]
+struct ListTasksOptions {
+    #[structopt(flatten)]
+    pac: ManifestOptions,
+
+    #[structopt(flatten)]
+    cargo_options: CargoOptions,
+}
+
 #[derive(StructOpt, Debug)]
 enum Command {
     Trace(TraceOptions),
     Replay(ReplayOptions),
+    DumpMaps(DumpMapsOptions),
+    ListTasks(ListTasksOptions),
+}
+
+/// Whether trace data is read from a target or replayed from a file.
+#[derive(Debug, Clone, Copy)]
+enum Mode {
+    Trace,
+    Replay,
+}
+
+impl Mode {
+    /// Status verb while trace data is, or once it has been, forwarded.
+    fn status(&self, done: bool) -> &'static str {
+        match (self, done) {
+            (Mode::Trace, false) => "Tracing",
+            (Mode::Trace, true) => "Traced",
+            (Mode::Replay, false) => "Replaying",
+            (Mode::Replay, true) => "Replayed",
+        }
+    }
 }
 
 #[derive(Debug, Error)]
@@ -179,6 +701,19 @@ pub enum RTICScopeError {
 }
 
 impl diag::DiagnosableError for RTICScopeError {
+    fn kind(&self) -> &'static str {
+        match self {
+            Self::CommonProbeOperationError(..) => "CommonProbeOperationError",
+            Self::IOError(..) => "IOError",
+            Self::ManifestError(..) => "ManifestError",
+            Self::MetadataError(..) => "MetadataError",
+            Self::CargoError(..) => "CargoError",
+            Self::SourceError(..) => "SourceError",
+            Self::SinkError(..) => "SinkError",
+            Self::Other(..) => "Other",
+        }
+    }
+
     fn diagnose(&self) -> Vec<String> {
         match self {
             RTICScopeError::ManifestError(_) => vec![
@@ -190,33 +725,70 @@ impl diag::DiagnosableError for RTICScopeError {
 }
 
 impl RTICScopeError {
+    /// The error this error wraps, if any.
+    fn inner(&self) -> Option<&dyn DiagnosableError> {
+        // XXX should we anyhow::Error::downcast somehow instead?
+        match self {
+            Self::ManifestError(e) => Some(e),
+            Self::MetadataError(e) => Some(e),
+            Self::CargoError(e) => Some(e),
+            Self::SourceError(e) => Some(e),
+            Self::SinkError(e) => Some(e),
+            _ => None,
+        }
+    }
+
+    /// The hints of this error and of the error it wraps, if any.
+    fn hints(&self) -> Vec<String> {
+        self.diagnose()
+            .into_iter()
+            .chain(self.inner().map(|e| e.diagnose()).unwrap_or_default())
+            .collect()
+    }
+
+    /// The machine-readable representation of this error.
+    pub fn diagnostic(&self) -> diag::Diagnostic {
+        diag::Diagnostic {
+            level: diag::Level::Error,
+            // Transparent errors are qualified by the variant they wrap.
+            kind: Some(match self.inner() {
+                Some(inner) => format!("{}::{}", self.kind(), inner.kind()),
+                None => self.kind().to_string(),
+            }),
+            message: self.to_string(),
+            suggestions: self.hints(),
+        }
+    }
+
     pub fn render(&self) {
-        log::err(format!("{:#?}", self)); // TODO iterator over errors instead
+        match diag::format() {
+            diag::MessageFormat::Human => {
+                log::err(format!("{:#?}", self)); // TODO iterator over errors instead
 
-        // print eventual hints
-        // XXX should we anyhow::Error::downcast somehow instead?
-        use crate::diag::DiagnosableError;
-        type DE = dyn DiagnosableError;
-        for hint in self.diagnose().iter().chain(
-            match self {
-                Self::ManifestError(e) => Some(e as &DE),
-                Self::MetadataError(e) => Some(e as &DE),
-                Self::CargoError(e) => Some(e as &DE),
-                Self::SourceError(e) => Some(e as &DE),
-                Self::SinkError(e) => Some(e as &DE),
-                _ => None,
+                // print eventual hints
+                for hint in self.hints() {
+                    log::hint(hint);
+                }
             }
-            .map(|e| e.diagnose())
-            .unwrap_or_default()
-            .iter(),
-        ) {
-            log::hint(hint.to_owned());
+            diag::MessageFormat::Json => self.diagnostic().emit(),
         }
     }
 }
 
 fn main() {
-    if let Err(e) = block_on(main_try()) {
+    // Handle CLI options
+    let mut args: Vec<_> = std::env::args().collect();
+    // When called by cargo, first argument will be "rtic-scope".
+    if args.get(1) == Some(&"rtic-scope".to_string()) {
+        args.remove(1);
+    }
+    let matches = Opts::clap()
+        .after_help(CargoOptions::help_message("cargo rtic-scope trace").as_str())
+        .get_matches_from(&args);
+    let opts = Opts::from_clap(&matches);
+    diag::set_format(opts.message_format);
+
+    if let Err(e) = block_on(main_try(opts)) {
         e.render();
         std::process::exit(1); // TODO make retval depend on error type?
     }
@@ -229,17 +801,10 @@ fn main() {
 // TODO remove this global
 static mut SESSION: Option<probe_rs::Session> = None;
 
-async fn main_try() -> Result<(), RTICScopeError> {
-    // Handle CLI options
-    let mut args: Vec<_> = std::env::args().collect();
-    // When called by cargo, first argument will be "rtic-scope".
-    if args.get(1) == Some(&"rtic-scope".to_string()) {
-        args.remove(1);
+async fn main_try(mut opts: Opts) -> Result<(), RTICScopeError> {
+    if let Command::Replay(opts) = &mut opts.cmd {
+        opts.resolve_recording()?;
     }
-    let matches = Opts::clap()
-        .after_help(CargoOptions::help_message("cargo rtic-scope trace").as_str())
-        .get_matches_from(&args);
-    let opts = Opts::from_clap(&matches);
 
     // Should we quit early?
     if let Command::Trace(opts) = &opts.cmd {
@@ -262,6 +827,8 @@ async fn main_try() -> Result<(), RTICScopeError> {
                 match &opts.cmd {
                     Command::Trace(opts) => &opts.flash_options.cargo_options,
                     Command::Replay(opts) => &opts.cargo_options,
+                    Command::DumpMaps(opts) => &opts.cargo_options,
+                    Command::ListTasks(opts) => &opts.cargo_options,
                 }
             }
             .to_cargo_options(),
@@ -270,13 +837,14 @@ async fn main_try() -> Result<(), RTICScopeError> {
 
     // Configure source and sinks. Recover the information we need to
     // map ITM packets to RTIC tasks.
-    let (source, mut sinks, metadata) = match opts.cmd {
+    let overflow_policy = opts.overflow_policy;
+    let (mode, (mut source, mut sinks, mut metadata)) = match opts.cmd {
         Command::Trace(ref opts) => match trace(opts, cart).await? {
-            Some(tup) => tup,
+            Some(tup) => (Mode::Trace, tup),
             None => return Ok(()), // NOTE --resolve-only was passed
         },
         Command::Replay(ref opts) => {
-            match replay(opts, cart).await.with_context(|| {
+            match replay(opts, overflow_policy, cart).await.with_context(|| {
                 format!("Failed to {}", {
                     if opts.list {
                         "index traces"
@@ -285,15 +853,45 @@ async fn main_try() -> Result<(), RTICScopeError> {
                     }
                 })
             })? {
-                Some(tup) => tup,
-                None => return Ok(()), // NOTE --list was passed
+                Some(tup) => (Mode::Replay, tup),
+                None => return Ok(()), // NOTE --list, --info, --retime, or --compare was passed
             }
         }
+        Command::DumpMaps(ref opts) => return dump_maps(opts, cart).await,
+        Command::ListTasks(ref opts) => return list_tasks(opts, cart).await,
     };
 
+    if opts.swap_comparators {
+        metadata.swap_comparators();
+    }
+    if opts.dispatchers {
+        metadata.show_dispatchers();
+    }
+    if opts.debug_packets {
+        metadata.debug_packets();
+    }
+    metadata.resync_after(opts.resync_after);
+
     // Spawn frontend children and get path to sockets. Create and push sinks.
+    let mut handshake_metadata =
+        serde_json::to_value(&metadata).context("Failed to serialize trace metadata")?;
+    if let Some(object) = handshake_metadata.as_object_mut() {
+        object.insert(
+            "hardware_tasks".to_string(),
+            metadata.hardware_task_names().into(),
+        );
+        object.insert(
+            "software_tasks".to_string(),
+            metadata.software_task_names().into(),
+        );
+    }
+    let handshake = api::Handshake::new(env!("CARGO_PKG_VERSION"), handshake_metadata);
     let mut children = vec![];
-    for frontend in &opts.frontends {
+    let frontends = match opts.export {
+        Some(_) => &[][..],
+        None => &opts.frontends[..],
+    };
+    for frontend in frontends {
         // Try to spawn the frontend from PATH. If that fails, try a relative path instead.
         let executables = [
             format!("rtic-scope-frontend-{}", frontend), // PATH
@@ -331,7 +929,11 @@ async fn main_try() -> Result<(), RTICScopeError> {
             .context("Failed to read socket path from frontend child process")?;
             let socket = std::os::unix::net::UnixStream::connect(&socket_path)
                 .context("Failed to connect to frontend socket")?;
-            sinks.push(Box::new(sinks::FrontendSink::new(socket)));
+            sinks.push(Box::new(sinks::FrontendSink::new(
+                socket,
+                &handshake,
+                opts.max_chunk_events,
+            )?));
         }
 
         let stderr = child
@@ -341,6 +943,35 @@ async fn main_try() -> Result<(), RTICScopeError> {
         children.push((child, stderr));
     }
 
+    if let Some(path) = &opts.archive {
+        let raw = match &opts.cmd {
+            Command::Replay(ReplayOptions {
+                raw_options:
+                    RawFileOptions {
+                        file: Some(file), ..
+                    },
+                ..
+            }) => Some(file.as_path()),
+            _ => None,
+        };
+        sinks.push(Box::new(sinks::ArchiveSink::new(path, &metadata, raw)?));
+    }
+
+    for (exporter, output) in opts.exports() {
+        sinks.push(exporter.sink(
+            output,
+            &metadata,
+            std::time::Duration::from_micros(opts.utilization_window),
+        )?);
+    }
+
+    if opts.raw_bytes && !source.retain_bytes() {
+        log::warn(format!(
+            "source {} does not decode a raw ITM byte stream; raw bytes will not be attached to events",
+            source.describe()
+        ));
+    }
+
     if let sources::BufferStatus::Unknown = source.avail_buffer() {
         log::warn(format!(
             "buffer size of source {} could not be found; buffer may overflow and corrupt trace stream without further warning",
@@ -363,7 +994,7 @@ async fn main_try() -> Result<(), RTICScopeError> {
 
     // All preparatory I/O and information recovery done. Forward all
     // trace packets to all sinks.
-    let stats = run_loop(source, sinks, metadata.clone(), &opts, stderrs).await;
+    let stats = run_loop(source, sinks, metadata.clone(), mode, &opts, stderrs).await;
 
     // Wait for frontends to proccess all packets and flush any
     // remaining stderr lines.
@@ -388,15 +1019,30 @@ async fn main_try() -> Result<(), RTICScopeError> {
         }
     }
 
-    let stats = stats?;
+    let (stats, analyses) = stats?;
     let duration = instant.elapsed();
+    let events_per_sec = stats.events as f32 / duration.as_secs_f32();
     log::status(
-        match opts.cmd {
-            Command::Trace(_) => "Traced",
-            Command::Replay(_) => "Replayed",
-        },
-        format!("{}.", format_status_message(&metadata, &stats, &duration)),
+        mode.status(true),
+        format!(
+            "{}.",
+            format_status_message(&metadata, &stats, &duration, events_per_sec)
+        ),
     );
+    if let Some(latency) = analyses.latency {
+        log::status("Latency", latency.to_string());
+        if let Some(path) = &opts.latency_histograms {
+            fs::write(
+                path,
+                serde_json::to_string_pretty(latency.histograms())
+                    .context("Failed to serialize latency histograms")?,
+            )
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        }
+    }
+    if let Some(summary) = analyses.summary {
+        log::status("Summary", summary.to_string());
+    }
 
     Ok(())
 }
@@ -405,6 +1051,7 @@ fn format_status_message(
     metadata: &recovery::TraceMetadata,
     stats: &Stats,
     duration: &std::time::Duration,
+    events_per_sec: f32,
 ) -> String {
     fn format_duration(duration: &std::time::Duration) -> String {
         match duration.as_secs() {
@@ -430,11 +1077,12 @@ fn format_status_message(
     }
 
     format!(
-        "{}: {} packets processed in {time} (~{packets_per_sec:.1} packets/s; {} malformed, {} non-mappable); {sinks}",
+        "{}: {} packets processed in {time} (~{packets_per_sec:.1} packets/s, ~{events_per_sec:.1} events/s; {} malformed, {} non-mappable, {} overflows); {sinks}",
         metadata.program_name,
         stats.packets,
         stats.malformed,
         stats.nonmappable,
+        stats.overflows,
         time = format_duration(duration),
         packets_per_sec = stats.packets as f32 / duration.as_secs() as f32,
         sinks = format!("{}/{} sinks operational", stats.sinks.0, stats.sinks.1),
@@ -474,102 +1122,156 @@ where
     }
 }
 
-#[derive(Default)]
-struct Stats {
-    /// How many ITM packets we have received from the source.
-    pub packets: usize,
-    /// How many malformed ITM packets we have received from the source.
-    pub malformed: usize,
-    /// How many unmappable ITM packets we have received from the
-    /// source.
-    pub nonmappable: usize,
-    /// How many sinks we started with, and how many that remained
-    /// functional until the end.
-    pub sinks: (usize, usize),
-}
-
 async fn run_loop<R>(
-    mut source: Box<dyn sources::Source>,
-    mut sinks: Vec<Box<dyn sinks::Sink>>,
+    source: Box<dyn sources::Source>,
+    sinks: Vec<Box<dyn sinks::Sink>>,
     metadata: recovery::TraceMetadata,
+    mode: Mode,
     opts: &Opts,
     mut stderrs: StderrLines<R>,
-) -> Result<Stats, RTICScopeError>
+) -> Result<(Stats, Analyses), RTICScopeError>
 where
     R: async_std::io::BufRead + std::marker::Unpin,
 {
-    // Setup SIGINT handler.
-    let (tx, halt) = channel::bounded(0);
-    ctrlc::set_handler(move || tx.send(()).expect("Could not signal SIGINT on channel"))
-        .context("Failed to install SIGINT handler")?;
+    let rename = renamer(&opts.rename)?;
+    let mut virtual_tasks = VirtualTasks::parse(&opts.virtual_tasks)?;
+    let task_filter = TaskFilter::new(&opts.include, &opts.mask, &metadata);
+    for task in task_filter.unmatched_mask(&metadata.tasks()) {
+        log::warn(format!("cannot mask {}: no such task", task));
+    }
+
+    // Setup SIGINT handler. The first SIGINT stops reading from the
+    // source, after which all that was received is drained to the
+    // sinks such that the recorded trace remains replayable. A second
+    // SIGINT exits at once, e.g. if a sink does not drain.
+    let (tx, halt) = channel::bounded(1);
+    let mut interrupted = false;
+    ctrlc::set_handler(move || {
+        if !std::mem::replace(&mut interrupted, true) {
+            let _ = tx.try_send(());
+        } else {
+            log::warn("Interrupted again: exiting without draining sinks".to_string());
+            std::process::exit(130);
+        }
+    })
+    .context("Failed to install SIGINT handler")?;
 
-    // Keep tabs on which sinks have broken during drain, if any.
-    let mut sinks: Vec<(Box<dyn sinks::Sink>, bool)> =
-        sinks.drain(..).map(|s| (s, false)).collect();
+    // Fan out to all sinks; each sink is drained on its own thread
+    // such that a slow sink does not stall the others.
+    let mut sinks = sinks::Broadcast::new(sinks, sinks::broadcast::DEFAULT_CAPACITY);
 
     let mut stats = Stats {
         sinks: (sinks.len(), sinks.len()),
         ..Stats::default()
     };
 
-    let handle_packet = |data: TraceData,
-                         stats: &mut Stats,
-                         sinks: &mut Vec<(Box<dyn sinks::Sink>, bool)>|
-     -> Result<(), anyhow::Error> {
+    let mut analyses = Analyses {
+        latency: (opts.latency || opts.latency_histograms.is_some()).then(|| {
+            let report = LatencyReport::new(metadata.hardware_tasks(), opts.overflow_policy);
+            match opts.latency_histograms {
+                Some(_) => report
+                    .with_histograms(std::time::Duration::from_nanos(opts.histogram_bucket_width)),
+                None => report,
+            }
+        }),
+        summary: opts
+            .summary
+            .then(|| TaskSummary::new(metadata.tasks(), opts.overflow_policy)),
+    };
+
+    let mut handle_packet = |data: TraceData,
+                             raw: Option<api::RawBytes>,
+                             host_dropped: usize,
+                             stats: &mut Stats,
+                             analyses: &mut Analyses,
+                             sinks: &mut sinks::Broadcast|
+     -> Result<Duration, anyhow::Error> {
         // Try to recover RTIC information for the packets.
-        let chunk = metadata.build_event_chunk(data.clone());
+        let mut chunk = metadata.build_event_chunk(data.clone());
+        chunk.raw = raw;
+        if host_dropped > 0 {
+            chunk
+                .events
+                .insert(0, api::EventType::HostDropped(host_dropped));
+        }
+        let offset = api::timestamp_offset(&chunk.timestamp);
+        analyses.update(&chunk);
+        for e in metadata
+            .check_comparator_roles()
+            .into_iter()
+            .chain(metadata.check_software_ids())
+        {
+            diag::warn(&e);
+        }
 
         // Report any unmappable/unknown events that occured, and record stats
-        stats.packets += data.consumed_packets;
+        stats.update(data.consumed_packets, &chunk);
         for event in chunk.events.iter() {
             match event {
                 api::EventType::Unmappable(ref packet, ref reason) => {
-                    stats.nonmappable += 1;
                     log::warn(format!(
                         "cannot map {:?} packet: {}",
                         packet, reason
                     ));
                 }
                 api::EventType::Unknown(ref packet) => {
-                    stats.nonmappable += 1;
                     log::warn(format!(
                         "cannot map {:?} packet",
                         packet
                     ));
                 }
                 api::EventType::Invalid(ref malformed) => {
-                    stats.malformed += 1;
                     log::warn(format!("malformed packet: {}: {:?}", malformed, malformed));
                 },
+                api::EventType::Log { port, message } => log::status(&format!("Port {}", port), message.clone()),
+                api::EventType::Resynced(dropped) => log::warn(format!("{} packets were dropped until the trace stream was resynchronized", dropped)),
                 api::EventType::Overflow => log::warn("Overflow detected! Packets may have been dropped and/or timestamps will potentially be diverged until the next global timestamp.".to_string()),
                 _ => (),
             }
         }
 
-        for (sink, is_broken) in sinks.iter_mut() {
-            if let Err(e) = sink.drain(data.clone(), chunk.clone()) {
-                log::err(format!(
-                    "failed to drain trace packets to {}: {:?}",
-                    sink.describe(),
-                    e
-                ));
-                *is_broken = true;
-            }
+        // Scope the chunk for presentation.
+        if !virtual_tasks.is_empty() {
+            virtual_tasks.apply(&mut chunk);
+        }
+        if !task_filter.is_empty() {
+            task_filter.apply(&mut chunk);
+        }
+        if !opts.rename.is_empty() {
+            chunk.rename_tasks(&rename);
+        }
+        if !opts.event_kinds.is_empty() {
+            chunk.retain_kinds(&opts.event_kinds);
         }
 
-        // remove broken sinks, if any
-        //
-        // TODO replace weth Vec::drain_filter when stable.
-        sinks.retain(|(_, is_broken)| !is_broken);
+        // broken sinks are removed by the broadcast
+        for (sink, e) in sinks.drain(&data, &chunk) {
+            log::err(format!(
+                "failed to drain trace packets to {}: {:?}",
+                sink, e
+            ));
+        }
         stats.sinks.0 = sinks.len();
         if sinks.is_empty() {
             bail!("All sinks are broken. Cannot continue.");
         }
 
-        Ok(())
+        Ok(offset)
     };
 
-    let (tx, packet) = channel::unbounded();
+    // Tracing stops as if interrupted once a limit is reached.
+    let (duration_limit, packet_limit) = match &opts.cmd {
+        Command::Trace(opts) => (opts.duration, opts.max_packets),
+        _ => (None, None),
+    };
+
+    // A malformed packet at the very end of a finite stream is likely
+    // only truncated by the stop of the source it was recorded from.
+    let mut source = sources::TrailingTruncation::new(source);
+
+    // Decoding runs ahead of resolution by a bounded amount, such
+    // that the source is paused once resolution cannot keep up.
+    let (tx, packet) = channel::bounded(sinks::broadcast::DEFAULT_CAPACITY);
     let packet_poller = std::thread::spawn(move || {
         let mut buffer_warning = false;
 
@@ -584,30 +1286,68 @@ where
                 }
             }
 
+            // NOTE(is_err) the receiver is dropped once a trace limit
+            // is reached, after which the source is no longer read.
             match data {
-                packet @ Ok(_) => tx.send(Some(packet)).unwrap(),
-                err @ Err(_) => {
-                    tx.send(Some(err)).unwrap();
+                Ok(data) => {
+                    if tx
+                        .send(Some(Ok((data, source.raw_bytes(), source.host_dropped()))))
+                        .is_err()
+                    {
+                        break;
+                    }
+                }
+                Err(e) => {
+                    let _ = tx.send(Some(Err(e)));
                     break;
                 }
             }
         }
 
-        tx.send(None).unwrap(); // EOF
+        let _ = tx.send(None); // EOF
     });
 
     let instant = std::time::Instant::now();
     use std::time::Duration;
 
+    // Render the status line at most a few times per second.
+    let mut status_line = StatusLine::new(Duration::from_millis(250));
+
+    // Receiving from a channel that never delivers pauses the source.
+    let paused = channel::never();
+
+    // A source error ends the trace, but only after the sinks have
+    // drained all that was received before it.
+    let mut source_error = None;
+
     loop {
+        let source = if opts.backpressure && sinks.is_full() {
+            &paused
+        } else {
+            &packet
+        };
         channel::select! {
-            recv(packet) -> packet => match packet.unwrap() {
-                Some(packet) => {
-                    handle_packet(packet.context("Failed to read trace data from source")?, &mut stats, &mut sinks)?;
+            recv(source) -> packet => match packet.unwrap() {
+                Some(Ok((data, raw, host_dropped))) => {
+                    let offset = handle_packet(data, raw, host_dropped, &mut stats, &mut analyses, &mut sinks)?;
+                    if duration_limit.map_or(false, |limit| offset >= limit)
+                        || packet_limit.map_or(false, |limit| stats.packets >= limit)
+                    {
+                        log::status("Stopping", "trace limit reached".to_string());
+                        break;
+                    }
+                },
+                Some(Err(e)) => {
+                    source_error = Some(e);
+                    break;
                 },
                 None => break,
             },
             recv(halt) -> _ => {
+                log::status(
+                    "Stopping",
+                    "draining received trace data to sinks; interrupt again to exit at once".to_string(),
+                );
                 break;
             },
             default(Duration::from_millis(100)) => (),
@@ -617,14 +1357,16 @@ where
             log::frontend(error.context("Failed to read frontend stderr")?);
         }
 
-        let duration = instant.elapsed();
-        log::cont_status(
-            match opts.cmd {
-                Command::Trace(_) => "Tracing",
-                Command::Replay(_) => "Replaying",
-            },
-            format!("{}...", format_status_message(&metadata, &stats, &duration)),
-        );
+        if let Some(events_per_sec) = status_line.poll(&stats) {
+            let duration = instant.elapsed();
+            log::cont_status(
+                mode.status(false),
+                format!(
+                    "{}...",
+                    format_status_message(&metadata, &stats, &duration, events_per_sec)
+                ),
+            );
+        }
     }
 
     // The thread can simply be joined in all cases except when a halt
@@ -635,7 +1377,126 @@ where
     // can let the OS reap the thread.
     drop(packet_poller);
 
-    Ok(stats)
+    // Let all sinks drain what they have buffered.
+    for (sink, dropped) in sinks.dropped() {
+        log::warn(format!(
+            "{} could not keep up: {} chunks were dropped",
+            sink, dropped
+        ));
+    }
+    for (sink, e) in sinks.finish() {
+        log::err(format!(
+            "failed to drain trace packets to {}: {:?}",
+            sink, e
+        ));
+        stats.sinks.0 -= 1;
+    }
+
+    if let Some(e) = source_error {
+        return Err(e.into());
+    }
+
+    Ok((stats, analyses))
+}
+
+/// Renames tasks for presentation per `--rename <path>=<name>`. The
+/// longest matching path, or module path, is replaced.
+fn renamer(renames: &[String]) -> Result<impl Fn(&str) -> String, anyhow::Error> {
+    let renames = renames
+        .iter()
+        .map(|rename| {
+            rename
+                .split_once('=')
+                .map(|(from, to)| (from.to_string(), to.to_string()))
+                .with_context(|| format!("Invalid rename {}: expected <path>=<name>", rename))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(move |name: &str| {
+        renames
+            .iter()
+            .filter_map(|(from, to)| {
+                if name == from {
+                    Some((from.len(), to.clone()))
+                } else {
+                    name.strip_prefix(from.as_str())
+                        .and_then(|rest| rest.strip_prefix("::"))
+                        .map(|rest| (from.len(), format!("{}::{}", to, rest)))
+                }
+            })
+            .max_by_key(|(len, _)| *len)
+            .map(|(_, name)| name)
+            .unwrap_or_else(|| name.to_string())
+    })
+}
+
+/// Warns if the source of `artifact` is newer than the artifact itself.
+fn warn_stale(artifact: &Artifact) {
+    if let Some(e) = recovery::check_staleness(artifact) {
+        diag::warn(&e);
+    }
+}
+
+/// Resolves the task maps of the application without tracing the
+/// target.
+async fn resolve_maps(
+    pac: &ManifestOptions,
+    cart: impl futures::Future<Output = Result<(CargoWrapper, Artifact), CargoError>>,
+) -> Result<recovery::TraceLookupMaps, RTICScopeError> {
+    let (cargo, artifact) = cart.await?;
+    log::status(
+        "Recovering",
+        format!(
+            "metadata for {} ({})...",
+            artifact.target.name, artifact.target.src_path
+        ),
+    );
+
+    let rtic_app = recovery::RticApp::parse(&artifact)?;
+    let manip = manifest::ManifestProperties::new(&cargo, rtic_app.device().as_deref(), Some(pac))?;
+    warn_stale(&artifact);
+    let maps = recovery::TraceLookupMaps::from(&cargo, &artifact, &rtic_app, &manip)?;
+
+    Ok(maps)
+}
+
+/// Prints one "<task>: <id>" line per resolved task. Fails if no tasks
+/// were resolved.
+fn print_tasks(maps: &recovery::TraceLookupMaps) -> Result<(), RTICScopeError> {
+    let mut ntasks = 0;
+    for (id, name) in maps.tasks() {
+        println!("{}: {}", name, id);
+        ntasks += 1;
+    }
+    if ntasks == 0 {
+        return Err(recovery::RecoveryError::NoTasks.into());
+    }
+
+    Ok(())
+}
+
+async fn dump_maps(
+    opts: &DumpMapsOptions,
+    cart: impl futures::Future<Output = Result<(CargoWrapper, Artifact), CargoError>>,
+) -> Result<(), RTICScopeError> {
+    let maps = resolve_maps(&opts.pac, cart).await?;
+
+    match opts.format.as_str() {
+        "text" => print_tasks(&maps)?,
+        _ => println!(
+            "{}",
+            serde_json::to_string_pretty(&maps.dump()).context("Failed to serialize task maps")?
+        ),
+    }
+
+    Ok(())
+}
+
+async fn list_tasks(
+    opts: &ListTasksOptions,
+    cart: impl futures::Future<Output = Result<(CargoWrapper, Artifact), CargoError>>,
+) -> Result<(), RTICScopeError> {
+    print_tasks(&resolve_maps(&opts.pac, cart).await?)
 }
 
 type TraceTuple = (
@@ -653,7 +1514,7 @@ async fn trace(
     log::status(
         "Recovering",
         format!("metadata for {}{}", prog, {
-            if opts.resolve_only || opts.dont_touch_target {
+            if opts.resolve_only || !opts.touch_target() {
                 "..."
             } else {
                 " and preparing target..."
@@ -662,13 +1523,36 @@ async fn trace(
     );
 
     // Read the RTIC Scope manifest metadata block
-    let manip = manifest::ManifestProperties::new(&cargo, Some(&opts.pac))?;
+    let rtic_app = recovery::RticApp::parse(&artifact)?;
+    let manip =
+        manifest::ManifestProperties::new(&cargo, rtic_app.device().as_deref(), Some(&opts.pac))?;
 
     // Build the translation maps
-    let maps = recovery::TraceLookupMaps::from(&cargo, &artifact, &manip)?;
+    warn_stale(&artifact);
+    let maps = recovery::TraceLookupMaps::from(&cargo, &artifact, &rtic_app, &manip)?;
 
     if opts.resolve_only {
-        println!("{:#?}", maps);
+        if let Some(path) = &opts.emit_header {
+            let lang = header::Language::of(path);
+            fs::write(path, header::generate(&maps, &artifact.target.name, lang))
+                .with_context(|| format!("Failed to write header to {}", path.display()))?;
+            log::status(
+                "Emitted",
+                format!("{:?} header to {}", lang, path.display()),
+            );
+        }
+        if opts.lookup.is_empty() && opts.emit_header.is_none() {
+            println!("{:#?}", maps);
+        }
+        for name in &opts.lookup {
+            let ids = maps.lookup(name);
+            if ids.is_empty() {
+                log::warn(format!("{} does not map to any task", name));
+            }
+            for id in ids {
+                println!("{}: {}", name, id);
+            }
+        }
         return Ok(None);
     }
 
@@ -682,7 +1566,7 @@ async fn trace(
     )
     .context("Failed to generate trace sink file")?;
 
-    if !opts.dont_touch_target {
+    if opts.touch_target() {
         let session = unsafe {
             SESSION = Some(
                 opts.flash_options
@@ -709,12 +1593,25 @@ async fn trace(
         )?;
     }
 
-    let trace_source: Box<dyn sources::Source> = if let Some(dev) = &opts.serial {
+    let trace_source: Box<dyn sources::Source> = if opts.stdin {
+        Box::new(sources::StdinSource::new(&manip))
+    } else if let Some(SourceUri::Tcp(addr)) = &opts.source {
+        Box::new(
+            sources::TcpSource::connect(addr, &manip)
+                .with_context(|| format!("Failed to connect to tcp://{}", addr))?,
+        )
+    } else if let Some(dev) = &opts.serial {
         Box::new(sources::TTYSource::new(
             sources::tty::configure(dev, manip.tpiu_baud)
                 .with_context(|| format!("Failed to configure {}", dev))?,
             &manip,
         ))
+    } else if opts.rtt {
+        Box::new(sources::RttSource::new(
+            unsafe { SESSION.as_mut().unwrap() },
+            opts.rtt_channel,
+            &manip,
+        ))
     } else {
         Box::new(sources::ProbeSource::new(
             unsafe { SESSION.as_mut().unwrap() },
@@ -723,16 +1620,10 @@ async fn trace(
     };
 
     // Sample the timestamp of target and flush metadata to file.
-    let metadata = TraceMetadata::from(
-        artifact.target.name,
-        maps,
-        Local::now(), // XXX this is the approximate reset timestamp
-        manip.tpiu_freq,
-        opts.comment.clone(),
-    );
+    let metadata = TraceMetadata::recover(&cargo, &artifact, maps, &manip, opts.comment.clone());
     trace_sink.drain_metadata(&metadata)?;
 
-    if !opts.dont_touch_target {
+    if opts.touch_target() {
         // Reset the target device
         unsafe { SESSION.as_mut().unwrap() }
             .core(0)
@@ -749,14 +1640,19 @@ async fn trace(
     log::status(
         "Recovered",
         format!(
-            "{ntotal} task(s) from {prog}: {nhard} hard, {nsoft} soft.{}",
-            if !opts.dont_touch_target {
+            "{ntotal} task(s) from {prog} ({profile} build): {nhard} hard, {nsoft} soft.{}",
+            if opts.touch_target() {
                 "Target reset and flashed."
             } else {
                 ""
             },
             ntotal = metadata.hardware_tasks_len() + metadata.software_tasks_len(),
             prog = metadata.program_name,
+            profile = metadata
+                .build_profile
+                .as_ref()
+                .map(|p| p.name())
+                .unwrap_or("unknown"),
             nhard = metadata.hardware_tasks_len(),
             nsoft = metadata.software_tasks_len()
         ),
@@ -767,6 +1663,7 @@ async fn trace(
 
 async fn replay(
     opts: &ReplayOptions,
+    overflow_policy: intervals::OverflowPolicy,
     cart: impl futures::Future<Output = Result<(CargoWrapper, Artifact), CargoError>>,
 ) -> Result<Option<TraceTuple>, RTICScopeError> {
     match opts {
@@ -780,44 +1677,32 @@ async fn replay(
             ..
         } => {
             let (cargo, artifact) = cart.await?;
-            let manip = manifest::ManifestProperties::new(&cargo, None)?;
+            let rtic_app = recovery::RticApp::parse(&artifact)?;
+            let manip =
+                manifest::ManifestProperties::new(&cargo, rtic_app.device().as_deref(), Some(pac))?;
             let src =
                 sources::RawFileSource::new(fs::OpenOptions::new().read(true).open(file)?, &manip);
-            let maps = recovery::TraceLookupMaps::from(&cargo, &artifact, &manip)?;
-            let metadata = recovery::TraceMetadata::from(
-                artifact.target.name,
-                maps,
-                chrono::Local::now(),
-                pac.tpiu_freq.unwrap_or(manip.tpiu_freq),
-                comment.clone(),
-            );
+            warn_stale(&artifact);
+            let maps = recovery::TraceLookupMaps::from(&cargo, &artifact, &rtic_app, &manip)?;
+            let metadata = TraceMetadata::recover(&cargo, &artifact, maps, &manip, comment.clone());
 
             Ok(Some((Box::new(src), vec![], metadata)))
         }
-        ReplayOptions {
-            list: true,
-            trace_dir,
-            ..
-        } => {
-            let traces = sinks::file::find_trace_files(
-                trace_dir.clone().unwrap_or(
-                    cargo_metadata::MetadataCommand::new()
-                        .exec()
-                        .context("cargo metadata command failed")?
-                        .target_directory
-                        .join("rtic-traces")
-                        .into(),
-                ),
-            )?;
-            println!("index\ttrace file");
+        ReplayOptions { list: true, .. } => {
+            let traces = opts.trace_files()?;
+            println!("index\ttrace file\tprofile");
             for (i, trace) in traces.enumerate() {
                 let metadata =
                     sources::FileSource::new(fs::OpenOptions::new().read(true).open(&trace)?)?
                         .metadata();
                 println!(
-                    "{}\t{}\t{}",
+                    "{}\t{}\t{}\t{}",
                     i,
                     trace.display(),
+                    metadata
+                        .build_profile
+                        .map(|p| p.to_string())
+                        .unwrap_or_else(|| "unknown".to_string()),
                     metadata.comment.unwrap_or_else(|| "".to_string())
                 );
             }
@@ -826,36 +1711,288 @@ async fn replay(
         }
         ReplayOptions {
             trace_file: Some(file),
+            retime: Some(freq),
             ..
-        } => {
-            let src = sources::FileSource::new(fs::OpenOptions::new().read(true).open(&file)?)?;
-            let metadata = src.metadata();
+        } => retime(file, opts.edit_output(file, "--retime")?, *freq).map(|_| None),
+        ReplayOptions {
+            trace_file: Some(file),
+            compare: Some(other),
+            ..
+        } => compare(file, other, overflow_policy).map(|_| None),
+        ReplayOptions {
+            trace_file: Some(file),
+            reresolve: Some(other),
+            ..
+        } => reresolve(file, opts.edit_output(file, "--reresolve")?, other).map(|_| None),
+        ReplayOptions {
+            trace_file: Some(file),
+            ..
+        } if opts.edits_bookmarks() => {
+            bookmark(file, opts.edit_output(file, "--bookmark")?, opts).map(|_| None)
+        }
+        ReplayOptions {
+            trace_file: Some(file),
+            repro: Some(out),
+            ..
+        } => repro(file, out, opts, cart).await.map(|_| None),
+        ReplayOptions {
+            trace_file: Some(file),
+            ..
+        } if archive::is_archive(file)? => {
+            let archive = archive::Archive::read(fs::File::open(file)?)?;
+            let metadata = archive.metadata.clone();
+            if opts.info {
+                println!("{}", metadata);
+                println!(
+                    "archive: {} trace data chunks, {} event chunks, {} raw bytes",
+                    archive.trace.len(),
+                    archive.events.len(),
+                    archive.raw.len()
+                );
+                if archive.app.is_some() {
+                    println!(
+                        "reproduction: bundles the application source and manifest properties"
+                    );
+                }
+                return Ok(None);
+            }
+            check_source(&metadata, opts);
+            let mut src = sources::ArchiveSource::new(archive);
+            if let Some(range) = replay_range(opts) {
+                src.restrict(range);
+            }
             Ok(Some((Box::new(src), vec![], metadata)))
         }
         ReplayOptions {
-            index: Some(idx),
-            trace_dir,
+            trace_file: Some(file),
             ..
         } => {
-            let mut traces = sinks::file::find_trace_files(
-                trace_dir.clone().unwrap_or(
-                    cargo_metadata::MetadataCommand::new()
-                        .exec()
-                        .context("cargo metadata command failed")?
-                        .target_directory
-                        .join("rtic-traces")
-                        .into(),
-                ),
-            )?;
-            let trace = traces
-                .nth(*idx)
-                .with_context(|| format!("No trace with index {}", *idx))?;
-
-            let src = sources::FileSource::new(fs::OpenOptions::new().read(true).open(&trace)?)?;
+            let mut src = sources::FileSource::new(fs::OpenOptions::new().read(true).open(&file)?)?;
+            if let Some(range) = replay_range(opts) {
+                src.restrict(range)?;
+            }
             let metadata = src.metadata();
-
+            if opts.info {
+                println!("{}", metadata);
+                return Ok(None);
+            }
+            check_source(&metadata, opts);
             Ok(Some((Box::new(src), vec![], metadata)))
         }
         _ => unreachable!(),
     }
 }
+
+/// Warns if the source of the application differs from the source the
+/// trace of `metadata` was recorded with. The source file is located
+/// via `cargo metadata` if the trace records the hash of its source;
+/// the application is not built.
+fn check_source(metadata: &recovery::TraceMetadata, opts: &ReplayOptions) {
+    if metadata.source_hash.is_none() {
+        return;
+    }
+    // NOTE(ok) the trace is replayed regardless, e.g. outside of the
+    // project it was recorded in.
+    if let Some(src) = source_path(&metadata.program_name, opts) {
+        if let Some(e) = metadata.check_source(&src) {
+            diag::warn(&e);
+        }
+    }
+}
+
+/// The source file of the binary or example `name` in the workspace
+/// given via `opts`, if any.
+fn source_path(name: &str, opts: &ReplayOptions) -> Option<PathBuf> {
+    let cargo_opts = opts.cargo_options.to_cargo_options();
+    let mut cmd = cargo_metadata::MetadataCommand::new();
+    cmd.no_deps();
+    if let Some(path) = cargo_opts
+        .iter()
+        .position(|opt| opt.as_str() == "--manifest-path")
+        .and_then(|idx| cargo_opts.get(idx + 1))
+    {
+        cmd.manifest_path(path);
+    }
+    let metadata = cmd.exec().ok()?;
+
+    metadata
+        .packages
+        .iter()
+        .filter(|package| metadata.workspace_members.contains(&package.id))
+        .flat_map(|package| package.targets.iter())
+        .find(|target| {
+            target.name == name && target.kind.iter().any(|k| k == "bin" || k == "example")
+        })
+        .map(|target| target.src_path.clone().into_std_path_buf())
+}
+
+/// Corrects the timestamps of the given trace file for `freq` into
+/// `output`.
+fn retime(
+    trace: &std::path::Path,
+    output: &std::path::Path,
+    freq: u32,
+) -> Result<(), RTICScopeError> {
+    let freq = manifest::check_tpiu_freq(freq)?;
+    let recorded_freq = retime::retime_file(trace, output, freq)?;
+    log::status(
+        "Retimed",
+        format!(
+            "{} from {} Hz to {} Hz into {}.",
+            trace.display(),
+            recorded_freq,
+            freq,
+            output.display()
+        ),
+    );
+
+    Ok(())
+}
+
+/// Compares the task execution times of the `other` trace file against
+/// those of the `base` trace file.
+fn compare(
+    base: &std::path::Path,
+    other: &std::path::Path,
+    policy: intervals::OverflowPolicy,
+) -> Result<(), RTICScopeError> {
+    let comparison = compare::Comparison::new(
+        &compare::executions(base, policy)?,
+        &compare::executions(other, policy)?,
+    );
+    log::status(
+        "Compared",
+        format!("{} against {}:", other.display(), base.display()),
+    );
+    println!("{}", comparison);
+
+    Ok(())
+}
+
+/// Re-resolves the unmappable events of the archive `file` against the
+/// lookup maps of the `other` trace file or archive into `output`.
+fn reresolve(
+    file: &std::path::Path,
+    output: &std::path::Path,
+    other: &std::path::Path,
+) -> Result<(), RTICScopeError> {
+    let maps = if archive::is_archive(other)? {
+        archive::Archive::read(fs::File::open(other)?)?.metadata
+    } else {
+        sources::FileSource::new(fs::OpenOptions::new().read(true).open(other)?)?.metadata()
+    };
+
+    let (resolved, unmappable) = archive::reresolve_file(file, output, &maps)?;
+    log::status(
+        "Re-resolved",
+        format!(
+            "{} events of {} against {} into {}; {} remain unmappable.",
+            resolved,
+            file.display(),
+            other.display(),
+            output.display(),
+            unmappable
+        ),
+    );
+
+    Ok(())
+}
+
+/// Adds and removes the bookmarks given in `opts` to and from the
+/// trace file or archive `file` into `output`.
+fn bookmark(
+    file: &std::path::Path,
+    output: &std::path::Path,
+    opts: &ReplayOptions,
+) -> Result<(), RTICScopeError> {
+    let metadata = bookmarks::edit_file(file, output, &opts.bookmarks, &opts.remove_bookmarks)?;
+    log::status(
+        "Bookmarked",
+        format!(
+            "{} into {}: {} bookmarks.",
+            file.display(),
+            output.display(),
+            metadata.bookmarks().len()
+        ),
+    );
+
+    Ok(())
+}
+
+/// Bundles the `trace` file, restricted as per `opts`, with the source
+/// and manifest properties of the application into a reproduction at
+/// `out`.
+async fn repro(
+    trace: &std::path::Path,
+    out: &std::path::Path,
+    opts: &ReplayOptions,
+    cart: impl futures::Future<Output = Result<(CargoWrapper, Artifact), CargoError>>,
+) -> Result<(), RTICScopeError> {
+    use std::io::Write;
+
+    let (cargo, artifact) = cart.await?;
+    let rtic_app = recovery::RticApp::parse(&artifact)?;
+    let manip = manifest::ManifestProperties::new(&cargo, rtic_app.device().as_deref(), None)?;
+    let app = recovery::app_source(&artifact)?;
+
+    let mut src = sources::FileSource::new(fs::OpenOptions::new().read(true).open(trace)?)?;
+    if let Some(range) = replay_range(opts) {
+        src.restrict(range)?;
+    }
+    let metadata = src.metadata();
+    if let Some(e) = metadata.check_source(artifact.target.src_path.as_std_path()) {
+        diag::warn(&e);
+    }
+    let fixture = src.collect::<Result<Vec<TraceData>, _>>()?;
+    let chunks = fixture.len();
+
+    let mut file = std::io::BufWriter::new(fs::File::create(out)?);
+    repro::reproduction(app, manip, metadata, fixture).write(&mut file)?;
+    file.flush()?;
+    log::status(
+        "Bundled",
+        format!(
+            "{} trace data chunks of {} into {}. Task names have been anonymized.",
+            chunks,
+            trace.display(),
+            out.display()
+        ),
+    );
+
+    Ok(())
+}
+
+/// The range of trace data to replay, if restricted via `--from`
+/// and/or `--until`.
+fn replay_range(opts: &ReplayOptions) -> Option<std::ops::Range<std::time::Duration>> {
+    use std::time::Duration;
+
+    if opts.from.is_none() && opts.until.is_none() {
+        return None;
+    }
+
+    Some(
+        Duration::from_nanos(opts.from.unwrap_or(0))
+            ..Duration::from_nanos(opts.until.unwrap_or(u64::MAX)),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::time::Duration;
+
+    /// Ensure that durations that do not fit a [`Duration`] are
+    /// rejected instead of panicking.
+    #[test]
+    fn seconds() {
+        assert_eq!(parse_seconds("0"), Ok(Duration::ZERO));
+        assert_eq!(parse_seconds("1.5"), Ok(Duration::from_millis(1500)));
+        assert!(parse_seconds("1e19").is_ok());
+        assert!(parse_seconds(&(u64::MAX as f64).to_string()).is_err());
+        for invalid in ["-0.5", "inf", "NaN", "1e300", "soon"] {
+            assert!(parse_seconds(invalid).is_err(), "{}", invalid);
+        }
+    }
+}
@@ -4,11 +4,15 @@
 //! supplied/overridden via command-line options.
 use crate::build::CargoWrapper;
 use crate::diag;
+use crate::log;
 use crate::ManifestOptions;
 
 use std::convert::TryInto;
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
 
 use cortex_m::peripheral::itm::LocalTimestampOptions;
+use indexmap::IndexMap;
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
@@ -20,10 +24,23 @@ struct ManifestPropertiesIntermediate {
     pub interrupt_path: Option<String>,
     pub tpiu_freq: Option<u32>,
     pub tpiu_baud: Option<u32>,
+    pub timestamp_freq: Option<u32>,
     pub lts_prescaler: Option<u8>,
     pub dwt_enter_id: Option<usize>,
     pub dwt_exit_id: Option<usize>,
+    pub dwt_returned_id: Option<usize>,
+    pub dwt_monotonic_id: Option<usize>,
     pub expect_malformed: Option<bool>,
+    pub task_overrides: Option<PathBuf>,
+    pub svd: Option<PathBuf>,
+    pub internal_exceptions: Option<IndexMap<String, u16>>,
+    pub interrupts: Option<IndexMap<String, u16>>,
+    pub clock_change_port: Option<u8>,
+    pub watchpoints: Option<IndexMap<String, u8>>,
+    pub tpiu_source_id: Option<u8>,
+    pub log_ports: Option<Vec<u8>>,
+    pub adhoc_build_timeout: Option<u64>,
+    pub trace_helpers: Option<IndexMap<String, usize>>,
 }
 
 impl ManifestPropertiesIntermediate {
@@ -44,10 +61,23 @@ impl ManifestPropertiesIntermediate {
             interrupt_path,
             tpiu_freq,
             tpiu_baud,
+            timestamp_freq,
             lts_prescaler,
             dwt_enter_id,
             dwt_exit_id,
-            expect_malformed
+            dwt_returned_id,
+            dwt_monotonic_id,
+            expect_malformed,
+            task_overrides,
+            svd,
+            internal_exceptions,
+            interrupts,
+            clock_change_port,
+            watchpoints,
+            tpiu_source_id,
+            log_ports,
+            adhoc_build_timeout,
+            trace_helpers
         );
     }
 }
@@ -60,10 +90,59 @@ pub struct ManifestProperties {
     pub interrupt_path: String,
     pub tpiu_freq: u32,
     pub tpiu_baud: u32,
+    /// Frequency of the clock that local timestamps count, if other
+    /// than the TPIU clock: e.g. the processor clock, if the ITM is not
+    /// configured to timestamp from the asynchronous clock (TCR.SWOENA).
+    pub timestamp_freq: Option<u32>,
     pub lts_prescaler: LocalTimestampOptions,
     pub dwt_enter_id: usize,
     pub dwt_exit_id: usize,
+    /// DWT comparator that traces the completion of async software
+    /// tasks, if configured on the target via
+    /// `cortex_m_rtic_trace::configure_returned`.
+    pub dwt_returned_id: Option<usize>,
+    /// DWT comparator to which the firmware writes the counter of the
+    /// RTIC monotonic, if configured on the target via
+    /// `cortex_m_rtic_trace::configure_monotonic`.
+    pub dwt_monotonic_id: Option<usize>,
     pub expect_malformed: bool,
+    pub task_overrides: Option<PathBuf>,
+    /// Internal exceptions (name to exception number) in addition to
+    /// the core exceptions of the target's architecture profile, e.g.
+    /// vendor-specific fixed vectors.
+    pub internal_exceptions: IndexMap<String, u16>,
+    /// External interrupts (name to IRQ number) that are resolved
+    /// without building the PAC. Takes precedence over the PAC.
+    pub interrupts: IndexMap<String, u16>,
+    /// SVD file of the target from which external interrupts not in
+    /// `interrupts` are resolved without building the PAC. Takes
+    /// precedence over the PAC.
+    pub svd: Option<PathBuf>,
+    /// ITM stimulus port on which the firmware writes the new TPIU
+    /// frequency (in Hz, as a little-endian `u32`) when it changes the
+    /// clock configuration mid-trace.
+    pub clock_change_port: Option<u8>,
+    /// Names of the variables watched by DWT comparators that are not
+    /// used for software task tracing (name to comparator). The data
+    /// traces of these comparators are named accordingly.
+    pub watchpoints: IndexMap<String, u8>,
+    /// TPIU trace source ID of the ITM if the trace stream is formatted
+    /// by the TPIU (e.g. for high-speed SWO or a trace port), in which
+    /// case only the data of this source is decoded. `None` if the
+    /// stream is not framed.
+    pub tpiu_source_id: Option<u8>,
+    /// ITM stimulus ports on which the firmware writes lines of text,
+    /// e.g. ad-hoc logging, which are presented as a separate log
+    /// instead of being mapped to task events.
+    pub log_ports: Vec<u8>,
+    /// Seconds after which the build of the intermediate crate that
+    /// resolves interrupts via the PAC is killed.
+    pub adhoc_build_timeout: u64,
+    /// Attribute macros (by name) that expand to functions carrying
+    /// `#[trace]`, and the number of `#[trace]` attributes they emit.
+    /// Such attributes are not seen when software task IDs are
+    /// recovered from the source.
+    pub trace_helpers: IndexMap<String, usize>,
 }
 
 #[derive(Error, Debug)]
@@ -78,6 +157,8 @@ pub enum ManifestMetadataError {
     MissingInterruptPath,
     #[error("Manifest metadata is missing TPIU frequency")]
     MissingFreq,
+    #[error("TPIU frequency must be non-zero")]
+    ZeroFreq,
     #[error("Manifest metadata is missing TPIU baud rate")]
     MissingBaud,
     #[error("Manifest metadata is missing LTS prescaler")]
@@ -86,24 +167,118 @@ pub enum ManifestMetadataError {
     MissingDWTUnit,
     #[error("Manifest metadata is missing conditional whether malformed packets are expected")]
     MissingExpectMalformed,
+    #[error("Watchpoint {0} is on DWT comparator {1}, which is reserved by RTIC Scope")]
+    WatchpointComparator(String, u8),
+    #[error("Log port {0} is not a free ITM stimulus port")]
+    LogPort(u8),
 }
 
+/// Hint on missing PAC properties that could not be detected.
+const DETECTED_PAC_HINT: &str = "PAC properties are otherwise detected from `#[app(device = ...)]` if the device is a direct dependency of the application";
+
 impl diag::DiagnosableError for ManifestMetadataError {
+    fn kind(&self) -> &'static str {
+        match self {
+            Self::DeserializationFailed(..) => "DeserializationFailed",
+            Self::MissingName => "MissingName",
+            Self::MissingVersion => "MissingVersion",
+            Self::MissingInterruptPath => "MissingInterruptPath",
+            Self::MissingFreq => "MissingFreq",
+            Self::ZeroFreq => "ZeroFreq",
+            Self::MissingBaud => "MissingBaud",
+            Self::MissingLTSPrescaler => "MissingLTSPrescaler",
+            Self::MissingDWTUnit => "MissingDWTUnit",
+            Self::MissingExpectMalformed => "MissingExpectMalformed",
+            Self::WatchpointComparator(..) => "WatchpointComparator",
+            Self::LogPort(..) => "LogPort",
+        }
+    }
+
     fn diagnose(&self) -> Vec<String> {
         match self {
-            Self::MissingName => vec!["Add `pac_name = \"<your PAC name>\"` to [package.metadata.rtic-scope] in Cargo.toml or specify --pac-name".into()],
-            Self::MissingVersion => vec!["Add `pac_version = \"your PAC version\"` to [package.metadata.rtic-scope] in Cargo.toml or specify --pac-version".into()],
-            Self::MissingInterruptPath => vec!["Add `interrupt_path = \"path to your PAC's Interrupt enum\"` to [package.metadata.rtic-scope] in Cargo.toml or specify --pac-interrupt-path".into()],
+            Self::MissingName => vec!["Add `pac_name = \"<your PAC name>\"` to [package.metadata.rtic-scope] in Cargo.toml or specify --pac-name".into(), DETECTED_PAC_HINT.into()],
+            Self::MissingVersion => vec!["Add `pac_version = \"your PAC version\"` to [package.metadata.rtic-scope] in Cargo.toml or specify --pac-version".into(), DETECTED_PAC_HINT.into()],
+            Self::MissingInterruptPath => vec!["Add `interrupt_path = \"path to your PAC's Interrupt enum\"` to [package.metadata.rtic-scope] in Cargo.toml or specify --pac-interrupt-path".into(), DETECTED_PAC_HINT.into()],
             Self::MissingFreq => vec!["Add `tpiu_freq = \"your TPIU frequency\"` to [package.metadata.rtic-scope] in Cargo.toml or specify --tpiu-freq".into()],
+            Self::ZeroFreq => vec!["Timestamps are calculated from the TPIU frequency, which must be the frequency of the clock that drives the TPIU (usually the core clock)".into()],
             Self::MissingBaud => vec!["Add `tpiu_baud = \"your TPIU baud rate\"` to [package.metadata.rtic-scope] in Cargo.toml or specify --tpiu-baud".into()],
             Self::MissingLTSPrescaler => vec!["Add `lts_prescaler = <your LTS prescaler value (accepted values: 1, 4, 16, 64)>` to [package.metadata.rtic-scope] in Cargo.toml".into()],
             Self::MissingDWTUnit => vec!["Add `dwt_enter_id = \"your enter DWT unit ID\"` and `dwt_exit_id = \"your exit DWT unit ID\"` to [package.metadata.rtic-scope] in Cargo.toml".into()],
             Self::MissingExpectMalformed => vec!["Add `expect_malformed = <whether malformed packets are expected>` to [package.metadata.rtic-scope] in Cargo.toml".into()],
+            Self::WatchpointComparator(_, _) => vec!["Watchpoints must be on comparators other than `dwt_enter_id`, `dwt_exit_id`, `dwt_returned_id`, and `dwt_monotonic_id`".into()],
+            Self::LogPort(_) => vec!["Log ports must be ITM stimulus ports 0-31 other than `clock_change_port`".into()],
             _ => vec![],
         }
     }
 }
 
+/// Default of [`ManifestProperties::adhoc_build_timeout`]. A clean
+/// build of a PAC can take a few minutes.
+const DEFAULT_ADHOC_BUILD_TIMEOUT: u64 = 600;
+
+/// TPIU frequencies that are plausible for the trace clock of an MCU.
+const PLAUSIBLE_TPIU_FREQ: RangeInclusive<u32> = 100_000..=1_000_000_000;
+
+/// Verifies that the TPIU frequency can be used to calculate
+/// timestamps. Warns if the frequency is implausible, which likely
+/// indicates a configuration error.
+pub fn check_tpiu_freq(freq: u32) -> Result<u32, ManifestMetadataError> {
+    if freq == 0 {
+        return Err(ManifestMetadataError::ZeroFreq);
+    }
+    if let Some(warning) = implausible_tpiu_freq(freq) {
+        log::warn(warning);
+    }
+
+    Ok(freq)
+}
+
+fn implausible_tpiu_freq(freq: u32) -> Option<String> {
+    if PLAUSIBLE_TPIU_FREQ.contains(&freq) {
+        return None;
+    }
+
+    Some(format!(
+        "TPIU frequency of {} Hz is outside the plausible range of {}-{} Hz for an MCU trace clock; timestamps are likely incorrect",
+        freq,
+        PLAUSIBLE_TPIU_FREQ.start(),
+        PLAUSIBLE_TPIU_FREQ.end()
+    ))
+}
+
+/// Verifies that all log ports are stimulus ports of the ITM, and that
+/// none is the port on which clock changes are signaled.
+fn check_log_ports(
+    log_ports: Vec<u8>,
+    clock_change_port: Option<u8>,
+) -> Result<Vec<u8>, ManifestMetadataError> {
+    for port in &log_ports {
+        if *port >= 32 || Some(*port) == clock_change_port {
+            return Err(ManifestMetadataError::LogPort(*port));
+        }
+    }
+
+    Ok(log_ports)
+}
+
+/// Verifies that no watchpoint is on one of the comparators that trace
+/// software tasks.
+fn check_watchpoints(
+    watchpoints: IndexMap<String, u8>,
+    task_comparators: &[Option<usize>],
+) -> Result<IndexMap<String, u8>, ManifestMetadataError> {
+    for (name, comparator) in &watchpoints {
+        if task_comparators.contains(&Some(*comparator as usize)) {
+            return Err(ManifestMetadataError::WatchpointComparator(
+                name.to_owned(),
+                *comparator,
+            ));
+        }
+    }
+
+    Ok(watchpoints)
+}
+
 impl TryInto<ManifestProperties> for ManifestPropertiesIntermediate {
     type Error = ManifestMetadataError;
 
@@ -115,8 +290,9 @@ impl TryInto<ManifestProperties> for ManifestPropertiesIntermediate {
                 .interrupt_path
                 .ok_or(Self::Error::MissingInterruptPath)?,
             pac_features: self.pac_features.unwrap_or_else(|| [].to_vec()),
-            tpiu_freq: self.tpiu_freq.ok_or(Self::Error::MissingFreq)?,
+            tpiu_freq: check_tpiu_freq(self.tpiu_freq.ok_or(Self::Error::MissingFreq)?)?,
             tpiu_baud: self.tpiu_baud.ok_or(Self::Error::MissingBaud)?,
+            timestamp_freq: self.timestamp_freq.map(check_tpiu_freq).transpose()?,
             lts_prescaler: self
                 .lts_prescaler
                 .ok_or(Self::Error::MissingLTSPrescaler)?
@@ -124,16 +300,45 @@ impl TryInto<ManifestProperties> for ManifestPropertiesIntermediate {
                 .map_err(|_| Self::Error::MissingLTSPrescaler)?,
             dwt_enter_id: self.dwt_enter_id.ok_or(Self::Error::MissingDWTUnit)?,
             dwt_exit_id: self.dwt_exit_id.ok_or(Self::Error::MissingDWTUnit)?,
+            dwt_returned_id: self.dwt_returned_id,
+            dwt_monotonic_id: self.dwt_monotonic_id,
             expect_malformed: self
                 .expect_malformed
                 .ok_or(Self::Error::MissingExpectMalformed)?,
+            task_overrides: self.task_overrides,
+            internal_exceptions: self.internal_exceptions.unwrap_or_default(),
+            interrupts: self.interrupts.unwrap_or_default(),
+            svd: self.svd,
+            clock_change_port: self.clock_change_port,
+            watchpoints: check_watchpoints(
+                self.watchpoints.unwrap_or_default(),
+                &[
+                    self.dwt_enter_id,
+                    self.dwt_exit_id,
+                    self.dwt_returned_id,
+                    self.dwt_monotonic_id,
+                ],
+            )?,
+            tpiu_source_id: self.tpiu_source_id,
+            log_ports: check_log_ports(self.log_ports.unwrap_or_default(), self.clock_change_port)?,
+            adhoc_build_timeout: self
+                .adhoc_build_timeout
+                .unwrap_or(DEFAULT_ADHOC_BUILD_TIMEOUT),
+            trace_helpers: self.trace_helpers.unwrap_or_default(),
         })
     }
 }
 
 impl ManifestProperties {
+    /// Frequency of the clock that local timestamps count, before the
+    /// LTS prescaler: the TPIU clock unless `timestamp_freq` is set.
+    pub fn timestamp_clock(&self) -> u32 {
+        self.timestamp_freq.unwrap_or(self.tpiu_freq)
+    }
+
     pub fn new(
         cargo: &CargoWrapper,
+        device: Option<&str>,
         opts: Option<&ManifestOptions>,
     ) -> Result<Self, ManifestMetadataError> {
         let package_meta = cargo.package().unwrap().metadata.get("rtic-scope");
@@ -171,10 +376,178 @@ impl ManifestProperties {
                 pac_features,
                 interrupt_path,
                 tpiu_freq,
-                tpiu_baud
+                tpiu_baud,
+                tpiu_source_id,
+                timestamp_freq,
+                task_overrides,
+                svd,
+                adhoc_build_timeout
             );
         }
 
+        // Detect the PAC properties that are not configured from the
+        // device of the RTIC application. A configured PAC other than
+        // the device is left as is.
+        if int.pac_name.is_none()
+            || int.pac_version.is_none()
+            || int.pac_features.is_none()
+            || int.interrupt_path.is_none()
+        {
+            if let Some(pac) = device.and_then(|d| detect_pac(cargo, d)) {
+                if int.pac_name.is_none() || int.pac_name == pac.pac_name {
+                    log::status(
+                        "Detected",
+                        format!(
+                            "PAC {} with features {:?} and interrupts at {}, version {}",
+                            pac.pac_name.as_deref().unwrap_or_default(),
+                            pac.pac_features.as_deref().unwrap_or_default(),
+                            pac.interrupt_path.as_deref().unwrap_or_default(),
+                            pac.pac_version.as_deref().unwrap_or_default(),
+                        ),
+                    );
+                    int.complete_with(pac);
+                }
+            }
+        }
+
         int.try_into()
     }
 }
+
+/// The PAC properties of `device`, the path to the device of the RTIC
+/// application (e.g. `stm32f4::stm32f401`): the package of the crate
+/// the path starts with, at the version and with the features it is
+/// resolved with for the application.
+fn detect_pac(cargo: &CargoWrapper, device: &str) -> Option<ManifestPropertiesIntermediate> {
+    let metadata = cargo.metadata();
+    let resolve = metadata.resolve.as_ref()?;
+    let node = |id| resolve.nodes.iter().find(|node| &node.id == id);
+
+    let root = &cargo.package().ok()?.id;
+    let (krate, path) = device.split_once("::").unwrap_or((device, ""));
+    let dep = node(root)?.deps.iter().find(|dep| dep.name == krate)?;
+    let package = metadata.packages.iter().find(|p| p.id == dep.pkg)?;
+
+    Some(ManifestPropertiesIntermediate {
+        pac_name: Some(package.name.clone()),
+        pac_version: Some(format!("={}", package.version)),
+        pac_features: Some(pac_features(&node(&dep.pkg)?.features)),
+        interrupt_path: Some(interrupt_path(&package.name, path)),
+        ..Default::default()
+    })
+}
+
+/// The features a PAC is built with to resolve interrupts on the host:
+/// those of the application, except the runtime.
+fn pac_features(enabled: &[String]) -> Vec<String> {
+    enabled
+        .iter()
+        .filter(|f| !matches!(f.as_str(), "default" | "rt"))
+        .cloned()
+        .collect()
+}
+
+/// The path to the interrupt enum of the device at `path` in the PAC
+/// `package`. The crate is named as by the intermediate crate, which
+/// depends on the PAC without renaming it.
+fn interrupt_path(package: &str, path: &str) -> String {
+    [package.replace('-', "_").as_str(), path, "Interrupt"]
+        .iter()
+        .filter(|segment| !segment.is_empty())
+        .copied()
+        .collect::<Vec<_>>()
+        .join("::")
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+
+    /// Properties of a target with a 16 MHz TPIU clock whose interrupts
+    /// are not resolved via a PAC.
+    pub fn properties() -> ManifestProperties {
+        ManifestProperties {
+            pac_name: "".to_string(),
+            pac_version: "".to_string(),
+            pac_features: vec![],
+            interrupt_path: "".to_string(),
+            tpiu_freq: 16_000_000,
+            tpiu_baud: 115_200,
+            timestamp_freq: None,
+            lts_prescaler: LocalTimestampOptions::Enabled,
+            dwt_enter_id: 1,
+            dwt_exit_id: 2,
+            dwt_returned_id: None,
+            dwt_monotonic_id: None,
+            expect_malformed: false,
+            task_overrides: None,
+            internal_exceptions: IndexMap::new(),
+            interrupts: IndexMap::new(),
+            svd: None,
+            clock_change_port: None,
+            watchpoints: IndexMap::new(),
+            tpiu_source_id: None,
+            log_ports: vec![],
+            adhoc_build_timeout: DEFAULT_ADHOC_BUILD_TIMEOUT,
+            trace_helpers: IndexMap::new(),
+        }
+    }
+
+    /// Ensure that a zero TPIU frequency is rejected.
+    #[test]
+    fn zero_tpiu_freq() {
+        assert!(matches!(
+            check_tpiu_freq(0),
+            Err(ManifestMetadataError::ZeroFreq)
+        ));
+    }
+
+    /// Ensure that watchpoints on the comparators of software tasks are
+    /// rejected.
+    #[test]
+    fn watchpoints() {
+        let watchpoints = IndexMap::from_iter([("state".to_string(), 3)]);
+        assert!(check_watchpoints(watchpoints.clone(), &[Some(1), Some(2), None]).is_ok());
+        assert!(matches!(
+            check_watchpoints(watchpoints, &[Some(1), Some(2), Some(3)]),
+            Err(ManifestMetadataError::WatchpointComparator(_, 3))
+        ));
+    }
+
+    /// Ensure that implausible TPIU frequencies are warned about.
+    #[test]
+    fn implausible_tpiu_freqs() {
+        assert!(implausible_tpiu_freq(1).is_some());
+        assert!(implausible_tpiu_freq(4_000_000_000).is_some());
+        assert!(implausible_tpiu_freq(16_000_000).is_none());
+        assert_eq!(check_tpiu_freq(1).unwrap(), 1);
+    }
+
+    /// Ensure that the PAC properties detected from the device of the
+    /// application can be used by the intermediate crate.
+    #[test]
+    fn detected_pac() {
+        assert_eq!(
+            interrupt_path("stm32f4", "stm32f401"),
+            "stm32f4::stm32f401::Interrupt"
+        );
+        assert_eq!(
+            interrupt_path("nrf52840-pac", ""),
+            "nrf52840_pac::Interrupt"
+        );
+        assert_eq!(
+            pac_features(&["default".into(), "rt".into(), "stm32f401".into()]),
+            vec!["stm32f401".to_string()]
+        );
+    }
+
+    /// Ensure that timestamps count the TPIU clock unless another clock
+    /// is set.
+    #[test]
+    fn timestamp_clock() {
+        let mut props = properties();
+        assert_eq!(props.timestamp_clock(), 16_000_000);
+        props.timestamp_freq = Some(64_000_000);
+        assert_eq!(props.timestamp_clock(), 64_000_000);
+    }
+}
@@ -2,20 +2,31 @@
 //! associate ITM packets with RTIC tasks.
 use crate::build::{self, CargoWrapper};
 use crate::diag;
+use crate::intervals::Intervals;
+use crate::log;
 use crate::manifest::ManifestProperties;
+use crate::sources::SourceError;
+use crate::symbols::{self, Symbols};
 
+use std::cell::RefCell;
+use std::env;
 use std::fs;
 use std::io::Write;
 use std::iter::FromIterator;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-use cargo_metadata::Artifact;
+use cargo_metadata::{Artifact, ArtifactProfile};
 use chrono::Local;
 use include_dir::{dir::ExtractMode, include_dir};
-use itm::{ExceptionAction, MemoryAccessType, TimestampedTracePackets, TracePacket, VectActive};
+use itm::{
+    ExceptionAction, MalformedPacket, MemoryAccessType, TimestampedTracePackets, TracePacket,
+    VectActive,
+};
 
 use indexmap::{IndexMap, IndexSet};
 use proc_macro2::{TokenStream, TokenTree};
-use quote::{format_ident, quote};
+use quote::{format_ident, quote, ToTokens};
 use rtic_scope_api::{EventChunk, EventType, TaskAction};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
@@ -44,17 +55,133 @@ pub enum RecoveryError {
     LibLoadFail(#[source] libloading::Error),
     #[error("Failed to lookup symbol in the intermediate shared object: {0}")]
     LibLookupFail(#[source] libloading::Error),
+    #[error("Failed to read software task overrides file: {0}")]
+    OverridesRead(#[source] std::io::Error),
+    #[error("Failed to parse software task overrides file: {0}")]
+    OverridesParse(#[source] serde_json::Error),
+    #[error("The internal exception {0} has an invalid or reserved exception number {1}")]
+    InvalidInternalException(String, u16),
+    #[error("The interrupt {0} has an invalid IRQ number {1}")]
+    InvalidInterruptNumber(String, u16),
+    #[error("Failed to read module source file {0:?}: {1}")]
+    ModuleRead(PathBuf, #[source] std::io::Error),
+    #[error("Failed to parse module source file {0:?}: {1}")]
+    ModuleParse(PathBuf, #[source] syn::Error),
+    #[error("{0} of {1} software tasks exited before they were entered")]
+    SwappedComparators(usize, usize),
+    #[error("Failed to read included source file {0:?}: {1}")]
+    IncludeRead(PathBuf, #[source] std::io::Error),
+    #[error("Failed to parse included source file {0:?}: {1}")]
+    IncludeParse(PathBuf, #[source] syn::Error),
+    #[error("The source file {0:?} includes itself")]
+    IncludeCycle(PathBuf),
+    #[error("Failed to read PAC source file {0:?}: {1}")]
+    PACSourceRead(PathBuf, #[source] std::io::Error),
+    #[error("The PAC's {0} implements neither `number()` nor `nr()`")]
+    UnknownInterruptMethod(String),
+    #[error("The source file {0:?} was modified after the artifact {1:?} was built")]
+    StaleArtifact(PathBuf, PathBuf),
+    #[error("The source of the application differs from the source the trace was recorded with")]
+    SourceMismatch,
+    #[error("Software task ID {0} was traced, but only {1} software tasks were recovered from the source")]
+    SoftwareIdOutOfRange(usize, usize),
+    #[error("Software task {0} is conditionally compiled on {1}, which cannot be evaluated")]
+    UncertainConfiguration(String, String),
+    #[error("Failed to read SVD file {0:?}: {1}")]
+    SvdRead(PathBuf, #[source] std::io::Error),
+    #[error("Failed to parse SVD file {0:?}: {1}")]
+    SvdParse(PathBuf, String),
+    #[error("No hardware or software tasks were recovered from the RTIC application")]
+    NoTasks,
+    #[error("The interrupt {0} is bound by more than one hardware task")]
+    DuplicateBind(String, String, String),
 }
 
 impl diag::DiagnosableError for RecoveryError {
+    fn kind(&self) -> &'static str {
+        match self {
+            Self::MissingSoftwareMapping(..) => "MissingSoftwareMapping",
+            Self::InvalidSoftwareValue(..) => "InvalidSoftwareValue",
+            Self::MissingHardwareMapping(..) => "MissingHardwareMapping",
+            Self::SourceRead(..) => "SourceRead",
+            Self::TokenizeFail(..) => "TokenizeFail",
+            Self::RTICArgumentsMissing => "RTICArgumentsMissing",
+            Self::RTICParseFail(..) => "RTICParseFail",
+            Self::LibExtractFail(..) => "LibExtractFail",
+            Self::LibBuildFail(..) => "LibBuildFail",
+            Self::LibLoadFail(..) => "LibLoadFail",
+            Self::LibLookupFail(..) => "LibLookupFail",
+            Self::OverridesRead(..) => "OverridesRead",
+            Self::OverridesParse(..) => "OverridesParse",
+            Self::InvalidInternalException(..) => "InvalidInternalException",
+            Self::InvalidInterruptNumber(..) => "InvalidInterruptNumber",
+            Self::ModuleRead(..) => "ModuleRead",
+            Self::ModuleParse(..) => "ModuleParse",
+            Self::SwappedComparators(..) => "SwappedComparators",
+            Self::IncludeRead(..) => "IncludeRead",
+            Self::IncludeParse(..) => "IncludeParse",
+            Self::IncludeCycle(..) => "IncludeCycle",
+            Self::PACSourceRead(..) => "PACSourceRead",
+            Self::UnknownInterruptMethod(..) => "UnknownInterruptMethod",
+            Self::StaleArtifact(..) => "StaleArtifact",
+            Self::SourceMismatch => "SourceMismatch",
+            Self::SoftwareIdOutOfRange(..) => "SoftwareIdOutOfRange",
+            Self::UncertainConfiguration(..) => "UncertainConfiguration",
+            Self::SvdRead(..) => "SvdRead",
+            Self::SvdParse(..) => "SvdParse",
+            Self::NoTasks => "NoTasks",
+            Self::DuplicateBind(..) => "DuplicateBind",
+        }
+    }
+
     fn diagnose(&self) -> Vec<String> {
         match self {
             RecoveryError::RTICArgumentsMissing => vec![
                 "RTIC Scope expects an RTIC application declaration on the form `#[rtic::app(...)] mod app { ... }` where the first `...` is the application arguments.".to_string(),
             ],
+            RecoveryError::InvalidInternalException(_, _) => vec![
+                "Internal exceptions are declared with their exception number (i.e. their position in the vector table, 16 or larger), e.g. `internal_exceptions = { VendorFault = 16 }`.".to_string(),
+            ],
+            RecoveryError::OverridesParse(_) => vec![
+                "The overrides file is expected on the form `{ \"replace\": false, \"tasks\": { \"0\": \"app::some_task\" } }`, where `replace` denotes whether the recovered associations should be discarded.".to_string(),
+            ],
+            RecoveryError::SwappedComparators(_, _) => vec![
+                "The DWT comparators for entering and exiting software tasks may be swapped: check `dwt_enter_id` and `dwt_exit_id` against the firmware.".to_string(),
+                "Pass --swap-comparators to swap them for this run.".to_string(),
+            ],
+            RecoveryError::UnknownInterruptMethod(_) => vec![
+                "RTIC Scope expects the interrupt enum to implement `cortex_m::interrupt::InterruptNumber` (`number()`) or, for PACs generated by older versions of svd2rust, `bare_metal::Nr` (`nr()`).".to_string(),
+                "Check that `interrupt_path` points to the `Interrupt` enum of the PAC, or declare the IRQ numbers of the bound interrupts via `interrupts` in the manifest.".to_string(),
+            ],
+            RecoveryError::LibBuildFail(build::CargoError::BuildTimeout(_, _)) => vec![
+                "The intermediate crate may be stuck fetching the PAC or building its dependencies. Build it manually via `cargo build --manifest-path target/cargo-rtic-trace-libadhoc/Cargo.toml` to see where.".to_string(),
+                "Raise the timeout via `adhoc_build_timeout` in the manifest or --adhoc-build-timeout, or declare the IRQ numbers of the bound interrupts via `interrupts` in the manifest to not build the PAC at all.".to_string(),
+            ],
+            RecoveryError::SvdParse(_, _) => vec![
+                "`svd` is expected to point to the SVD file from which the PAC is generated. Alternatively, declare the IRQ numbers of the bound interrupts via `interrupts` in the manifest.".to_string(),
+            ],
+            RecoveryError::StaleArtifact(_, _) => vec![
+                "The recovered metadata may not match the firmware on the target: rebuild and flash the application.".to_string(),
+            ],
+            RecoveryError::DuplicateBind(bind, first, second) => vec![
+                format!("Both app::{} and app::{} bind {}. RTIC rejects this, so the application was likely parsed differently than by rustc: please report this as a bug.", first, second, bind),
+            ],
+            RecoveryError::NoTasks => vec![
+                "Hardware tasks are recovered from #[task(binds = ...)] and software tasks from #[trace]-annotated functions: check that the application uses either.".to_string(),
+            ],
+            RecoveryError::SourceMismatch => vec![
+                "Check out the revision the trace was recorded with (see `replay --info`) before resolving the trace against the source.".to_string(),
+            ],
+            RecoveryError::SoftwareIdOutOfRange(_, _) => vec![
+                "The source may have been edited since the firmware on the target was built, such that the #[trace] attributes of the source no longer match those of the firmware: rebuild and flash the application.".to_string(),
+            ],
+            RecoveryError::UncertainConfiguration(task, _) => vec![
+                format!("Configuration options other than `feature`, `debug_assertions`, and `test` are assumed to hold. If they do not, the IDs of {} and all subsequently traced software tasks are off.", task),
+                "Request a fixed ID via #[trace(id = N)], or declare the tasks via --task-overrides.".to_string(),
+            ],
             RecoveryError::InvalidSoftwareValue(_) => vec![
-                "Invalid DataTraceValue payloads are those of zero length or with non-zero subsequent bytes (only the first byte may be non-zero).".to_string(),
-                "RTIC Scope supports up to 255 software tasks at the present.".to_string(),
+                "Software task IDs are written as a little-endian u16 (or as a single byte by earlier versions of cortex-m-rtic-trace); payloads of any other length are invalid.".to_string(),
+                "RTIC Scope supports up to 65535 software tasks at the present.".to_string(),
             ],
             _ => vec![],
         }
@@ -66,31 +193,151 @@ impl diag::DiagnosableError for RecoveryError {
 pub struct TraceLookupMaps {
     software: SoftwareMap,
     hardware: HardwareMap,
+    /// Full task path to task priority, for RTIC tasks.
+    #[serde(default)]
+    priorities: IndexMap<String, u8>,
+    /// Function symbols of the application, by which sampled program
+    /// counters are resolved. Not available for traces recorded by
+    /// earlier versions.
+    #[serde(default)]
+    symbols: Symbols,
+    /// Full path of the `#[idle]` task, if any, which executes in
+    /// thread mode.
+    #[serde(default)]
+    idle: Option<String>,
 }
 
 impl TraceLookupMaps {
     pub fn from(
         cargo: &CargoWrapper,
         artifact: &Artifact,
+        rtic_app: &RticApp,
         manip: &ManifestProperties,
     ) -> Result<Self, RecoveryError> {
-        // Parse the RTIC app from the source code and analyze it via
-        // rtic-syntax.
-        let src = syn::parse_str::<TokenStream>(
-            &fs::read_to_string(artifact.target.src_path.as_std_path())
-                .map_err(RecoveryError::SourceRead)?,
-        )
-        .map_err(RecoveryError::TokenizeFail)?;
-        let (app, ast) = Self::parse_rtic_app(src)?;
+        // Resolve the IRQ numbers of bound interrupts from the vector
+        // table of the firmware, if possible, such that the PAC need
+        // not be built. Declared IRQ numbers take precedence.
+        let mut manip = manip.clone();
+        if let Some(elf) = &artifact.executable {
+            if let Ok(handlers) = symbols::interrupt_handlers(elf.as_std_path()) {
+                for (name, irqn) in handlers {
+                    manip.interrupts.entry(name).or_insert(irqn);
+                }
+            }
+        }
+        let manip = &manip;
+
+        let mut src_ctx = SourceContext::from(artifact);
+        src_ctx.trace_helpers = manip.trace_helpers.clone();
+        let app = &rtic_app.app;
+
+        let mut software = SoftwareMap::from(app, rtic_app.ast.clone(), &src_ctx, manip, cargo)?;
+        if let Some(path) = &manip.task_overrides {
+            let overrides: SoftwareTaskOverrides = serde_json::from_str(
+                &fs::read_to_string(path).map_err(RecoveryError::OverridesRead)?,
+            )
+            .map_err(RecoveryError::OverridesParse)?;
+            software.apply_overrides(overrides);
+        }
+
+        // Record the priorities of all hardware and software tasks.
+        let mut priorities = app
+            .hardware_tasks
+            .iter()
+            .map(|(name, hwt)| (name, hwt.args.priority))
+            .chain(
+                app.software_tasks
+                    .iter()
+                    .map(|(name, swt)| (name, swt.args.priority)),
+            )
+            .map(|(name, prio)| (format!("app::{}", name), prio))
+            .collect::<IndexMap<_, _>>();
+
+        // The #[idle] task executes in thread mode, below all tasks.
+        let idle = app.idle.as_ref().map(|idle| format!("app::{}", idle.name));
+        if let Some(idle) = &idle {
+            priorities.insert(idle.clone(), 0);
+        }
+
+        // Read the function symbols of the application. Only needed
+        // to resolve PC samples: warn instead of failing.
+        let profile = cargo.target().and_then(ArchProfile::from_target);
+
+        let symbols = match &artifact.executable {
+            Some(elf) => Symbols::read(elf.as_std_path()).unwrap_or_else(|e| {
+                log::warn(format!(
+                    "cannot read the symbols of {}: {}; PC samples will not be resolved",
+                    elf, e
+                ));
+                Symbols::default()
+            }),
+            None => Symbols::default(),
+        };
 
         Ok(Self {
-            software: SoftwareMap::from(&app, ast, manip, cargo)?,
-            hardware: HardwareMap::from(&app, cargo, manip)?,
+            software,
+            hardware: HardwareMap::from(app, cargo, manip, profile)?,
+            priorities,
+            symbols,
+            idle,
         })
     }
 
+    /// Renames the tasks of which the last path segment is a key of
+    /// `idents` to the respective value.
+    fn rename_tasks(&mut self, idents: &IndexMap<String, String>) {
+        let rename = |path: &mut Vec<String>| {
+            if let Some(ident) = path.last_mut() {
+                if let Some(new) = idents.get(ident) {
+                    *ident = new.clone();
+                }
+            }
+        };
+        let rename_str = |name: &str| {
+            let mut path = name.split("::").map(str::to_string).collect();
+            rename(&mut path);
+            path.join("::")
+        };
+
+        self.hardware.0.values_mut().for_each(rename);
+        self.software.map.values_mut().for_each(rename);
+        for dispatcher in self.software.dispatchers.values_mut() {
+            for task in dispatcher.tasks.iter_mut() {
+                *task = rename_str(task);
+            }
+        }
+        if let Some(overrides) = &mut self.software.overrides {
+            for name in overrides.tasks.values_mut() {
+                *name = rename_str(name);
+            }
+        }
+        self.priorities = std::mem::take(&mut self.priorities)
+            .into_iter()
+            .map(|(name, prio)| (rename_str(&name), prio))
+            .collect();
+    }
+
+    /// The software task dispatcher bound to `veca`, if any.
+    pub fn dispatcher(&self, veca: &VectActive) -> Option<&Dispatcher> {
+        self.software.dispatchers.get(veca)
+    }
+
+    /// Priority of the task with the given full path, if it is an
+    /// RTIC task.
+    pub fn priority(&self, name: &str) -> Option<u8> {
+        self.priorities.get(name).copied()
+    }
+
+    /// Name of the function that encloses `pc`, if known.
+    pub fn symbol(&self, pc: u32) -> Option<&str> {
+        self.symbols.resolve(pc)
+    }
+
+    /// Extracts and parses the RTIC application declared in `src`.
+    /// `include!`d files are read relative to `dir`.
     fn parse_rtic_app(
         src: TokenStream,
+        dir: &Path,
     ) -> Result<(rtic_syntax::P<rtic_syntax::ast::App>, TokenStream), RecoveryError> {
         // iterate over the tokenstream until we find #[app(...)] mod app { ... }
         let mut rtic_app = src.into_iter().skip_while(|token| {
@@ -115,7 +362,7 @@ impl TraceLookupMaps {
             }
             args.ok_or(RecoveryError::RTICArgumentsMissing)?
         };
-        let ast = rtic_app.collect::<TokenStream>();
+        let ast = splice_includes(rtic_app.collect(), dir, &mut vec![])?;
 
         // parse the found tokenstreams
         let (app, _analysis) = {
@@ -144,6 +391,85 @@ impl TraceLookupMaps {
         ))
     }
 
+    /// Reverse lookup of the identifiers over which the task with the
+    /// given full path (e.g. `"app::some_task"`) is traced. All
+    /// matches are returned if the path is ambiguous.
+    pub fn lookup(&self, name: &str) -> Vec<TaskId> {
+        let path = [name];
+
+        self.exception_number(&path)
+            .map(TaskId::Hardware)
+            .into_iter()
+            .chain(self.software_ids(&path).into_iter().map(TaskId::Software))
+            .collect()
+    }
+
+    /// The exception or interrupt, as carried by exception trace
+    /// packets, that the hardware task with the given path is bound to.
+    /// The path is given by segment (e.g. `["app", "some_task"]`) or
+    /// joined by `::`, as in resolved events.
+    pub fn exception_number(&self, path: &[&str]) -> Option<VectActive> {
+        let path = segments(path);
+        self.hardware
+            .0
+            .iter()
+            .find(|(_, p)| **p == path)
+            .map(|(veca, _)| veca.to_owned())
+    }
+
+    /// The IDs over which the software task with the given path is
+    /// traced, in order. A task carries multiple IDs if it is traced
+    /// via a trace helper; see `trace_helpers` in the manifest. The path
+    /// is given as for [`Self::exception_number`].
+    pub fn software_ids(&self, path: &[&str]) -> Vec<usize> {
+        let path = segments(path);
+        self.software
+            .map
+            .iter()
+            .filter(|(_, p)| **p == path)
+            .map(|(id, _)| *id)
+            .collect()
+    }
+
+    /// All tasks by the identifier over which they are traced and
+    /// their full path. Hardware tasks come first.
+    pub fn tasks(&self) -> impl Iterator<Item = (TaskId, String)> + '_ {
+        self.hardware
+            .0
+            .iter()
+            .map(|(veca, path)| (TaskId::Hardware(veca.to_owned()), path.join("::")))
+            .chain(
+                self.software
+                    .map
+                    .iter()
+                    .map(|(id, path)| (TaskId::Software(*id), path.join("::"))),
+            )
+    }
+
+    /// The task maps in a stable form for external tooling.
+    pub fn dump(&self) -> MapsDump {
+        let mut dump = MapsDump {
+            exceptions: IndexMap::new(),
+            interrupts: IndexMap::new(),
+            software: self.software.map.clone(),
+            priorities: self.priorities.clone(),
+        };
+        for (veca, path) in self.hardware.0.iter() {
+            match veca {
+                VectActive::Exception(exception) => {
+                    dump.exceptions
+                        .insert(exception_bind(*exception).to_string(), path.clone());
+                }
+                VectActive::Interrupt { irqn } => {
+                    dump.interrupts.insert(*irqn, path.clone());
+                }
+                VectActive::ThreadMode => (),
+            }
+        }
+
+        dump
+    }
+
     pub fn is_used_comparator(&self, cmp_id: u8) -> bool {
         let cmp_id: usize = cmp_id.into();
         self.software.comparators.get(&cmp_id).is_some()
@@ -155,10 +481,13 @@ impl TraceLookupMaps {
         value: &[u8],
     ) -> Result<Option<EventType>, RecoveryError> {
         if let Some(action) = self.software.comparators.get(&(*comp as usize)) {
-            if value.len() != 1 {
-                return Err(RecoveryError::InvalidSoftwareValue(value.to_owned()));
-            }
-            let value = value[0] as usize;
+            // NOTE(1) the ID is a u8 in traces of applications built
+            // against earlier versions of cortex-m-rtic-trace.
+            let value = match value {
+                [id] => *id as usize,
+                [lo, hi] => u16::from_le_bytes([*lo, *hi]) as usize,
+                _ => return Err(RecoveryError::InvalidSoftwareValue(value.to_owned())),
+            };
 
             let name = self
                 .software
@@ -168,8 +497,10 @@ impl TraceLookupMaps {
                 .join("::");
 
             Ok(Some(EventType::Task {
+                priority: self.priority(&name),
                 name,
                 action: action.to_owned(),
+                depth: 0,
             }))
         } else {
             Ok(None)
@@ -177,30 +508,135 @@ impl TraceLookupMaps {
     }
 }
 
+/// The hardware and software task maps, as dumped via `cargo
+/// rtic-scope dump-maps`. Maps keyed by number are serialized as arrays
+/// of `[key, path]` pairs, such that the keys remain integers.
+#[derive(Serialize, Debug)]
+pub struct MapsDump {
+    /// Hardware tasks by the name of the core exception they are bound
+    /// to, e.g. `SysTick`.
+    pub exceptions: IndexMap<String, Vec<String>>,
+    /// Hardware tasks by the IRQ number of the interrupt they are bound
+    /// to.
+    #[serde(with = "vectorize")]
+    pub interrupts: IndexMap<u16, Vec<String>>,
+    /// Software tasks by ID.
+    #[serde(with = "vectorize")]
+    pub software: IndexMap<usize, Vec<String>>,
+    /// RTIC priority of each task by full path, e.g. `app::foo`.
+    pub priorities: IndexMap<String, u8>,
+}
+
+/// The segments of a task path given by segment, joined by `::`, or a
+/// mix thereof.
+fn segments<'a>(path: &[&'a str]) -> Vec<&'a str> {
+    path.iter()
+        .flat_map(|segment| segment.split("::"))
+        .collect()
+}
+
+/// The identifier over which a task is traced.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TaskId {
+    /// A hardware task bound to the given exception or interrupt.
+    Hardware(VectActive),
+    /// A software task of the given ID, written to the DWT watch
+    /// addresses.
+    Software(usize),
+}
+
+impl std::fmt::Display for TaskId {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            TaskId::Hardware(VectActive::Interrupt { irqn }) => {
+                write!(f, "hardware task bound to IRQ {}", irqn)
+            }
+            TaskId::Hardware(veca) => write!(f, "hardware task bound to {:?}", veca),
+            TaskId::Software(id) => write!(f, "software task of ID {}", id),
+        }
+    }
+}
+
+/// User-supplied software task associations that take precedence over
+/// the recovered associations. An escape hatch for when the recovered
+/// associations are wrong.
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct SoftwareTaskOverrides {
+    /// Whether the recovered associations should be discarded instead
+    /// of merged with.
+    #[serde(default)]
+    pub replace: bool,
+
+    /// Software task ID to full task path, e.g. `"app::some_task"`.
+    pub tasks: IndexMap<usize, String>,
+}
+
+/// Version of the convention by which the `#[trace]` macro assigns
+/// software task IDs, and by which [`SoftwareMap::parse_ast`] mirrors
+/// it. Bump on any change to the assignment order. Traces recorded
+/// before this version was tracked are of version 0, which assigned IDs
+/// as version 1 does.
+///
+/// Recorded traces are always replayed with the associations stored in
+/// the trace file; IDs are never re-derived.
+const TRACE_ID_CONVENTION: u32 = 1;
+
+/// An interrupt from which RTIC dispatches the software tasks of a
+/// priority level.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct Dispatcher {
+    /// Name of the interrupt, e.g. `EXTI0`.
+    pub name: String,
+    /// Priority of the dispatched software tasks.
+    pub priority: u8,
+    /// Full paths of the dispatched software tasks.
+    pub tasks: Vec<String>,
+}
+
+impl Dispatcher {
+    /// The full path under which the dispatcher is presented as a
+    /// task, e.g. `app::dispatcher::EXTI0`.
+    pub fn path(&self) -> String {
+        format!("app::dispatcher::{}", self.name)
+    }
+}
+
 #[derive(Clone, Serialize, Deserialize, Debug)]
 struct SoftwareMap {
+    /// The [`TRACE_ID_CONVENTION`] with which `map` was derived.
+    #[serde(default)]
+    pub id_convention: u32,
     pub task_dispatchers: IndexSet<VectActive>,
+    /// The dispatchers that dispatch software tasks. Not available for
+    /// traces recorded by earlier versions.
+    #[serde(default, with = "vectorize")]
+    pub dispatchers: IndexMap<VectActive, Dispatcher>,
     #[serde(with = "vectorize")]
     pub comparators: IndexMap<usize, TaskAction>,
     #[serde(with = "vectorize")]
     pub map: IndexMap<usize, Vec<String>>,
+    /// Overrides applied over `map`, recorded for reproducibility.
+    #[serde(default)]
+    pub overrides: Option<SoftwareTaskOverrides>,
 }
 impl SoftwareMap {
     pub fn from(
         app: &rtic_syntax::ast::App,
         ast: TokenStream,
+        src: &SourceContext,
         manip: &ManifestProperties,
         cargo: &CargoWrapper,
     ) -> Result<Self, RecoveryError> {
         let actions = [
-            (manip.dwt_enter_id, TaskAction::Entered),
-            (manip.dwt_exit_id, TaskAction::Exited),
+            Some((manip.dwt_enter_id, TaskAction::Entered)),
+            Some((manip.dwt_exit_id, TaskAction::Exited)),
+            manip.dwt_returned_id.map(|id| (id, TaskAction::Completed)),
         ];
-        let map = Self::parse_ast(ast);
+        let map = Self::parse_ast(ast, src)?;
 
         // Extract all dispatcher interrupt idents from #[app(..,
         // dispatchers = [..])] and resolve the associated VectActive.
-        let task_dispatchers: IndexSet<VectActive> = resolve_int_nrs(
+        let resolved = resolve_int_nrs(
             cargo,
             manip,
             app.args
@@ -208,30 +644,104 @@ impl SoftwareMap {
                 .iter()
                 .map(|(ident, _ext_int_attrs)| ident.to_string())
                 .collect(),
-        )?
-        .values()
-        .cloned()
-        .collect();
+        )?;
 
         Ok(Self {
-            task_dispatchers,
-            comparators: IndexMap::from_iter(actions.iter().cloned()),
+            id_convention: TRACE_ID_CONVENTION,
+            task_dispatchers: resolved.values().cloned().collect(),
+            dispatchers: Self::dispatchers(app, &resolved),
+            comparators: actions.into_iter().flatten().collect(),
             map,
+            overrides: None,
         })
     }
 
-    fn parse_ast(app: TokenStream) -> IndexMap<usize, Vec<String>> {
-        struct TaskIDGenerator(usize);
+    /// Associates the dispatchers of `app`, of which the interrupts are
+    /// resolved in `resolved`, with the software tasks they dispatch.
+    /// As by RTIC, dispatchers are assigned in declaration order to the
+    /// priority levels of the software tasks, highest priority first.
+    /// Surplus dispatchers are unused.
+    fn dispatchers(
+        app: &rtic_syntax::ast::App,
+        resolved: &IndexMap<String, VectActive>,
+    ) -> IndexMap<VectActive, Dispatcher> {
+        let priorities: std::collections::BTreeSet<u8> = app
+            .software_tasks
+            .values()
+            .map(|swt| swt.args.priority)
+            .filter(|prio| *prio > 0)
+            .collect();
+
+        app.args
+            .extern_interrupts
+            .keys()
+            .zip(priorities.into_iter().rev())
+            .filter_map(|(ident, priority)| {
+                let name = ident.to_string();
+                let veca = *resolved.get(&name)?;
+                let tasks = app
+                    .software_tasks
+                    .iter()
+                    .filter(|(_, swt)| swt.args.priority == priority)
+                    .map(|(task, _)| format!("app::{}", task))
+                    .collect();
+
+                Some((
+                    veca,
+                    Dispatcher {
+                        name,
+                        priority,
+                        tasks,
+                    },
+                ))
+            })
+            .collect()
+    }
+
+    pub fn apply_overrides(&mut self, overrides: SoftwareTaskOverrides) {
+        if overrides.replace {
+            self.map.clear();
+        }
+        for (id, name) in overrides.tasks.iter() {
+            self.map
+                .insert(*id, name.split("::").map(|s| s.to_string()).collect());
+        }
+        self.overrides = Some(overrides);
+    }
+
+    fn parse_ast(
+        app: TokenStream,
+        src: &SourceContext,
+    ) -> Result<IndexMap<usize, Vec<String>>, RecoveryError> {
+        struct TaskIDGenerator {
+            next: usize,
+            assigned: IndexSet<usize>,
+        }
         impl TaskIDGenerator {
             pub fn new() -> Self {
-                TaskIDGenerator(0)
+                TaskIDGenerator {
+                    next: 0,
+                    assigned: IndexSet::new(),
+                }
             }
 
-            /// Generate a unique task id. Returned values mirror the behavior
-            /// of the `trace`-macro from the tracing module.
+            /// Generate a unique task id, skipping IDs that have been
+            /// claimed. Returned values mirror the behavior of the
+            /// `trace`-macro from the tracing module.
             pub fn generate(&mut self) -> usize {
-                let id = self.0;
-                self.0 += 1;
+                loop {
+                    let id = self.next;
+                    self.next += 1;
+                    if self.assigned.insert(id) {
+                        return id;
+                    }
+                }
+            }
+
+            /// Claim an ID requested via `#[trace(id = N)]`. The macro
+            /// refuses to assign an ID twice.
+            pub fn claim(&mut self, id: usize) -> usize {
+                self.assigned.insert(id);
                 id
             }
         }
@@ -244,9 +754,11 @@ impl SoftwareMap {
         fn traverse_item(
             item: &syn::Item,
             ctx: &mut Vec<syn::Ident>,
+            dir: &Path,
+            src: &SourceContext,
             assocs: &mut IndexMap<usize, Vec<String>>,
             id_gen: &mut TaskIDGenerator,
-        ) {
+        ) -> Result<(), RecoveryError> {
             match item {
                 // handle
                 //
@@ -259,15 +771,81 @@ impl SoftwareMap {
                 //   }
                 //
                 syn::Item::Fn(fun) => {
+                    // skip functions that are not compiled
+                    let attrs = match src.attributes(&fun.attrs) {
+                        Some(attrs) => attrs,
+                        None => return Ok(()),
+                    };
+
                     // record the full path of the function
                     ctx.push(fun.sig.ident.clone());
+                    let generated = id_gen.next;
+
+                    // is the function decorated with #[trace], or with
+                    // helpers that expand to #[trace]? Attributes are
+                    // expanded, and thus assigned IDs, in order.
+                    //
+                    // NOTE this includes the #[init] and #[idle]
+                    // functions, which are named by their path as
+                    // any other task, e.g. `app::init`.
+                    //
+                    // NOTE generic functions are assigned a single ID:
+                    // the trace macro is expanded once per source
+                    // function, so all monomorphizations write the
+                    // same ID and resolve to the same name.
+                    //
+                    // NOTE all #[trace] attributes emitted by a helper
+                    // are attributed to the decorated function.
+                    for attr in attrs.iter() {
+                        let name = match attr.path().segments.last() {
+                            Some(segment) => segment.ident.to_string(),
+                            None => continue,
+                        };
+                        let traces = match src.trace_helpers.get(&name) {
+                            _ if name == "trace" => match requested_id(attr) {
+                                Some(id) => {
+                                    assocs.insert(
+                                        id_gen.claim(id),
+                                        ctx.iter().map(|i| i.to_string()).collect(),
+                                    );
+                                    continue;
+                                }
+                                None => 1,
+                            },
+                            Some(traces) => *traces,
+                            None => {
+                                if name.contains("trace") {
+                                    log::warn(format!(
+                                        "#[{}] on {} may expand to #[trace], which is not seen before expansion: the IDs of all subsequent software tasks are off unless it is declared in `trace_helpers`",
+                                        name,
+                                        ctx.iter().map(|i| i.to_string()).collect::<Vec<_>>().join("::"),
+                                    ));
+                                }
+                                continue;
+                            }
+                        };
+                        for _ in 0..traces {
+                            assocs.insert(
+                                id_gen.generate(),
+                                ctx.iter().map(|i| i.to_string()).collect(),
+                            );
+                        }
+                    }
 
-                    // is the function decorated with #[trace]?
-                    if fun.attrs.iter().any(|a| a.path == syn::parse_quote!(trace)) {
-                        assocs.insert(
-                            id_gen.generate(),
-                            ctx.iter().map(|i| i.to_string()).collect(),
+                    // is the function assigned generated IDs, but
+                    // conditionally compiled on options we cannot
+                    // evaluate? Then the trace macro may not have
+                    // generated them at all.
+                    let uncertain = SourceContext::uncertain(&fun.attrs);
+                    if id_gen.next > generated && !uncertain.is_empty() {
+                        let e = RecoveryError::UncertainConfiguration(
+                            ctx.iter()
+                                .map(|i| i.to_string())
+                                .collect::<Vec<_>>()
+                                .join("::"),
+                            uncertain.join(", "),
                         );
+                        diag::warn(&e);
                     }
 
                     // walk down all other nested functions
@@ -275,7 +853,7 @@ impl SoftwareMap {
                         syn::Stmt::Item(item) => Some(item),
                         _ => None,
                     }) {
-                        traverse_item(item, ctx, assocs, id_gen);
+                        traverse_item(item, ctx, dir, src, assocs, id_gen)?;
                     }
 
                     // we've handled with function, return to upper scope
@@ -283,6 +861,51 @@ impl SoftwareMap {
                 }
                 // handle
                 //
+                //   impl Shared {
+                //       #[trace]
+                //       async fn method(&self) {
+                //           // ...
+                //       }
+                //   }
+                //
+                // of which methods are named by the Self type, e.g.
+                // `app::Shared::method`, and are otherwise handled as
+                // functions.
+                //
+                // NOTE the Self type of an impl for a non-path type,
+                // e.g. `impl Trait for &T`, cannot be named: its
+                // methods are named by the enclosing scope instead.
+                syn::Item::Impl(imp) => {
+                    // skip impls that are not compiled
+                    if src.attributes(&imp.attrs).is_none() {
+                        return Ok(());
+                    }
+
+                    let self_ty = match &*imp.self_ty {
+                        syn::Type::Path(ty) => ty.path.segments.last(),
+                        _ => None,
+                    };
+                    if let Some(segment) = self_ty {
+                        ctx.push(segment.ident.clone());
+                    }
+                    for method in imp.items.iter().filter_map(|item| match item {
+                        syn::ImplItem::Method(method) => Some(method),
+                        _ => None,
+                    }) {
+                        let fun = syn::Item::Fn(syn::ItemFn {
+                            attrs: method.attrs.clone(),
+                            vis: method.vis.clone(),
+                            sig: method.sig.clone(),
+                            block: Box::new(method.block.clone()),
+                        });
+                        traverse_item(&fun, ctx, dir, src, assocs, id_gen)?;
+                    }
+                    if self_ty.is_some() {
+                        ctx.pop();
+                    }
+                }
+                // handle
+                //
                 //   mod scope {
                 //       #[trace]
                 //       fn fun() {
@@ -290,69 +913,586 @@ impl SoftwareMap {
                 //       }
                 //   }
                 //
+                // and out-of-line modules, `mod scope;`, which are
+                // read from disk.
+                //
+                // NOTE visibility of modules and functions is
+                // irrelevant: all are walked in declaration order.
                 syn::Item::Mod(m) => {
+                    // skip modules that are not compiled
+                    let attrs = match src.attributes(&m.attrs) {
+                        Some(attrs) => attrs,
+                        None => return Ok(()),
+                    };
+                    let path = SourceContext::path(&attrs);
+                    let name = m.ident.to_string();
+
                     ctx.push(m.ident.clone());
                     if let Some((_, items)) = &m.content {
+                        let dir = dir.join(path.unwrap_or(name));
                         for item in items {
-                            traverse_item(item, ctx, assocs, id_gen);
+                            traverse_item(item, ctx, &dir, src, assocs, id_gen)?;
+                        }
+                    } else {
+                        let (file, dir) = match path {
+                            Some(path) => {
+                                let file = dir.join(path);
+                                let dir = file.parent().unwrap_or(dir).to_owned();
+                                (file, dir)
+                            }
+                            None => {
+                                let file = dir.join(format!("{}.rs", name));
+                                let dir = dir.join(&name);
+                                if file.exists() {
+                                    (file, dir)
+                                } else {
+                                    (dir.join("mod.rs"), dir)
+                                }
+                            }
+                        };
+                        let content = fs::read_to_string(&file)
+                            .map_err(|e| RecoveryError::ModuleRead(file.clone(), e))?;
+                        let items = syn::parse_file(&content)
+                            .map_err(|e| RecoveryError::ModuleParse(file.clone(), e))?
+                            .items;
+                        for item in items.iter() {
+                            traverse_item(item, ctx, &dir, src, assocs, id_gen)?;
                         }
                     }
                     ctx.pop();
                 }
+                // warn about
+                //
+                //   macro_rules! task {
+                //       ($name:ident) => {
+                //           #[trace]
+                //           fn $name() {}
+                //       };
+                //   }
+                //
+                // of which invocations are not expanded.
+                syn::Item::Macro(m) if m.mac.path.is_ident("macro_rules") => {
+                    let emits_trace = m
+                        .mac
+                        .tokens
+                        .to_string()
+                        .replace(' ', "")
+                        .contains("#[trace]");
+                    if let (true, Some(ident)) = (emits_trace, &m.ident) {
+                        log::warn(format!(
+                            "macro {}! expands to #[trace], which is not seen before expansion: the IDs of all software tasks traced after its first invocation are off. Declare the tasks via --task-overrides instead",
+                            ident
+                        ));
+                    }
+                }
+                _ => (),
+            }
+
+            Ok(())
+        }
+
+        traverse_item(&app, &mut ctx, &src.dir, src, &mut assocs, &mut id_gen)?;
+
+        Ok(assocs)
+    }
+}
+
+/// The software task ID requested via `#[trace(id = N)]`, if any.
+fn requested_id(attr: &syn::Meta) -> Option<usize> {
+    match attr {
+        syn::Meta::List(list) => list.nested.iter().find_map(|nested| match nested {
+            syn::NestedMeta::Meta(syn::Meta::NameValue(nv)) if nv.path.is_ident("id") => {
+                match &nv.lit {
+                    syn::Lit::Int(id) => id.base10_parse().ok(),
+                    _ => None,
+                }
+            }
+            _ => None,
+        }),
+        _ => None,
+    }
+}
+
+/// Reads and tokenizes the source file of `artifact`.
+fn read_source(artifact: &Artifact) -> Result<TokenStream, RecoveryError> {
+    read_source_file(artifact.target.src_path.as_std_path())
+}
+
+fn read_source_file(src: &Path) -> Result<TokenStream, RecoveryError> {
+    syn::parse_str::<TokenStream>(&fs::read_to_string(src).map_err(RecoveryError::SourceRead)?)
+        .map_err(RecoveryError::TokenizeFail)
+}
+
+/// Checks whether the source file of `artifact` was modified after its
+/// executable was built, in which case the metadata recovered from the
+/// source may not match the firmware.
+pub fn check_staleness(artifact: &Artifact) -> Option<RecoveryError> {
+    stale_source(
+        artifact.target.src_path.as_std_path(),
+        artifact.executable.as_ref()?.as_std_path(),
+    )
+}
+
+fn stale_source(src: &Path, executable: &Path) -> Option<RecoveryError> {
+    let modified = |path: &Path| fs::metadata(path).and_then(|m| m.modified()).ok();
+    if modified(src)? > modified(executable)? {
+        Some(RecoveryError::StaleArtifact(
+            src.to_path_buf(),
+            executable.to_path_buf(),
+        ))
+    } else {
+        None
+    }
+}
+
+/// A hash of the source tokens of the application declared in `src`
+/// (see [`app_source`]), by which the source a trace was recorded with
+/// is recognized. Comments and formatting do not affect the hash. The
+/// application need not be built.
+pub fn source_hash(src: &Path) -> Result<String, RecoveryError> {
+    Ok(format!(
+        "{:016x}",
+        fnv1a(source_tokens(src)?.to_string().as_bytes())
+    ))
+}
+
+/// The 64-bit FNV-1a hash of `bytes`, which, unlike the hashers of
+/// `std`, is stable across Rust versions.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// The tokens of the source file of `artifact` with all `include!`d
+/// files spliced in, e.g. to reproduce the parsing of the application
+/// elsewhere.
+pub fn app_source(artifact: &Artifact) -> Result<TokenStream, RecoveryError> {
+    source_tokens(artifact.target.src_path.as_std_path())
+}
+
+/// The tokens of the source file `src` with all `include!`d files
+/// spliced in.
+fn source_tokens(src: &Path) -> Result<TokenStream, RecoveryError> {
+    splice_includes(
+        read_source_file(src)?,
+        src.parent().unwrap_or_else(|| Path::new("")),
+        &mut vec![],
+    )
+}
+
+/// The RTIC application of an artifact, parsed from its source code
+/// and analyzed via rtic-syntax once per command.
+pub struct RticApp {
+    app: rtic_syntax::P<rtic_syntax::ast::App>,
+    ast: TokenStream,
+}
+
+impl RticApp {
+    pub fn parse(artifact: &Artifact) -> Result<Self, RecoveryError> {
+        let (app, ast) = TraceLookupMaps::parse_rtic_app(
+            read_source(artifact)?,
+            &SourceContext::from(artifact).dir,
+        )?;
+        Ok(Self { app, ast })
+    }
+
+    /// The path of the device of the application, as given by
+    /// `#[app(device = ...)]`, e.g. `stm32f4::stm32f401`.
+    pub fn device(&self) -> Option<String> {
+        self.app
+            .args
+            .device
+            .as_ref()
+            .map(|device| device.to_token_stream().to_string().replace(' ', ""))
+    }
+}
+
+/// Splices the content of the files referenced by `include!("...")`
+/// invocations in `tokens` in place of the invocations, such that the
+/// tasks declared therein are part of the application in declaration
+/// order. Included files may include other files. Paths are relative
+/// to `dir`, the directory of the including file; `stack` holds the
+/// files being included.
+fn splice_includes(
+    tokens: TokenStream,
+    dir: &Path,
+    stack: &mut Vec<PathBuf>,
+) -> Result<TokenStream, RecoveryError> {
+    let mut spliced = TokenStream::new();
+    let mut tokens = tokens.into_iter().peekable();
+    while let Some(token) = tokens.next() {
+        match token {
+            TokenTree::Ident(ref ident) if ident == "include" => {
+                // Only include!s with a literal path are spliced.
+                let mut invocation = tokens.clone();
+                let path = match (invocation.next(), invocation.next()) {
+                    (Some(TokenTree::Punct(bang)), Some(TokenTree::Group(args)))
+                        if bang.as_char() == '!' =>
+                    {
+                        syn::parse2::<syn::LitStr>(args.stream()).ok()
+                    }
+                    _ => None,
+                };
+                let path = match path {
+                    Some(path) => dir.join(path.value()),
+                    None => {
+                        spliced.extend([token]);
+                        continue;
+                    }
+                };
+                tokens = invocation;
+                if matches!(tokens.peek(), Some(TokenTree::Punct(p)) if p.as_char() == ';') {
+                    tokens.next();
+                }
+
+                let canonical = fs::canonicalize(&path)
+                    .map_err(|e| RecoveryError::IncludeRead(path.clone(), e))?;
+                if stack.contains(&canonical) {
+                    return Err(RecoveryError::IncludeCycle(path));
+                }
+                let included = syn::parse_str::<TokenStream>(
+                    &fs::read_to_string(&path)
+                        .map_err(|e| RecoveryError::IncludeRead(path.clone(), e))?,
+                )
+                .map_err(|e| RecoveryError::IncludeParse(path.clone(), e))?;
+
+                stack.push(canonical);
+                spliced.extend(splice_includes(
+                    included,
+                    path.parent().unwrap_or(dir),
+                    stack,
+                )?);
+                stack.pop();
+            }
+            TokenTree::Group(group) => {
+                let mut g = proc_macro2::Group::new(
+                    group.delimiter(),
+                    splice_includes(group.stream(), dir, stack)?,
+                );
+                g.set_span(group.span());
+                spliced.extend([TokenTree::Group(g)]);
+            }
+            token => spliced.extend([token]),
+        }
+    }
+
+    Ok(spliced)
+}
+
+/// The context in which the source of the RTIC application is
+/// traversed for `#[trace]`d functions.
+#[derive(Default)]
+struct SourceContext {
+    /// Features the application was built with, against which `#[cfg]`
+    /// and `#[cfg_attr]` are evaluated.
+    features: Vec<String>,
+    /// Whether the application was built with debug assertions, against
+    /// which `#[cfg(debug_assertions)]` is evaluated.
+    debug_assertions: bool,
+    /// Directory of the source file the application is declared in.
+    /// Out-of-line modules are read relative to it.
+    dir: PathBuf,
+    /// Attribute macros that expand to functions carrying `#[trace]`,
+    /// and the number of `#[trace]` attributes they emit.
+    trace_helpers: IndexMap<String, usize>,
+}
+
+impl SourceContext {
+    pub fn from(artifact: &Artifact) -> Self {
+        Self {
+            features: artifact.features.clone(),
+            debug_assertions: artifact.profile.debug_assertions,
+            dir: artifact
+                .target
+                .src_path
+                .parent()
+                .map(|dir| dir.as_std_path().to_owned())
+                .unwrap_or_default(),
+            trace_helpers: IndexMap::new(),
+        }
+    }
+
+    /// The attributes of an item after `#[cfg_attr]` expansion, or
+    /// `None` if the item is configured out via `#[cfg]`. Attributes
+    /// that are not meta items are represented by their path.
+    pub fn attributes(&self, attrs: &[syn::Attribute]) -> Option<Vec<syn::Meta>> {
+        let mut metas = vec![];
+        for attr in attrs {
+            let meta = attr
+                .parse_meta()
+                .unwrap_or_else(|_| syn::Meta::Path(attr.path.clone()));
+            if !self.expand(meta, &mut metas) {
+                return None;
+            }
+        }
+
+        Some(metas)
+    }
+
+    /// Expands `meta` into `metas`. Returns `false` if `meta` is a
+    /// `#[cfg]` that does not hold.
+    fn expand(&self, meta: syn::Meta, metas: &mut Vec<syn::Meta>) -> bool {
+        match meta {
+            syn::Meta::List(list) if list.path.is_ident("cfg") => {
+                list.nested.first().map_or(true, |p| self.predicate(p))
+            }
+            syn::Meta::List(list) if list.path.is_ident("cfg_attr") => {
+                let mut nested = list.nested.into_iter();
+                match nested.next() {
+                    Some(p) if self.predicate(&p) => nested.all(|attr| match attr {
+                        syn::NestedMeta::Meta(meta) => self.expand(meta, metas),
+                        syn::NestedMeta::Lit(_) => true,
+                    }),
+                    _ => true,
+                }
+            }
+            meta => {
+                metas.push(meta);
+                true
+            }
+        }
+    }
+
+    /// The configuration options of the `#[cfg]` and `#[cfg_attr]`
+    /// predicates in `attrs` that cannot be evaluated, and are thus
+    /// assumed to hold.
+    pub fn uncertain(attrs: &[syn::Attribute]) -> Vec<String> {
+        fn options(pred: &syn::NestedMeta, unknown: &mut Vec<String>) {
+            use syn::{Meta, NestedMeta};
+
+            match pred {
+                NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("feature") => (),
+                NestedMeta::Meta(Meta::List(list))
+                    if ["all", "any", "not"]
+                        .iter()
+                        .any(|op| list.path.is_ident(op)) =>
+                {
+                    list.nested.iter().for_each(|p| options(p, unknown))
+                }
+                NestedMeta::Meta(Meta::Path(path))
+                    if path.is_ident("test") || path.is_ident("debug_assertions") => {}
+                NestedMeta::Meta(meta) => {
+                    unknown.push(meta.to_token_stream().to_string().replace(' ', ""))
+                }
+                NestedMeta::Lit(_) => (),
+            }
+        }
+
+        let mut unknown = vec![];
+        for attr in attrs {
+            match attr.parse_meta() {
+                Ok(syn::Meta::List(list))
+                    if list.path.is_ident("cfg") || list.path.is_ident("cfg_attr") =>
+                {
+                    if let Some(pred) = list.nested.first() {
+                        options(pred, &mut unknown);
+                    }
+                }
                 _ => (),
             }
         }
 
-        traverse_item(&app, &mut ctx, &mut assocs, &mut id_gen);
+        unknown
+    }
+
+    /// Evaluates a configuration predicate. Only `feature` and
+    /// `debug_assertions` options are known: `test` is considered
+    /// unset, and all other options set.
+    fn predicate(&self, pred: &syn::NestedMeta) -> bool {
+        use syn::{Lit, Meta, NestedMeta};
 
-        assocs
+        match pred {
+            NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("feature") => match &nv.lit {
+                Lit::Str(feature) => self.features.contains(&feature.value()),
+                _ => false,
+            },
+            NestedMeta::Meta(Meta::List(list)) if list.path.is_ident("all") => {
+                list.nested.iter().all(|p| self.predicate(p))
+            }
+            NestedMeta::Meta(Meta::List(list)) if list.path.is_ident("any") => {
+                list.nested.iter().any(|p| self.predicate(p))
+            }
+            NestedMeta::Meta(Meta::List(list)) if list.path.is_ident("not") => {
+                !list.nested.first().map_or(true, |p| self.predicate(p))
+            }
+            NestedMeta::Meta(Meta::Path(path)) if path.is_ident("debug_assertions") => {
+                self.debug_assertions
+            }
+            NestedMeta::Meta(Meta::Path(path)) => !path.is_ident("test"),
+            _ => true,
+        }
+    }
+
+    /// The `#[path = "..."]` of a module, if any.
+    pub fn path(metas: &[syn::Meta]) -> Option<String> {
+        metas.iter().find_map(|meta| match meta {
+            syn::Meta::NameValue(nv) if nv.path.is_ident("path") => match &nv.lit {
+                syn::Lit::Str(path) => Some(path.value()),
+                _ => None,
+            },
+            _ => None,
+        })
     }
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
 struct HardwareMap(#[serde(with = "vectorize")] IndexMap<VectActive, Vec<String>>);
-impl HardwareMap {
-    pub fn from(
-        app: &rtic_syntax::ast::App,
-        cargo: &CargoWrapper,
-        manip: &ManifestProperties,
-    ) -> Result<Self, RecoveryError> {
-        use cortex_m::peripheral::scb::Exception;
-        macro_rules! resolve_core_interrupts {
-            ($($excpt:ident),+) => {{
-                [$({
-                    let exception = Exception::$excpt;
-                    (format!("{:?}", exception), exception)
-                },)+]
-            }}
-        }
-        // Exceptions common to all ARMv7-M targets. Known as /processor
-        // core exceptions/ or /internal interrupts/ These exceptions
-        // will be received over ITM as-is, and no additional
-        // information need to be recovered to use them. These labels
-        // are the same ones one can bind hardware tasks to, e.g.
-        //
-        //    #[task(binds = SysTick)]
-        //    fn task(_: task::Context) {}
-        //
-        // This list is sourced from the ARMv7-M arch. reference manual,
-        // table B1-4.
-        let internal_ints: IndexMap<String, Exception> = IndexMap::from_iter(
-            resolve_core_interrupts!(
+/// (interrupt name, task name) associations.
+type TaskBindMaps = IndexMap<String, String>;
+
+/// The architecture profile of a Cortex-M target, which determines the
+/// set of core exceptions.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ArchProfile {
+    /// ARMv6-M, e.g. Cortex-M0(+).
+    V6M,
+    /// ARMv7-M, e.g. Cortex-M3/M4/M7.
+    V7M,
+    /// ARMv8-M Baseline, e.g. Cortex-M23.
+    V8MBaseline,
+    /// ARMv8-M Mainline, e.g. Cortex-M33.
+    V8MMainline,
+}
+
+impl ArchProfile {
+    /// The profile of the given target triple, if it is a Cortex-M
+    /// target.
+    pub fn from_target(triple: &str) -> Option<Self> {
+        let arch = triple.split('-').next()?;
+        match arch {
+            "thumbv6m" => Some(Self::V6M),
+            "thumbv7m" | "thumbv7em" => Some(Self::V7M),
+            "thumbv8m.base" => Some(Self::V8MBaseline),
+            "thumbv8m.main" => Some(Self::V8MMainline),
+            _ => None,
+        }
+    }
+
+    /// The core exceptions of the profile. Sourced from the ARMv6-M
+    /// (table B1-4), ARMv7-M (table B1-4), and ARMv8-M (table B3-4)
+    /// arch. reference manuals.
+    fn exceptions(&self) -> &'static [cortex_m::peripheral::scb::Exception] {
+        use cortex_m::peripheral::scb::Exception::*;
+
+        match self {
+            Self::V6M | Self::V8MBaseline => &[NonMaskableInt, HardFault, SVCall, PendSV, SysTick],
+            Self::V7M => &[
                 NonMaskableInt,
                 HardFault,
                 MemoryManagement,
                 BusFault,
                 UsageFault,
-                SecureFault,
                 SVCall,
                 DebugMonitor,
                 PendSV,
-                SysTick
+                SysTick,
+            ],
+            Self::V8MMainline => &[
+                NonMaskableInt,
+                HardFault,
+                MemoryManagement,
+                BusFault,
+                UsageFault,
+                SecureFault,
+                SVCall,
+                DebugMonitor,
+                PendSV,
+                SysTick,
+            ],
+        }
+    }
+}
+
+impl HardwareMap {
+    pub fn from(
+        app: &rtic_syntax::ast::App,
+        cargo: &CargoWrapper,
+        manip: &ManifestProperties,
+        profile: Option<ArchProfile>,
+    ) -> Result<Self, RecoveryError> {
+        let (mut known_maps, unknown_maps) =
+            Self::partition_binds(app, &manip.internal_exceptions, profile)?;
+
+        if unknown_maps.is_empty() {
+            return Ok(Self(known_maps));
+        }
+
+        // Resolve unknown maps by help of a cdylib; extend the known
+        // map collection.
+        let resolved_maps: IndexMap<VectActive, Vec<String>> = resolve_int_nrs(
+            cargo,
+            manip,
+            unknown_maps.iter().map(|(k, _v)| k.to_owned()).collect(),
+        )?
+        .iter()
+        .map(|(bind, irqn)| {
+            (
+                irqn.to_owned(),
+                vec![
+                    "app".to_string(),
+                    unknown_maps.get(bind).unwrap().to_owned(),
+                ],
             )
+        })
+        .collect();
+        known_maps.extend(resolved_maps);
+
+        Ok(Self(known_maps))
+    }
+
+    /// Exceptions common to all targets of the architecture `profile`,
+    /// by the name hardware tasks are bound to them, e.g.
+    ///
+    ///    #[task(binds = SysTick)]
+    ///    fn task(_: task::Context) {}
+    ///
+    /// Known as /processor core exceptions/ or /internal interrupts/.
+    /// These exceptions will be received over ITM as-is, and no
+    /// additional information need to be recovered to use them. If the
+    /// profile is unknown, the exceptions of all profiles are known.
+    fn core_exceptions(profile: Option<ArchProfile>) -> IndexMap<String, VectActive> {
+        profile
+            .unwrap_or(ArchProfile::V8MMainline)
+            .exceptions()
             .iter()
-            .cloned(),
-        );
+            .copied()
+            .map(|exception| {
+                (
+                    exception_bind(exception).to_string(),
+                    VectActive::Exception(exception),
+                )
+            })
+            .collect()
+    }
+
+    /// Partitions the hardware tasks of `app` into those bound to
+    /// internal exceptions, which are resolved, and those bound to
+    /// external interrupts, which must be resolved via the PAC.
+    /// `user_internal` are internal exceptions (name to exception
+    /// number) in addition to those of the architecture `profile`,
+    /// e.g. vendor-specific fixed vectors.
+    fn partition_binds(
+        app: &rtic_syntax::ast::App,
+        user_internal: &IndexMap<String, u16>,
+        profile: Option<ArchProfile>,
+    ) -> Result<(IndexMap<VectActive, Vec<String>>, TaskBindMaps), RecoveryError> {
+        let mut internal_ints = Self::core_exceptions(profile);
+
+        // Extend with the user-declared internal exceptions. The
+        // architecture-defined exceptions are all known above, so
+        // these must be enumerated after them.
+        for (name, number) in user_internal.iter() {
+            internal_ints.insert(
+                name.to_owned(),
+                Some(*number)
+                    .filter(|n| *n >= 16)
+                    .and_then(VectActive::from)
+                    .ok_or_else(|| {
+                        RecoveryError::InvalidInternalException(name.to_owned(), *number)
+                    })?,
+            );
+        }
 
         // Find all bound exceptions from the #[task(bound = ...)]
         // arguments in the now-parsed source file. Partition internal
@@ -360,59 +1500,362 @@ impl HardwareMap {
         // required for the external interrupts: over ITM we'll receive
         // the IRQ number which we need to associate to a label (found
         // in PAC::Interrupt).
-        type TaskBindMaps = IndexMap<String, String>;
-        let (known_maps, unknown_maps): (TaskBindMaps, TaskBindMaps) = app
+        let binds: Vec<(String, String)> = app
             .hardware_tasks
             .iter()
             // Find (interrupt name, task name) associations.
             .map(|(task_name, hwt)| (hwt.args.binds.to_string(), task_name.to_string()))
+            .collect();
+        Self::check_binds(&binds)?;
+        let (known_maps, unknown_maps): (TaskBindMaps, TaskBindMaps) = binds
+            .into_iter()
             // Separate core interrupts from device-specific interrupts
             .partition(|(bind, _)| internal_ints.contains_key(bind));
-        let mut known_maps = known_maps
+        let known_maps = known_maps
             .iter()
             .map(|(bind, task_name)| {
                 (
-                    VectActive::Exception(*internal_ints.get(bind).unwrap()),
+                    internal_ints.get(bind).unwrap().to_owned(),
                     vec!["app".to_string(), task_name.to_owned()],
                 )
             })
             .collect();
 
-        if unknown_maps.is_empty() {
-            return Ok(Self(known_maps));
+        Ok((known_maps, unknown_maps))
+    }
+
+    /// Ensures that no interrupt is bound by more than one task, given
+    /// (interrupt name, task name) associations. Otherwise, only the
+    /// last of the tasks would be mapped.
+    fn check_binds(binds: &[(String, String)]) -> Result<(), RecoveryError> {
+        let mut seen: IndexMap<&str, &str> = IndexMap::new();
+        for (bind, task_name) in binds {
+            if let Some(first) = seen.insert(bind, task_name) {
+                return Err(RecoveryError::DuplicateBind(
+                    bind.to_owned(),
+                    first.to_owned(),
+                    task_name.to_owned(),
+                ));
+            }
         }
 
-        // Resolve unknown maps by help of a cdylib; extend the known
-        // map collection.
-        let resolved_maps: IndexMap<VectActive, Vec<String>> = resolve_int_nrs(
-            cargo,
-            manip,
-            unknown_maps.iter().map(|(k, _v)| k.to_owned()).collect(),
-        )?
+        Ok(())
+    }
+}
+
+/// The [`TaskAction`] of a task bound to an exception that performed
+/// `action`.
+fn task_action(action: &ExceptionAction) -> TaskAction {
+    match action {
+        ExceptionAction::Entered => TaskAction::Entered,
+        ExceptionAction::Exited => TaskAction::Exited,
+        ExceptionAction::Returned => TaskAction::Returned,
+    }
+}
+
+/// The name `exception` is bound to by hardware tasks, as spelled by
+/// `cortex-m-rt` and RTIC. Spelled out rather than derived from the
+/// `Debug` representation of the exception, which need not match.
+fn exception_bind(exception: cortex_m::peripheral::scb::Exception) -> &'static str {
+    use cortex_m::peripheral::scb::Exception;
+
+    match exception {
+        Exception::NonMaskableInt => "NonMaskableInt",
+        Exception::HardFault => "HardFault",
+        Exception::MemoryManagement => "MemoryManagement",
+        Exception::BusFault => "BusFault",
+        Exception::UsageFault => "UsageFault",
+        Exception::SecureFault => "SecureFault",
+        Exception::SVCall => "SVCall",
+        Exception::DebugMonitor => "DebugMonitor",
+        Exception::PendSV => "PendSV",
+        Exception::SysTick => "SysTick",
+    }
+}
+
+/// Resolves the given external interrupt `binds` to their
+/// [`VectActive`]. Interrupts in the user-supplied table
+/// (`interrupts` in the manifest) are resolved directly; the remaining
+/// interrupts are resolved via the PAC.
+fn resolve_int_nrs(
+    cargo: &CargoWrapper,
+    pacp: &ManifestProperties,
+    binds: Vec<String>,
+) -> Result<IndexMap<String, VectActive>, RecoveryError> {
+    let (mut resolved, binds) = resolve_user_int_nrs(&pacp.interrupts, binds)?;
+    let (svd_resolved, binds) = match &pacp.svd {
+        Some(svd) if !binds.is_empty() => resolve_svd_int_nrs(svd, binds)?,
+        _ => (IndexMap::new(), binds),
+    };
+    resolved.extend(svd_resolved);
+    if !binds.is_empty() {
+        resolved.extend(resolve_pac_int_nrs(cargo, pacp, binds)?);
+    }
+
+    Ok(resolved)
+}
+
+/// Resolves the given external interrupt `binds` via the `table` of
+/// interrupt name to IRQ number, e.g. as supplied by the user. Returns
+/// the resolved binds and the binds not found in the table.
+fn resolve_user_int_nrs(
+    table: &IndexMap<String, u16>,
+    binds: Vec<String>,
+) -> Result<(IndexMap<String, VectActive>, Vec<String>), RecoveryError> {
+    let (known, unknown): (Vec<String>, Vec<String>) =
+        binds.into_iter().partition(|b| table.contains_key(b));
+
+    let known = known
+        .into_iter()
+        .map(|bind| {
+            let irqn = *table.get(&bind).unwrap();
+
+            // Convert the IRQn to a VectActive.
+            //
+            // The offset denotes at what offset from the start of the
+            // interrupt vector external (device-specific) interrupts
+            // are enumerated. cortex_m::interrupt::InterruptNumber
+            // enumerates starting at this offset so we must
+            // compensate. See also B1.5.2 in the ARMv7-M Architecture
+            // Reference Manual.
+            const DEVICE_INTERRUPTS_OFFSET: u16 = 16;
+            let veca = irqn
+                .checked_add(DEVICE_INTERRUPTS_OFFSET)
+                .and_then(VectActive::from)
+                .ok_or_else(|| RecoveryError::InvalidInterruptNumber(bind.clone(), irqn))?;
+            Ok((bind, veca))
+        })
+        .collect::<Result<_, RecoveryError>>()?;
+
+    Ok((known, unknown))
+}
+
+/// Resolves the given external interrupt `binds` via the `<interrupt>`
+/// entries of the SVD file at `path`, from which the PAC is generated.
+/// Returns the resolved binds and the binds not found in the file.
+fn resolve_svd_int_nrs(
+    path: &Path,
+    binds: Vec<String>,
+) -> Result<(IndexMap<String, VectActive>, Vec<String>), RecoveryError> {
+    let xml = fs::read_to_string(path).map_err(|e| RecoveryError::SvdRead(path.to_owned(), e))?;
+    let device = svd_parser::parse(&xml)
+        .map_err(|e| RecoveryError::SvdParse(path.to_owned(), format!("{:#}", e)))?;
+
+    resolve_user_int_nrs(&svd_interrupts(&device), binds)
+}
+
+/// The IRQ numbers of the interrupts of all peripherals of `device`
+/// by name. An interrupt may be listed by multiple peripherals.
+fn svd_interrupts(device: &svd_parser::svd::Device) -> IndexMap<String, u16> {
+    device
+        .peripherals
         .iter()
-        .map(|(bind, irqn)| {
+        .flat_map(|p| p.interrupt.iter())
+        // NOTE(MAX) out-of-range numbers are refused on resolution
+        .map(|int| {
             (
-                irqn.to_owned(),
-                vec![
-                    "app".to_string(),
-                    unknown_maps.get(bind).unwrap().to_owned(),
-                ],
+                int.name.clone(),
+                u16::try_from(int.value).unwrap_or(u16::MAX),
             )
         })
-        .collect();
-        known_maps.extend(resolved_maps);
+        .collect()
+}
 
-        Ok(Self(known_maps))
+/// The method by which the PAC's interrupt enum exposes IRQ numbers.
+/// This differs between svd2rust versions.
+#[derive(Debug, PartialEq)]
+enum InterruptMethod {
+    /// `cortex_m::interrupt::InterruptNumber::number`, since cortex-m
+    /// v0.7.
+    Number,
+    /// `bare_metal::Nr::nr`, before cortex-m v0.7.
+    Nr,
+}
+
+impl InterruptMethod {
+    /// Detects the method from the PAC source in which the interrupt
+    /// enum is declared.
+    fn detect(src: &str) -> Option<Self> {
+        if src.contains("InterruptNumber for Interrupt") {
+            Some(Self::Number)
+        } else if src.contains("Nr for Interrupt") {
+            Some(Self::Nr)
+        } else {
+            None
+        }
+    }
+
+    /// Detects the method of the PAC in `pacp` by inspecting its
+    /// source. If the PAC is not a dependency of the traced
+    /// application its source cannot be found and `number()` is
+    /// assumed.
+    fn of(cargo: &CargoWrapper, pacp: &ManifestProperties) -> Result<Self, RecoveryError> {
+        let lib = match pac_package(cargo, pacp)
+            .into_iter()
+            .flat_map(|p| p.targets.iter())
+            .find(|t| t.kind.iter().any(|k| k == "lib"))
+        {
+            Some(target) => target.src_path.clone().into_std_path_buf(),
+            None => return Ok(Self::Number),
+        };
+
+        // The enum is declared in the module of the interrupt path,
+        // e.g. src/stm32f401/mod.rs for stm32f4::stm32f401::Interrupt.
+        let src_dir = lib.parent().unwrap();
+        let modules: Vec<&str> = pacp
+            .interrupt_path
+            .split("::")
+            .map(str::trim)
+            .skip(1)
+            .collect();
+        let mut candidates = vec![];
+        for depth in (1..modules.len()).rev() {
+            let module = modules[..depth].iter().collect::<PathBuf>();
+            candidates.push(src_dir.join(&module).join("mod.rs"));
+            candidates.push(src_dir.join(&module).with_extension("rs"));
+        }
+        candidates.push(lib.clone());
+
+        for candidate in candidates.iter().filter(|c| c.exists()) {
+            let src = fs::read_to_string(candidate)
+                .map_err(|e| RecoveryError::PACSourceRead(candidate.clone(), e))?;
+            if let Some(method) = Self::detect(&src) {
+                return Ok(method);
+            }
+        }
+
+        Err(RecoveryError::UnknownInterruptMethod(
+            pacp.interrupt_path.clone(),
+        ))
+    }
+
+    /// The dependency and import required by the method, if not
+    /// already part of the adhoc crate.
+    fn dependency(&self) -> Option<(&'static str, TokenStream)> {
+        match self {
+            Self::Number => None,
+            Self::Nr => Some((
+                "bare-metal = \"0.2\"",
+                quote!(
+                    use bare_metal::Nr;
+                ),
+            )),
+        }
+    }
+
+    /// An expression that evaluates to the IRQ number of `int_ident` as
+    /// a `u16`.
+    fn call(&self, int_ident: &proc_macro2::Ident) -> TokenStream {
+        match self {
+            Self::Number => quote!(Interrupt::#int_ident.number()),
+            Self::Nr => quote!(Interrupt::#int_ident.nr() as u16),
+        }
     }
 }
 
-fn resolve_int_nrs(
+/// The PAC in `pacp` as resolved for the traced application, if it is
+/// a dependency of it.
+fn pac_package<'a>(
+    cargo: &'a CargoWrapper,
+    pacp: &ManifestProperties,
+) -> Option<&'a cargo_metadata::Package> {
+    let pac_name = pacp.pac_name.replace('-', "_");
+    cargo
+        .metadata()
+        .packages
+        .iter()
+        .find(|p| p.name.replace('-', "_") == pac_name)
+}
+
+/// IRQ numbers resolved via a PAC, cached in the target directory such
+/// that the intermediate crate is only built for interrupts that have
+/// not been resolved before. Discarded when the PAC changes.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+struct AdhocCache {
+    /// The package ID of the PAC as resolved by cargo, which includes
+    /// its exact version and source, or its name and version
+    /// requirement if it is not a dependency of the application.
+    pac_id: String,
+    pac_features: Vec<String>,
+    interrupt_path: String,
+    /// IRQ number by interrupt name.
+    irqns: IndexMap<String, u16>,
+}
+
+impl AdhocCache {
+    /// Reads the cache at `path` if it is of the PAC `pac_id`, as
+    /// configured in `pacp`. Otherwise, or if it cannot be read, an
+    /// empty cache is returned.
+    fn read(path: &Path, pac_id: String, pacp: &ManifestProperties) -> Self {
+        let empty = Self {
+            pac_id,
+            pac_features: pacp.pac_features.clone(),
+            interrupt_path: pacp.interrupt_path.clone(),
+            irqns: IndexMap::new(),
+        };
+
+        fs::read_to_string(path)
+            .ok()
+            .and_then(|json| serde_json::from_str::<Self>(&json).ok())
+            .filter(|cache| {
+                (&cache.pac_id, &cache.pac_features, &cache.interrupt_path)
+                    == (&empty.pac_id, &empty.pac_features, &empty.interrupt_path)
+            })
+            .unwrap_or(empty)
+    }
+
+    fn write(&self, path: &Path) -> std::io::Result<()> {
+        fs::write(path, serde_json::to_string(self)?)
+    }
+}
+
+/// Resolves the given external interrupt `binds` via the PAC. Binds
+/// not found in the cache in the target directory are resolved by
+/// [`build_pac_int_nrs`] and added to it.
+fn resolve_pac_int_nrs(
     cargo: &CargoWrapper,
     pacp: &ManifestProperties,
     binds: Vec<String>,
 ) -> Result<IndexMap<String, VectActive>, RecoveryError> {
+    let path = cargo.target_dir().join("cargo-rtic-trace-libadhoc.json");
+    let pac_id = match pac_package(cargo, pacp) {
+        Some(package) => package.id.repr.clone(),
+        None => format!("{} {}", pacp.pac_name, pacp.pac_version),
+    };
+    let mut cache = AdhocCache::read(&path, pac_id, pacp);
+
+    let missing: Vec<String> = binds
+        .iter()
+        .filter(|bind| !cache.irqns.contains_key(*bind))
+        .cloned()
+        .collect();
+    if !missing.is_empty() {
+        cache.irqns.extend(build_pac_int_nrs(cargo, pacp, missing)?);
+        if let Err(e) = cache.write(&path) {
+            log::warn(format!(
+                "cannot cache the resolved interrupts in {}: {}",
+                path.display(),
+                e
+            ));
+        }
+    }
+
+    // NOTE(unknown) all binds are in the cache at this point
+    let (resolved, _unknown) = resolve_user_int_nrs(&cache.irqns, binds)?;
+    Ok(resolved)
+}
+
+/// Resolves the IRQ numbers of the given external interrupt `binds` by
+/// building and loading a cdylib that links against the PAC.
+fn build_pac_int_nrs(
+    cargo: &CargoWrapper,
+    pacp: &ManifestProperties,
+    binds: Vec<String>,
+) -> Result<IndexMap<String, u16>, RecoveryError> {
     const ADHOC_FUNC_PREFIX: &str = "rtic_scope_func_";
 
+    let method = InterruptMethod::of(cargo, pacp)?;
+
     // Extract adhoc source to a temporary directory and apply adhoc
     // modifications.
     let target_dir = cargo.target_dir().join("cargo-rtic-trace-libadhoc");
@@ -431,7 +1874,7 @@ fn resolve_int_nrs(
             .append(true)
             .open(target_dir.join("Cargo.toml"))
             .map_err(RecoveryError::LibExtractFail)?;
-        let dep = format!(
+        let mut dep = format!(
             "\n{} = {{ version = \"{}\", features = [{}]}}\n",
             pacp.pac_name,
             pacp.pac_version,
@@ -441,6 +1884,9 @@ fn resolve_int_nrs(
                 .collect::<Vec<String>>()
                 .join(","),
         );
+        if let Some((method_dep, _)) = method.dependency() {
+            dep.push_str(&format!("{}\n", method_dep));
+        }
         manifest
             .write_all(dep.as_bytes())
             .map_err(RecoveryError::LibExtractFail)?;
@@ -454,7 +1900,10 @@ fn resolve_int_nrs(
             .map_err(RecoveryError::LibExtractFail)?;
         let import = str::parse::<TokenStream>(&pacp.interrupt_path)
             .expect("Failed to tokenize pacp.interrupt_path");
-        let import = quote!(use #import;);
+        let mut import = quote!(use #import;);
+        if let Some((_, method_import)) = method.dependency() {
+            import.extend(method_import);
+        }
         src.write_all(format!("\n{}\n", import).as_bytes())
             .map_err(RecoveryError::LibExtractFail)?;
 
@@ -462,10 +1911,11 @@ fn resolve_int_nrs(
         for bind in &binds {
             let fun = format_ident!("{}{}", ADHOC_FUNC_PREFIX, bind);
             let int_ident = format_ident!("{}", bind);
+            let irqn = method.call(&int_ident);
             let fun = quote!(
                 #[no_mangle]
                 pub extern fn #fun() -> u16 {
-                    Interrupt::#int_ident.number()
+                    #irqn
                 }
             );
             src.write_all(format!("\n{}\n", fun).as_bytes())
@@ -474,45 +1924,306 @@ fn resolve_int_nrs(
     }
 
     // Build the adhoc library, load it, and resolve all exception idents
-    let artifact = cargo.build(
+    let artifact = cargo.build_with_timeout(
         &target_dir,
         // Host target triple need not be specified when CARGO is set.
         None,
         "cdylib",
+        Some(Duration::from_secs(pacp.adhoc_build_timeout)),
     )?;
     let lib = unsafe {
         libloading::Library::new(artifact.filenames.first().unwrap())
             .map_err(RecoveryError::LibLoadFail)?
     };
-    let binds: Result<Vec<(String, VectActive)>, RecoveryError> = binds
-        .iter()
+    binds
+        .into_iter()
         .map(|b| {
             let func: libloading::Symbol<extern "C" fn() -> u16> = unsafe {
                 lib.get(format!("{}{}", ADHOC_FUNC_PREFIX, b).as_bytes())
                     .map_err(RecoveryError::LibLookupFail)?
             };
 
-            // Convert the IRQn to a VectActive.
-            //
-            // The offset denotes at what offset from the start of the
-            // interrupt vector external (device-specific) interrupts
-            // are enumerated. cortex_m::interrupt::InterruptNumber
-            // (used above) enumerates starting at this offset so we
-            // must compensate. See also B1.5.2 in the ARMv7-M
-            // Architecture Reference Manual.
-            const DEVICE_INTERRUPTS_OFFSET: u16 = 16;
-            let irqn =
-                VectActive::from(func() + DEVICE_INTERRUPTS_OFFSET).expect("Invalid/reserved IRQn");
-
-            Ok((b.to_string(), irqn))
+            Ok((b, func()))
         })
-        .collect();
-    Ok(binds?.iter().cloned().collect())
+        .collect()
+}
+
+/// The profile with which the traced application was built. Timing
+/// differs considerably between e.g. debug and release builds, so
+/// traces of different profiles should not be compared as if
+/// equivalent.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct BuildProfile {
+    /// The optimization level (`opt-level`) of the artifact.
+    pub opt_level: String,
+
+    /// Whether debug assertions were enabled.
+    pub debug_assertions: bool,
+
+    /// Whether overflow checks were enabled.
+    pub overflow_checks: bool,
+
+    /// Additional flags passed to `rustc` via `RUSTFLAGS`, if any.
+    pub rustflags: Option<String>,
+}
+
+impl BuildProfile {
+    /// Records the profile of the given artifact. Additional `rustc`
+    /// flags are read from the environment.
+    pub fn from(artifact: &Artifact) -> Self {
+        Self::from_profile(&artifact.profile, env::var("RUSTFLAGS").ok())
+    }
+
+    fn from_profile(profile: &ArtifactProfile, rustflags: Option<String>) -> Self {
+        Self {
+            opt_level: profile.opt_level.clone(),
+            debug_assertions: profile.debug_assertions,
+            overflow_checks: profile.overflow_checks,
+            rustflags: rustflags.filter(|f| !f.is_empty()),
+        }
+    }
+
+    /// Name of the profile following cargo nomenclature: `release` if
+    /// the artifact was optimized without debug assertions, otherwise
+    /// `debug`.
+    pub fn name(&self) -> &'static str {
+        if self.opt_level != "0" && !self.debug_assertions {
+            "release"
+        } else {
+            "debug"
+        }
+    }
+}
+
+impl std::fmt::Display for BuildProfile {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} (opt-level {})", self.name(), self.opt_level)?;
+        if let Some(rustflags) = &self.rustflags {
+            write!(f, ", RUSTFLAGS=\"{}\"", rustflags)?;
+        }
+        Ok(())
+    }
+}
+
+/// Where the traced application was built from, such that a recorded
+/// trace can be related to a firmware revision and reproduced. Each
+/// field is only available if it could be determined.
+#[derive(Clone, Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct Provenance {
+    /// Commit of the git repository that contains the source of the
+    /// application.
+    pub git_commit: Option<String>,
+
+    /// Whether tracked files of the repository were modified since
+    /// [`Provenance::git_commit`].
+    pub git_dirty: bool,
+
+    /// Output of `rustc -V`.
+    pub rustc_version: Option<String>,
+
+    /// Output of `cargo -V`.
+    pub cargo_version: Option<String>,
+
+    /// Target triple the application was built for, e.g.
+    /// `thumbv7em-none-eabihf`.
+    pub target: Option<String>,
+
+    /// When the executable of the application was last built.
+    pub built_at: Option<chrono::DateTime<Local>>,
+}
+
+impl Provenance {
+    pub fn from(cargo: &CargoWrapper, artifact: &Artifact) -> Self {
+        let (git_commit, git_dirty) =
+            git_revision(artifact.target.src_path.as_std_path()).unwrap_or_default();
+        let executable = artifact.executable.as_ref().map(|e| e.as_std_path());
+
+        Self {
+            git_commit,
+            git_dirty,
+            rustc_version: cargo.rustc_version(),
+            cargo_version: cargo.cargo_version(),
+            target: cargo.target().map(str::to_string),
+            built_at: executable
+                .and_then(|e| fs::metadata(e).and_then(|m| m.modified()).ok())
+                .map(chrono::DateTime::from),
+        }
+    }
+}
+
+impl std::fmt::Display for Provenance {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let mut fields = vec![];
+        if let Some(commit) = &self.git_commit {
+            fields.push(format!(
+                "commit {}{}",
+                commit,
+                if self.git_dirty { " (dirty)" } else { "" }
+            ));
+        }
+        if let Some(target) = &self.target {
+            fields.push(format!("target {}", target));
+        }
+        fields.extend(self.rustc_version.iter().cloned());
+        fields.extend(self.cargo_version.iter().cloned());
+        if let Some(built_at) = &self.built_at {
+            fields.push(format!("built at {}", built_at));
+        }
+        write!(f, "{}", fields.join(", "))
+    }
+}
+
+/// The HEAD commit of the git repository that contains `path`, and
+/// whether tracked files were modified since.
+fn git_revision(path: &Path) -> Option<(Option<String>, bool)> {
+    let repo = git2::Repository::discover(path).ok()?;
+    let commit = repo.head().ok()?.peel_to_commit().ok()?.id().to_string();
+    let dirty = repo
+        .statuses(Some(git2::StatusOptions::new().include_untracked(false)))
+        .map_or(false, |statuses| !statuses.is_empty());
+
+    Some((Some(commit), dirty))
+}
+
+/// A segment of a trace during which the TPIU clock ran at a
+/// frequency other than the one the trace was decoded at.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct ClockSegment {
+    /// TPIU clock cycle since target reset, as decoded at
+    /// [`TraceMetadata::tpiu_freq`], at which the segment starts.
+    pub cycle: u64,
+    /// Frequency of the TPIU clock during the segment.
+    pub freq: u32,
+    /// Offset since target reset at which the segment starts.
+    pub start: Duration,
+}
+
+/// State of the decoding of global timestamps (GTS). A GTS1 packet
+/// carries bits [25:0] of the global cycle count; a GTS2 packet carries
+/// the remaining high-order bits and follows a GTS1 packet of which the
+/// wrap flag is set.
+#[derive(Clone, Default)]
+struct GlobalClock {
+    /// High-order bits of the global cycle count, if known.
+    high: Option<u64>,
+    /// Low-order bits of a GTS1 packet awaiting its GTS2 packet.
+    pending: Option<u64>,
+    /// Nanoseconds added to the accumulated local timestamps: the
+    /// difference between the last complete global timestamp and the
+    /// local timestamp at which it was received.
+    anchor: i128,
+}
+
+/// State of the resynchronization after a sustained run of malformed
+/// packets; see [`TraceMetadata::resync_after`].
+#[derive(Clone, Default)]
+struct Resync {
+    /// Number of consecutive malformed packets after which packets are
+    /// dropped until the next synchronization packet.
+    threshold: Option<usize>,
+    /// Malformed packets of the current run.
+    run: usize,
+    /// Packets dropped since the run reached the threshold, if
+    /// resynchronizing.
+    dropped: Option<usize>,
+}
+
+/// Correlation of the RTIC monotonic with the trace timeline, from the
+/// samples of the monotonic counter that the firmware writes to the
+/// DWT comparator given by `dwt_monotonic_id`.
+#[derive(Clone, Default)]
+struct Monotonic {
+    /// The second to last sample: offset and ticks.
+    prev: Option<(Duration, u64)>,
+    /// The last sample. Ticks are extended past the wrap of the 32-bit
+    /// counter.
+    last: Option<(Duration, u64)>,
+}
+
+impl Monotonic {
+    /// Records that the counter read `value` at `offset`. The counter is
+    /// assumed to wrap at most once between two samples.
+    fn sample(&mut self, offset: Duration, value: u32) {
+        let ticks = match self.last {
+            Some((_, last)) => last + value.wrapping_sub(last as u32) as u64,
+            None => value as u64,
+        };
+        self.prev = self.last.replace((offset, ticks));
+    }
+
+    /// The ticks of the monotonic at `offset`: exact at a sample, and
+    /// otherwise extrapolated from the rate between the last two
+    /// samples.
+    fn ticks(&self, offset: Duration) -> Option<u64> {
+        let (t1, k1) = self.last?;
+        if offset == t1 {
+            return Some(k1);
+        }
+        let (t0, k0) = self.prev?;
+        let period = t1.checked_sub(t0).filter(|p| !p.is_zero())?;
+        let rate = (k1 - k0) as f64 / period.as_nanos() as f64;
+        let delta = offset.as_nanos() as f64 - t1.as_nanos() as f64;
+        Some((k1 as f64 + delta * rate).round().max(0.0) as u64)
+    }
 }
 
+/// The state of the decoding of a trace into events, which evolves with
+/// each chunk built by [`TraceMetadata::build_event_chunk`]. Not
+/// serialized: the state of a replay is recovered from its trace data.
+#[derive(Clone, Default)]
+struct DecoderState {
+    /// Software tasks that have been entered but not yet exited, in
+    /// order of entry. A software task can only be preempted by tasks
+    /// of higher priority, which must exit before it is resumed; the
+    /// top of the stack is thus the innermost executing software task.
+    /// Used to attribute returns to task dispatchers.
+    software_stack: Vec<String>,
+
+    /// The first action observed of each software task. Used to detect
+    /// swapped DWT comparators.
+    first_actions: IndexMap<String, TaskAction>,
+
+    /// Whether swapped DWT comparators have been reported.
+    swap_reported: bool,
+
+    /// The first traced software task ID that is not in the recovered
+    /// maps, and whether it has been reported.
+    unmapped_software_id: (Option<usize>, bool),
+
+    /// Global timestamps that re-anchor the accumulated local
+    /// timestamps.
+    global_clock: GlobalClock,
+
+    resync: Resync,
+
+    monotonic: Monotonic,
+
+    /// Bytes written to each log port since its last line terminator.
+    log_lines: IndexMap<u8, Vec<u8>>,
+
+    /// Tasks that are active, in the order they were entered; see
+    /// [`TraceMetadata::nest`].
+    active: Intervals,
+}
+
+/// Version of the serialized [`TraceMetadata`]. Bumped on changes that
+/// earlier versions cannot read, e.g. of the type of a field. Traces of
+/// the previous version are migrated by [`TraceMetadata::from_header`].
+pub const FORMAT_VERSION: u32 = 1;
+
 /// Contains all metadata for a single trace.
 #[derive(Clone, Serialize, Deserialize)]
 pub struct TraceMetadata {
+    /// Version of the format the trace was recorded in. Traces recorded
+    /// before the format was versioned are of version 0.
+    #[serde(default)]
+    format_version: u32,
+
+    /// Version of RTIC Scope the trace was recorded with. Not available
+    /// for traces recorded before the format was versioned.
+    #[serde(default)]
+    rtic_scope_version: Option<String>,
+
     /// Name of the RTIC application that was/is traced.
     pub program_name: String,
 
@@ -524,33 +2235,641 @@ pub struct TraceMetadata {
     /// Note: this timestamp is sampled host-side and is approximate.
     reset_timestamp: chrono::DateTime<Local>,
 
-    /// Frequency of the target TPIU clock. Used to generate absolute
-    /// timestamps. Set via `tpiu_freq` in
+    /// Frequency of the clock counted by local timestamps: the target
+    /// TPIU clock, unless another is set. Used to generate absolute
+    /// timestamps. Set via `tpiu_freq` (or `timestamp_freq`) in
     /// `[{package,workspace}.metadata.rtic-scope]` from `Cargo.toml` or
-    /// overridden via the `--tpiu-freq` trace option.
+    /// overridden via the `--tpiu-freq` (or `--timestamp-freq`) trace
+    /// option.
     tpiu_freq: u32,
 
     /// Optional comment of this particular trace.
     pub comment: Option<String>,
-}
 
-impl TraceMetadata {
-    pub fn from(
+    /// Profile with which the traced application was built. Not
+    /// available for traces recorded by earlier versions.
+    #[serde(default)]
+    pub build_profile: Option<BuildProfile>,
+
+    /// Where the traced application was built from. Not available for
+    /// traces recorded by earlier versions.
+    #[serde(default)]
+    pub provenance: Option<Provenance>,
+
+    /// Labeled timestamps of interest, e.g. of a glitch, in order of
+    /// offset. Not available for traces recorded by earlier versions.
+    #[serde(default)]
+    bookmarks: Vec<(rtic_scope_api::Timestamp, String)>,
+
+    /// Hash of the application source the trace was recorded with; see
+    /// [`source_hash`]. Not available for traces recorded by earlier
+    /// versions.
+    #[serde(default)]
+    pub source_hash: Option<String>,
+
+    /// ITM stimulus port on which the firmware signals a change of the
+    /// TPIU clock frequency by writing the new frequency (in Hz) as a
+    /// `u32`.
+    #[serde(default)]
+    clock_change_port: Option<u8>,
+
+    /// ITM stimulus ports on which the firmware writes lines of text,
+    /// e.g. ad-hoc logging, instead of task tracing information.
+    #[serde(default)]
+    log_ports: IndexSet<u8>,
+
+    /// Names of the variables watched by DWT comparators that are not
+    /// used for software task tracing, by comparator.
+    #[serde(default)]
+    watchpoints: IndexMap<u8, String>,
+
+    /// DWT comparator to which the firmware writes the counter of the
+    /// RTIC monotonic; see [`EventChunk::monotonic_ticks`].
+    #[serde(default)]
+    monotonic_comparator: Option<u8>,
+
+    /// Changes of the TPIU clock frequency during the trace, in order.
+    #[serde(default)]
+    clock_segments: RefCell<Vec<ClockSegment>>,
+
+    /// Whether the entry and exit of software task dispatchers are
+    /// presented as tasks; see [`Dispatcher::path`].
+    #[serde(skip)]
+    show_dispatchers: bool,
+
+    /// Whether each packet is logged alongside the events it is mapped
+    /// to; see [`TraceMetadata::debug_packets`].
+    #[serde(skip)]
+    debug_packets: bool,
+
+    #[serde(skip)]
+    state: RefCell<DecoderState>,
+}
+
+impl TraceMetadata {
+    pub fn from(
         program_name: String,
         maps: TraceLookupMaps,
         reset_timestamp: chrono::DateTime<Local>,
         tpiu_freq: u32,
         comment: Option<String>,
+        build_profile: Option<BuildProfile>,
     ) -> Self {
         Self {
+            format_version: FORMAT_VERSION,
+            rtic_scope_version: Some(env!("CARGO_PKG_VERSION").to_string()),
             program_name,
             maps,
             reset_timestamp,
             tpiu_freq,
             comment,
+            build_profile,
+            provenance: None,
+            bookmarks: vec![],
+            source_hash: None,
+            clock_change_port: None,
+            log_ports: IndexSet::new(),
+            watchpoints: IndexMap::new(),
+            monotonic_comparator: None,
+            clock_segments: RefCell::new(vec![]),
+            show_dispatchers: false,
+            debug_packets: false,
+            state: RefCell::new(DecoderState::default()),
+        }
+    }
+
+    /// The metadata of a trace of `artifact` that is about to be
+    /// recorded or decoded from a raw stream: the lookup maps, build
+    /// profile, provenance, and source hash of the application, and the
+    /// properties of the manifest by which packets are mapped. The
+    /// reset timestamp is approximated by the current time.
+    pub fn recover(
+        cargo: &CargoWrapper,
+        artifact: &Artifact,
+        maps: TraceLookupMaps,
+        manip: &ManifestProperties,
+        comment: Option<String>,
+    ) -> Self {
+        let mut metadata = Self::from(
+            artifact.target.name.clone(),
+            maps,
+            Local::now(),
+            manip.timestamp_clock(),
+            comment,
+            Some(BuildProfile::from(artifact)),
+        );
+        metadata.provenance = Some(Provenance::from(cargo, artifact));
+        metadata.source_hash = source_hash(artifact.target.src_path.as_std_path()).ok();
+        metadata.set_clock_change_port(manip.clock_change_port);
+        metadata.set_log_ports(&manip.log_ports);
+        metadata.set_watchpoints(&manip.watchpoints);
+        metadata.set_monotonic_comparator(manip.dwt_monotonic_id);
+        metadata
+    }
+
+    /// Deserializes the metadata header of a recorded trace. Traces of
+    /// a newer format version are rejected; traces of the previous
+    /// version are migrated.
+    pub fn from_header(header: serde_json::Value) -> Result<Self, SourceError> {
+        let version = header
+            .get("format_version")
+            .and_then(serde_json::Value::as_u64)
+            .unwrap_or(0);
+        if version > FORMAT_VERSION as u64 {
+            return Err(SourceError::IncompatibleFormat(
+                version,
+                header
+                    .get("rtic_scope_version")
+                    .and_then(serde_json::Value::as_str)
+                    .map(str::to_string),
+            ));
+        }
+
+        // NOTE(version 0) all fields added since are defaulted on
+        // deserialization: the previous version is migrated as is.
+        let mut metadata: Self = serde_json::from_value(header)?;
+        metadata.format_version = FORMAT_VERSION;
+
+        Ok(metadata)
+    }
+
+    /// Timestamp of target reset, after which tracing begins.
+    pub fn reset_timestamp(&self) -> chrono::DateTime<Local> {
+        self.reset_timestamp
+    }
+
+    pub fn set_clock_change_port(&mut self, port: Option<u8>) {
+        self.clock_change_port = port;
+    }
+
+    /// Presents the payloads written to the given ITM stimulus ports as
+    /// lines of text instead of mapping them to task events, as
+    /// configured via `log_ports` in the manifest.
+    pub fn set_log_ports(&mut self, ports: &[u8]) {
+        self.log_ports = ports.iter().copied().collect();
+    }
+
+    /// The [`EventType::Log`] of each line completed by `payload`
+    /// written to log port `port`. Lines are terminated by `\n`; an
+    /// optional preceding `\r` is dropped.
+    fn log(&self, port: u8, payload: &[u8]) -> Vec<EventType> {
+        let mut state = self.state.borrow_mut();
+        let line = state.log_lines.entry(port).or_default();
+        let mut events = vec![];
+        for byte in payload {
+            if *byte != b'\n' {
+                line.push(*byte);
+                continue;
+            }
+            if line.last() == Some(&b'\r') {
+                line.pop();
+            }
+            events.push(EventType::Log {
+                port,
+                message: String::from_utf8_lossy(line).into_owned(),
+            });
+            line.clear();
+        }
+        events
+    }
+
+    /// Names the variables watched by the given DWT comparators (name
+    /// to comparator), as configured via `watchpoints` in the manifest.
+    pub fn set_watchpoints(&mut self, watchpoints: &IndexMap<String, u8>) {
+        self.watchpoints = watchpoints
+            .iter()
+            .map(|(name, comparator)| (*comparator, name.to_owned()))
+            .collect();
+    }
+
+    /// Correlates the RTIC monotonic with the trace timeline via the
+    /// samples of its counter written to the given DWT comparator, as
+    /// configured via `dwt_monotonic_id` in the manifest.
+    pub fn set_monotonic_comparator(&mut self, comparator: Option<usize>) {
+        self.monotonic_comparator = comparator.map(|c| c as u8);
+    }
+
+    /// An [`EventType::DataTrace`] of a comparator not used for
+    /// software task tracing, named if the comparator is a watchpoint.
+    fn data_trace(
+        &self,
+        comparator: u8,
+        access_type: &MemoryAccessType,
+        value: &[u8],
+    ) -> EventType {
+        EventType::DataTrace {
+            comparator,
+            access_type: access_type.clone(),
+            value: value.to_vec(),
+            name: self.watchpoints.get(&comparator).cloned(),
+        }
+    }
+
+    /// Replaces the lookup maps with those of `other`, e.g. of a trace
+    /// of a rebuilt application.
+    pub fn replace_maps(&mut self, other: &TraceMetadata) {
+        self.maps = other.maps.clone();
+    }
+
+    /// The number of TPIU clock cycles since target reset denoted by
+    /// `offset`, as decoded at [`TraceMetadata::tpiu_freq`].
+    fn cycles(&self, offset: Duration) -> u64 {
+        (offset.as_nanos() * self.tpiu_freq as u128 / 1_000_000_000) as u64
+    }
+
+    /// The offset since target reset at the given TPIU clock `cycle`,
+    /// accounting for changes of the TPIU clock frequency.
+    fn offset_at(&self, cycle: u64) -> Duration {
+        let (start, from, freq) = match self
+            .clock_segments
+            .borrow()
+            .iter()
+            .rev()
+            .find(|seg| seg.cycle <= cycle)
+        {
+            Some(seg) => (seg.start, seg.cycle, seg.freq),
+            None => (Duration::ZERO, 0, self.tpiu_freq),
+        };
+
+        start + Duration::from_nanos(((cycle - from) as u128 * 1_000_000_000 / freq as u128) as u64)
+    }
+
+    /// Corrects a `timestamp`, decoded at
+    /// [`TraceMetadata::tpiu_freq`], for changes of the TPIU clock
+    /// frequency.
+    fn correct_timestamp(&self, timestamp: itm::Timestamp) -> itm::Timestamp {
+        use itm::Timestamp;
+
+        if self.clock_segments.borrow().is_empty() {
+            return timestamp;
+        }
+        let correct = |offset: Duration| self.offset_at(self.cycles(offset));
+        match timestamp {
+            Timestamp::Sync(offset) => Timestamp::Sync(correct(offset)),
+            Timestamp::AssocEventDelay(offset) => Timestamp::AssocEventDelay(correct(offset)),
+            Timestamp::UnknownDelay { prev, curr } => Timestamp::UnknownDelay {
+                prev: correct(prev),
+                curr: correct(curr),
+            },
+            Timestamp::UnknownAssocEventDelay { prev, curr } => Timestamp::UnknownAssocEventDelay {
+                prev: correct(prev),
+                curr: correct(curr),
+            },
+        }
+    }
+
+    /// Accounts for the global timestamp packet `packet`, received at
+    /// the local `offset`. Global timestamps are assumed to count
+    /// cycles of the TPIU clock.
+    fn global_timestamp(&self, packet: &TracePacket, offset: Duration) {
+        let mut state = self.state.borrow_mut();
+        let clock = &mut state.global_clock;
+        let low = match packet {
+            TracePacket::GlobalTimestamp1 { ts, wrap, .. } => {
+                if *wrap {
+                    // The high-order bits have changed: wait for the
+                    // GTS2 packet that carries them.
+                    clock.high = None;
+                    clock.pending = Some(*ts);
+                    return;
+                }
+                *ts
+            }
+            TracePacket::GlobalTimestamp2 { ts } => {
+                clock.high = Some(*ts);
+                match clock.pending.take() {
+                    Some(low) => low,
+                    None => return,
+                }
+            }
+            _ => return,
+        };
+
+        if let Some(high) = clock.high {
+            let global = self.offset_at(high << 26 | low & ((1 << 26) - 1));
+            clock.anchor = global.as_nanos() as i128 - offset.as_nanos() as i128;
+        }
+    }
+
+    /// Shifts `timestamp` by the anchor of the last complete global
+    /// timestamp.
+    ///
+    /// Only the offsets are shifted. The quality of the local timestamp
+    /// is kept: a chunk of unknown delay after an overflow remains so,
+    /// with both its bounds shifted, as the global timestamp only
+    /// corrects the offset at which it is received.
+    fn anchor_timestamp(&self, timestamp: itm::Timestamp) -> itm::Timestamp {
+        use itm::Timestamp;
+
+        let anchor = self.state.borrow().global_clock.anchor;
+        if anchor == 0 {
+            return timestamp;
+        }
+        let shift = |offset: Duration| {
+            Duration::from_nanos((offset.as_nanos() as i128 + anchor).max(0) as u64)
+        };
+        match timestamp {
+            Timestamp::Sync(offset) => Timestamp::Sync(shift(offset)),
+            Timestamp::AssocEventDelay(offset) => Timestamp::AssocEventDelay(shift(offset)),
+            Timestamp::UnknownDelay { prev, curr } => Timestamp::UnknownDelay {
+                prev: shift(prev),
+                curr: shift(curr),
+            },
+            Timestamp::UnknownAssocEventDelay { prev, curr } => Timestamp::UnknownAssocEventDelay {
+                prev: shift(prev),
+                curr: shift(curr),
+            },
+        }
+    }
+
+    /// Records that the TPIU clock runs at `freq` from the given
+    /// `cycle` on. Changes that have already been recorded, e.g. when
+    /// a trace is replayed, are ignored.
+    fn change_clock(&self, cycle: u64, freq: u32) {
+        if matches!(self.clock_segments.borrow().last(), Some(seg) if seg.cycle >= cycle) {
+            return;
+        }
+        let start = self.offset_at(cycle);
+        self.clock_segments
+            .borrow_mut()
+            .push(ClockSegment { cycle, freq, start });
+    }
+
+    /// Presents the entry and exit of software task dispatchers as
+    /// tasks in subsequently built chunks instead of hiding them.
+    pub fn show_dispatchers(&mut self) {
+        self.show_dispatchers = true;
+    }
+
+    /// Log each packet of every chunk built by
+    /// [`TraceMetadata::build_event_chunk`] alongside the events it is
+    /// mapped to, e.g. to diagnose a misconfigured `dwt_enter_id`.
+    pub fn debug_packets(&mut self) {
+        self.debug_packets = true;
+    }
+
+    /// Describes each of `packets` alongside the events it was mapped
+    /// to, given the index of the first event of each packet in
+    /// `events`, and the malformed packets of the chunk.
+    fn describe_packets(
+        timestamp: &itm::Timestamp,
+        packets: &[TracePacket],
+        starts: &[usize],
+        events: &[EventType],
+        malformed_packets: &[MalformedPacket],
+    ) -> Vec<String> {
+        let mut lines = vec![format!("chunk at {:?}", timestamp)];
+        for (i, packet) in packets.iter().enumerate() {
+            let end = starts.get(i + 1).copied().unwrap_or(events.len());
+            let mapped = &events[starts[i]..end];
+            lines.push(if mapped.is_empty() {
+                format!("{:?} -> (no event)", packet)
+            } else {
+                format!(
+                    "{:?} -> {}",
+                    packet,
+                    mapped
+                        .iter()
+                        .map(|event| format!("{:?}", event))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )
+            });
+        }
+        for malformed in malformed_packets {
+            lines.push(format!("{:?} -> Invalid", malformed));
+        }
+
+        lines
+    }
+
+    /// Drop all packets after `threshold` consecutive malformed packets
+    /// until the next synchronization packet, or until a chunk without
+    /// malformed packets, and report them as a single
+    /// [`EventType::Resynced`]. A single bit error on the physical link
+    /// otherwise cascades into a flood of [`EventType::Invalid`].
+    pub fn resync_after(&mut self, threshold: Option<usize>) {
+        self.state.get_mut().resync = Resync {
+            threshold,
+            ..Resync::default()
+        };
+    }
+
+    /// Applies the resynchronization of [`Self::resync_after`] to the
+    /// packets of a chunk. Returns the packets that are to be mapped,
+    /// and the number of dropped packets if the stream resynchronized
+    /// in this chunk.
+    fn resync(
+        &self,
+        packets: Vec<TracePacket>,
+        malformed_packets: Vec<MalformedPacket>,
+    ) -> (Vec<TracePacket>, Vec<MalformedPacket>, Option<usize>) {
+        let mut state = self.state.borrow_mut();
+        let resync = &mut state.resync;
+        let threshold = match resync.threshold {
+            Some(threshold) => threshold,
+            None => return (packets, malformed_packets, None),
+        };
+
+        if resync.dropped.is_none() {
+            if malformed_packets.is_empty() {
+                resync.run = 0;
+                return (packets, malformed_packets, None);
+            }
+            resync.run += malformed_packets.len();
+            if resync.run < threshold {
+                return (packets, malformed_packets, None);
+            }
+            resync.dropped = Some(0);
+        }
+
+        // NOTE(malformed) the order of malformed packets relative to the
+        // valid packets of a chunk is unknown: all malformed packets of
+        // the chunk in which the stream resynchronizes are dropped.
+        let dropped = resync.dropped.unwrap_or_default();
+        let (packets, dropped) = match packets
+            .iter()
+            .position(|packet| matches!(packet, TracePacket::Sync))
+        {
+            Some(i) => (
+                packets[i + 1..].to_vec(),
+                dropped + i + malformed_packets.len(),
+            ),
+            // The decoder realigned by itself.
+            None if malformed_packets.is_empty() => (packets, dropped),
+            None => {
+                resync.dropped = Some(dropped + packets.len() + malformed_packets.len());
+                return (vec![], vec![], None);
+            }
+        };
+        resync.run = 0;
+        resync.dropped = None;
+
+        (packets, vec![], Some(dropped))
+    }
+
+    /// Swaps the roles of the DWT comparators for entering and exiting
+    /// software tasks.
+    pub fn swap_comparators(&mut self) {
+        for action in self.maps.software.comparators.values_mut() {
+            *action = match action {
+                TaskAction::Entered => TaskAction::Exited,
+                TaskAction::Exited => TaskAction::Entered,
+                action => action.clone(),
+            };
+        }
+    }
+
+    /// Checks whether the software tasks seen so far systematically
+    /// exit before they are entered, which indicates that the DWT
+    /// comparators for entering and exiting are swapped. A trace may
+    /// start while a task executes, so a majority of at least
+    /// [`Self::SWAP_MIN_TASKS`] tasks is required. Reported at most
+    /// once.
+    pub fn check_comparator_roles(&self) -> Option<RecoveryError> {
+        let mut state = self.state.borrow_mut();
+        if state.swap_reported {
+            return None;
+        }
+
+        let inverted = state
+            .first_actions
+            .values()
+            .filter(|action| matches!(action, TaskAction::Exited))
+            .count();
+        if inverted >= Self::SWAP_MIN_TASKS && inverted * 2 > state.first_actions.len() {
+            state.swap_reported = true;
+            Some(RecoveryError::SwappedComparators(
+                inverted,
+                state.first_actions.len(),
+            ))
+        } else {
+            None
+        }
+    }
+
+    const SWAP_MIN_TASKS: usize = 2;
+
+    /// Reports a traced software task ID that is not in the recovered
+    /// maps, which indicates that the source has been edited since the
+    /// firmware was built. Reported at most once.
+    pub fn check_software_ids(&self) -> Option<RecoveryError> {
+        let mut state = self.state.borrow_mut();
+        match state.unmapped_software_id {
+            (Some(id), false) => {
+                state.unmapped_software_id = (Some(id), true);
+                Some(RecoveryError::SoftwareIdOutOfRange(
+                    id,
+                    self.software_tasks_len(),
+                ))
+            }
+            _ => None,
+        }
+    }
+
+    /// Reports whether the source of the application declared in `src`
+    /// differs from the source the trace was recorded with, if known.
+    pub fn check_source(&self, src: &Path) -> Option<RecoveryError> {
+        let recorded = self.source_hash.as_ref()?;
+        match source_hash(src) {
+            Ok(current) if &current != recorded => Some(RecoveryError::SourceMismatch),
+            _ => None,
         }
     }
 
+    /// Full path of the software task of the given ID, if any.
+    pub fn software_task(&self, id: usize) -> Option<String> {
+        self.maps.software.map.get(&id).map(|path| path.join("::"))
+    }
+
+    pub fn tpiu_freq(&self) -> u32 {
+        self.tpiu_freq
+    }
+
+    pub fn set_tpiu_freq(&mut self, tpiu_freq: u32) {
+        self.tpiu_freq = tpiu_freq;
+    }
+
+    /// Anonymizes the trace for a bug report: the program is renamed,
+    /// each task is renamed to `task<n>`, and the comment and bookmarks
+    /// are dropped. Modules are not renamed. Returns the renamed task
+    /// idents.
+    pub fn anonymize(&mut self) -> IndexMap<String, String> {
+        let idents: IndexSet<String> = self
+            .maps
+            .tasks()
+            .map(|(_, name)| name)
+            .chain(self.maps.priorities.keys().cloned())
+            .filter_map(|name| name.rsplit("::").next().map(str::to_string))
+            .collect();
+        let idents: IndexMap<String, String> = idents
+            .into_iter()
+            .enumerate()
+            .map(|(i, ident)| (ident, format!("task{}", i)))
+            .collect();
+
+        self.maps.rename_tasks(&idents);
+        self.program_name = "anonymous".to_string();
+        self.comment = None;
+        self.bookmarks.clear();
+
+        idents
+    }
+
+    pub fn bookmarks(&self) -> &[(rtic_scope_api::Timestamp, String)] {
+        &self.bookmarks
+    }
+
+    /// Bookmarks `timestamp` with `label`.
+    pub fn add_bookmark(&mut self, timestamp: rtic_scope_api::Timestamp, label: String) {
+        self.bookmarks.push((timestamp, label));
+        self.bookmarks
+            .sort_by_key(|(ts, _)| rtic_scope_api::timestamp_offset(ts));
+    }
+
+    /// Removes all bookmarks labeled `label`. Returns whether any
+    /// bookmark was removed.
+    pub fn remove_bookmark(&mut self, label: &str) -> bool {
+        let len = self.bookmarks.len();
+        self.bookmarks.retain(|(_, l)| l != label);
+        self.bookmarks.len() != len
+    }
+
+    /// Full paths of all hardware tasks.
+    pub fn hardware_tasks(&self) -> IndexSet<String> {
+        self.maps
+            .hardware
+            .0
+            .values()
+            .map(|path| path.join("::"))
+            .collect()
+    }
+
+    /// Full paths of all hardware and software tasks. Hardware tasks
+    /// come first.
+    pub fn tasks(&self) -> IndexSet<String> {
+        self.maps.tasks().map(|(_, path)| path).collect()
+    }
+
+    /// Full paths of all hardware tasks, in the order of their
+    /// exception numbers.
+    pub fn hardware_task_names(&self) -> Vec<String> {
+        self.hardware_tasks().into_iter().collect()
+    }
+
+    /// Full paths of all software tasks, in the order of their IDs.
+    /// A task that is mapped to several IDs is listed once.
+    pub fn software_task_names(&self) -> Vec<String> {
+        self.maps
+            .software
+            .map
+            .values()
+            .map(|path| path.join("::"))
+            .collect::<IndexSet<_>>()
+            .into_iter()
+            .collect()
+    }
+
     pub fn hardware_tasks_len(&self) -> usize {
         self.maps.hardware.0.len()
     }
@@ -559,6 +2878,77 @@ impl TraceMetadata {
         self.maps.software.map.len()
     }
 
+    /// Keeps track of which software tasks are currently executing.
+    fn track_software_task(&self, event: &EventType) {
+        let mut state = self.state.borrow_mut();
+        if let EventType::Task { name, action, .. } = event {
+            state
+                .first_actions
+                .entry(name.clone())
+                .or_insert_with(|| action.clone());
+        }
+
+        let stack = &mut state.software_stack;
+        match event {
+            EventType::Task {
+                name,
+                action: TaskAction::Entered,
+                ..
+            } => stack.push(name.clone()),
+            EventType::Task {
+                name,
+                action: TaskAction::Exited,
+                ..
+            } => {
+                // Any software task entered after the one that now
+                // exits must have exited already: the exit was lost
+                // (e.g. on overflow).
+                if let Some(i) = stack.iter().rposition(|n| n == name) {
+                    stack.truncate(i);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// Sets the depth of each task event at `now`: the number of tasks
+    /// that are active at the event, including the task itself, as
+    /// paired by [`Intervals`]. A return to thread mode (`#[idle]`)
+    /// leaves no task active.
+    fn nest(&self, events: &mut [EventType], now: u64) {
+        let mut state = self.state.borrow_mut();
+        let active = &mut state.active;
+        for event in events.iter_mut() {
+            let (name, action, priority, depth) = match event {
+                EventType::Task {
+                    name,
+                    action,
+                    priority,
+                    depth,
+                } => (name, action, priority, depth),
+                _ => continue,
+            };
+            if Some(&*name) == self.maps.idle.as_ref() {
+                if let TaskAction::Returned = action {
+                    active.clear();
+                }
+                *depth = 0;
+                continue;
+            }
+
+            *depth = match action {
+                TaskAction::Entered => Some(active.enter(name, *priority, now)),
+                TaskAction::Exited => active.exit(name, now).map(|(depth, _)| depth),
+                TaskAction::Returned => active.resume(name, now),
+                TaskAction::Completed => None,
+            }
+            // The task was entered before the trace started, or an
+            // async software task completed after its last poll exited.
+            .unwrap_or(active.depth() + 1)
+            .min(u8::MAX as usize) as u8;
+        }
+    }
+
     pub fn build_event_chunk(
         &self,
         TimestampedTracePackets {
@@ -568,40 +2958,128 @@ impl TraceMetadata {
             consumed_packets: _,
         }: TimestampedTracePackets,
     ) -> EventChunk {
-        let mut events = vec![];
+        // The clock frequency changes after the packets of this chunk.
+        let cycle = self.cycles(rtic_scope_api::timestamp_offset(&timestamp));
+        let timestamp = self.correct_timestamp(timestamp);
+        let (packets, malformed_packets, resynced) = self.resync(packets, malformed_packets);
+        if resynced.is_some() {
+            // The dropped packets may have entered and exited any task:
+            // which tasks are executing is unknown.
+            let mut state = self.state.borrow_mut();
+            state.software_stack.clear();
+            state.active.clear();
+        }
+
+        let mut events: Vec<EventType> = resynced.map(EventType::Resynced).into_iter().collect();
+        let mut monotonic_sample = None;
+        let mut starts = vec![];
         for packet in packets.iter() {
+            if self.debug_packets {
+                starts.push(events.len());
+            }
             match packet {
                 TracePacket::Sync => (), // NOTE(noop) only used for byte alignment; contains no data
+                TracePacket::GlobalTimestamp1 { .. } | TracePacket::GlobalTimestamp2 { .. } => {
+                    self.global_timestamp(packet, rtic_scope_api::timestamp_offset(&timestamp))
+                }
+                TracePacket::Instrumentation { port, payload }
+                    if Some(*port) == self.clock_change_port =>
+                {
+                    match payload[..].try_into().map(u32::from_le_bytes) {
+                        Ok(freq) if freq > 0 => self.change_clock(cycle, freq),
+                        _ => events.push(EventType::Unmappable(
+                            packet.clone(),
+                            "a TPIU clock change must carry a non-zero u32 frequency".to_string(),
+                        )),
+                    }
+                }
+                TracePacket::Instrumentation { port, payload } if self.log_ports.contains(port) => {
+                    events.extend(self.log(*port, payload))
+                }
                 TracePacket::Overflow => events.push(EventType::Overflow),
 
-                // NOTE(noop) RTIC tasks always execute in handler mode;
-                // thread mode is always exited before a task is run and
-                // returned to on WFI.
-                TracePacket::ExceptionTrace {
-                    exception,
-                    action: _,
-                } if exception == &VectActive::ThreadMode => (),
+                // RTIC tasks always execute in handler mode; thread
+                // mode is always exited before a task is run and
+                // returned to once all tasks have finished. The #[idle]
+                // task then resumes. NOTE(noop) otherwise: the target
+                // sleeps.
+                TracePacket::ExceptionTrace { exception, action }
+                    if exception == &VectActive::ThreadMode =>
+                {
+                    if let Some(idle) = &self.maps.idle {
+                        events.push(EventType::Task {
+                            name: idle.clone(),
+                            action: task_action(action),
+                            priority: Some(0),
+                            depth: 0,
+                        });
+                    }
+                }
 
-                TracePacket::ExceptionTrace { exception, action } => events.push(EventType::Task {
-                    name: match self.maps.resolve_hardware_task(exception) {
+                TracePacket::ExceptionTrace { exception, action } => {
+                    let action = task_action(action);
+                    let name = match self.maps.resolve_hardware_task(exception) {
                         Ok(Some(name)) => name,
 
-                        // NOTE(noop) task dispatcher entered/exited: we
+                        // A task dispatcher was returned to: the
+                        // software task it executes is resumed.
+                        Ok(None) if matches!(action, TaskAction::Returned) => {
+                            match self.state.borrow().software_stack.last() {
+                                Some(name) => name.clone(),
+                                None => continue,
+                            }
+                        }
+
+                        // Task dispatcher entered/exited: presented as a
+                        // task if so requested. NOTE(noop) otherwise: we
                         // have already (or will) forward a message
                         // about the software task itself.
-                        Ok(None) => continue,
+                        Ok(None) => {
+                            if let Some(dispatcher) = self
+                                .maps
+                                .dispatcher(exception)
+                                .filter(|_| self.show_dispatchers)
+                            {
+                                events.push(EventType::Task {
+                                    name: dispatcher.path(),
+                                    action,
+                                    priority: Some(dispatcher.priority),
+                                    depth: 0,
+                                });
+                            }
+                            continue;
+                        }
 
                         Err(e) => {
                             events.push(EventType::Unmappable(packet.clone(), e.to_string()));
                             continue;
                         }
-                    },
-                    action: match action {
-                        ExceptionAction::Entered => TaskAction::Entered,
-                        ExceptionAction::Exited => TaskAction::Exited,
-                        ExceptionAction::Returned => TaskAction::Returned,
-                    },
-                }),
+                    };
+
+                    events.push(EventType::Task {
+                        priority: self.maps.priority(&name),
+                        name,
+                        action,
+                        depth: 0,
+                    });
+                }
+
+                TracePacket::DataTraceValue {
+                    comparator,
+                    access_type,
+                    value,
+                } if *access_type == MemoryAccessType::Write
+                    && Some(*comparator) == self.monotonic_comparator =>
+                {
+                    // NOTE(fold) little-endian, of at most 32 bits
+                    monotonic_sample = Some(
+                        value
+                            .iter()
+                            .take(4)
+                            .rev()
+                            .fold(0u32, |acc, b| acc << 8 | *b as u32),
+                    );
+                }
 
                 TracePacket::DataTraceValue {
                     comparator,
@@ -619,15 +3097,63 @@ impl TraceMetadata {
                     value,
                 } if *access_type == MemoryAccessType::Write => {
                     events.push(match self.maps.resolve_software_task(comparator, value) {
-                        Ok(Some(task_event)) => task_event,
-                        Ok(None) => EventType::Unknown(packet.clone()), // not a software task DWT comparator
-                        Err(e) => EventType::Unmappable(packet.clone(), e.to_string()),
+                        Ok(Some(task_event)) => {
+                            self.track_software_task(&task_event);
+                            task_event
+                        }
+                        // not a software task DWT comparator
+                        Ok(None) => self.data_trace(*comparator, access_type, value),
+                        Err(e) => {
+                            if let RecoveryError::MissingSoftwareMapping(id) = e {
+                                let mut state = self.state.borrow_mut();
+                                if state.unmapped_software_id.0.is_none() {
+                                    state.unmapped_software_id = (Some(id), false);
+                                }
+                            }
+                            EventType::Unmappable(packet.clone(), e.to_string())
+                        }
                     });
                 }
+
+                TracePacket::DataTraceValue {
+                    comparator,
+                    access_type,
+                    value,
+                } => events.push(self.data_trace(*comparator, access_type, value)),
+
+                // NOTE unresolvable samples are forwarded with the raw
+                // PC
+                TracePacket::PeriodicPcSample { pc } => events.push(EventType::PcSample {
+                    pc: *pc,
+                    symbol: pc.and_then(|pc| self.maps.symbol(pc)).map(str::to_string),
+                }),
                 _ => events.push(EventType::Unknown(packet.clone())),
             }
         }
 
+        if self.debug_packets {
+            log::debug(
+                Self::describe_packets(&timestamp, &packets, &starts, &events, &malformed_packets)
+                    .join("\n"),
+            );
+        }
+
+        let timestamp = self.anchor_timestamp(timestamp);
+        let monotonic_ticks = self.monotonic_comparator.and_then(|_| {
+            let offset = rtic_scope_api::timestamp_offset(&timestamp);
+            let mut state = self.state.borrow_mut();
+            let monotonic = &mut state.monotonic;
+            if let Some(value) = monotonic_sample {
+                monotonic.sample(offset, value);
+            }
+            monotonic.ticks(offset)
+        });
+
+        self.nest(
+            &mut events,
+            rtic_scope_api::timestamp_offset(&timestamp).as_nanos() as u64,
+        );
+
         // map malformed packets
         events.append(
             &mut malformed_packets
@@ -636,12 +3162,138 @@ impl TraceMetadata {
                 .collect(),
         );
 
-        EventChunk { timestamp, events }
+        EventChunk {
+            timestamp,
+            events,
+            raw: None,
+            monotonic_ticks,
+        }
+    }
+
+    /// Re-attempts the resolution of the unmappable and unknown events
+    /// of `chunk`, e.g. after the lookup maps have been replaced.
+    /// Returns the number of events that are no longer unmappable or
+    /// unknown.
+    ///
+    /// If the trace `data` the chunk was built from is given, the chunk
+    /// is built anew from it. The chunks of a trace must then be
+    /// re-resolved in order. Otherwise, each event is resolved from its
+    /// source packet alone: events that now resolve without error are
+    /// replaced, and all other events are left as-is.
+    pub fn reresolve(
+        &self,
+        chunk: &mut EventChunk,
+        data: Option<&TimestampedTracePackets>,
+    ) -> usize {
+        let unresolved = |events: &[EventType]| {
+            events
+                .iter()
+                .filter(|e| matches!(e, EventType::Unmappable(_, _) | EventType::Unknown(_)))
+                .count()
+        };
+
+        if let Some(data) = data {
+            let rebuilt = self.build_event_chunk(data.clone());
+            let before = unresolved(&chunk.events);
+            let after = unresolved(&rebuilt.events);
+            chunk.events = rebuilt.events;
+            return before.saturating_sub(after);
+        }
+
+        // NOTE(state) a packet resolved on its own must not affect the
+        // decoder state of the trace
+        let state = self.state.take();
+        let mut resolved = 0;
+        let events = std::mem::take(&mut chunk.events);
+        for event in events {
+            let packet = match &event {
+                EventType::Unmappable(packet, _) | EventType::Unknown(packet) => packet.clone(),
+                _ => {
+                    chunk.events.push(event);
+                    continue;
+                }
+            };
+
+            let mut events = self
+                .build_event_chunk(TimestampedTracePackets {
+                    timestamp: chunk.timestamp.clone(),
+                    packets: vec![packet],
+                    malformed_packets: vec![],
+                    consumed_packets: 1,
+                })
+                .events;
+            *self.state.borrow_mut() = DecoderState::default();
+            if unresolved(&events) > 0 {
+                chunk.events.push(event);
+            } else {
+                resolved += 1;
+                chunk.events.append(&mut events);
+            }
+        }
+        self.state.replace(state);
+
+        resolved
+    }
+}
+
+impl std::fmt::Display for TraceMetadata {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let freq = match self.tpiu_freq {
+            freq if freq % 1_000_000 == 0 => format!("{} MHz", freq / 1_000_000),
+            freq if freq % 1_000 == 0 => format!("{} kHz", freq / 1_000),
+            freq => format!("{} Hz", freq),
+        };
+        writeln!(
+            f,
+            "{}: {} hardware tasks, {} software tasks, traced at {} from {}",
+            self.program_name,
+            self.hardware_tasks_len(),
+            self.software_tasks_len(),
+            freq,
+            self.reset_timestamp,
+        )?;
+        if let Some(comment) = &self.comment {
+            writeln!(f, "comment: {}", comment)?;
+        }
+        if let Some(profile) = &self.build_profile {
+            writeln!(f, "build profile: {}", profile)?;
+        }
+        if let Some(provenance) = &self.provenance {
+            writeln!(f, "built from: {}", provenance)?;
+        }
+        if let Some(hash) = &self.source_hash {
+            writeln!(f, "source hash: {}", hash)?;
+        }
+        for dispatcher in self.maps.software.dispatchers.values() {
+            writeln!(
+                f,
+                "dispatcher {} (priority {}): {}",
+                dispatcher.name,
+                dispatcher.priority,
+                dispatcher.tasks.join(", ")
+            )?;
+        }
+        for seg in self.clock_segments.borrow().iter() {
+            writeln!(
+                f,
+                "TPIU clock changed to {} Hz at {:?}",
+                seg.freq, seg.start
+            )?;
+        }
+        for (timestamp, label) in self.bookmarks.iter() {
+            writeln!(
+                f,
+                "bookmark at {:?}: {}",
+                rtic_scope_api::timestamp_offset(timestamp),
+                label
+            )?;
+        }
+        write!(f, "{:#?}", self.maps)
     }
 }
 
 #[cfg(test)]
-mod test {
+pub(crate) mod test {
     use super::*;
 
     /// Ensure an RTIC application can be properly parsed.
@@ -673,6 +3325,1972 @@ mod test {
             #ast
         );
 
-        TraceLookupMaps::parse_rtic_app(src).unwrap();
+        TraceLookupMaps::parse_rtic_app(src, Path::new("")).unwrap();
+    }
+
+    /// Metadata of a trace at 16 MHz of the application of [`maps`].
+    pub fn metadata() -> TraceMetadata {
+        TraceMetadata::from(
+            "test".to_string(),
+            maps(),
+            Local::now(),
+            16_000_000,
+            None,
+            None,
+        )
+    }
+
+    /// Lookup maps for an application with two hardware tasks bound to
+    /// `SysTick` (`app::low`, priority 1) and `PendSV` (`app::high`,
+    /// priority 2), and two software tasks (`app::foo`, priority 1, and
+    /// `app::bar`, priority 2) dispatched by `SVCall` and traced via
+    /// DWT comparators 1 and 2.
+    pub fn maps() -> TraceLookupMaps {
+        use cortex_m::peripheral::scb::Exception;
+
+        TraceLookupMaps {
+            software: SoftwareMap {
+                id_convention: TRACE_ID_CONVENTION,
+                task_dispatchers: IndexSet::from_iter([VectActive::Exception(Exception::SVCall)]),
+                dispatchers: IndexMap::new(),
+                comparators: IndexMap::from_iter([
+                    (1, TaskAction::Entered),
+                    (2, TaskAction::Exited),
+                ]),
+                map: IndexMap::from_iter([
+                    (0, vec!["app".to_string(), "foo".to_string()]),
+                    (1, vec!["app".to_string(), "bar".to_string()]),
+                ]),
+                overrides: None,
+            },
+            hardware: HardwareMap(IndexMap::from_iter([
+                (
+                    VectActive::Exception(Exception::SysTick),
+                    vec!["app".to_string(), "low".to_string()],
+                ),
+                (
+                    VectActive::Exception(Exception::PendSV),
+                    vec!["app".to_string(), "high".to_string()],
+                ),
+            ])),
+            priorities: IndexMap::from_iter(
+                [("low", 1), ("high", 2), ("foo", 1), ("bar", 2)]
+                    .iter()
+                    .map(|(name, prio)| (format!("app::{}", name), *prio)),
+            ),
+            symbols: Symbols::default(),
+            idle: None,
+        }
+    }
+
+    fn packets(packets: Vec<TracePacket>) -> TimestampedTracePackets {
+        TimestampedTracePackets {
+            timestamp: itm::Timestamp::Sync(std::time::Duration::from_nanos(0)),
+            consumed_packets: packets.len(),
+            packets,
+            malformed_packets: vec![],
+        }
+    }
+
+    /// Ensure that reads and writes on comparators not used for
+    /// software task tracing are surfaced with their comparator.
+    #[test]
+    fn data_trace_read() {
+        let chunk = metadata().build_event_chunk(packets(vec![
+            TracePacket::DataTraceValue {
+                comparator: 3,
+                access_type: MemoryAccessType::Read,
+                value: vec![42],
+            },
+            TracePacket::DataTraceValue {
+                comparator: 0,
+                access_type: MemoryAccessType::Write,
+                value: vec![7],
+            },
+        ]));
+
+        assert!(matches!(
+            chunk.events.as_slice(),
+            [
+                EventType::DataTrace {
+                    comparator: 3,
+                    access_type: MemoryAccessType::Read,
+                    value: read,
+                    name: None,
+                },
+                EventType::DataTrace {
+                    comparator: 0,
+                    access_type: MemoryAccessType::Write,
+                    value: write,
+                    name: None,
+                },
+            ] if read == &[42] && write == &[7]
+        ));
+    }
+
+    /// Ensure that the ticks of the monotonic are exact at its samples,
+    /// and are otherwise extrapolated from the last two samples, also
+    /// past the wrap of the counter.
+    #[test]
+    fn monotonic_ticks() {
+        let mut metadata = metadata();
+        metadata.set_monotonic_comparator(Some(3));
+        let chunk = |nanos: u64, sample: Option<u32>| {
+            metadata
+                .build_event_chunk(TimestampedTracePackets {
+                    timestamp: itm::Timestamp::Sync(Duration::from_nanos(nanos)),
+                    packets: sample
+                        .map(|value| TracePacket::DataTraceValue {
+                            comparator: 3,
+                            access_type: MemoryAccessType::Write,
+                            value: value.to_le_bytes().to_vec(),
+                        })
+                        .into_iter()
+                        .collect(),
+                    malformed_packets: vec![],
+                    consumed_packets: 1,
+                })
+                .monotonic_ticks
+        };
+
+        assert_eq!(chunk(0, None), None);
+        assert_eq!(chunk(1_000, Some(u32::MAX - 9)), Some(u32::MAX as u64 - 9));
+        // a single sample: no rate
+        assert_eq!(chunk(1_500, None), None);
+        assert_eq!(chunk(2_000, Some(10)), Some(u32::MAX as u64 + 11));
+        assert_eq!(chunk(2_500, None), Some(u32::MAX as u64 + 21));
+    }
+
+    /// Ensure that each packet is described alongside the events it was
+    /// mapped to.
+    #[test]
+    fn describe_packets() {
+        let packets = vec![
+            TracePacket::Sync,
+            TracePacket::Overflow,
+            TracePacket::Instrumentation {
+                port: 1,
+                payload: vec![],
+            },
+        ];
+        let events = vec![EventType::Overflow, EventType::Unknown(packets[2].clone())];
+        let lines = TraceMetadata::describe_packets(
+            &itm::Timestamp::Sync(Duration::from_nanos(0)),
+            &packets,
+            &[0, 0, 1],
+            &events,
+            &[MalformedPacket::InvalidHeader(0xff)],
+        );
+
+        assert_eq!(lines.len(), 5);
+        assert!(lines[1].ends_with("-> (no event)"));
+        assert!(lines[2].starts_with("Overflow -> Overflow"));
+        assert!(lines[3].contains("-> Unknown(Instrumentation"));
+        assert!(lines[4].ends_with("-> Invalid"));
+    }
+
+    /// Ensure that the data traces of watchpoints are named.
+    #[test]
+    fn watchpoints() {
+        let mut metadata = metadata();
+        metadata.set_watchpoints(&IndexMap::from_iter([("state".to_string(), 3)]));
+        let chunk = metadata.build_event_chunk(packets(vec![
+            TracePacket::DataTraceValue {
+                comparator: 3,
+                access_type: MemoryAccessType::Write,
+                value: vec![2],
+            },
+            TracePacket::DataTraceValue {
+                comparator: 2,
+                access_type: MemoryAccessType::Read,
+                value: vec![5],
+            },
+        ]));
+
+        assert!(matches!(
+            chunk.events.as_slice(),
+            [
+                EventType::DataTrace {
+                    comparator: 3,
+                    name: Some(state),
+                    ..
+                },
+                EventType::DataTrace {
+                    comparator: 2,
+                    name: None,
+                    ..
+                },
+            ] if state == "state"
+        ));
+    }
+
+    /// Ensure that global timestamps re-anchor the local timestamps of
+    /// the chunk in which they are received and of all subsequent
+    /// chunks.
+    #[test]
+    fn global_timestamp_anchor() {
+        use std::time::Duration;
+
+        let metadata = metadata();
+        let at = |ns, packets: Vec<TracePacket>| {
+            let chunk = metadata.build_event_chunk(TimestampedTracePackets {
+                timestamp: itm::Timestamp::Sync(Duration::from_nanos(ns)),
+                consumed_packets: packets.len(),
+                packets,
+                malformed_packets: vec![],
+            });
+            assert!(chunk.events.is_empty());
+            rtic_scope_api::timestamp_offset(&chunk.timestamp).as_nanos()
+        };
+
+        assert_eq!(at(1000, vec![]), 1000);
+
+        // 48000 cycles at 16 MHz: 3 ms. The low-order bits are only
+        // applied once the high-order bits are known.
+        assert_eq!(
+            at(
+                2000,
+                vec![TracePacket::GlobalTimestamp1 {
+                    ts: 48_000,
+                    wrap: true,
+                    clkch: false,
+                }]
+            ),
+            2000
+        );
+        assert_eq!(
+            at(2500, vec![TracePacket::GlobalTimestamp2 { ts: 0 }]),
+            3_000_000
+        );
+        assert_eq!(at(3000, vec![]), 3_000_500);
+
+        // The local timestamps have drifted 500 ns behind.
+        assert_eq!(
+            at(
+                4000,
+                vec![TracePacket::GlobalTimestamp1 {
+                    ts: 48_032,
+                    wrap: false,
+                    clkch: false,
+                }]
+            ),
+            3_002_000
+        );
+        assert_eq!(at(5000, vec![]), 3_003_000);
+
+        // The quality of a diverged local timestamp is kept.
+        let chunk = metadata.build_event_chunk(TimestampedTracePackets {
+            timestamp: itm::Timestamp::UnknownDelay {
+                prev: Duration::from_nanos(5000),
+                curr: Duration::from_nanos(6000),
+            },
+            packets: vec![],
+            malformed_packets: vec![],
+            consumed_packets: 0,
+        });
+        assert!(matches!(
+            chunk.timestamp,
+            itm::Timestamp::UnknownDelay { prev, curr }
+                if prev == Duration::from_nanos(3_003_000) && curr == Duration::from_nanos(3_004_000)
+        ));
+    }
+
+    /// Ensure that bookmarks survive serialization, and that metadata
+    /// without bookmarks can still be read.
+    #[test]
+    fn bookmarks_round_trip() {
+        use std::time::Duration;
+
+        let mut metadata = metadata();
+        metadata.add_bookmark(
+            rtic_scope_api::Timestamp::Sync(Duration::from_micros(700)),
+            "glitch here".to_string(),
+        );
+        metadata.add_bookmark(
+            rtic_scope_api::Timestamp::AssocEventDelay(Duration::from_micros(300)),
+            "first".to_string(),
+        );
+
+        let json = serde_json::to_value(&metadata).unwrap();
+        let read: TraceMetadata = serde_json::from_value(json.clone()).unwrap();
+        let offsets: Vec<(Duration, &str)> = read
+            .bookmarks()
+            .iter()
+            .map(|(ts, label)| (rtic_scope_api::timestamp_offset(ts), label.as_str()))
+            .collect();
+        assert_eq!(
+            offsets,
+            vec![
+                (Duration::from_micros(300), "first"),
+                (Duration::from_micros(700), "glitch here"),
+            ]
+        );
+
+        let mut legacy = json;
+        legacy.as_object_mut().unwrap().remove("bookmarks");
+        let mut read: TraceMetadata = serde_json::from_value(legacy).unwrap();
+        assert!(read.bookmarks().is_empty());
+        assert!(!read.remove_bookmark("first"));
+        assert!(metadata.remove_bookmark("first"));
+        assert_eq!(metadata.bookmarks().len(), 1);
+    }
+
+    /// Ensure that an event that was unmappable with the maps at the
+    /// time of recording is resolved with maps that cover it.
+    #[test]
+    fn reresolve_unmappable() {
+        let uart = TracePacket::ExceptionTrace {
+            exception: VectActive::Interrupt { irqn: 3 },
+            action: ExceptionAction::Entered,
+        };
+        let mut chunk = metadata().build_event_chunk(packets(vec![
+            exception(
+                cortex_m::peripheral::scb::Exception::SysTick,
+                ExceptionAction::Entered,
+            ),
+            uart.clone(),
+        ]));
+        assert!(matches!(
+            chunk.events.last(),
+            Some(EventType::Unmappable(packet, _)) if packet == &uart
+        ));
+        let recorded = chunk.events[0].clone();
+
+        // The old maps do not cover the interrupt either.
+        assert_eq!(metadata().reresolve(&mut chunk, None), 0);
+
+        let mut maps = maps();
+        maps.hardware.0.insert(
+            VectActive::Interrupt { irqn: 3 },
+            vec!["app".to_string(), "uart".to_string()],
+        );
+        let mut new = metadata();
+        new.replace_maps(&TraceMetadata::from(
+            "test".to_string(),
+            maps,
+            Local::now(),
+            16_000_000,
+            None,
+            None,
+        ));
+        assert_eq!(new.reresolve(&mut chunk, None), 1);
+        assert!(new.state.borrow().active.depth() == 0);
+        assert_eq!(chunk.events.len(), 2);
+        assert_eq!(
+            serde_json::to_string(&chunk.events[0]).unwrap(),
+            serde_json::to_string(&recorded).unwrap()
+        );
+        assert!(matches!(
+            &chunk.events[1],
+            EventType::Task {
+                name,
+                action: TaskAction::Entered,
+                priority: None,
+                ..
+            } if name == "app::uart"
+        ));
+    }
+
+    /// Ensure that the chunks of a trace are re-resolved from their
+    /// trace data in order: a resolved task is nested in the tasks it
+    /// preempts.
+    #[test]
+    fn reresolve_in_order() {
+        let trace = [
+            packets(vec![exception(
+                cortex_m::peripheral::scb::Exception::SysTick,
+                ExceptionAction::Entered,
+            )]),
+            packets(vec![TracePacket::ExceptionTrace {
+                exception: VectActive::Interrupt { irqn: 3 },
+                action: ExceptionAction::Entered,
+            }]),
+        ];
+        let recorded = metadata();
+        let mut chunks: Vec<_> = trace
+            .iter()
+            .map(|data| recorded.build_event_chunk(data.clone()))
+            .collect();
+
+        let mut maps = maps();
+        maps.hardware.0.insert(
+            VectActive::Interrupt { irqn: 3 },
+            vec!["app".to_string(), "uart".to_string()],
+        );
+        let mut new = metadata();
+        new.replace_maps(&TraceMetadata::from(
+            "test".to_string(),
+            maps,
+            Local::now(),
+            16_000_000,
+            None,
+            None,
+        ));
+        let resolved: usize = trace
+            .iter()
+            .zip(chunks.iter_mut())
+            .map(|(data, chunk)| new.reresolve(chunk, Some(data)))
+            .sum();
+        assert_eq!(resolved, 1);
+        assert!(matches!(
+            chunks[1].events.as_slice(),
+            [EventType::Task { name, depth: 2, .. }] if name == "app::uart"
+        ));
+    }
+
+    fn exception(
+        exception: cortex_m::peripheral::scb::Exception,
+        action: ExceptionAction,
+    ) -> TracePacket {
+        TracePacket::ExceptionTrace {
+            exception: VectActive::Exception(exception),
+            action,
+        }
+    }
+
+    /// Ensure that a return from a preempting task is attributed to
+    /// the priority of the resumed task.
+    #[test]
+    fn preemption_return_priority() {
+        use cortex_m::peripheral::scb::Exception;
+
+        let metadata = metadata();
+
+        // Hardware task preempted by another hardware task.
+        let chunk = metadata.build_event_chunk(packets(vec![
+            exception(Exception::SysTick, ExceptionAction::Entered),
+            exception(Exception::PendSV, ExceptionAction::Entered),
+            exception(Exception::PendSV, ExceptionAction::Exited),
+            exception(Exception::SysTick, ExceptionAction::Returned),
+        ]));
+        assert!(matches!(
+            chunk.events.last(),
+            Some(EventType::Task {
+                name,
+                action: TaskAction::Returned,
+                priority: Some(1),
+                ..
+            }) if name == "app::low"
+        ));
+
+        // Software task preempted by a hardware task: the dispatcher is
+        // returned to.
+        let chunk = metadata.build_event_chunk(packets(vec![
+            exception(Exception::SVCall, ExceptionAction::Entered),
+            TracePacket::DataTraceValue {
+                comparator: 1,
+                access_type: MemoryAccessType::Write,
+                value: vec![0],
+            },
+            exception(Exception::PendSV, ExceptionAction::Entered),
+            exception(Exception::PendSV, ExceptionAction::Exited),
+            exception(Exception::SVCall, ExceptionAction::Returned),
+        ]));
+        assert!(matches!(
+            chunk.events.last(),
+            Some(EventType::Task {
+                name,
+                action: TaskAction::Returned,
+                priority: Some(1),
+                ..
+            }) if name == "app::foo"
+        ));
+    }
+
+    /// Ensure that the depth of task events follows preemption, also
+    /// across tail-chained exceptions and lost exits.
+    #[test]
+    fn nesting_depth() {
+        use cortex_m::peripheral::scb::Exception;
+
+        let metadata = metadata();
+        let depths = |chunk: EventChunk| -> Vec<(String, u8)> {
+            chunk
+                .events
+                .iter()
+                .filter_map(|event| match event {
+                    EventType::Task { name, depth, .. } => Some((name.clone(), *depth)),
+                    _ => None,
+                })
+                .collect()
+        };
+        let expected = |depths: &[(&str, u8)]| -> Vec<(String, u8)> {
+            depths
+                .iter()
+                .map(|(name, depth)| (format!("app::{}", name), *depth))
+                .collect()
+        };
+
+        let chunk = metadata.build_event_chunk(packets(vec![
+            exception(Exception::SysTick, ExceptionAction::Entered),
+            exception(Exception::PendSV, ExceptionAction::Entered),
+            exception(Exception::PendSV, ExceptionAction::Exited),
+            // tail-chained: app::low is not returned to in between
+            exception(Exception::PendSV, ExceptionAction::Entered),
+            exception(Exception::PendSV, ExceptionAction::Exited),
+            exception(Exception::SysTick, ExceptionAction::Returned),
+            software(1, 0),
+            software(2, 0),
+            exception(Exception::SysTick, ExceptionAction::Exited),
+        ]));
+        assert_eq!(
+            depths(chunk),
+            expected(&[
+                ("low", 1),
+                ("high", 2),
+                ("high", 2),
+                ("high", 2),
+                ("high", 2),
+                ("low", 1),
+                ("foo", 2),
+                ("foo", 2),
+                ("low", 1),
+            ])
+        );
+
+        // The exit of app::high is lost: the return to app::low resumes
+        // it regardless.
+        let chunk = metadata.build_event_chunk(packets(vec![
+            exception(Exception::SysTick, ExceptionAction::Entered),
+            exception(Exception::PendSV, ExceptionAction::Entered),
+            exception(Exception::SysTick, ExceptionAction::Returned),
+            exception(Exception::PendSV, ExceptionAction::Entered),
+        ]));
+        assert_eq!(
+            depths(chunk),
+            expected(&[("low", 1), ("high", 2), ("low", 1), ("high", 2)])
+        );
+    }
+
+    fn software(comparator: u8, id: u8) -> TracePacket {
+        TracePacket::DataTraceValue {
+            comparator,
+            access_type: MemoryAccessType::Write,
+            value: vec![id],
+        }
+    }
+
+    /// Ensure that the completion of an async software task is
+    /// resolved once its exit has been tracked.
+    #[test]
+    fn software_completion() {
+        let mut maps = maps();
+        maps.software.comparators.insert(3, TaskAction::Completed);
+        let metadata = TraceMetadata::from(
+            "test".to_string(),
+            maps,
+            Local::now(),
+            16_000_000,
+            None,
+            None,
+        );
+
+        let chunk = metadata.build_event_chunk(packets(vec![
+            software(1, 0),
+            software(2, 0),
+            software(3, 0),
+        ]));
+        let actions: Vec<&str> = chunk
+            .events
+            .iter()
+            .map(|event| match event {
+                EventType::Task { name, action, .. } if name == "app::foo" => match action {
+                    TaskAction::Entered => "entered",
+                    TaskAction::Exited => "exited",
+                    TaskAction::Returned => "returned",
+                    TaskAction::Completed => "completed",
+                },
+                e => panic!("unexpected event {:?}", e),
+            })
+            .collect();
+        assert_eq!(actions, ["entered", "exited", "completed"]);
+        assert!(metadata.state.borrow().software_stack.is_empty());
+    }
+
+    /// Ensure that packets are dropped after a run of malformed packets
+    /// until the next synchronization packet, that the drop is reported
+    /// once, and that no task is considered executing after it.
+    #[test]
+    fn resync() {
+        let mut metadata = metadata();
+        metadata.resync_after(Some(2));
+        metadata.build_event_chunk(packets(vec![exception(
+            cortex_m::peripheral::scb::Exception::SysTick,
+            ExceptionAction::Entered,
+        )]));
+        metadata
+            .state
+            .borrow_mut()
+            .software_stack
+            .push("app::foo".to_string());
+        let chunk = |packets: Vec<TracePacket>, malformed: usize| TimestampedTracePackets {
+            malformed_packets: vec![MalformedPacket::InvalidHeader(0xff); malformed],
+            ..self::packets(packets)
+        };
+        let kinds = |chunk: EventChunk| -> Vec<String> {
+            chunk
+                .events
+                .iter()
+                .map(|event| match event {
+                    EventType::Resynced(dropped) => format!("resynced {}", dropped),
+                    event => format!("{:?}", event.kind()),
+                })
+                .collect()
+        };
+
+        // Below the threshold, malformed packets are reported.
+        assert_eq!(
+            kinds(metadata.build_event_chunk(chunk(vec![TracePacket::Overflow], 1))),
+            ["Overflow", "Invalid"]
+        );
+        // The run reaches the threshold: the chunk is dropped.
+        assert!(
+            kinds(metadata.build_event_chunk(chunk(vec![TracePacket::Overflow], 1))).is_empty()
+        );
+        assert!(
+            kinds(metadata.build_event_chunk(chunk(vec![TracePacket::Overflow], 2))).is_empty()
+        );
+        // Packets after the synchronization packet are mapped.
+        assert_eq!(
+            kinds(metadata.build_event_chunk(chunk(
+                vec![
+                    TracePacket::Overflow,
+                    TracePacket::Sync,
+                    TracePacket::Overflow
+                ],
+                1
+            ))),
+            ["resynced 7", "Overflow"]
+        );
+        assert!(metadata.state.borrow().software_stack.is_empty());
+        assert!(metadata.state.borrow().active.depth() == 0);
+        // The run begins anew.
+        assert_eq!(
+            kinds(metadata.build_event_chunk(chunk(vec![], 1))),
+            ["Invalid"]
+        );
+    }
+
+    /// Ensure that timestamps after a TPIU clock change marker are
+    /// converted at the new frequency.
+    #[test]
+    fn clock_change() {
+        let mut metadata = metadata();
+        metadata.set_clock_change_port(Some(5));
+        let chunk = |ms, packets| TimestampedTracePackets {
+            timestamp: itm::Timestamp::Sync(Duration::from_millis(ms)),
+            consumed_packets: 1,
+            packets,
+            malformed_packets: vec![],
+        };
+
+        // 16 MHz -> 8 MHz at 1 ms
+        let before = metadata.build_event_chunk(chunk(
+            1,
+            vec![TracePacket::Instrumentation {
+                port: 5,
+                payload: 8_000_000u32.to_le_bytes().to_vec(),
+            }],
+        ));
+        assert!(
+            matches!(before.timestamp, itm::Timestamp::Sync(t) if t == Duration::from_millis(1))
+        );
+        assert!(before.events.is_empty());
+
+        // 16000 cycles after the change at 8 MHz
+        let after = metadata.build_event_chunk(chunk(2, vec![TracePacket::Overflow]));
+        assert!(
+            matches!(after.timestamp, itm::Timestamp::Sync(t) if t == Duration::from_millis(3))
+        );
+
+        assert_eq!(
+            *metadata.clock_segments.borrow(),
+            vec![ClockSegment {
+                cycle: 16_000,
+                freq: 8_000_000,
+                start: Duration::from_millis(1),
+            }]
+        );
+    }
+
+    /// Ensure that the payloads written to log ports are presented as
+    /// lines, also when a line spans several packets, and that other
+    /// ports are still mapped.
+    #[test]
+    fn log_ports() {
+        let mut metadata = metadata();
+        metadata.set_log_ports(&[1]);
+        let write = |port, payload: &[u8]| TracePacket::Instrumentation {
+            port,
+            payload: payload.to_vec(),
+        };
+
+        let chunk = metadata.build_event_chunk(packets(vec![
+            write(1, b"he"),
+            write(1, b"llo"),
+            write(1, b"\r\nwor"),
+            write(2, b"\n"),
+        ]));
+        assert!(matches!(
+            chunk.events.as_slice(),
+            [
+                EventType::Log { port: 1, message },
+                EventType::Unknown(TracePacket::Instrumentation { port: 2, .. }),
+            ] if message == "hello"
+        ));
+
+        let chunk = metadata.build_event_chunk(packets(vec![write(1, b"ld\n\n")]));
+        let messages: Vec<&str> = chunk
+            .events
+            .iter()
+            .map(|event| match event {
+                EventType::Log { message, .. } => message.as_str(),
+                event => panic!("unexpected event {:?}", event),
+            })
+            .collect();
+        assert_eq!(messages, ["world", ""]);
+    }
+
+    /// Ensure that software tasks that systematically exit before they
+    /// are entered are reported once as swapped comparators, and that
+    /// swapping the comparators resolves the trace.
+    #[test]
+    fn swapped_comparators() {
+        let metadata = metadata();
+        metadata.build_event_chunk(packets(vec![software(1, 0), software(2, 0)]));
+        assert!(metadata.check_comparator_roles().is_none());
+
+        // The firmware enters via comparator 2 and exits via comparator 1.
+        let mut metadata = self::metadata();
+        metadata.build_event_chunk(packets(vec![
+            software(2, 0),
+            software(1, 0),
+            software(2, 1),
+            software(1, 1),
+        ]));
+        assert!(matches!(
+            metadata.check_comparator_roles(),
+            Some(RecoveryError::SwappedComparators(2, 2))
+        ));
+        assert!(metadata.check_comparator_roles().is_none());
+
+        metadata.swap_comparators();
+        let chunk = metadata.build_event_chunk(packets(vec![software(2, 0)]));
+        assert!(matches!(
+            chunk.events[..],
+            [EventType::Task {
+                action: TaskAction::Entered,
+                ..
+            }]
+        ));
+    }
+
+    /// Ensure that nested software tasks are paired correctly.
+    #[test]
+    fn nested_software_tasks() {
+        use cortex_m::peripheral::scb::Exception;
+
+        let metadata = metadata();
+        let chunk = metadata.build_event_chunk(packets(vec![
+            exception(Exception::SVCall, ExceptionAction::Entered),
+            software(1, 0), // enter app::foo
+            exception(Exception::SVCall, ExceptionAction::Entered),
+            software(1, 1), // enter app::bar, preempting app::foo
+            software(2, 1), // exit app::bar
+            exception(Exception::SVCall, ExceptionAction::Returned),
+            software(2, 0), // exit app::foo
+        ]));
+
+        let events: Vec<(String, &'static str, Option<u8>)> = chunk
+            .events
+            .iter()
+            .map(|e| match e {
+                EventType::Task {
+                    name,
+                    action,
+                    priority,
+                    ..
+                } => (
+                    name.clone(),
+                    match action {
+                        TaskAction::Entered => "entered",
+                        TaskAction::Exited => "exited",
+                        TaskAction::Returned => "returned",
+                        TaskAction::Completed => "completed",
+                    },
+                    *priority,
+                ),
+                e => panic!("unexpected event {:?}", e),
+            })
+            .collect();
+        assert_eq!(
+            events,
+            [
+                ("app::foo".to_string(), "entered", Some(1)),
+                ("app::bar".to_string(), "entered", Some(2)),
+                ("app::bar".to_string(), "exited", Some(2)),
+                ("app::foo".to_string(), "returned", Some(1)),
+                ("app::foo".to_string(), "exited", Some(1)),
+            ]
+        );
+        assert!(metadata.state.borrow().software_stack.is_empty());
+    }
+
+    /// Ensure that a user-declared internal exception is not resolved
+    /// as an external interrupt.
+    #[test]
+    fn user_internal_exception() {
+        let src = quote!(
+            #[rtic::app(device = stm32f4::stm32f401)]
+            mod app {
+                #[shared]
+                struct Shared {}
+
+                #[local]
+                struct Local {}
+
+                #[init]
+                fn init(_: init::Context) -> (Shared, Local, init::Monotonics) {
+                    (Shared {}, Local {}, init::Monotonics())
+                }
+
+                #[task(binds = SysTick)]
+                fn core(_: core::Context) {}
+
+                #[task(binds = VendorFault)]
+                fn vendor(_: vendor::Context) {}
+
+                #[task(binds = EXTI0)]
+                fn external(_: external::Context) {}
+            }
+        );
+        let (app, _ast) = TraceLookupMaps::parse_rtic_app(src, Path::new("")).unwrap();
+        let user_internal = IndexMap::from_iter([("VendorFault".to_string(), 116)]);
+
+        let (known, unknown) =
+            HardwareMap::partition_binds(&app, &user_internal, Some(ArchProfile::V7M)).unwrap();
+        assert_eq!(
+            known.get(&VectActive::from(116).unwrap()).unwrap(),
+            &["app", "vendor"]
+        );
+        assert!(known
+            .get(&VectActive::Exception(
+                cortex_m::peripheral::scb::Exception::SysTick
+            ))
+            .is_some());
+        assert_eq!(unknown.len(), 1);
+        assert_eq!(unknown.get("EXTI0").unwrap(), "external");
+    }
+
+    /// Ensure that an interrupt bound by several tasks is rejected.
+    #[test]
+    fn duplicate_binds() {
+        let binds = |pairs: &[(&str, &str)]| {
+            pairs
+                .iter()
+                .map(|(bind, task)| (bind.to_string(), task.to_string()))
+                .collect::<Vec<_>>()
+        };
+
+        assert!(HardwareMap::check_binds(&binds(&[("EXTI0", "a"), ("EXTI1", "b")])).is_ok());
+        match HardwareMap::check_binds(&binds(&[("EXTI0", "a"), ("SysTick", "b"), ("EXTI0", "c")]))
+        {
+            Err(RecoveryError::DuplicateBind(bind, first, second)) => {
+                assert_eq!(
+                    (bind.as_str(), first.as_str(), second.as_str()),
+                    ("EXTI0", "a", "c")
+                );
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    /// Ensure that tasks of all kinds can be looked up by name.
+    #[test]
+    fn reverse_lookup() {
+        use cortex_m::peripheral::scb::Exception;
+
+        let mut maps = metadata().maps;
+        maps.hardware.0.insert(
+            VectActive::Interrupt { irqn: 5 },
+            vec!["app".to_string(), "ext".to_string()],
+        );
+        // ambiguous: also a software task
+        maps.software
+            .map
+            .insert(7, vec!["app".to_string(), "ext".to_string()]);
+
+        assert_eq!(
+            maps.lookup("app::low"),
+            vec![TaskId::Hardware(VectActive::Exception(Exception::SysTick))]
+        );
+        assert_eq!(maps.lookup("app::bar"), vec![TaskId::Software(1)]);
+        assert_eq!(
+            maps.lookup("app::ext"),
+            vec![
+                TaskId::Hardware(VectActive::Interrupt { irqn: 5 }),
+                TaskId::Software(7)
+            ]
+        );
+        assert!(maps.lookup("app::nonexistent").is_empty());
+
+        assert!(matches!(
+            maps.exception_number(&["app", "ext"]),
+            Some(VectActive::Interrupt { irqn: 5 })
+        ));
+        assert!(matches!(
+            maps.exception_number(&["app::low"]),
+            Some(VectActive::Exception(Exception::SysTick))
+        ));
+        assert!(maps.exception_number(&["app", "foo"]).is_none());
+        assert_eq!(maps.software_ids(&["app", "bar"]), [1]);
+        assert_eq!(maps.software_ids(&["app::bar"]), [1]);
+        assert!(maps.software_ids(&["app", "low"]).is_empty());
+    }
+
+    /// Ensure that task names are listed per kind, without duplicates.
+    #[test]
+    fn task_names() {
+        let mut metadata = metadata();
+        metadata
+            .maps
+            .software
+            .map
+            .insert(2, vec!["app".to_string(), "foo".to_string()]);
+
+        assert_eq!(metadata.hardware_task_names(), ["app::low", "app::high"]);
+        assert_eq!(metadata.software_task_names(), ["app::foo", "app::bar"]);
+        assert_eq!(metadata.software_tasks_len(), 3);
+    }
+
+    /// Ensure that external interrupts can be resolved purely from the
+    /// user-supplied table.
+    #[test]
+    fn user_interrupt_table() {
+        let table = IndexMap::from_iter([("EXTI0".to_string(), 6), ("USART1".to_string(), 37)]);
+        let (resolved, remaining) = resolve_user_int_nrs(
+            &table,
+            vec![
+                "EXTI0".to_string(),
+                "USART1".to_string(),
+                "TIM2".to_string(),
+            ],
+        )
+        .unwrap();
+
+        assert_eq!(
+            resolved.get("EXTI0").unwrap(),
+            &VectActive::Interrupt { irqn: 6 }
+        );
+        assert_eq!(
+            resolved.get("USART1").unwrap(),
+            &VectActive::Interrupt { irqn: 37 }
+        );
+        assert_eq!(remaining, vec!["TIM2".to_string()]);
+    }
+
+    /// Ensure that IRQ numbers are read from the interrupts of all
+    /// peripherals of an SVD file.
+    #[test]
+    fn svd_interrupt_numbers() {
+        let svd = r#"<?xml version="1.0" encoding="utf-8"?>
+<device schemaVersion="1.1" xmlns:xs="http://www.w3.org/2001/XMLSchema-instance">
+  <name>STM32F401</name>
+  <version>1.0</version>
+  <description>STM32F401</description>
+  <addressUnitBits>8</addressUnitBits>
+  <width>32</width>
+  <size>0x20</size>
+  <resetValue>0x0</resetValue>
+  <resetMask>0xFFFFFFFF</resetMask>
+  <peripherals>
+    <peripheral>
+      <name>EXTI</name>
+      <baseAddress>0x40013C00</baseAddress>
+      <interrupt>
+        <name>EXTI0</name>
+        <value>6</value>
+      </interrupt>
+      <interrupt>
+        <name>EXTI1</name>
+        <value>7</value>
+      </interrupt>
+    </peripheral>
+    <peripheral>
+      <name>USART1</name>
+      <baseAddress>0x40011000</baseAddress>
+      <interrupt>
+        <name>USART1</name>
+        <value>37</value>
+      </interrupt>
+    </peripheral>
+  </peripherals>
+</device>"#;
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("STM32F401.svd");
+        fs::write(&path, svd).unwrap();
+
+        let (resolved, remaining) = resolve_svd_int_nrs(
+            &path,
+            vec![
+                "EXTI1".to_string(),
+                "USART1".to_string(),
+                "TIM2".to_string(),
+            ],
+        )
+        .unwrap();
+        assert_eq!(
+            resolved.get("EXTI1").unwrap(),
+            &VectActive::Interrupt { irqn: 7 }
+        );
+        assert_eq!(
+            resolved.get("USART1").unwrap(),
+            &VectActive::Interrupt { irqn: 37 }
+        );
+        assert_eq!(remaining, vec!["TIM2".to_string()]);
+    }
+
+    /// Ensure that cached IRQ numbers are only read back for the PAC
+    /// they were resolved with, also if only the resolved version of
+    /// the PAC changed.
+    #[test]
+    fn adhoc_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cache.json");
+        let mut pacp = crate::manifest::test::properties();
+        pacp.pac_name = "stm32f4".to_string();
+        pacp.pac_version = "0.13".to_string();
+        let id = |version: &str| {
+            format!(
+                "stm32f4 {} (registry+https://github.com/rust-lang/crates.io-index)",
+                version
+            )
+        };
+
+        let mut cache = AdhocCache::read(&path, id("0.13.0"), &pacp);
+        assert!(cache.irqns.is_empty());
+        cache.irqns.insert("EXTI0".to_string(), 6);
+        cache.write(&path).unwrap();
+        assert_eq!(AdhocCache::read(&path, id("0.13.0"), &pacp), cache);
+
+        assert!(AdhocCache::read(&path, id("0.14.0"), &pacp)
+            .irqns
+            .is_empty());
+        pacp.pac_features = vec!["stm32f401".to_string()];
+        assert!(AdhocCache::read(&path, id("0.13.0"), &pacp)
+            .irqns
+            .is_empty());
+    }
+
+    /// Ensure that the IRQ number of a PAC generated by an older
+    /// svd2rust is resolved via `nr()`.
+    #[test]
+    fn interrupt_method_nr() {
+        let src = r#"
+            #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+            #[repr(u8)]
+            pub enum Interrupt {
+                #[doc = "6 - EXTI Line0 interrupt"]
+                EXTI0 = 6,
+            }
+            unsafe impl bare_metal::Nr for Interrupt {
+                #[inline(always)]
+                fn nr(&self) -> u8 {
+                    *self as u8
+                }
+            }
+        "#;
+        let method = InterruptMethod::detect(src).unwrap();
+        assert_eq!(method, InterruptMethod::Nr);
+        assert_eq!(
+            method.call(&format_ident!("EXTI0")).to_string(),
+            quote!(Interrupt::EXTI0.nr() as u16).to_string()
+        );
+        assert!(method.dependency().is_some());
+
+        assert_eq!(
+            InterruptMethod::detect(
+                "unsafe impl cortex_m::interrupt::InterruptNumber for Interrupt {}"
+            ),
+            Some(InterruptMethod::Number)
+        );
+        assert_eq!(InterruptMethod::detect("pub enum Interrupt {}"), None);
+    }
+
+    /// Ensure that overrides take precedence over recovered
+    /// associations.
+    #[test]
+    fn software_task_overrides() {
+        let mut maps = metadata().maps;
+        let overrides: SoftwareTaskOverrides =
+            serde_json::from_str(r#"{ "tasks": { "1": "app::baz", "2": "app::qux" } }"#).unwrap();
+        maps.software.apply_overrides(overrides);
+
+        let name = |id: u8| match maps.resolve_software_task(&1, &[id]) {
+            Ok(Some(EventType::Task { name, .. })) => name,
+            _ => panic!("failed to resolve software task {}", id),
+        };
+        assert_eq!(name(0), "app::foo");
+        assert_eq!(name(1), "app::baz");
+        assert_eq!(name(2), "app::qux");
+        assert!(maps.software.overrides.is_some());
+
+        let overrides: SoftwareTaskOverrides =
+            serde_json::from_str(r#"{ "replace": true, "tasks": { "2": "app::qux" } }"#).unwrap();
+        maps.software.apply_overrides(overrides);
+        assert!(maps.resolve_software_task(&1, &[0]).is_err());
+    }
+
+    /// Ensure that traces of the previous format version are migrated
+    /// and traces of a newer version are rejected.
+    #[test]
+    fn format_version() {
+        let mut header = serde_json::to_value(metadata()).unwrap();
+        assert_eq!(header["format_version"], FORMAT_VERSION);
+
+        // Recorded before the format was versioned.
+        let map = header.as_object_mut().unwrap();
+        map.remove("format_version");
+        map.remove("rtic_scope_version");
+        map.remove("bookmarks");
+        let migrated = TraceMetadata::from_header(header.clone()).unwrap();
+        assert_eq!(migrated.format_version, FORMAT_VERSION);
+        assert!(migrated.rtic_scope_version.is_none());
+
+        header["format_version"] = (FORMAT_VERSION + 1).into();
+        header["rtic_scope_version"] = "99.0.0".into();
+        assert!(matches!(
+            TraceMetadata::from_header(header),
+            Err(SourceError::IncompatibleFormat(v, Some(ref rtic_scope)))
+                if v == FORMAT_VERSION as u64 + 1 && rtic_scope == "99.0.0"
+        ));
+    }
+
+    /// Ensure that the summary line leads the metadata description.
+    #[test]
+    fn display_summary() {
+        let mut metadata = metadata();
+        metadata.comment = Some("a comment".to_string());
+        let display = metadata.to_string();
+        let mut lines = display.lines();
+
+        assert!(lines
+            .next()
+            .unwrap()
+            .starts_with("test: 2 hardware tasks, 2 software tasks, traced at 16 MHz from "));
+        assert_eq!(lines.next().unwrap(), "comment: a comment");
+    }
+
+    /// Ensure that a traced #[init] is resolved by its path.
+    #[test]
+    fn parse_traced_init() {
+        let ast = quote!(
+            mod app {
+                #[init]
+                #[trace]
+                fn init(_: init::Context) -> (Shared, Local, init::Monotonics) {
+                    (Shared {}, Local {}, init::Monotonics())
+                }
+
+                #[trace]
+                fn foo() {}
+            }
+        );
+        let map = SoftwareMap::parse_ast(ast, &SourceContext::default()).unwrap();
+        assert_eq!(map.get(&0).unwrap(), &["app", "init"]);
+        assert_eq!(map.get(&1).unwrap(), &["app", "foo"]);
+
+        let ast = quote!(
+            mod app {
+                #[trace]
+                #[init]
+                fn setup(_: setup::Context) -> (Shared, Local, setup::Monotonics) {
+                    (Shared {}, Local {}, setup::Monotonics())
+                }
+            }
+        );
+        let map = SoftwareMap::parse_ast(ast, &SourceContext::default()).unwrap();
+        assert_eq!(map.get(&0).unwrap(), &["app", "setup"]);
+    }
+
+    /// Ensure that functions in modules that are configured out are not
+    /// assigned IDs.
+    #[test]
+    fn parse_cfg_gated_module() {
+        let ast = quote!(
+            mod app {
+                #[cfg(feature = "x")]
+                mod gated {
+                    #[trace]
+                    fn foo() {}
+                }
+
+                #[cfg_attr(not(feature = "x"), cfg(any()))]
+                mod also_gated {
+                    #[trace]
+                    fn bar() {}
+                }
+
+                #[trace]
+                fn baz() {}
+            }
+        );
+
+        let map = SoftwareMap::parse_ast(ast.clone(), &SourceContext::default()).unwrap();
+        assert_eq!(map.len(), 1);
+        assert_eq!(map.get(&0).unwrap(), &["app", "baz"]);
+
+        let src = SourceContext {
+            features: vec!["x".to_string()],
+            ..SourceContext::default()
+        };
+        let map = SoftwareMap::parse_ast(ast, &src).unwrap();
+        assert_eq!(map.len(), 3);
+        assert_eq!(map.get(&0).unwrap(), &["app", "gated", "foo"]);
+        assert_eq!(map.get(&1).unwrap(), &["app", "also_gated", "bar"]);
+        assert_eq!(map.get(&2).unwrap(), &["app", "baz"]);
+    }
+
+    /// Ensure that traced functions gated on `debug_assertions` follow
+    /// the build profile, and that options which cannot be evaluated
+    /// are detected.
+    #[test]
+    fn parse_cfg_gated_functions() {
+        let ast = quote!(
+            mod app {
+                #[cfg(debug_assertions)]
+                #[trace]
+                fn debug() {}
+
+                #[cfg(all(feature = "x", target_os = "none"))]
+                #[trace]
+                fn target() {}
+
+                #[trace]
+                fn baz() {}
+            }
+        );
+        let paths = |src: &SourceContext| -> Vec<String> {
+            SoftwareMap::parse_ast(ast.clone(), src)
+                .unwrap()
+                .values()
+                .map(|path| path.join("::"))
+                .collect()
+        };
+
+        assert_eq!(paths(&SourceContext::default()), ["app::baz"]);
+        let src = SourceContext {
+            features: vec!["x".to_string()],
+            debug_assertions: true,
+            ..SourceContext::default()
+        };
+        assert_eq!(paths(&src), ["app::debug", "app::target", "app::baz"]);
+
+        let attrs = |attrs: TokenStream| {
+            syn::parse2::<syn::ItemFn>(quote!(#attrs fn f() {}))
+                .unwrap()
+                .attrs
+        };
+        assert!(SourceContext::uncertain(&attrs(quote!(
+            #[cfg(any(feature = "x", not(debug_assertions)))]
+            #[trace]
+        )))
+        .is_empty());
+        assert_eq!(
+            SourceContext::uncertain(&attrs(quote!(
+                #[cfg(all(feature = "x", target_os = "none"))]
+                #[cfg_attr(target_arch = "arm", inline)]
+            ))),
+            ["target_os=\"none\"", "target_arch=\"arm\""]
+        );
+    }
+
+    /// Ensure that visibility modifiers neither hide traced functions
+    /// nor affect the ID order.
+    #[test]
+    fn parse_visibility() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("app")).unwrap();
+        fs::write(
+            dir.path().join("app/tasks.rs"),
+            "#[trace] pub fn foo() {} pub(crate) mod inner { #[trace] pub(super) fn bar() {} }",
+        )
+        .unwrap();
+
+        let ast = quote!(
+            mod app {
+                pub mod tasks;
+                pub use tasks::foo;
+
+                #[trace]
+                pub fn a() {}
+
+                pub(crate) mod scope {
+                    #[trace]
+                    pub(in crate::app) fn b() {}
+
+                    #[trace]
+                    fn c() {}
+                }
+
+                #[trace]
+                fn d() {}
+            }
+        );
+        let src = SourceContext {
+            dir: dir.path().to_owned(),
+            ..SourceContext::default()
+        };
+        let map = SoftwareMap::parse_ast(ast, &src).unwrap();
+        let paths: Vec<String> = map.values().map(|path| path.join("::")).collect();
+        assert_eq!(
+            paths,
+            [
+                "app::tasks::foo",
+                "app::tasks::inner::bar",
+                "app::a",
+                "app::scope::b",
+                "app::scope::c",
+                "app::d"
+            ]
+        );
+        assert_eq!(
+            map.keys().copied().collect::<Vec<_>>(),
+            (0..6).collect::<Vec<_>>()
+        );
+    }
+
+    /// Ensure that the IDs of the functions traced after a helper
+    /// attribute that expands to `#[trace]` drift unless the helper is
+    /// declared.
+    #[test]
+    fn parse_trace_helpers() {
+        // #[traced_task] expands to #[trace] on the function it
+        // decorates, so the firmware assigns `a`: 0, `b`: 1, `c`: 2.
+        let ast = quote!(
+            mod app {
+                #[trace]
+                fn a() {}
+
+                #[traced_task]
+                fn b() {}
+
+                #[trace]
+                fn c() {}
+            }
+        );
+        let ids = |map: IndexMap<usize, Vec<String>>| -> Vec<(usize, String)> {
+            map.into_iter()
+                .map(|(id, path)| (id, path.join("::")))
+                .collect()
+        };
+
+        let map = SoftwareMap::parse_ast(ast.clone(), &SourceContext::default()).unwrap();
+        assert_eq!(
+            ids(map),
+            [(0, "app::a".to_string()), (1, "app::c".to_string())]
+        );
+
+        let src = SourceContext {
+            trace_helpers: IndexMap::from_iter([("traced_task".to_string(), 1)]),
+            ..SourceContext::default()
+        };
+        let map = SoftwareMap::parse_ast(ast, &src).unwrap();
+        assert_eq!(
+            ids(map),
+            [
+                (0, "app::a".to_string()),
+                (1, "app::b".to_string()),
+                (2, "app::c".to_string())
+            ]
+        );
+    }
+
+    /// Ensure that explicitly requested IDs are mirrored, and that
+    /// automatically assigned IDs skip them.
+    #[test]
+    fn parse_explicit_ids() {
+        let ast = quote!(
+            mod app {
+                #[trace]
+                fn a() {}
+
+                #[trace(id = 1)]
+                fn b() {}
+
+                #[trace]
+                fn c() {}
+
+                #[trace(id = 10)]
+                fn d() {}
+
+                #[trace]
+                fn e() {}
+            }
+        );
+
+        let map = SoftwareMap::parse_ast(ast, &SourceContext::default()).unwrap();
+        assert_eq!(
+            map.into_iter()
+                .map(|(id, path)| (id, path.join("::")))
+                .collect::<Vec<_>>(),
+            [
+                (0, "app::a".to_string()),
+                (1, "app::b".to_string()),
+                (2, "app::c".to_string()),
+                (10, "app::d".to_string()),
+                (3, "app::e".to_string()),
+            ]
+        );
+    }
+
+    /// Ensure that methods in impl blocks and async functions are
+    /// resolved, with methods named by their Self type.
+    #[test]
+    fn parse_impl_methods() {
+        let ast = quote!(
+            mod app {
+                #[trace]
+                async fn a() {}
+
+                impl Shared {
+                    #[trace]
+                    async fn method(&self) {}
+
+                    fn untraced(&self) {}
+
+                    #[trace]
+                    pub fn other(&mut self) {}
+                }
+
+                impl<T> Trait for &T {
+                    #[trace]
+                    fn unnamed(&self) {}
+                }
+
+                #[trace]
+                fn b() {}
+            }
+        );
+
+        let map = SoftwareMap::parse_ast(ast, &SourceContext::default()).unwrap();
+        assert_eq!(
+            map.into_iter()
+                .map(|(id, path)| (id, path.join("::")))
+                .collect::<Vec<_>>(),
+            [
+                (0, "app::a".to_string()),
+                (1, "app::Shared::method".to_string()),
+                (2, "app::Shared::other".to_string()),
+                (3, "app::unnamed".to_string()),
+                (4, "app::b".to_string()),
+            ]
+        );
+    }
+
+    /// Ensure that path-remapped modules are followed.
+    #[test]
+    fn parse_path_remapped_module() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("app")).unwrap();
+        fs::write(dir.path().join("app/remapped.rs"), "#[trace] fn foo() {}").unwrap();
+
+        let ast = quote!(
+            mod app {
+                #[cfg_attr(feature = "x", path = "remapped.rs")]
+                #[cfg_attr(not(feature = "x"), path = "missing.rs")]
+                mod tasks;
+
+                #[trace]
+                fn bar() {}
+            }
+        );
+        let src = SourceContext {
+            features: vec!["x".to_string()],
+            dir: dir.path().to_owned(),
+            ..SourceContext::default()
+        };
+        let map = SoftwareMap::parse_ast(ast, &src).unwrap();
+        assert_eq!(map.get(&0).unwrap(), &["app", "tasks", "foo"]);
+        assert_eq!(map.get(&1).unwrap(), &["app", "bar"]);
+    }
+
+    /// Ensure that tasks declared in an `include!`d file are part of
+    /// the application, in declaration order.
+    #[test]
+    fn parse_included_tasks() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("tasks")).unwrap();
+        fs::write(
+            dir.path().join("tasks/mod.rs"),
+            r#"
+            #[task(binds = SysTick)]
+            fn tick(_: tick::Context) {}
+
+            include!("software.rs");
+            "#,
+        )
+        .unwrap();
+        fs::write(
+            dir.path().join("tasks/software.rs"),
+            "#[task] fn foo(_: foo::Context) { #[trace] fn traced() {} }",
+        )
+        .unwrap();
+
+        let src = quote!(
+            #[rtic::app(device = stm32f4::stm32f401, dispatchers = [EXTI1])]
+            mod app {
+                #[shared]
+                struct Shared {}
+
+                #[local]
+                struct Local {}
+
+                #[init]
+                fn init(_: init::Context) -> (Shared, Local, init::Monotonics) {
+                    (Shared {}, Local {}, init::Monotonics())
+                }
+
+                #[trace]
+                fn before() {}
+
+                include!("tasks/mod.rs");
+
+                #[trace]
+                fn after() {}
+            }
+        );
+        let (app, ast) = TraceLookupMaps::parse_rtic_app(src, dir.path()).unwrap();
+        assert!(app.hardware_tasks.contains_key(&format_ident!("tick")));
+        assert!(app.software_tasks.contains_key(&format_ident!("foo")));
+
+        let map = SoftwareMap::parse_ast(ast, &SourceContext::default()).unwrap();
+        assert_eq!(map.get(&0).unwrap(), &["app", "before"]);
+        assert_eq!(map.get(&1).unwrap(), &["app", "foo", "traced"]);
+        assert_eq!(map.get(&2).unwrap(), &["app", "after"]);
+
+        // A file that includes itself
+        fs::write(dir.path().join("cycle.rs"), "include!(\"cycle.rs\");").unwrap();
+        assert!(matches!(
+            splice_includes(quote!(include!("cycle.rs");), dir.path(), &mut vec![]),
+            Err(RecoveryError::IncludeCycle(_))
+        ));
+    }
+
+    /// Ensure that a trace recorded before the ID convention was
+    /// tracked, with a numbering that differs from the current
+    /// convention, is replayed with the stored associations.
+    #[test]
+    fn replay_legacy_numbering() {
+        let mut json = serde_json::to_value(&metadata()).unwrap();
+        let map = &mut json["maps"]["software"];
+        map.as_object_mut().unwrap().remove("id_convention");
+        map["map"] = serde_json::json!([[5, ["app", "legacy"]]]);
+
+        let metadata: TraceMetadata = serde_json::from_value(json).unwrap();
+        assert_eq!(metadata.maps.software.id_convention, 0);
+
+        let chunk = metadata.build_event_chunk(packets(vec![software(1, 5)]));
+        assert!(matches!(
+            chunk.events.as_slice(),
+            [EventType::Task { name, action: TaskAction::Entered, .. }] if name == "app::legacy"
+        ));
+    }
+
+    /// Ensure that a generic traced function is assigned a single ID
+    /// and does not misalign the IDs of subsequent functions.
+    #[test]
+    fn parse_generic_trace() {
+        let ast = quote!(
+            mod app {
+                #[trace]
+                fn generic<T: Copy, const N: usize>(_: [T; N]) {}
+
+                #[trace]
+                fn after() {}
+            }
+        );
+
+        let map = SoftwareMap::parse_ast(ast, &SourceContext::default()).unwrap();
+        assert_eq!(map.len(), 2);
+        assert_eq!(map.get(&0).unwrap(), &["app", "generic"]);
+        assert_eq!(map.get(&1).unwrap(), &["app", "after"]);
+    }
+
+    /// Ensure the build profile is recorded for debug and release
+    /// artifacts.
+    #[test]
+    fn build_profile() {
+        let profile = |opt_level: &str, debug: bool| -> ArtifactProfile {
+            serde_json::from_value(serde_json::json!({
+                "opt_level": opt_level,
+                "debuginfo": if debug { Some(2) } else { None },
+                "debug_assertions": debug,
+                "overflow_checks": debug,
+                "test": false,
+            }))
+            .unwrap()
+        };
+
+        let debug = BuildProfile::from_profile(&profile("0", true), None);
+        assert_eq!(debug.name(), "debug");
+        assert_eq!(debug.opt_level, "0");
+        assert!(debug.debug_assertions);
+
+        let release =
+            BuildProfile::from_profile(&profile("3", false), Some("-C target-cpu=native".into()));
+        assert_eq!(release.name(), "release");
+        assert_eq!(release.opt_level, "3");
+        assert!(!release.debug_assertions);
+        assert_eq!(release.rustflags.as_deref(), Some("-C target-cpu=native"));
+        assert_ne!(debug, release);
+    }
+
+    /// Ensure that provenance is presented.
+    #[test]
+    fn provenance() {
+        let provenance = Provenance {
+            git_commit: Some("0123abc".to_string()),
+            git_dirty: true,
+            rustc_version: Some("rustc 1.60.0".to_string()),
+            target: Some("thumbv7em-none-eabihf".to_string()),
+            ..Provenance::default()
+        };
+        assert_eq!(
+            provenance.to_string(),
+            "commit 0123abc (dirty), target thumbv7em-none-eabihf, rustc 1.60.0"
+        );
+    }
+
+    /// Ensure that a traced software task ID beyond the recovered maps
+    /// is reported once.
+    #[test]
+    fn software_id_out_of_range() {
+        let metadata = metadata();
+        metadata.build_event_chunk(packets(vec![software(1, 0), software(2, 0)]));
+        assert!(metadata.check_software_ids().is_none());
+
+        metadata.build_event_chunk(packets(vec![software(1, 7), software(1, 9)]));
+        assert!(matches!(
+            metadata.check_software_ids(),
+            Some(RecoveryError::SoftwareIdOutOfRange(7, 2))
+        ));
+        assert!(metadata.check_software_ids().is_none());
+    }
+
+    /// Ensure that the source hash is insensitive to comments and
+    /// formatting, but not to the tokens of the source.
+    #[test]
+    fn source_hashes() {
+        let hash = |src: &str| fnv1a(src.parse::<TokenStream>().unwrap().to_string().as_bytes());
+        assert_eq!(
+            hash("#[trace] fn foo() {}"),
+            hash("// foo\n#[trace]\nfn foo() {\n}")
+        );
+        assert_ne!(hash("#[trace] fn foo() {}"), hash("fn foo() {}"));
+        assert_eq!(fnv1a(b""), 0xcbf2_9ce4_8422_2325);
+    }
+
+    /// Ensure that a source file modified after the executable was
+    /// built is reported.
+    #[test]
+    fn stale_artifact() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("main.rs");
+        let executable = dir.path().join("app");
+        let now = std::time::SystemTime::now();
+        for (path, modified) in [(&src, now), (&executable, now - Duration::from_secs(60))] {
+            fs::File::create(path)
+                .unwrap()
+                .set_modified(modified)
+                .unwrap();
+        }
+        assert!(matches!(
+            stale_source(&src, &executable),
+            Some(RecoveryError::StaleArtifact(_, _))
+        ));
+        assert!(stale_source(&executable, &src).is_none());
+        assert!(stale_source(&dir.path().join("missing.rs"), &executable).is_none());
+    }
+
+    /// Ensure that PC samples are resolved to the enclosing function,
+    /// and are forwarded with the raw PC otherwise.
+    #[test]
+    fn pc_samples() {
+        let mut maps = maps();
+        maps.symbols = [(0x0800_0041, 0x10, "main")].into_iter().collect();
+        let metadata = TraceMetadata::from(
+            "test".to_string(),
+            maps,
+            Local::now(),
+            16_000_000,
+            None,
+            None,
+        );
+
+        let chunk = metadata.build_event_chunk(packets(vec![
+            TracePacket::PeriodicPcSample {
+                pc: Some(0x0800_0044),
+            },
+            TracePacket::PeriodicPcSample {
+                pc: Some(0x2000_0000),
+            },
+            TracePacket::PeriodicPcSample { pc: None },
+        ]));
+        let samples: Vec<(Option<u32>, Option<String>)> = chunk
+            .events
+            .into_iter()
+            .map(|event| match event {
+                EventType::PcSample { pc, symbol } => (pc, symbol),
+                event => panic!("unexpected event {:?}", event),
+            })
+            .collect();
+        assert_eq!(
+            samples,
+            [
+                (Some(0x0800_0044), Some("main".to_string())),
+                (Some(0x2000_0000), None),
+                (None, None),
+            ]
+        );
+    }
+
+    /// Ensure that a return to thread mode resumes the #[idle] task, if
+    /// any.
+    #[test]
+    fn idle_thread_mode() {
+        let returned = || {
+            packets(vec![TracePacket::ExceptionTrace {
+                exception: VectActive::ThreadMode,
+                action: ExceptionAction::Returned,
+            }])
+        };
+        assert!(metadata().build_event_chunk(returned()).events.is_empty());
+
+        let mut maps = maps();
+        maps.idle = Some("app::idle".to_string());
+        let metadata = TraceMetadata::from(
+            "test".to_string(),
+            maps,
+            Local::now(),
+            16_000_000,
+            None,
+            None,
+        );
+        assert!(matches!(
+            &metadata.build_event_chunk(returned()).events[..],
+            [EventType::Task {
+                name,
+                action: TaskAction::Returned,
+                priority: Some(0),
+                ..
+            }] if name == "app::idle"
+        ));
+    }
+
+    /// Ensure that the dumped maps key interrupts and software tasks by
+    /// integer.
+    #[test]
+    fn dump_maps() {
+        let mut maps = maps();
+        maps.hardware.0.insert(
+            VectActive::Interrupt { irqn: 3 },
+            vec!["app".into(), "irq".into()],
+        );
+
+        assert_eq!(
+            serde_json::to_value(maps.dump()).unwrap(),
+            serde_json::json!({
+                "exceptions": {
+                    "SysTick": ["app", "low"],
+                    "PendSV": ["app", "high"],
+                },
+                "interrupts": [[3, ["app", "irq"]]],
+                "software": [[0, ["app", "foo"]], [1, ["app", "bar"]]],
+                "priorities": {
+                    "app::low": 1,
+                    "app::high": 2,
+                    "app::foo": 1,
+                    "app::bar": 2,
+                },
+            })
+        );
+    }
+
+    /// Ensure that every fixed exception resolves from its bind name.
+    #[test]
+    fn core_exceptions() {
+        let exceptions = HardwareMap::core_exceptions(None);
+        let mut resolved = 0;
+        for number in 0..16 {
+            if let Some(VectActive::Exception(exception)) = VectActive::from(number) {
+                assert_eq!(
+                    exceptions.get(exception_bind(exception)),
+                    Some(&VectActive::Exception(exception)),
+                    "exception number {}",
+                    number
+                );
+                resolved += 1;
+            }
+        }
+        assert_eq!(resolved, exceptions.len());
+
+        // The spellings of cortex-m-rt's #[exception] and RTIC's binds.
+        assert_eq!(
+            exceptions.keys().collect::<Vec<_>>(),
+            [
+                "NonMaskableInt",
+                "HardFault",
+                "MemoryManagement",
+                "BusFault",
+                "UsageFault",
+                "SecureFault",
+                "SVCall",
+                "DebugMonitor",
+                "PendSV",
+                "SysTick"
+            ]
+        );
+    }
+
+    /// Ensure that the core exceptions depend on the architecture
+    /// profile of the target.
+    #[test]
+    fn arch_profiles() {
+        assert_eq!(
+            ArchProfile::from_target("thumbv6m-none-eabi"),
+            Some(ArchProfile::V6M)
+        );
+        assert_eq!(
+            ArchProfile::from_target("thumbv7em-none-eabihf"),
+            Some(ArchProfile::V7M)
+        );
+        assert_eq!(
+            ArchProfile::from_target("thumbv8m.base-none-eabi"),
+            Some(ArchProfile::V8MBaseline)
+        );
+        assert_eq!(
+            ArchProfile::from_target("thumbv8m.main-none-eabihf"),
+            Some(ArchProfile::V8MMainline)
+        );
+        assert_eq!(ArchProfile::from_target("x86_64-unknown-linux-gnu"), None);
+
+        let v6m = HardwareMap::core_exceptions(Some(ArchProfile::V6M));
+        assert!(v6m.contains_key("SysTick"));
+        assert!(!v6m.contains_key("BusFault"));
+        assert!(!HardwareMap::core_exceptions(Some(ArchProfile::V7M)).contains_key("SecureFault"));
+        assert!(HardwareMap::core_exceptions(Some(ArchProfile::V8MMainline))
+            .contains_key("SecureFault"));
+    }
+
+    /// Ensure that dispatchers are assigned to priority levels as by
+    /// RTIC, and are presented as tasks if so requested.
+    #[test]
+    fn dispatchers() {
+        let src = quote!(
+            #[rtic::app(device = stm32f4::stm32f401, dispatchers = [EXTI0, EXTI1, EXTI2])]
+            mod app {
+                #[shared]
+                struct Shared {}
+
+                #[local]
+                struct Local {}
+
+                #[init]
+                fn init(_: init::Context) -> (Shared, Local, init::Monotonics) {
+                    (Shared {}, Local {}, init::Monotonics())
+                }
+
+                #[task(priority = 1)]
+                fn foo(_: foo::Context) {}
+
+                #[task(priority = 2)]
+                fn bar(_: bar::Context) {}
+
+                #[task(priority = 1)]
+                fn baz(_: baz::Context) {}
+            }
+        );
+        let (app, _) = TraceLookupMaps::parse_rtic_app(src, Path::new(".")).unwrap();
+        let resolved = IndexMap::from_iter(["EXTI0", "EXTI1", "EXTI2"].iter().enumerate().map(
+            |(i, name)| {
+                (
+                    name.to_string(),
+                    VectActive::Interrupt { irqn: 6 + i as u16 },
+                )
+            },
+        ));
+        let dispatchers = SoftwareMap::dispatchers(&app, &resolved);
+        assert_eq!(
+            dispatchers.values().cloned().collect::<Vec<_>>(),
+            [
+                Dispatcher {
+                    name: "EXTI0".to_string(),
+                    priority: 2,
+                    tasks: vec!["app::bar".to_string()],
+                },
+                Dispatcher {
+                    name: "EXTI1".to_string(),
+                    priority: 1,
+                    tasks: vec!["app::foo".to_string(), "app::baz".to_string()],
+                },
+            ]
+        );
+
+        let mut metadata = metadata();
+        let irq = VectActive::Interrupt { irqn: 6 };
+        metadata.maps.software.task_dispatchers.insert(irq);
+        metadata
+            .maps
+            .software
+            .dispatchers
+            .insert(irq, dispatchers[&irq].clone());
+        let entered = || {
+            packets(vec![TracePacket::ExceptionTrace {
+                exception: irq,
+                action: ExceptionAction::Entered,
+            }])
+        };
+        assert!(metadata.build_event_chunk(entered()).events.is_empty());
+        metadata.show_dispatchers();
+        assert!(matches!(
+            &metadata.build_event_chunk(entered()).events[..],
+            [EventType::Task {
+                name,
+                action: TaskAction::Entered,
+                priority: Some(2),
+                ..
+            }] if name == "app::dispatcher::EXTI0"
+        ));
+        assert!(metadata
+            .to_string()
+            .contains("dispatcher EXTI0 (priority 2): app::bar"));
+    }
+
+    /// Ensure that software task IDs are resolved from both 16-bit and
+    /// legacy single-byte payloads.
+    #[test]
+    fn wide_software_ids() {
+        let mut maps = maps();
+        maps.software
+            .map
+            .insert(300, vec!["app".to_string(), "wide".to_string()]);
+        let name = |value: &[u8]| match maps.resolve_software_task(&1, value) {
+            Ok(Some(EventType::Task { name, .. })) => Ok(name),
+            Ok(_) => panic!("not a task event"),
+            Err(e) => Err(e),
+        };
+
+        assert_eq!(name(&[44, 1]).unwrap(), "app::wide");
+        assert_eq!(name(&[1, 0]).unwrap(), "app::bar");
+        assert_eq!(name(&[1]).unwrap(), "app::bar");
+        assert!(matches!(
+            name(&[1, 0, 0, 0]),
+            Err(RecoveryError::InvalidSoftwareValue(_))
+        ));
+        assert!(matches!(
+            name(&[]),
+            Err(RecoveryError::InvalidSoftwareValue(_))
+        ));
     }
 }
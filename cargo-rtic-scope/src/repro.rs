@@ -0,0 +1,162 @@
+//! Minimal reproductions of resolver and decoder issues for bug
+//! reports. A reproduction is an [`Archive`] of a short trace fixture
+//! that additionally bundles the tokens of the application source and
+//! the manifest properties, anonymized such that task names are not
+//! disclosed. It is replayed via `replay --trace-file`.
+use crate::archive::Archive;
+use crate::manifest::ManifestProperties;
+use crate::recovery::TraceMetadata;
+use crate::TraceData;
+
+use indexmap::IndexMap;
+use proc_macro2::{Group, Ident, TokenStream, TokenTree};
+
+/// Renames all idents in `tokens` that are keys of `idents` to the
+/// respective value.
+fn anonymize_tokens(tokens: TokenStream, idents: &IndexMap<String, String>) -> TokenStream {
+    tokens
+        .into_iter()
+        .map(|token| match token {
+            TokenTree::Ident(ident) => match idents.get(&ident.to_string()) {
+                Some(new) => TokenTree::Ident(Ident::new(new, ident.span())),
+                None => TokenTree::Ident(ident),
+            },
+            TokenTree::Group(group) => {
+                let mut anonymized =
+                    Group::new(group.delimiter(), anonymize_tokens(group.stream(), idents));
+                anonymized.set_span(group.span());
+                TokenTree::Group(anonymized)
+            }
+            token => token,
+        })
+        .collect()
+}
+
+/// Bundles the `app` source tokens, the `manifest` properties, and the
+/// `trace` fixture of `metadata` into an anonymized reproduction.
+pub fn reproduction(
+    app: TokenStream,
+    mut manifest: ManifestProperties,
+    mut metadata: TraceMetadata,
+    trace: Vec<TraceData>,
+) -> Archive {
+    let idents = metadata.anonymize();
+    // NOTE(None) the overrides have already been applied to the
+    // metadata, and the path is only meaningful on this host.
+    manifest.task_overrides = None;
+
+    let events = trace
+        .iter()
+        .cloned()
+        .map(|data| metadata.build_event_chunk(data))
+        .collect();
+
+    Archive {
+        metadata,
+        trace,
+        events,
+        raw: vec![],
+        app: Some(anonymize_tokens(app, &idents).to_string()),
+        manifest: Some(manifest),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::manifest::test::properties;
+    use crate::recovery::test::metadata;
+
+    use std::fs;
+    use std::time::Duration;
+
+    use cortex_m::peripheral::scb::Exception;
+    use itm::{ExceptionAction, MemoryAccessType, TracePacket, VectActive};
+    use quote::quote;
+    use rtic_scope_api as api;
+
+    /// Ensure that a reproduction replays to the anonymized events of
+    /// the original trace.
+    #[test]
+    fn replays_anonymized() {
+        let app = quote!(
+            #[rtic::app(device = pac)]
+            mod app {
+                #[task(binds = SysTick, priority = 1)]
+                fn low(_: low::Context) {
+                    foo::spawn().unwrap();
+                }
+
+                #[task(priority = 1)]
+                fn foo(_: foo::Context) {}
+            }
+        );
+        let exception = |exception, action| TracePacket::ExceptionTrace {
+            exception: VectActive::Exception(exception),
+            action,
+        };
+        let trace: Vec<TraceData> = [
+            vec![exception(Exception::SysTick, ExceptionAction::Entered)],
+            vec![
+                exception(Exception::SysTick, ExceptionAction::Exited),
+                exception(Exception::SVCall, ExceptionAction::Entered),
+                TracePacket::DataTraceValue {
+                    comparator: 1,
+                    access_type: MemoryAccessType::Write,
+                    value: vec![0],
+                },
+            ],
+            vec![TracePacket::Overflow],
+        ]
+        .into_iter()
+        .enumerate()
+        .map(|(i, packets)| TraceData {
+            timestamp: api::Timestamp::Sync(Duration::from_micros(i as u64)),
+            consumed_packets: packets.len(),
+            packets,
+            malformed_packets: vec![],
+        })
+        .collect();
+
+        let original = metadata();
+        let expected: Vec<api::EventChunk> = trace
+            .iter()
+            .cloned()
+            .map(|data| {
+                let mut chunk = original.build_event_chunk(data);
+                chunk.rename_tasks(|name| match name {
+                    "app::low" => "app::task0".to_string(),
+                    "app::foo" => "app::task2".to_string(),
+                    name => panic!("unexpected task {}", name),
+                });
+                chunk
+            })
+            .collect();
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("repro.rsa");
+        let mut file = fs::File::create(&path).unwrap();
+        reproduction(app, properties(), metadata(), trace)
+            .write(&mut file)
+            .unwrap();
+        drop(file);
+
+        let repro = Archive::read(fs::File::open(&path).unwrap()).unwrap();
+        assert_eq!(repro.metadata.program_name, "anonymous");
+        let app = repro.app.unwrap();
+        assert!(app
+            .replace(' ', "")
+            .contains("fntask0(_:task0::Context){task2::spawn()"));
+        assert!(!app.contains("low") && !app.contains("foo"));
+        assert!(repro.manifest.is_some());
+
+        let replayed: Vec<api::EventChunk> = repro
+            .trace
+            .into_iter()
+            .map(|data| repro.metadata.build_event_chunk(data))
+            .collect();
+        let json = |chunks: &[api::EventChunk]| serde_json::to_string(chunks).unwrap();
+        assert_eq!(json(&replayed), json(&expected));
+        assert_eq!(json(&repro.events), json(&expected));
+    }
+}
@@ -0,0 +1,113 @@
+//! Correction of the timestamps of a recorded trace for a TPIU
+//! frequency other than the one recorded, e.g. after clock drift of
+//! the target has been discovered.
+//!
+//! Timestamps are calculated from the number of TPIU clock cycles
+//! counted by the target divided by the TPIU frequency. A timestamp
+//! recorded at frequency `f_rec` is thus corrected for the actual
+//! frequency `f_act` by a factor of `f_rec / f_act`.
+use crate::sinks::{FileSink, Sink};
+use crate::sources::FileSource;
+use crate::RTICScopeError;
+
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+use rtic_scope_api as api;
+
+/// Corrects `timestamp`, recorded at `recorded_freq`, for
+/// `actual_freq`.
+pub fn retime(timestamp: &api::Timestamp, recorded_freq: u32, actual_freq: u32) -> api::Timestamp {
+    let scale = |d: &Duration| {
+        Duration::from_nanos(
+            (d.as_nanos() * recorded_freq as u128 / actual_freq as u128)
+                .try_into()
+                .unwrap_or(u64::MAX),
+        )
+    };
+
+    match timestamp {
+        api::Timestamp::Sync(offset) => api::Timestamp::Sync(scale(offset)),
+        api::Timestamp::AssocEventDelay(offset) => api::Timestamp::AssocEventDelay(scale(offset)),
+        api::Timestamp::UnknownDelay { prev, curr } => api::Timestamp::UnknownDelay {
+            prev: scale(prev),
+            curr: scale(curr),
+        },
+        api::Timestamp::UnknownAssocEventDelay { prev, curr } => {
+            api::Timestamp::UnknownAssocEventDelay {
+                prev: scale(prev),
+                curr: scale(curr),
+            }
+        }
+    }
+}
+
+/// Writes the trace file at `path` to `output` with all timestamps
+/// corrected for `actual_freq`. Returns the frequency that was
+/// previously recorded.
+pub fn retime_file(path: &Path, output: &Path, actual_freq: u32) -> Result<u32, RTICScopeError> {
+    let src = FileSource::new(fs::OpenOptions::new().read(true).open(path)?)?;
+    let mut metadata = src.metadata();
+    let recorded_freq = metadata.tpiu_freq();
+    metadata.set_tpiu_freq(actual_freq);
+
+    let mut sink = FileSink::create(output)?;
+    sink.drain_metadata(&metadata)?;
+    for data in src {
+        let mut data = data?;
+        data.timestamp = retime(&data.timestamp, recorded_freq, actual_freq);
+        let chunk = metadata.build_event_chunk(data.clone());
+        sink.drain(data, chunk)?;
+    }
+    sink.finalize()?;
+
+    Ok(recorded_freq)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Ensure that timestamps scale with a frequency correction.
+    #[test]
+    fn retime_timestamps() {
+        let ns = Duration::from_nanos;
+
+        // The target clock was actually half of that recorded:
+        // everything took twice as long.
+        assert_eq!(
+            api::timestamp_offset(&retime(
+                &api::Timestamp::Sync(ns(1000)),
+                16_000_000,
+                8_000_000
+            )),
+            ns(2000)
+        );
+
+        match retime(
+            &api::Timestamp::UnknownDelay {
+                prev: ns(300),
+                curr: ns(900),
+            },
+            12_000_000,
+            16_000_000,
+        ) {
+            api::Timestamp::UnknownDelay { prev, curr } => {
+                assert_eq!(prev, ns(225));
+                assert_eq!(curr, ns(675));
+            }
+            ts => panic!("unexpected timestamp {:?}", ts),
+        }
+
+        // No correction.
+        assert_eq!(
+            api::timestamp_offset(&retime(
+                &api::Timestamp::AssocEventDelay(ns(1234)),
+                16_000_000,
+                16_000_000
+            )),
+            ns(1234)
+        );
+    }
+}
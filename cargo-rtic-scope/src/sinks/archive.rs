@@ -0,0 +1,75 @@
+//! Sink which records the trace into a self-describing archive. See
+//! [`crate::archive`].
+use crate::archive::{self, Stream};
+use crate::recovery::TraceMetadata;
+use crate::sinks::{Sink, SinkError};
+use crate::TraceData;
+
+use std::fs;
+use std::io::{BufWriter, Read, Write};
+use std::path::Path;
+
+use rtic_scope_api as api;
+
+pub struct ArchiveSink {
+    file: BufWriter<fs::File>,
+}
+
+impl ArchiveSink {
+    /// Creates an archive at `path` with the given `metadata` and the
+    /// raw ITM byte stream read from `raw`, if available.
+    pub fn new(
+        path: &Path,
+        metadata: &TraceMetadata,
+        raw: Option<&Path>,
+    ) -> Result<Self, SinkError> {
+        let setup_err = |e| {
+            SinkError::SetupIOError(
+                Some(format!("Failed to create archive {}", path.display())),
+                e,
+            )
+        };
+
+        let mut file = BufWriter::new(fs::File::create(path).map_err(setup_err)?);
+        archive::write_header(&mut file).map_err(setup_err)?;
+        archive::write_record(
+            &mut file,
+            Stream::Metadata,
+            serde_json::to_string(metadata)?.as_bytes(),
+        )
+        .map_err(setup_err)?;
+
+        if let Some(raw) = raw {
+            let mut raw = fs::File::open(raw).map_err(setup_err)?;
+            let mut buf = vec![0; archive::RAW_RECORD_SIZE];
+            loop {
+                match raw.read(&mut buf).map_err(setup_err)? {
+                    0 => break,
+                    n => archive::write_record(&mut file, Stream::Raw, &buf[..n])
+                        .map_err(setup_err)?,
+                }
+            }
+        }
+
+        Ok(Self { file })
+    }
+}
+
+impl Sink for ArchiveSink {
+    fn drain(&mut self, data: TraceData, chunk: api::EventChunk) -> Result<(), SinkError> {
+        let data = serde_json::to_string(&data)?;
+        let chunk = serde_json::to_string(&chunk)?;
+
+        archive::write_record(&mut self.file, Stream::Trace, data.as_bytes())
+            .and_then(|_| archive::write_record(&mut self.file, Stream::Events, chunk.as_bytes()))
+            .map_err(SinkError::DrainIOError)
+    }
+
+    fn describe(&self) -> String {
+        format!("archive ({:?})", self.file.get_ref())
+    }
+
+    fn finalize(&mut self) -> Result<(), SinkError> {
+        self.file.flush().map_err(SinkError::DrainIOError)
+    }
+}
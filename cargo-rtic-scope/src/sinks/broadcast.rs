@@ -0,0 +1,370 @@
+//! Fan-out of decoded trace data to multiple sinks. Each sink is
+//! drained on its own thread behind a bounded buffer so that a slow
+//! sink (e.g. a frontend that cannot keep up) does not stall the
+//! others. Chunks that do not fit into the buffer of a [lossy](Sink::lossy)
+//! sink are dropped for that sink, which is notified via
+//! [`api::EventType::HostDropped`] with the next chunk it receives.
+//! Other sinks, e.g. recordings, never lose chunks: the broadcast
+//! waits until their buffer has space.
+use crate::sinks::{Sink, SinkError};
+use crate::TraceData;
+
+use std::thread;
+
+use crossbeam_channel::{self as channel, TrySendError};
+use rtic_scope_api as api;
+
+/// Default amount of chunks that may be buffered per sink before
+/// chunks are dropped for that sink.
+pub const DEFAULT_CAPACITY: usize = 1024;
+
+type Item = (TraceData, api::EventChunk);
+
+struct Outlet {
+    description: String,
+    /// Whether chunks are dropped for this sink when its buffer is
+    /// full, instead of waiting for it to drain.
+    lossy: bool,
+    tx: Option<channel::Sender<Item>>,
+    handle: Option<thread::JoinHandle<Result<(), SinkError>>>,
+    /// Number of chunks dropped because the buffer of this sink was
+    /// full.
+    dropped: usize,
+    /// Number of chunks dropped since the last chunk that was
+    /// forwarded to this sink.
+    pending: usize,
+}
+
+impl Outlet {
+    /// Stops the worker thread after it has drained all buffered
+    /// chunks and reports whether the sink broke while doing so.
+    fn join(&mut self) -> Result<(), SinkError> {
+        drop(self.tx.take());
+        match self.handle.take().map(|h| h.join()) {
+            Some(Ok(res)) => res,
+            Some(Err(_)) => Err(SinkError::Panicked(self.description.clone())),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Decoded trace data is forwarded to every sink without decoding it
+/// again. Sinks that fail to drain are removed.
+pub struct Broadcast {
+    outlets: Vec<Outlet>,
+}
+
+impl Broadcast {
+    /// Spawns a drain thread for each of the given `sinks`, each with a
+    /// buffer of `capacity` chunks.
+    pub fn new(sinks: Vec<Box<dyn Sink>>, capacity: usize) -> Self {
+        let outlets = sinks
+            .into_iter()
+            .map(|mut sink| {
+                let (tx, rx) = channel::bounded::<Item>(capacity);
+                let description = sink.describe();
+                let lossy = sink.lossy();
+                let handle = thread::spawn(move || {
+                    for (data, chunk) in rx.iter() {
+                        sink.drain(data, chunk)?;
+                    }
+                    sink.finalize()
+                });
+
+                Outlet {
+                    description,
+                    lossy,
+                    tx: Some(tx),
+                    handle: Some(handle),
+                    dropped: 0,
+                    pending: 0,
+                }
+            })
+            .collect();
+
+        Self { outlets }
+    }
+
+    /// Number of operational sinks.
+    pub fn len(&self) -> usize {
+        self.outlets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.outlets.is_empty()
+    }
+
+    /// Whether the buffer of any lossy sink is full, such that the
+    /// next chunk would be dropped for it. Used to apply backpressure
+    /// to the source instead.
+    pub fn is_full(&self) -> bool {
+        self.outlets
+            .iter()
+            .filter(|o| o.lossy)
+            .any(|o| o.tx.as_ref().map_or(false, |tx| tx.is_full()))
+    }
+
+    /// Forwards a copy of `data` and `chunk` to all sinks. Returns the
+    /// description and error of each sink that broke since the
+    /// previous call; such sinks are removed. If the buffer of a lossy
+    /// sink is full the chunk is dropped for that sink only; see
+    /// [`Broadcast::dropped`]. Otherwise, this blocks until the sink
+    /// has buffer space.
+    pub fn drain(&mut self, data: &TraceData, chunk: &api::EventChunk) -> Vec<(String, SinkError)> {
+        let mut broken = vec![];
+
+        for (i, outlet) in self.outlets.iter_mut().enumerate() {
+            let tx = outlet.tx.as_ref().unwrap();
+            let mut chunk = chunk.clone();
+            if outlet.pending > 0 {
+                chunk
+                    .events
+                    .insert(0, api::EventType::HostDropped(outlet.pending));
+            }
+            let item = (data.clone(), chunk);
+            if !outlet.lossy {
+                if tx.send(item).is_err() {
+                    broken.push(i);
+                }
+                continue;
+            }
+            match tx.try_send(item) {
+                Ok(()) => outlet.pending = 0,
+                Err(TrySendError::Full(_)) => {
+                    outlet.dropped += 1;
+                    outlet.pending += 1;
+                }
+                Err(TrySendError::Disconnected(_)) => broken.push(i),
+            }
+        }
+
+        // Remove the sinks that broke and retrieve their errors.
+        //
+        // TODO replace with Vec::drain_filter when stable.
+        let mut errors = vec![];
+        for i in broken.into_iter().rev() {
+            let mut outlet = self.outlets.remove(i);
+            if let Err(e) = outlet.join() {
+                errors.push((outlet.description, e));
+            }
+        }
+
+        errors
+    }
+
+    /// Description and number of dropped chunks for each sink that has
+    /// not been able to keep up.
+    pub fn dropped(&self) -> Vec<(String, usize)> {
+        self.outlets
+            .iter()
+            .filter(|o| o.dropped > 0)
+            .map(|o| (o.description.clone(), o.dropped))
+            .collect()
+    }
+
+    /// Waits for all sinks to drain their buffered chunks and to
+    /// finalize their output. Returns the description and error of each
+    /// sink that failed to do so.
+    pub fn finish(mut self) -> Vec<(String, SinkError)> {
+        self.outlets
+            .iter_mut()
+            .filter_map(|o| o.join().err().map(|e| (o.description.clone(), e)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use itm::{Timestamp, TracePacket};
+
+    struct RecordingSink(Arc<Mutex<Vec<api::EventChunk>>>);
+
+    impl Sink for RecordingSink {
+        fn drain(&mut self, _: TraceData, chunk: api::EventChunk) -> Result<(), SinkError> {
+            self.0.lock().unwrap().push(chunk);
+            Ok(())
+        }
+
+        fn describe(&self) -> String {
+            "recording sink".to_string()
+        }
+    }
+
+    /// Ensure that all sinks receive identical chunks.
+    #[test]
+    fn identical_chunks() {
+        let records: Vec<_> = (0..3).map(|_| Arc::new(Mutex::new(vec![]))).collect();
+        let mut broadcast = Broadcast::new(
+            records
+                .iter()
+                .map(|r| Box::new(RecordingSink(r.clone())) as Box<dyn Sink>)
+                .collect(),
+            DEFAULT_CAPACITY,
+        );
+
+        for i in 0..100 {
+            let timestamp = Timestamp::Sync(Duration::from_nanos(i));
+            let data = TraceData {
+                timestamp: timestamp.clone(),
+                packets: vec![TracePacket::Overflow],
+                malformed_packets: vec![],
+                consumed_packets: 1,
+            };
+            let chunk = api::EventChunk {
+                timestamp,
+                events: vec![api::EventType::Overflow],
+                raw: None,
+                monotonic_ticks: None,
+            };
+            assert!(broadcast.drain(&data, &chunk).is_empty());
+        }
+        assert!(broadcast.dropped().is_empty());
+        assert!(broadcast.finish().is_empty());
+
+        let expected = format!("{:?}", records[0].lock().unwrap());
+        assert_eq!(records[0].lock().unwrap().len(), 100);
+        for record in records.iter() {
+            assert_eq!(format!("{:?}", record.lock().unwrap()), expected);
+        }
+    }
+
+    struct GatedSink {
+        gate: channel::Receiver<()>,
+        record: Arc<Mutex<Vec<api::EventChunk>>>,
+        lossy: bool,
+    }
+
+    impl Sink for GatedSink {
+        fn drain(&mut self, _: TraceData, chunk: api::EventChunk) -> Result<(), SinkError> {
+            // NOTE(Err) the gate is open for good once dropped
+            let _ = self.gate.recv();
+            self.record.lock().unwrap().push(chunk);
+            Ok(())
+        }
+
+        fn describe(&self) -> String {
+            "gated sink".to_string()
+        }
+
+        fn lossy(&self) -> bool {
+            self.lossy
+        }
+    }
+
+    /// Ensure that a slow sink fills its buffer, and that the chunks
+    /// dropped meanwhile are reported to it as host-side drops.
+    #[test]
+    fn host_dropped() {
+        let (open, gate) = channel::unbounded();
+        let record = Arc::new(Mutex::new(vec![]));
+        let mut broadcast = Broadcast::new(
+            vec![Box::new(GatedSink {
+                gate,
+                record: record.clone(),
+                lossy: true,
+            })],
+            1,
+        );
+        let drain = |broadcast: &mut Broadcast, i| {
+            let timestamp = Timestamp::Sync(Duration::from_nanos(i));
+            let data = TraceData {
+                timestamp: timestamp.clone(),
+                packets: vec![TracePacket::Overflow],
+                malformed_packets: vec![],
+                consumed_packets: 1,
+            };
+            let chunk = api::ChunkBuilder::at(Duration::from_nanos(i))
+                .overflow()
+                .build();
+            assert!(broadcast.drain(&data, &chunk).is_empty());
+        };
+        let wait_for_space = |broadcast: &Broadcast| {
+            while broadcast.is_full() {
+                thread::sleep(Duration::from_millis(1));
+            }
+        };
+
+        // The sink blocks on the first chunk, and the second fills its
+        // buffer.
+        drain(&mut broadcast, 0);
+        wait_for_space(&broadcast);
+        drain(&mut broadcast, 1);
+        assert!(broadcast.is_full());
+
+        drain(&mut broadcast, 2);
+        drain(&mut broadcast, 3);
+        assert_eq!(broadcast.dropped(), [("gated sink".to_string(), 2)]);
+
+        open.send(()).unwrap();
+        wait_for_space(&broadcast);
+        drain(&mut broadcast, 4);
+        drop(open);
+        assert!(broadcast.finish().is_empty());
+
+        let record = record.lock().unwrap();
+        let offsets: Vec<_> = record
+            .iter()
+            .map(|c| api::timestamp_offset(&c.timestamp).as_nanos())
+            .collect();
+        assert_eq!(offsets, [0, 1, 4]);
+        assert!(matches!(
+            record[2].events[..],
+            [api::EventType::HostDropped(2), api::EventType::Overflow]
+        ));
+        assert!(record[..2]
+            .iter()
+            .all(|c| matches!(c.events[..], [api::EventType::Overflow])));
+    }
+
+    /// Ensure that no chunk is dropped for a sink that is not lossy,
+    /// however slow it drains.
+    #[test]
+    fn lossless() {
+        let (open, gate) = channel::unbounded();
+        let record = Arc::new(Mutex::new(vec![]));
+        let mut broadcast = Broadcast::new(
+            vec![Box::new(GatedSink {
+                gate,
+                record: record.clone(),
+                lossy: false,
+            })],
+            1,
+        );
+        assert!(!broadcast.is_full());
+
+        // Opens the gate for one chunk at a time while the broadcast
+        // blocks on a full buffer.
+        let opener = thread::spawn(move || {
+            for _ in 0..10 {
+                thread::sleep(Duration::from_millis(1));
+                open.send(()).unwrap();
+            }
+        });
+        for i in 0..10 {
+            let chunk = api::ChunkBuilder::at(Duration::from_nanos(i))
+                .overflow()
+                .build();
+            let data = TraceData {
+                timestamp: chunk.timestamp.clone(),
+                packets: vec![TracePacket::Overflow],
+                malformed_packets: vec![],
+                consumed_packets: 1,
+            };
+            assert!(broadcast.drain(&data, &chunk).is_empty());
+        }
+        opener.join().unwrap();
+        assert!(broadcast.dropped().is_empty());
+        assert!(broadcast.finish().is_empty());
+
+        let record = record.lock().unwrap();
+        assert_eq!(record.len(), 10);
+        assert!(record
+            .iter()
+            .all(|c| matches!(c.events[..], [api::EventType::Overflow])));
+    }
+}
@@ -0,0 +1,239 @@
+//! Sink which exports the trace in the Chrome Trace Event Format, e.g.
+//! to view it in Perfetto or chrome://tracing.
+//!
+//! Each task is presented on a track of its own. Entering a task begins
+//! a slice on its track, and exiting it ends the slice. A return to a
+//! preempted task is marked by an instant event on its track: the slice
+//! of the task remains open while it is preempted. The completion of an
+//! async software task is likewise marked on its track. Overflows,
+//! malformed packets, and resynchronizations are marked by global
+//! instant events. Events are timestamped in microseconds since target
+//! reset.
+use crate::sinks::{Sink, SinkError};
+use crate::TraceData;
+
+use std::fs;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use indexmap::IndexMap;
+use rtic_scope_api as api;
+use serde_json::{json, Value};
+
+/// Process ID of all events. The trace is of a single target.
+const PID: usize = 1;
+
+pub struct ChromeSink {
+    file: BufWriter<fs::File>,
+    /// Track (thread) ID of each task, in order of appearance.
+    tracks: IndexMap<String, usize>,
+    /// Whether an event has been written, after which events are
+    /// separated by commas.
+    written: bool,
+}
+
+impl ChromeSink {
+    /// Creates a file at `path` to which the trace of `program` is
+    /// written.
+    pub fn new(path: &Path, program: &str) -> Result<Self, SinkError> {
+        let file = fs::File::create(path).map_err(|e| {
+            SinkError::SetupIOError(Some(format!("Failed to create {}", path.display())), e)
+        })?;
+
+        let mut sink = Self {
+            file: BufWriter::new(file),
+            tracks: IndexMap::new(),
+            written: false,
+        };
+        write!(sink.file, "{{\"displayTimeUnit\":\"ns\",\"traceEvents\":[")
+            .map_err(|e| SinkError::SetupIOError(None, e))?;
+        sink.write(json!({
+            "name": "process_name",
+            "ph": "M",
+            "pid": PID,
+            "args": { "name": program },
+        }))
+        .map_err(|e| SinkError::SetupIOError(None, e))?;
+
+        Ok(sink)
+    }
+
+    fn write(&mut self, event: Value) -> std::io::Result<()> {
+        if self.written {
+            writeln!(self.file, ",")?;
+        } else {
+            writeln!(self.file)?;
+        }
+        self.written = true;
+        write!(self.file, "{}", event)
+    }
+
+    /// The track of `task`, which is named on first appearance.
+    fn track(&mut self, task: &str) -> std::io::Result<usize> {
+        if let Some(tid) = self.tracks.get(task) {
+            return Ok(*tid);
+        }
+
+        // NOTE(+ 1) track 0 is left for global events
+        let tid = self.tracks.len() + 1;
+        self.tracks.insert(task.to_string(), tid);
+        self.write(json!({
+            "name": "thread_name",
+            "ph": "M",
+            "pid": PID,
+            "tid": tid,
+            "args": { "name": task },
+        }))?;
+
+        Ok(tid)
+    }
+
+    /// The events of `chunk` in the Chrome Trace Event Format.
+    fn events(&mut self, chunk: &api::EventChunk) -> std::io::Result<Vec<Value>> {
+        let ts = api::timestamp_offset(&chunk.timestamp).as_nanos() as f64 / 1000.0;
+
+        let mut events = vec![];
+        for event in chunk.events.iter() {
+            let event = match event {
+                api::EventType::Task { name, action, .. } => {
+                    let tid = self.track(name)?;
+                    let ph = match action {
+                        api::TaskAction::Entered => "B",
+                        api::TaskAction::Exited => "E",
+                        api::TaskAction::Returned | api::TaskAction::Completed => "i",
+                    };
+                    let mut event = json!({
+                        "name": name,
+                        "ph": ph,
+                        "ts": ts,
+                        "pid": PID,
+                        "tid": tid,
+                    });
+                    let instant = match action {
+                        api::TaskAction::Returned => Some("resumed"),
+                        api::TaskAction::Completed => Some("completed"),
+                        _ => None,
+                    };
+                    if let Some(instant) = instant {
+                        event["name"] = json!(instant);
+                        event["s"] = json!("t");
+                    }
+                    event
+                }
+                api::EventType::Overflow => json!({
+                    "name": "overflow",
+                    "ph": "i",
+                    "s": "g",
+                    "ts": ts,
+                    "pid": PID,
+                    "tid": 0,
+                }),
+                api::EventType::Invalid(malformed) => json!({
+                    "name": "invalid",
+                    "ph": "i",
+                    "s": "g",
+                    "ts": ts,
+                    "pid": PID,
+                    "tid": 0,
+                    "args": { "packet": format!("{:?}", malformed) },
+                }),
+                api::EventType::Resynced(dropped) => json!({
+                    "name": "resynced",
+                    "ph": "i",
+                    "s": "g",
+                    "ts": ts,
+                    "pid": PID,
+                    "tid": 0,
+                    "args": { "dropped": dropped },
+                }),
+                _ => continue,
+            };
+            events.push(event);
+        }
+
+        Ok(events)
+    }
+}
+
+impl Sink for ChromeSink {
+    fn drain(&mut self, _: TraceData, chunk: api::EventChunk) -> Result<(), SinkError> {
+        for event in self.events(&chunk).map_err(SinkError::DrainIOError)? {
+            self.write(event).map_err(SinkError::DrainIOError)?;
+        }
+
+        Ok(())
+    }
+
+    fn describe(&self) -> String {
+        format!("Chrome trace exporter ({:?})", self.file.get_ref())
+    }
+
+    /// Closes the event array.
+    fn finalize(&mut self) -> Result<(), SinkError> {
+        writeln!(self.file, "\n]}}")
+            .and_then(|_| self.file.flush())
+            .map_err(SinkError::DrainIOError)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::sinks::test::drain_chunks;
+
+    use std::time::Duration;
+
+    /// Ensure that task actions and overflows are exported as begin,
+    /// end, and instant events on the expected tracks.
+    #[test]
+    fn trace_events() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("trace.json");
+        let mut sink = ChromeSink::new(&path, "blinky").unwrap();
+
+        let at = |ns| api::ChunkBuilder::at(Duration::from_nanos(ns));
+        drain_chunks(
+            &mut sink,
+            [
+                at(1_500).entered("app::low"),
+                at(2_000).entered("app::high"),
+                at(2_250).exited("app::high").returned("app::low"),
+                at(3_000).overflow(),
+                at(4_000).exited("app::low"),
+            ],
+        );
+
+        let trace: Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        let events: Vec<(String, String, String, u64)> = trace["traceEvents"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|e| {
+                (
+                    e["name"].as_str().unwrap().to_string(),
+                    e["ph"].as_str().unwrap().to_string(),
+                    e["ts"].to_string(),
+                    e["tid"].as_u64().unwrap_or(0),
+                )
+            })
+            .collect();
+        let event = |name: &str, ph: &str, ts: &str, tid| {
+            (name.to_string(), ph.to_string(), ts.to_string(), tid)
+        };
+        assert_eq!(
+            events,
+            [
+                event("process_name", "M", "null", 0),
+                event("thread_name", "M", "null", 1),
+                event("app::low", "B", "1.5", 1),
+                event("thread_name", "M", "null", 2),
+                event("app::high", "B", "2.0", 2),
+                event("app::high", "E", "2.25", 2),
+                event("resumed", "i", "2.25", 1),
+                event("overflow", "i", "3.0", 0),
+                event("app::low", "E", "4.0", 1),
+            ]
+        );
+    }
+}
@@ -0,0 +1,306 @@
+//! Sink which exports [`api::EventChunk`]s in the Common Trace Format
+//! (CTF) 1.8, readable by e.g. babeltrace.
+//!
+//! Only a minimal subset of CTF is used: a single stream of a single
+//! packet in which task enters, exits, returns, completions, and
+//! overflows are recorded as events along with their timestamp.
+//! Bookmarks of the trace are recorded as marker events in between.
+use crate::sinks::{Sink, SinkError};
+use crate::TraceData;
+
+use std::fs;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use rtic_scope_api as api;
+
+/// Magic number that starts each CTF packet.
+const CTF_MAGIC: u32 = 0xC1FC_1FC1;
+
+/// Priority recorded for tasks of unknown priority.
+const UNKNOWN_PRIORITY: u8 = u8::MAX;
+
+/// TSDL description of the binary stream written by [`CtfSink`].
+const METADATA: &str = r#"/* CTF 1.8 */
+
+typealias integer { size = 8; align = 8; signed = false; } := uint8_t;
+typealias integer { size = 32; align = 8; signed = false; } := uint32_t;
+
+trace {
+    major = 1;
+    minor = 8;
+    byte_order = le;
+    packet.header := struct {
+        uint32_t magic;
+    };
+};
+
+clock {
+    name = monotonic;
+    description = "Offset since target reset";
+    freq = 1000000000;
+};
+
+typealias integer {
+    size = 64; align = 8; signed = false;
+    map = clock.monotonic.value;
+} := uint64_clock_monotonic_t;
+
+stream {
+    event.header := struct {
+        uint32_t id;
+        uint64_clock_monotonic_t timestamp;
+    };
+};
+
+event {
+    name = "task_entered";
+    id = 0;
+    fields := struct {
+        uint8_t priority;
+        string name;
+    };
+};
+
+event {
+    name = "task_exited";
+    id = 1;
+    fields := struct {
+        uint8_t priority;
+        string name;
+    };
+};
+
+event {
+    name = "task_returned";
+    id = 2;
+    fields := struct {
+        uint8_t priority;
+        string name;
+    };
+};
+
+event {
+    name = "overflow";
+    id = 3;
+};
+
+event {
+    name = "bookmark";
+    id = 4;
+    fields := struct {
+        string label;
+    };
+};
+
+event {
+    name = "task_completed";
+    id = 5;
+    fields := struct {
+        uint8_t priority;
+        string name;
+    };
+};
+"#;
+
+pub struct CtfSink {
+    stream: BufWriter<fs::File>,
+    /// Bookmarks not yet recorded, as `(offset in nanoseconds, label)`
+    /// in reverse order of offset.
+    bookmarks: Vec<(u64, String)>,
+}
+
+impl CtfSink {
+    /// Creates a CTF trace in the directory `dir` in which the given
+    /// `bookmarks` are marked.
+    pub fn new(dir: &Path, bookmarks: &[(api::Timestamp, String)]) -> Result<Self, SinkError> {
+        let setup_err = |e| {
+            SinkError::SetupIOError(
+                Some(format!("Failed to create CTF trace in {}", dir.display())),
+                e,
+            )
+        };
+
+        fs::create_dir_all(dir).map_err(setup_err)?;
+        fs::write(dir.join("metadata"), METADATA).map_err(setup_err)?;
+        let mut stream = BufWriter::new(fs::File::create(dir.join("stream")).map_err(setup_err)?);
+        stream
+            .write_all(&CTF_MAGIC.to_le_bytes())
+            .map_err(setup_err)?;
+
+        let mut bookmarks: Vec<(u64, String)> = bookmarks
+            .iter()
+            .map(|(ts, label)| (api::timestamp_offset(ts).as_nanos() as u64, label.clone()))
+            .collect();
+        bookmarks.sort_by(|a, b| b.0.cmp(&a.0));
+
+        Ok(Self { stream, bookmarks })
+    }
+
+    /// Encodes the bookmarks at offsets before `until`.
+    fn encode_bookmarks(&mut self, until: u64) -> Vec<u8> {
+        let mut buf = vec![];
+        while matches!(self.bookmarks.last(), Some((offset, _)) if *offset < until) {
+            // NOTE(unwrap) checked above
+            let (offset, label) = self.bookmarks.pop().unwrap();
+            buf.extend(4u32.to_le_bytes());
+            buf.extend(offset.to_le_bytes());
+            buf.extend(label.as_bytes());
+            buf.push(0);
+        }
+
+        buf
+    }
+}
+
+/// Encodes the events of `chunk` as described by [`METADATA`]. Events
+/// without a CTF equivalent are skipped.
+fn encode_chunk(chunk: &api::EventChunk) -> Vec<u8> {
+    let timestamp = api::timestamp_offset(&chunk.timestamp).as_nanos() as u64;
+    let mut buf = vec![];
+
+    for event in chunk.events.iter() {
+        let id: u32 = match event {
+            api::EventType::Task { action, .. } => match action {
+                api::TaskAction::Entered => 0,
+                api::TaskAction::Exited => 1,
+                api::TaskAction::Returned => 2,
+                api::TaskAction::Completed => 5,
+            },
+            api::EventType::Overflow => 3,
+            _ => continue,
+        };
+        buf.extend(id.to_le_bytes());
+        buf.extend(timestamp.to_le_bytes());
+
+        if let api::EventType::Task { name, priority, .. } = event {
+            buf.push(priority.unwrap_or(UNKNOWN_PRIORITY));
+            buf.extend(name.as_bytes());
+            buf.push(0);
+        }
+    }
+
+    buf
+}
+
+impl Sink for CtfSink {
+    fn drain(&mut self, _: TraceData, chunk: api::EventChunk) -> Result<(), SinkError> {
+        let offset = api::timestamp_offset(&chunk.timestamp).as_nanos() as u64;
+        let bookmarks = self.encode_bookmarks(offset);
+        self.stream
+            .write_all(&bookmarks)
+            .and_then(|_| self.stream.write_all(&encode_chunk(&chunk)))
+            .map_err(SinkError::DrainIOError)
+    }
+
+    fn describe(&self) -> String {
+        format!("CTF exporter ({:?})", self.stream.get_ref())
+    }
+
+    /// Marks the bookmarks past the last event.
+    fn finalize(&mut self) -> Result<(), SinkError> {
+        let bookmarks = self.encode_bookmarks(u64::MAX);
+        self.stream
+            .write_all(&bookmarks)
+            .and_then(|_| self.stream.flush())
+            .map_err(SinkError::DrainIOError)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::sinks::test::drain_chunks;
+
+    use std::convert::TryInto;
+    use std::time::Duration;
+
+    /// Decodes a stream written by [`CtfSink`] according to
+    /// [`METADATA`] into `(event id, timestamp, priority, name)`. The
+    /// label of a bookmark is decoded as its name.
+    fn decode(mut stream: &[u8]) -> Vec<(u32, u64, Option<(u8, String)>)> {
+        fn take<'a>(stream: &mut &'a [u8], n: usize) -> &'a [u8] {
+            let (head, tail) = (*stream).split_at(n);
+            *stream = tail;
+            head
+        }
+        let stream = &mut stream;
+
+        assert_eq!(
+            u32::from_le_bytes(take(stream, 4).try_into().unwrap()),
+            CTF_MAGIC
+        );
+
+        let mut events = vec![];
+        while !stream.is_empty() {
+            let id = u32::from_le_bytes(take(stream, 4).try_into().unwrap());
+            let timestamp = u64::from_le_bytes(take(stream, 8).try_into().unwrap());
+            let string = |stream: &mut &[u8]| {
+                let len = stream.iter().position(|b| *b == 0).unwrap();
+                let string = String::from_utf8(take(stream, len).to_vec()).unwrap();
+                take(stream, 1); // null terminator
+                string
+            };
+            let fields = match id {
+                0..=2 | 5 => {
+                    let priority = take(stream, 1)[0];
+                    Some((priority, string(stream)))
+                }
+                4 => Some((0, string(stream))),
+                _ => None,
+            };
+            events.push((id, timestamp, fields));
+        }
+
+        events
+    }
+
+    /// Ensure that the metadata describes the written stream.
+    #[test]
+    fn encode_decode() {
+        let dir = tempfile::tempdir().unwrap();
+        let bookmarks = [
+            (
+                api::Timestamp::Sync(Duration::from_nanos(2000)),
+                "after".to_string(),
+            ),
+            (
+                api::Timestamp::Sync(Duration::from_nanos(1000)),
+                "before".to_string(),
+            ),
+        ];
+        let mut sink = CtfSink::new(dir.path(), &bookmarks).unwrap();
+        drain_chunks(
+            &mut sink,
+            [api::ChunkBuilder::at(Duration::from_nanos(1500))
+                .task_with_priority("app::foo", api::TaskAction::Entered, 1)
+                .overflow()
+                .exited("app::foo")],
+        );
+
+        let metadata = fs::read_to_string(dir.path().join("metadata")).unwrap();
+        assert!(metadata.starts_with("/* CTF 1.8 */"));
+        for name in [
+            "task_entered",
+            "task_exited",
+            "task_returned",
+            "overflow",
+            "bookmark",
+            "task_completed",
+        ] {
+            assert!(metadata.contains(&format!("name = \"{}\";", name)));
+        }
+
+        let stream = fs::read(dir.path().join("stream")).unwrap();
+        assert_eq!(
+            decode(&stream),
+            vec![
+                (4, 1000, Some((0, "before".to_string()))),
+                (0, 1500, Some((1, "app::foo".to_string()))),
+                (3, 1500, None),
+                (1, 1500, Some((UNKNOWN_PRIORITY, "app::foo".to_string()))),
+                (4, 2000, Some((0, "after".to_string()))),
+            ]
+        );
+    }
+}
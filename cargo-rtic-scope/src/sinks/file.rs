@@ -1,5 +1,11 @@
 //! A simple file sink which receives JSON-serialized [`TraceData`].
 //! Used for replay functionality.
+//!
+//! The trace file is written incrementally: a JSON line of the
+//! [`TraceMetadata`] header, followed by a JSON line for each chunk of
+//! [`TraceData`] as it is drained. Nothing is accumulated in memory,
+//! and a recording that is killed loses at most the chunk that was
+//! being written.
 use crate::recovery::TraceMetadata;
 use crate::sinks::{Sink, SinkError};
 use crate::TraceData;
@@ -7,6 +13,7 @@ use std::fs;
 
 use std::io::Write;
 use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
 use cargo_metadata::Artifact;
 use chrono::prelude::*;
@@ -16,8 +23,13 @@ use serde_json;
 
 const TRACE_FILE_EXT: &str = ".trace";
 
+/// Interval at which the trace file is synchronized to disk, such that
+/// a crash of the host loses at most the chunks of the last interval.
+const SYNC_INTERVAL: Duration = Duration::from_secs(1);
+
 pub struct FileSink {
     file: fs::File,
+    last_sync: Instant,
 }
 
 impl FileSink {
@@ -78,32 +90,69 @@ impl FileSink {
                 )
             })?;
 
-        Ok(Self { file })
+        Ok(Self::new(file))
     }
 
-    /// Serialize [TraceMetadata] to replay file.
-    pub fn drain_metadata(&mut self, metadata: &TraceMetadata) -> Result<(), SinkError> {
-        {
-            let json = serde_json::to_string(&metadata)?;
-            self.file.write_all(json.as_bytes())
+    fn new(file: fs::File) -> Self {
+        Self {
+            file,
+            last_sync: Instant::now(),
+        }
+    }
+
+    /// Creates a trace file at the given path, overwriting any
+    /// existing file.
+    pub fn create(path: &Path) -> Result<Self, SinkError> {
+        let file = fs::File::create(path).map_err(|e| {
+            SinkError::SetupIOError(
+                Some(format!(
+                    "Failed to create output trace file {}",
+                    path.display()
+                )),
+                e,
+            )
+        })?;
+
+        Ok(Self::new(file))
+    }
+
+    /// Writes `json` as a line. The file is not buffered: the line is
+    /// handed to the OS at once.
+    fn write_line(&mut self, mut json: String) -> Result<(), SinkError> {
+        json.push('\n');
+        self.file
+            .write_all(json.as_bytes())
+            .map_err(SinkError::DrainIOError)?;
+
+        if self.last_sync.elapsed() >= SYNC_INTERVAL {
+            self.file.sync_data().map_err(SinkError::DrainIOError)?;
+            self.last_sync = Instant::now();
         }
-        .map_err(SinkError::DrainIOError)?;
 
         Ok(())
     }
+
+    /// Serialize [TraceMetadata] to replay file.
+    pub fn drain_metadata(&mut self, metadata: &TraceMetadata) -> Result<(), SinkError> {
+        let json = serde_json::to_string(&metadata)?;
+        self.write_line(json)
+    }
 }
 
 impl Sink for FileSink {
     fn drain(&mut self, data: TraceData, _: api::EventChunk) -> Result<(), SinkError> {
         let json = serde_json::to_string(&data)?;
-        self.file
-            .write_all(json.as_bytes())
-            .map_err(SinkError::DrainIOError)
+        self.write_line(json)
     }
 
     fn describe(&self) -> String {
         format!("file sink: {:?}", self.file)
     }
+
+    /// Synchronizes what was written since the last interval.
+    fn finalize(&mut self) -> Result<(), SinkError> {
+        self.file.sync_data().map_err(SinkError::DrainIOError)
+    }
 }
 
 /// Attempts to find a git repository starting from the given path
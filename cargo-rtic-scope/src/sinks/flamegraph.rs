@@ -0,0 +1,139 @@
+//! Sink which exports the execution time of tasks as the "folded"
+//! stacks of FlameGraph, e.g. to render with `flamegraph.pl` or
+//! `inferno-flamegraph`:
+//! ```text
+//! app::low 1200
+//! app::low;app::high 300
+//! ```
+//!
+//! A stack is the tasks that have been entered but not yet exited, in
+//! the order they were entered: a task that preempts another is thus
+//! on top of it. Each stack is weighted by the time spent executing
+//! it, in nanoseconds since target reset, as computed from the TPIU
+//! frequency of the trace. Time during which no task executes (e.g.
+//! when the target sleeps) is not accounted for.
+use crate::intervals::Intervals;
+use crate::sinks::{Sink, SinkError};
+use crate::TraceData;
+
+use std::fs;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use indexmap::IndexMap;
+use rtic_scope_api as api;
+
+/// Accumulates the time spent executing each stack of tasks.
+#[derive(Default)]
+pub struct Folded {
+    /// Timestamp of the previous chunk, in nanoseconds.
+    last: Option<u64>,
+    /// Tasks that have been entered but not yet exited.
+    stack: Intervals,
+    /// Time spent per stack, in order of first execution.
+    weights: IndexMap<String, u64>,
+}
+
+impl Folded {
+    /// Accounts for `chunk`: the time since the previous chunk is
+    /// attributed to the stack that executed during it.
+    pub fn update(&mut self, chunk: &api::EventChunk) {
+        let now = api::timestamp_offset(&chunk.timestamp).as_nanos() as u64;
+        if let Some(last) = self.last {
+            // Timestamps that go backwards are not accounted for.
+            if now > last && self.stack.depth() > 0 {
+                let stack: Vec<&str> = self.stack.open().iter().map(|o| o.task.as_str()).collect();
+                *self.weights.entry(stack.join(";")).or_insert(0) += now - last;
+            }
+        }
+        self.last = Some(self.last.map_or(now, |last| last.max(now)));
+        self.stack.update(chunk);
+    }
+
+    /// The folded stacks and their weights, one per line.
+    pub fn lines(&self) -> impl Iterator<Item = String> + '_ {
+        self.weights
+            .iter()
+            .map(|(stack, weight)| format!("{} {}", stack, weight))
+    }
+}
+
+pub struct FlamegraphSink {
+    file: BufWriter<fs::File>,
+    folded: Folded,
+}
+
+impl FlamegraphSink {
+    /// Creates a file at `path` to which the folded stacks are written
+    /// once the trace ends.
+    pub fn new(path: &Path) -> Result<Self, SinkError> {
+        let file = fs::File::create(path).map_err(|e| {
+            SinkError::SetupIOError(Some(format!("Failed to create {}", path.display())), e)
+        })?;
+
+        Ok(Self {
+            file: BufWriter::new(file),
+            folded: Folded::default(),
+        })
+    }
+
+    fn write(&mut self) -> std::io::Result<()> {
+        for line in self.folded.lines() {
+            writeln!(self.file, "{}", line)?;
+        }
+        self.file.flush()
+    }
+}
+
+impl Sink for FlamegraphSink {
+    fn drain(&mut self, _: TraceData, chunk: api::EventChunk) -> Result<(), SinkError> {
+        self.folded.update(&chunk);
+        Ok(())
+    }
+
+    fn describe(&self) -> String {
+        format!("flamegraph exporter ({:?})", self.file.get_ref())
+    }
+
+    /// The stacks are aggregated over the whole trace: write them once
+    /// it has ended.
+    fn finalize(&mut self) -> Result<(), SinkError> {
+        self.write().map_err(SinkError::DrainIOError)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::time::Duration;
+
+    /// Ensure that a preempting task is folded on top of the task it
+    /// preempts, and that stacks are weighted by their execution time.
+    #[test]
+    fn preemption() {
+        let at = |us| api::ChunkBuilder::at(Duration::from_micros(us));
+        let chunks = [
+            at(0).entered("app::low"),
+            at(200).entered("app::high"),
+            at(500).exited("app::high").returned("app::low"),
+            at(800).exited("app::low"),
+            // sleeping
+            at(1800).entered("app::high"),
+            at(1900).exited("app::high"),
+        ];
+
+        let mut folded = Folded::default();
+        for chunk in chunks {
+            folded.update(&chunk.build());
+        }
+        assert_eq!(
+            folded.lines().collect::<Vec<_>>(),
+            [
+                "app::low 500000",
+                "app::low;app::high 300000",
+                "app::high 100000"
+            ]
+        );
+    }
+}
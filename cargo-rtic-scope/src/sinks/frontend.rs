@@ -1,5 +1,5 @@
 //! Sub-proccess sink which received JSON-serialized
-//! [`api::EventChunk`]s.
+//! [`api::EventChunk`]s, preceded by an [`api::Handshake`].
 use crate::sinks::{Sink, SinkError};
 use crate::TraceData;
 
@@ -8,26 +8,95 @@ use std::io::Write;
 
 pub struct FrontendSink {
     socket: std::os::unix::net::UnixStream,
+    max_events: Option<usize>,
 }
 
 impl FrontendSink {
-    pub fn new(socket: std::os::unix::net::UnixStream) -> Self {
-        Self { socket }
+    /// Sends `handshake` to the frontend over `socket`. Chunks of more
+    /// than `max_events` events are split before they are sent.
+    pub fn new(
+        mut socket: std::os::unix::net::UnixStream,
+        handshake: &api::Handshake,
+        max_events: Option<usize>,
+    ) -> Result<Self, SinkError> {
+        let json = serde_json::to_string(handshake)? + "\n";
+        socket.write_all(json.as_bytes()).map_err(|e| {
+            SinkError::SetupIOError(Some("Failed to send handshake".to_string()), e)
+        })?;
+
+        Ok(Self { socket, max_events })
     }
 }
 
 impl Sink for FrontendSink {
     fn drain(&mut self, _: TraceData, chunk: api::EventChunk) -> Result<(), SinkError> {
-        let json = serde_json::to_string(&chunk)?
-        // reportedly required for async frontends
-        + "\n";
+        let chunks = match self.max_events {
+            Some(max) => chunk.split(max),
+            None => vec![chunk],
+        };
+
+        for chunk in chunks {
+            let json = serde_json::to_string(&chunk)?
+            // reportedly required for async frontends
+            + "\n";
 
-        self.socket
-            .write_all(json.as_bytes())
-            .map_err(SinkError::DrainIOError)
+            self.socket
+                .write_all(json.as_bytes())
+                .map_err(SinkError::DrainIOError)?;
+        }
+
+        Ok(())
     }
 
     fn describe(&self) -> String {
         format!("frontend using socket {:?}", self.socket)
     }
+
+    fn lossy(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::io::{BufRead, BufReader};
+    use std::os::unix::net::UnixStream;
+    use std::time::Duration;
+
+    use itm::{Timestamp, TracePacket};
+
+    /// Ensure that the handshake is sent before any events.
+    #[test]
+    fn handshake_first() {
+        let (tx, rx) = UnixStream::pair().unwrap();
+        let handshake = api::Handshake::new("0.0.0", serde_json::json!({ "program_name": "test" }));
+        let mut sink = FrontendSink::new(tx, &handshake, None).unwrap();
+
+        sink.drain(
+            TraceData {
+                timestamp: Timestamp::Sync(Duration::from_nanos(1)),
+                packets: vec![TracePacket::Overflow],
+                malformed_packets: vec![],
+                consumed_packets: 1,
+            },
+            api::ChunkBuilder::at(Duration::from_nanos(1))
+                .overflow()
+                .build(),
+        )
+        .unwrap();
+        drop(sink);
+
+        let mut lines = BufReader::new(rx).lines();
+        let received: api::Handshake =
+            serde_json::from_str(&lines.next().unwrap().unwrap()).unwrap();
+        assert!(received.is_compatible());
+        assert_eq!(received.version, "0.0.0");
+        assert_eq!(received.metadata["program_name"], "test");
+
+        let chunk: api::EventChunk = serde_json::from_str(&lines.next().unwrap().unwrap()).unwrap();
+        assert!(matches!(chunk.events[..], [api::EventType::Overflow]));
+        assert!(lines.next().is_none());
+    }
 }
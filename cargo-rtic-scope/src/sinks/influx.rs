@@ -0,0 +1,128 @@
+//! Sink which exports the executions of tasks as InfluxDB line
+//! protocol records, e.g. to import them into an existing
+//! InfluxDB/Grafana dashboard.
+//!
+//! Each execution is a record of the `task` measurement, tagged with
+//! the program and task name, with the duration of the execution (in
+//! nanoseconds) and whether it spans an overflow as fields. Records
+//! are timestamped at the entry of the task, in nanoseconds since the
+//! Unix epoch as approximated via the host-side timestamp of target
+//! reset.
+use crate::intervals::OverflowPolicy;
+use crate::latency::{Execution, Executions};
+use crate::sinks::{Sink, SinkError};
+use crate::TraceData;
+
+use std::fs;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use rtic_scope_api as api;
+
+/// Escapes commas, equal signs, and spaces in a tag key or value.
+fn escape_tag(tag: &str) -> String {
+    let mut escaped = String::with_capacity(tag.len());
+    for c in tag.chars() {
+        if matches!(c, ',' | '=' | ' ') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Formats `exec` of `program` as a line protocol record, where
+/// `epoch` is the time of target reset in nanoseconds since the Unix
+/// epoch.
+fn record(program: &str, epoch: i64, exec: &Execution) -> String {
+    format!(
+        "task,program={},task={} duration_ns={}i,gap={} {}",
+        escape_tag(program),
+        escape_tag(&exec.task),
+        exec.duration,
+        exec.gap,
+        epoch as i128 + exec.start as i128,
+    )
+}
+
+pub struct InfluxSink {
+    file: BufWriter<fs::File>,
+    program: String,
+    epoch: i64,
+    executions: Executions,
+}
+
+impl InfluxSink {
+    /// Creates a file at `path` to which the executions of the tasks of
+    /// `program` are written. `reset` is the time of target reset.
+    pub fn new(
+        path: &Path,
+        program: &str,
+        reset: chrono::DateTime<chrono::Local>,
+    ) -> Result<Self, SinkError> {
+        let file = fs::File::create(path).map_err(|e| {
+            SinkError::SetupIOError(Some(format!("Failed to create {}", path.display())), e)
+        })?;
+
+        Ok(Self {
+            file: BufWriter::new(file),
+            program: program.to_string(),
+            epoch: reset.timestamp_nanos(),
+            executions: Executions::new(OverflowPolicy::KeepAndFlag),
+        })
+    }
+}
+
+impl Sink for InfluxSink {
+    fn drain(&mut self, _: TraceData, chunk: api::EventChunk) -> Result<(), SinkError> {
+        self.executions.update(&chunk);
+        for exec in self.executions.take() {
+            writeln!(self.file, "{}", record(&self.program, self.epoch, &exec))
+                .map_err(SinkError::DrainIOError)?;
+        }
+
+        Ok(())
+    }
+
+    fn describe(&self) -> String {
+        format!("InfluxDB exporter ({:?})", self.file.get_ref())
+    }
+
+    fn finalize(&mut self) -> Result<(), SinkError> {
+        self.file.flush().map_err(SinkError::DrainIOError)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::sinks::test::drain_chunks;
+
+    use std::time::Duration;
+
+    /// Ensure that an execution is exported as a line protocol record.
+    #[test]
+    fn line_protocol() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("trace.lp");
+        let reset = chrono::TimeZone::timestamp(&chrono::Local, 1_600_000_000, 0);
+        let mut sink = InfluxSink::new(&path, "blinky app", reset).unwrap();
+
+        let at = |us| api::ChunkBuilder::at(Duration::from_micros(us));
+        drain_chunks(
+            &mut sink,
+            [
+                at(100).entered("app::foo"),
+                at(250).exited("app::foo").entered("app::bar,baz"),
+                at(300).exited("app::bar,baz"),
+            ],
+        );
+
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "task,program=blinky\\ app,task=app::foo duration_ns=150000i,gap=false 1600000000000100000\n\
+             task,program=blinky\\ app,task=app::bar\\,baz duration_ns=50000i,gap=false 1600000000000250000\n"
+        );
+    }
+}
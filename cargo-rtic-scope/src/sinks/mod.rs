@@ -1,8 +1,12 @@
 //! A sink to which [`TraceData`] and [`api::EventChunk`]s are for
 //! online and post-mortem analysis.
 use crate::diag;
+use crate::recovery::TraceMetadata;
 use crate::TraceData;
 
+use std::path::Path;
+use std::time::Duration;
+
 use rtic_scope_api as api;
 use thiserror::Error;
 
@@ -26,17 +30,243 @@ pub enum SinkError {
     ResetError(#[from] probe_rs::Error),
     #[error("Failed to setup sink because the source failed: {0}")]
     SourceError(#[from] crate::sources::SourceError),
+    #[error("The drain thread of {0} panicked")]
+    Panicked(String),
+}
+
+impl diag::DiagnosableError for SinkError {
+    fn kind(&self) -> &'static str {
+        match self {
+            Self::SetupIOError(..) => "SetupIOError",
+            Self::NoGitRoot(..) => "NoGitRoot",
+            Self::GitError(..) => "GitError",
+            Self::DrainSerError(..) => "DrainSerError",
+            Self::DrainIOError(..) => "DrainIOError",
+            Self::ResetError(..) => "ResetError",
+            Self::SourceError(..) => "SourceError",
+            Self::Panicked(..) => "Panicked",
+        }
+    }
 }
 
-impl diag::DiagnosableError for SinkError {}
+mod archive;
+pub use archive::ArchiveSink;
+
+pub mod broadcast;
+pub use broadcast::Broadcast;
+
+mod chrome;
+pub use chrome::ChromeSink;
+
+mod ctf;
+pub use ctf::CtfSink;
 
 pub mod file;
 pub use file::FileSink;
 
+mod flamegraph;
+pub use flamegraph::FlamegraphSink;
+
 mod frontend;
 pub use frontend::FrontendSink;
 
-pub trait Sink {
+mod influx;
+pub use influx::InfluxSink;
+
+mod utilization;
+pub use utilization::UtilizationSink;
+
+mod vcd;
+pub use vcd::VcdSink;
+
+pub trait Sink: std::marker::Send {
     fn drain(&mut self, data: TraceData, chunk: api::EventChunk) -> Result<(), SinkError>;
     fn describe(&self) -> String;
+
+    /// Completes the output once the trace has ended, e.g. by writing
+    /// what was aggregated over the whole trace and flushing buffered
+    /// writes. Called once, after the last chunk has been drained.
+    fn finalize(&mut self) -> Result<(), SinkError> {
+        Ok(())
+    }
+
+    /// Whether chunks may be dropped for this sink when it cannot keep
+    /// up, e.g. for a live frontend. Otherwise, e.g. for recordings and
+    /// exports, the source is stalled until the sink has drained.
+    fn lossy(&self) -> bool {
+        false
+    }
+}
+
+/// An exporter that can be selected via `--export` to export a trace
+/// to a single output instead of forwarding it to frontends.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Exporter {
+    /// [`CtfSink`], to a directory.
+    Ctf,
+    /// [`UtilizationSink`], to a CSV file.
+    Utilization,
+    /// [`InfluxSink`], to an InfluxDB line protocol file.
+    Influx,
+    /// [`ChromeSink`], to a Chrome Trace Event Format JSON file.
+    Chrome,
+    /// [`VcdSink`], to a Value Change Dump file.
+    Vcd,
+    /// [`FlamegraphSink`], to a file of folded stacks.
+    Flamegraph,
+}
+
+impl std::str::FromStr for Exporter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ctf" => Ok(Self::Ctf),
+            "utilization" => Ok(Self::Utilization),
+            "influx" => Ok(Self::Influx),
+            "chrome" => Ok(Self::Chrome),
+            "vcd" => Ok(Self::Vcd),
+            "flamegraph" => Ok(Self::Flamegraph),
+            _ => Err(format!(
+                "unknown exporter {}; expected one of: ctf, utilization, influx, chrome, vcd, flamegraph",
+                s
+            )),
+        }
+    }
+}
+
+impl Exporter {
+    /// Creates the sink that exports the trace of `metadata` to
+    /// `output`. `utilization_window` is the size of the windows of
+    /// [`Exporter::Utilization`].
+    pub fn sink(
+        &self,
+        output: &Path,
+        metadata: &TraceMetadata,
+        utilization_window: Duration,
+    ) -> Result<Box<dyn Sink>, SinkError> {
+        Ok(match self {
+            Self::Ctf => Box::new(CtfSink::new(output, metadata.bookmarks())?),
+            Self::Utilization => Box::new(UtilizationSink::new(output, utilization_window)?),
+            Self::Influx => Box::new(InfluxSink::new(
+                output,
+                &metadata.program_name,
+                metadata.reset_timestamp(),
+            )?),
+            Self::Chrome => Box::new(ChromeSink::new(output, &metadata.program_name)?),
+            Self::Vcd => Box::new(VcdSink::new(
+                output,
+                &metadata.program_name,
+                metadata.reset_timestamp(),
+            )?),
+            Self::Flamegraph => Box::new(FlamegraphSink::new(output)?),
+        })
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+    use crate::recovery::test::metadata;
+    use crate::sources::FileSource;
+
+    use std::fs;
+
+    use cortex_m::peripheral::scb::Exception;
+    use itm::{ExceptionAction, TracePacket, VectActive};
+
+    /// Drains the `chunks` into `sink` with trace data of no packets,
+    /// and finalizes it.
+    pub(crate) fn drain_chunks(
+        sink: &mut dyn Sink,
+        chunks: impl IntoIterator<Item = api::ChunkBuilder>,
+    ) {
+        for chunk in chunks {
+            let chunk = chunk.build();
+            let data = TraceData {
+                timestamp: api::Timestamp::Sync(api::timestamp_offset(&chunk.timestamp)),
+                packets: vec![],
+                malformed_packets: vec![],
+                consumed_packets: 0,
+            };
+            sink.drain(data, chunk).unwrap();
+        }
+        sink.finalize().unwrap();
+    }
+
+    /// Ensure that exporting a replayed trace yields the same output as
+    /// exporting the trace live.
+    #[test]
+    fn replay_matches_live() {
+        let dir = tempfile::tempdir().unwrap();
+        let metadata = metadata();
+        let exception = |exception, action| TracePacket::ExceptionTrace {
+            exception: VectActive::Exception(exception),
+            action,
+        };
+        let trace: Vec<TraceData> = [
+            (0, exception(Exception::SysTick, ExceptionAction::Entered)),
+            (400, exception(Exception::PendSV, ExceptionAction::Entered)),
+            (700, exception(Exception::PendSV, ExceptionAction::Exited)),
+            (
+                700,
+                exception(Exception::SysTick, ExceptionAction::Returned),
+            ),
+            (1600, exception(Exception::SysTick, ExceptionAction::Exited)),
+        ]
+        .into_iter()
+        .map(|(us, packet)| TraceData {
+            timestamp: api::Timestamp::Sync(Duration::from_micros(us)),
+            packets: vec![packet],
+            malformed_packets: vec![],
+            consumed_packets: 1,
+        })
+        .collect();
+
+        // The CTF exporter outputs a directory of which the stream is
+        // compared.
+        for (exporter, stream) in [
+            (Exporter::Ctf, Some("stream")),
+            (Exporter::Utilization, None),
+            (Exporter::Influx, None),
+            (Exporter::Chrome, None),
+            (Exporter::Vcd, None),
+            (Exporter::Flamegraph, None),
+        ] {
+            let export = |name: &str| dir.path().join(format!("{:?}-{}", exporter, name));
+            let read = |name: &str| {
+                let path = export(name);
+                fs::read(stream.map_or(path.clone(), |s| path.join(s))).unwrap()
+            };
+            let window = Duration::from_millis(1);
+
+            // Export live, recording the trace on the way.
+            let recording = dir.path().join("recording.trace");
+            let mut file = FileSink::create(&recording).unwrap();
+            file.drain_metadata(&metadata).unwrap();
+            let mut sink = exporter.sink(&export("live"), &metadata, window).unwrap();
+            for data in trace.iter().cloned() {
+                let chunk = metadata.build_event_chunk(data.clone());
+                file.drain(data.clone(), chunk.clone()).unwrap();
+                sink.drain(data, chunk).unwrap();
+            }
+            file.finalize().unwrap();
+            sink.finalize().unwrap();
+
+            // Export the recording.
+            let src = FileSource::new(fs::File::open(&recording).unwrap()).unwrap();
+            let replayed = src.metadata();
+            let mut sink = exporter.sink(&export("replay"), &replayed, window).unwrap();
+            for data in src {
+                let data = data.unwrap();
+                let chunk = replayed.build_event_chunk(data.clone());
+                sink.drain(data, chunk).unwrap();
+            }
+            sink.finalize().unwrap();
+
+            let live = read("live");
+            assert!(!live.is_empty());
+            assert_eq!(live, read("replay"));
+        }
+    }
 }
@@ -0,0 +1,229 @@
+//! Sink which exports the CPU utilization per task priority as a CSV
+//! time series of fixed-size windows.
+//!
+//! Execution time is attributed to the most recently entered task that
+//! has not yet exited; a preempted task is thus not considered busy.
+//! Tasks of unknown priority are not accounted for.
+use crate::intervals::Intervals;
+use crate::sinks::{Sink, SinkError};
+use crate::TraceData;
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::time::Duration;
+
+use rtic_scope_api as api;
+
+/// Utilization of a priority level during a window.
+#[derive(Debug, PartialEq)]
+pub struct Sample {
+    /// Start of the window, in nanoseconds since target reset.
+    pub start: u64,
+    pub priority: u8,
+    /// Fraction of the window spent executing tasks of `priority`.
+    pub utilization: f64,
+}
+
+/// Accumulates busy time per priority level over windows of fixed
+/// size.
+pub struct Utilization {
+    /// Size of a window, in nanoseconds.
+    window: u64,
+    /// Index of the current window.
+    current: u64,
+    /// Timestamp of the previous chunk, in nanoseconds.
+    last: Option<u64>,
+    /// Tasks that have been entered but not yet exited, and their
+    /// priority.
+    stack: Intervals,
+    /// Busy time per priority in the current window. Contains all
+    /// priorities seen so far.
+    busy: BTreeMap<u8, u64>,
+}
+
+impl Utilization {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window: (window.as_nanos() as u64).max(1),
+            current: 0,
+            last: None,
+            stack: Intervals::default(),
+            busy: BTreeMap::new(),
+        }
+    }
+
+    /// Accounts for `chunk` and returns the samples of the windows that
+    /// were completed by it.
+    pub fn update(&mut self, chunk: &api::EventChunk) -> Vec<Sample> {
+        let now = api::timestamp_offset(&chunk.timestamp).as_nanos() as u64;
+        let mut samples = vec![];
+        self.advance(now, &mut samples);
+
+        for event in chunk.events.iter() {
+            if let api::EventType::Task {
+                action: api::TaskAction::Entered,
+                priority: Some(prio),
+                ..
+            } = event
+            {
+                self.busy.entry(*prio).or_insert(0);
+            }
+        }
+        self.stack.update(chunk);
+
+        samples
+    }
+
+    /// Returns the samples of the current, incomplete window.
+    pub fn finish(&mut self) -> Vec<Sample> {
+        let mut samples = vec![];
+        if self.last.is_some() {
+            self.emit(&mut samples);
+        }
+        samples
+    }
+
+    /// Attributes the time since the previous chunk to the priority of
+    /// the executing task, completing windows on the way.
+    fn advance(&mut self, now: u64, samples: &mut Vec<Sample>) {
+        let mut from = match self.last {
+            Some(last) => last,
+            None => {
+                self.current = now / self.window;
+                now
+            }
+        };
+        let priority = self.stack.open().last().and_then(|o| o.priority);
+
+        while from < now {
+            let end = (self.current + 1) * self.window;
+            let to = now.min(end);
+            if let Some(prio) = priority {
+                *self.busy.entry(prio).or_insert(0) += to - from;
+            }
+            if to == end {
+                self.emit(samples);
+            }
+            from = to;
+        }
+
+        // Timestamps that go backwards are not accounted for.
+        self.last = Some(now.max(from));
+    }
+
+    fn emit(&mut self, samples: &mut Vec<Sample>) {
+        let start = self.current * self.window;
+        for (prio, busy) in self.busy.iter_mut() {
+            samples.push(Sample {
+                start,
+                priority: *prio,
+                utilization: *busy as f64 / self.window as f64,
+            });
+            *busy = 0;
+        }
+        self.current += 1;
+    }
+}
+
+pub struct UtilizationSink {
+    file: BufWriter<fs::File>,
+    utilization: Utilization,
+}
+
+impl UtilizationSink {
+    /// Creates a CSV file at `path` to which the utilization during
+    /// each `window` is written.
+    pub fn new(path: &Path, window: Duration) -> Result<Self, SinkError> {
+        let setup_err =
+            |e| SinkError::SetupIOError(Some(format!("Failed to create {}", path.display())), e);
+        let mut file = BufWriter::new(fs::File::create(path).map_err(setup_err)?);
+        writeln!(file, "start_ns,priority,utilization").map_err(setup_err)?;
+
+        Ok(Self {
+            file,
+            utilization: Utilization::new(window),
+        })
+    }
+
+    fn write(&mut self, samples: Vec<Sample>) -> std::io::Result<()> {
+        for Sample {
+            start,
+            priority,
+            utilization,
+        } in samples
+        {
+            writeln!(self.file, "{},{},{}", start, priority, utilization)?;
+        }
+        Ok(())
+    }
+}
+
+impl Sink for UtilizationSink {
+    fn drain(&mut self, _: TraceData, chunk: api::EventChunk) -> Result<(), SinkError> {
+        let samples = self.utilization.update(&chunk);
+        self.write(samples).map_err(SinkError::DrainIOError)
+    }
+
+    fn describe(&self) -> String {
+        format!("utilization exporter ({:?})", self.file.get_ref())
+    }
+
+    /// Writes the incomplete window.
+    fn finalize(&mut self) -> Result<(), SinkError> {
+        let samples = self.utilization.finish();
+        self.write(samples)
+            .and_then(|_| self.file.flush())
+            .map_err(SinkError::DrainIOError)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Ensure that the utilization of a window matches the sum of the
+    /// busy intervals within it.
+    #[test]
+    fn busy_intervals() {
+        let at = |us| api::ChunkBuilder::at(Duration::from_micros(us));
+        let chunks = [
+            at(0).task_with_priority("app::foo", api::TaskAction::Entered, 1),
+            at(200).task_with_priority("app::bar", api::TaskAction::Entered, 2),
+            at(500)
+                .task_with_priority("app::bar", api::TaskAction::Exited, 2)
+                .task_with_priority("app::foo", api::TaskAction::Returned, 1),
+            at(800).task_with_priority("app::foo", api::TaskAction::Exited, 1),
+            at(1800).task_with_priority("app::foo", api::TaskAction::Entered, 1),
+            at(2300).task_with_priority("app::foo", api::TaskAction::Exited, 1),
+        ];
+
+        let mut utilization = Utilization::new(Duration::from_millis(1));
+        let mut samples: Vec<Sample> = chunks
+            .into_iter()
+            .flat_map(|chunk| utilization.update(&chunk.build()))
+            .collect();
+        samples.extend(utilization.finish());
+
+        let sample = |start, priority, utilization| Sample {
+            start,
+            priority,
+            utilization,
+        };
+        assert_eq!(
+            samples,
+            vec![
+                // foo: [0, 200) and [500, 800); bar: [200, 500)
+                sample(0, 1, 0.5),
+                sample(0, 2, 0.3),
+                // foo: [1800, 2000)
+                sample(1_000_000, 1, 0.2),
+                sample(1_000_000, 2, 0.0),
+                // foo: [2000, 2300)
+                sample(2_000_000, 1, 0.3),
+                sample(2_000_000, 2, 0.0),
+            ]
+        );
+    }
+}
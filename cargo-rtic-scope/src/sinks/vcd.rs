@@ -0,0 +1,220 @@
+//! Sink which exports the trace as a Value Change Dump (VCD), e.g. to
+//! inspect task preemption in GTKWave.
+//!
+//! Each hardware and software task of the trace metadata is a one-bit
+//! signal that is high from the entry of the task until its exit. A
+//! preempted task thus remains high while the preempting task is high.
+//! Time is in nanoseconds since target reset, as computed from the
+//! TPIU frequency of the trace.
+//!
+//! Signals are declared in the order their tasks first appear, such
+//! that renamed, virtual, and dispatcher tasks are exported as well.
+//! As declarations precede all value changes, the changes are buffered
+//! in a temporary file until the trace ends.
+use crate::sinks::{Sink, SinkError};
+use crate::TraceData;
+
+use std::fs;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use indexmap::IndexMap;
+use rtic_scope_api as api;
+
+/// The identifier code of the `n`th signal: a base-94 number of
+/// printable ASCII characters.
+fn code(mut n: usize) -> String {
+    let mut code = String::new();
+    loop {
+        code.push((b'!' + (n % 94) as u8) as char);
+        n /= 94;
+        if n == 0 {
+            return code;
+        }
+        n -= 1;
+    }
+}
+
+/// A one-bit signal of a task.
+struct Signal {
+    code: String,
+    high: bool,
+}
+
+pub struct VcdSink {
+    file: BufWriter<fs::File>,
+    program: String,
+    reset: chrono::DateTime<chrono::Local>,
+    signals: IndexMap<String, Signal>,
+    /// Value changes, written to `file` after the declarations.
+    changes: BufWriter<fs::File>,
+    /// Time of the last value change, in nanoseconds.
+    time: Option<u64>,
+}
+
+impl VcdSink {
+    /// Creates a file at `path` for a trace of `program`. `reset` is
+    /// the time of target reset.
+    pub fn new(
+        path: &Path,
+        program: &str,
+        reset: chrono::DateTime<chrono::Local>,
+    ) -> Result<Self, SinkError> {
+        let file = fs::File::create(path).map_err(|e| {
+            SinkError::SetupIOError(Some(format!("Failed to create {}", path.display())), e)
+        })?;
+        let changes = tempfile::tempfile().map_err(|e| {
+            SinkError::SetupIOError(Some("Failed to create a temporary file".to_string()), e)
+        })?;
+
+        Ok(Self {
+            file: BufWriter::new(file),
+            program: program.to_string(),
+            reset,
+            signals: IndexMap::new(),
+            changes: BufWriter::new(changes),
+            time: None,
+        })
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        let f = &mut self.file;
+        writeln!(f, "$date {} $end", self.reset.to_rfc3339())?;
+        writeln!(
+            f,
+            "$version cargo-rtic-scope {} $end",
+            env!("CARGO_PKG_VERSION")
+        )?;
+        writeln!(f, "$timescale 1 ns $end")?;
+        writeln!(f, "$scope module {} $end", self.program.replace(' ', "_"))?;
+        for (task, signal) in self.signals.iter() {
+            writeln!(f, "$var wire 1 {} {} $end", signal.code, task)?;
+        }
+        writeln!(f, "$upscope $end")?;
+        writeln!(f, "$enddefinitions $end")?;
+        writeln!(f, "$dumpvars")?;
+        for signal in self.signals.values() {
+            writeln!(f, "0{}", signal.code)?;
+        }
+        writeln!(f, "$end")
+    }
+
+    /// Buffers the value changes of `chunk`.
+    fn write_chunk(&mut self, chunk: &api::EventChunk) -> io::Result<()> {
+        let now = api::timestamp_offset(&chunk.timestamp).as_nanos() as u64;
+
+        for event in chunk.events.iter() {
+            let (name, high) = match event {
+                api::EventType::Task {
+                    name,
+                    action: api::TaskAction::Entered,
+                    ..
+                } => (name, true),
+                api::EventType::Task {
+                    name,
+                    action: api::TaskAction::Exited,
+                    ..
+                } => (name, false),
+                _ => continue,
+            };
+            let n = self.signals.len();
+            let signal = self.signals.entry(name.clone()).or_insert_with(|| Signal {
+                code: code(n),
+                high: false,
+            });
+            if signal.high == high {
+                continue;
+            }
+            signal.high = high;
+
+            // NOTE(max) value changes must be in order of time
+            if self.time.map_or(true, |time| now > time) {
+                writeln!(self.changes, "#{}", now)?;
+                self.time = Some(now);
+            }
+            writeln!(self.changes, "{}{}", high as u8, signal.code)?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes the declarations of all signals seen, followed by the
+    /// buffered value changes.
+    fn write_dump(&mut self) -> io::Result<()> {
+        self.changes.flush()?;
+        self.write_header()?;
+        let changes = self.changes.get_mut();
+        changes.seek(SeekFrom::Start(0))?;
+        io::copy(changes, &mut self.file)?;
+        self.file.flush()
+    }
+}
+
+impl Sink for VcdSink {
+    fn drain(&mut self, _: TraceData, chunk: api::EventChunk) -> Result<(), SinkError> {
+        self.write_chunk(&chunk).map_err(SinkError::DrainIOError)
+    }
+
+    fn describe(&self) -> String {
+        format!("VCD exporter ({:?})", self.file.get_ref())
+    }
+
+    fn finalize(&mut self) -> Result<(), SinkError> {
+        self.write_dump().map_err(SinkError::DrainIOError)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::sinks::test::drain_chunks;
+
+    use std::time::Duration;
+
+    use indexmap::IndexSet;
+
+    /// Ensure that identifier codes are unique beyond a single
+    /// character.
+    #[test]
+    fn codes() {
+        assert_eq!(code(0), "!");
+        assert_eq!(code(93), "~");
+        assert_eq!(code(94), "!!");
+        let codes: IndexSet<String> = (0..10_000).map(code).collect();
+        assert_eq!(codes.len(), 10_000);
+    }
+
+    /// Ensure that a preempted task remains high while the preempting
+    /// task executes, and that signals are declared for all tasks in
+    /// order of appearance, also those not in the trace metadata.
+    #[test]
+    fn preemption() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("trace.vcd");
+        let reset = chrono::TimeZone::timestamp(&chrono::Local, 1_600_000_000, 0);
+        let mut sink = VcdSink::new(&path, "blinky", reset).unwrap();
+
+        let at = |ns| api::ChunkBuilder::at(Duration::from_nanos(ns));
+        drain_chunks(
+            &mut sink,
+            [
+                at(100).entered("app::low"),
+                at(200).entered("app::high"),
+                at(300).exited("app::high").returned("app::low"),
+                at(400).exited("app::low").entered("app::renamed"),
+            ],
+        );
+
+        let vcd = fs::read_to_string(&path).unwrap();
+        let (header, changes) = vcd.split_once("$enddefinitions $end\n").unwrap();
+        assert!(header.contains("$timescale 1 ns $end"));
+        assert!(header.contains("$var wire 1 ! app::low $end"));
+        assert!(header.contains("$var wire 1 \" app::high $end"));
+        assert!(header.contains("$var wire 1 # app::renamed $end"));
+        assert_eq!(
+            changes,
+            "$dumpvars\n0!\n0\"\n0#\n$end\n#100\n1!\n#200\n1\"\n#300\n0\"\n#400\n0!\n1#\n"
+        );
+    }
+}
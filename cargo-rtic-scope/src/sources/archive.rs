@@ -0,0 +1,91 @@
+//! Source from which the [`TraceData`] of an archive is read for replay
+//! purposes. See [`crate::archive`].
+use crate::archive::Archive;
+use crate::sources::{BufferStatus, Source, SourceError};
+use crate::TraceData;
+
+use std::ops::Range;
+use std::time::Duration;
+
+use rtic_scope_api as api;
+
+pub struct ArchiveSource {
+    trace: std::vec::IntoIter<TraceData>,
+}
+
+impl ArchiveSource {
+    pub fn new(archive: Archive) -> Self {
+        Self {
+            trace: archive.trace.into_iter(),
+        }
+    }
+
+    /// Restricts iteration to the [`TraceData`] within the given
+    /// `range`, as [`crate::sources::FileSource::restrict`] does for
+    /// trace files.
+    pub fn restrict(&mut self, range: Range<Duration>) {
+        self.trace = self
+            .trace
+            .by_ref()
+            .filter(|data| range.contains(&api::timestamp_offset(&data.timestamp)))
+            .collect::<Vec<_>>()
+            .into_iter();
+    }
+}
+
+impl Iterator for ArchiveSource {
+    type Item = Result<TraceData, SourceError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.trace.next().map(Ok)
+    }
+}
+
+impl Source for ArchiveSource {
+    fn avail_buffer(&self) -> BufferStatus {
+        BufferStatus::NotApplicable
+    }
+
+    fn finite(&self) -> bool {
+        true
+    }
+
+    fn describe(&self) -> String {
+        "archive".to_string()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use itm::TracePacket;
+
+    /// Ensure that a restricted archive only replays the trace data
+    /// within the range.
+    #[test]
+    fn restrict_range() {
+        let archive = Archive {
+            metadata: crate::recovery::test::metadata(),
+            trace: (0..10)
+                .map(|ns| TraceData {
+                    timestamp: api::Timestamp::Sync(Duration::from_nanos(ns)),
+                    packets: vec![TracePacket::Overflow],
+                    malformed_packets: vec![],
+                    consumed_packets: 1,
+                })
+                .collect(),
+            events: vec![],
+            raw: vec![],
+            app: None,
+            manifest: None,
+        };
+
+        let mut source = ArchiveSource::new(archive);
+        source.restrict(Duration::from_nanos(3)..Duration::from_nanos(6));
+        let offsets: Vec<u128> = source
+            .map(|data| api::timestamp_offset(&data.unwrap().timestamp).as_nanos())
+            .collect();
+        assert_eq!(offsets, [3, 4, 5]);
+    }
+}
@@ -1,16 +1,24 @@
 //! File source from which serialized [`TraceData`] is read for replay
 //! purposes.
+use crate::log;
 use crate::recovery::TraceMetadata;
 use crate::sources::{BufferStatus, Source, SourceError};
 use crate::TraceData;
 
 use std::fs;
-use std::io::BufReader;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::ops::Range;
+use std::time::Duration;
+
+use rtic_scope_api as api;
+use serde::Deserialize;
 
 /// Something data is deserialized from. Always a file.
 pub struct FileSource {
     reader: BufReader<fs::File>,
     metadata: TraceMetadata,
+    index: Option<TraceIndex>,
+    restricted: Option<std::vec::IntoIter<TraceData>>,
 }
 
 impl FileSource {
@@ -18,9 +26,9 @@ impl FileSource {
         let mut reader = BufReader::new(fd);
         let metadata = {
             let mut stream =
-                serde_json::Deserializer::from_reader(&mut reader).into_iter::<TraceMetadata>();
-            if let Some(Ok(metadata)) = stream.next() {
-                metadata
+                serde_json::Deserializer::from_reader(&mut reader).into_iter::<serde_json::Value>();
+            if let Some(Ok(header)) = stream.next() {
+                TraceMetadata::from_header(header)?
             } else {
                 return Err(SourceError::SetupError(
                     "Failed to deserialize metadata header".to_string(),
@@ -28,22 +36,140 @@ impl FileSource {
             }
         };
 
-        Ok(Self { reader, metadata })
+        Ok(Self {
+            reader,
+            metadata,
+            index: None,
+            restricted: None,
+        })
     }
 
     pub fn metadata(&self) -> TraceMetadata {
         self.metadata.clone()
     }
+
+    /// Returns all [`TraceData`] with a timestamp within the given
+    /// `range` (offset since target reset). The trace file is indexed
+    /// on the first query; subsequent queries only read the matching
+    /// chunks. Does not affect iteration.
+    pub fn query(&mut self, range: Range<Duration>) -> Result<Vec<TraceData>, SourceError> {
+        let pos = self
+            .reader
+            .stream_position()
+            .map_err(SourceError::IterIOError)?;
+
+        if self.index.is_none() {
+            self.reader
+                .seek(SeekFrom::Start(0))
+                .map_err(SourceError::IterIOError)?;
+            self.index = Some(TraceIndex::build(&mut self.reader)?);
+        }
+        let data = self.index.as_ref().unwrap().query(&mut self.reader, range);
+
+        self.reader
+            .seek(SeekFrom::Start(pos))
+            .map_err(SourceError::IterIOError)?;
+        data
+    }
+
+    /// Restricts iteration to the [`TraceData`] within the given
+    /// `range`. See [`FileSource::query`].
+    pub fn restrict(&mut self, range: Range<Duration>) -> Result<(), SourceError> {
+        self.restricted = Some(self.query(range)?.into_iter());
+        Ok(())
+    }
+}
+
+/// Maps the timestamps of the [`TraceData`] in a trace file to their
+/// byte offset in the file.
+pub struct TraceIndex {
+    /// `(timestamp offset, byte offset)` pairs, sorted by timestamp.
+    entries: Vec<(Duration, u64)>,
+}
+
+impl TraceIndex {
+    /// Indexes a serialized trace read from `reader`, which must be
+    /// positioned at the start of the trace file.
+    pub fn build<R: Read>(reader: R) -> Result<Self, SourceError> {
+        /// Only the timestamp of each chunk need be deserialized. The
+        /// metadata header does not have one.
+        #[derive(Deserialize)]
+        struct Indexed {
+            #[serde(default)]
+            timestamp: Option<api::Timestamp>,
+        }
+
+        let mut stream = serde_json::Deserializer::from_reader(reader).into_iter::<Indexed>();
+        let mut entries = vec![];
+        loop {
+            let offset = stream.byte_offset() as u64;
+            match stream.next() {
+                Some(Ok(Indexed {
+                    timestamp: Some(ts),
+                })) => entries.push((api::timestamp_offset(&ts), offset)),
+                Some(Ok(_)) => (),                   // metadata header
+                Some(Err(e)) if e.is_eof() => break, // partially written chunk
+                Some(Err(e)) => return Err(SourceError::IterDeserError(e)),
+                None => break,
+            }
+        }
+
+        // Chunks are recorded in order, but ensure that the index is
+        // sorted regardless.
+        entries.sort_by_key(|(ts, _)| *ts);
+
+        Ok(Self { entries })
+    }
+
+    /// Reads the [`TraceData`] with a timestamp within `range` from
+    /// `reader` by seeking to the indexed offsets. No other chunks are
+    /// read.
+    pub fn query<R: Read + Seek>(
+        &self,
+        reader: &mut R,
+        range: Range<Duration>,
+    ) -> Result<Vec<TraceData>, SourceError> {
+        let start = self.entries.partition_point(|(ts, _)| *ts < range.start);
+        let end = self.entries.partition_point(|(ts, _)| *ts < range.end);
+
+        self.entries[start..end.max(start)]
+            .iter()
+            .map(|(_, offset)| {
+                reader
+                    .seek(SeekFrom::Start(*offset))
+                    .map_err(SourceError::IterIOError)?;
+                serde_json::Deserializer::from_reader(&mut *reader)
+                    .into_iter::<TraceData>()
+                    .next()
+                    .unwrap_or_else(|| {
+                        Err(serde::de::Error::custom("index points past end of file"))
+                    })
+                    .map_err(SourceError::IterDeserError)
+            })
+            .collect()
+    }
 }
 
 impl Iterator for FileSource {
     type Item = Result<TraceData, SourceError>;
 
     fn next(&mut self) -> Option<Self::Item> {
+        if let Some(restricted) = &mut self.restricted {
+            return restricted.next().map(Ok);
+        }
+
         let mut stream =
             serde_json::Deserializer::from_reader(&mut self.reader).into_iter::<TraceData>();
         match stream.next() {
             Some(Ok(data)) => Some(Ok(data)),
+            // The recording was killed while a chunk was written: the
+            // trace ends with the last complete chunk.
+            Some(Err(e)) if e.is_eof() => {
+                log::warn(
+                    "trace file ends in a partially written chunk, which is ignored".to_string(),
+                );
+                None
+            }
             Some(Err(e)) => Some(Err(SourceError::IterDeserError(e))),
             None => None,
         }
@@ -55,7 +181,105 @@ impl Source for FileSource {
         BufferStatus::NotApplicable
     }
 
+    fn finite(&self) -> bool {
+        true
+    }
+
     fn describe(&self) -> String {
         format!("file ({:?})", self.reader.get_ref())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::io::Cursor;
+
+    use itm::TracePacket;
+
+    /// Ensure that a trace file of which the last chunk was only
+    /// partially written is replayed up until that chunk.
+    #[test]
+    fn partial_chunk() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("killed.trace");
+        let mut sink = crate::sinks::FileSink::create(&path).unwrap();
+        sink.drain_metadata(&crate::recovery::test::metadata())
+            .unwrap();
+        let data = |ns| TraceData {
+            timestamp: api::Timestamp::Sync(Duration::from_nanos(ns)),
+            packets: vec![TracePacket::Overflow],
+            malformed_packets: vec![],
+            consumed_packets: 1,
+        };
+        for ns in 0..3 {
+            let chunk = api::ChunkBuilder::at(Duration::from_nanos(ns)).build();
+            crate::sinks::Sink::drain(&mut sink, data(ns), chunk).unwrap();
+        }
+        drop(sink);
+
+        // Chunks are written as lines.
+        let mut file = fs::read(&path).unwrap();
+        assert_eq!(file.iter().filter(|&&b| b == b'\n').count(), 4);
+        let partial = serde_json::to_vec(&data(3)).unwrap();
+        file.extend(&partial[..partial.len() / 2]);
+        fs::write(&path, file).unwrap();
+
+        let mut source = FileSource::new(fs::File::open(&path).unwrap()).unwrap();
+        let offsets: Vec<u128> = source
+            .by_ref()
+            .map(|data| api::timestamp_offset(&data.unwrap().timestamp).as_nanos())
+            .collect();
+        assert_eq!(offsets, [0, 1, 2]);
+        assert_eq!(
+            source
+                .query(Duration::ZERO..Duration::from_nanos(10))
+                .unwrap()
+                .len(),
+            3
+        );
+    }
+
+    /// Ensure that a range query returns the correct chunks without
+    /// reading unrelated chunks.
+    #[test]
+    fn query_range() {
+        let mut file = br#"{"program_name":"blinky"}"#.to_vec();
+        let mut offsets = vec![];
+        for i in 0..10 {
+            offsets.push(file.len());
+            let data = TraceData {
+                timestamp: api::Timestamp::Sync(Duration::from_nanos(i)),
+                packets: vec![TracePacket::Overflow],
+                malformed_packets: vec![],
+                consumed_packets: 1,
+            };
+            file.extend(serde_json::to_vec(&data).unwrap());
+        }
+
+        let index = TraceIndex::build(Cursor::new(&file)).unwrap();
+        assert_eq!(index.entries.len(), 10);
+
+        // Corrupt all chunks outside of the queried range: a read of
+        // any of them would fail.
+        for (i, offset) in offsets.iter().enumerate() {
+            if !(3..6).contains(&i) {
+                file[*offset] = b'!';
+            }
+        }
+
+        let data = index
+            .query(
+                &mut Cursor::new(&file),
+                Duration::from_nanos(3)..Duration::from_nanos(6),
+            )
+            .unwrap();
+        assert_eq!(
+            data.iter()
+                .map(|d| api::timestamp_offset(&d.timestamp).as_nanos())
+                .collect::<Vec<_>>(),
+            vec![3, 4, 5]
+        );
+    }
+}
@@ -4,6 +4,7 @@
 use crate::diag;
 use crate::TraceData;
 
+use rtic_scope_api as api;
 use thiserror::Error;
 
 #[derive(Debug)]
@@ -29,6 +30,8 @@ pub enum SourceError {
     SetupIOError(#[source] std::io::Error),
     #[error("Failed to setup source probe: {0}")]
     ProbeError(#[from] probe_rs::Error),
+    #[error("Failed to configure SWO tracing at {1} Hz ({2} baud): {0}")]
+    SwoSetupError(#[source] probe_rs::Error, u32, u32),
     #[error("Failed to deserialize trace data from source: {0}")]
     IterDeserError(#[from] serde_json::Error),
     #[error("Failed to read trace data from file: {0}")]
@@ -39,9 +42,70 @@ pub enum SourceError {
     ResetError(#[source] probe_rs::Error),
     #[error("Failed to decode ITM packets: {0}")]
     DecodeError(#[from] itm::DecoderError),
+    #[error(
+        "Trace is of format version {0}, which is newer than this version of RTIC Scope supports"
+    )]
+    IncompatibleFormat(u64, Option<String>),
+    #[error("Failed to attach to the RTT control block of the target: {0}")]
+    RttAttachError(#[source] probe_rs_rtt::Error),
+    #[error("The target has no RTT up-channel {0}")]
+    MissingRttChannel(usize),
+    #[error("Failed to read trace data from RTT: {0}")]
+    IterRttError(#[source] probe_rs_rtt::Error),
+    #[error("Received an RTT frame of unknown kind {0}")]
+    InvalidRttFrame(u8),
 }
 
-impl diag::DiagnosableError for SourceError {}
+impl diag::DiagnosableError for SourceError {
+    fn kind(&self) -> &'static str {
+        match self {
+            Self::SetupError(..) => "SetupError",
+            Self::SetupIOError(..) => "SetupIOError",
+            Self::ProbeError(..) => "ProbeError",
+            Self::SwoSetupError(..) => "SwoSetupError",
+            Self::IterDeserError(..) => "IterDeserError",
+            Self::IterIOError(..) => "IterIOError",
+            Self::IterProbeError(..) => "IterProbeError",
+            Self::ResetError(..) => "ResetError",
+            Self::DecodeError(..) => "DecodeError",
+            Self::IncompatibleFormat(..) => "IncompatibleFormat",
+            Self::RttAttachError(..) => "RttAttachError",
+            Self::MissingRttChannel(..) => "MissingRttChannel",
+            Self::IterRttError(..) => "IterRttError",
+            Self::InvalidRttFrame(..) => "InvalidRttFrame",
+        }
+    }
+
+    fn diagnose(&self) -> Vec<String> {
+        match self {
+            SourceError::SwoSetupError(_, _, _) => vec![
+                "Ensure that the probe supports SWO capture (e.g. CMSIS-DAP v2, or an ST-Link V2-1 or later).".to_string(),
+                "`tpiu_freq` must be the frequency of the clock that drives the TPIU of the target, and `tpiu_baud` a rate that the probe supports and that evenly divides it.".to_string(),
+                "Alternatively, capture SWO externally and trace via --serial, --stdin, or --source.".to_string(),
+            ],
+            SourceError::DecodeError(_) | SourceError::IterIOError(_) | SourceError::IterProbeError(_) | SourceError::IterRttError(_) => vec![
+                "The trace data received before the error has been drained to all sinks.".to_string(),
+            ],
+            SourceError::RttAttachError(_) => vec![
+                "Ensure that `cortex-m-rtic-trace` is built with its `rtt` feature and that the application calls `cortex_m_rtic_trace::configure_rtt` after setting up RTT.".to_string(),
+            ],
+            SourceError::MissingRttChannel(_) => vec![
+                "The up-channel given by --rtt-channel must be set up by the application, e.g. via `rtt_target::rtt_init!`.".to_string(),
+            ],
+            SourceError::InvalidRttFrame(_) => vec![
+                "The up-channel must only be written to by `cortex-m-rtic-trace`, and in a mode that writes frames whole or not at all (e.g. `ChannelMode::NoBlockSkip`).".to_string(),
+            ],
+            SourceError::IncompatibleFormat(_, recorded_with) => vec![
+                format!(
+                    "The trace was recorded with RTIC Scope {}; run `cargo install cargo-rtic-scope` to upgrade from {}",
+                    recorded_with.as_deref().map_or("a newer version".to_string(), |v| format!("v{}", v)),
+                    env!("CARGO_PKG_VERSION"),
+                ),
+            ],
+            _ => vec![],
+        }
+    }
+}
 
 pub trait Source: Iterator<Item = Result<TraceData, SourceError>> + std::marker::Send {
     fn reset_target(&mut self, _reset_halt: bool) -> Result<(), SourceError> {
@@ -53,17 +117,107 @@ pub trait Source: Iterator<Item = Result<TraceData, SourceError>> + std::marker:
         BufferStatus::Unknown
     }
 
+    /// Retains the raw bytes from which trace data is decoded, which
+    /// are then available via [`Source::raw_bytes`]. Returns `false` if
+    /// the source does not decode a raw ITM byte stream.
+    fn retain_bytes(&mut self) -> bool {
+        false
+    }
+
+    /// The raw bytes from which the trace data most recently yielded
+    /// was decoded, if retained.
+    fn raw_bytes(&mut self) -> Option<api::RawBytes> {
+        None
+    }
+
+    /// The number of events that were dropped before the trace data
+    /// most recently yielded because the host did not read them in
+    /// time, if the source reports it.
+    fn host_dropped(&mut self) -> usize {
+        0
+    }
+
+    /// Whether the stream ends because the source is stopped, which
+    /// may truncate the last packet. A malformed packet at the end of
+    /// such a stream is discarded by [`TrailingTruncation`] if the
+    /// source is [finite](Source::finite).
+    fn truncated_by_stop(&self) -> bool {
+        true
+    }
+
+    /// Whether the source ends by itself, e.g. at the end of a file,
+    /// instead of waiting for data as it arrives.
+    fn finite(&self) -> bool {
+        false
+    }
+
     fn describe(&self) -> String;
 }
 
+impl<S: Source + ?Sized> Source for Box<S> {
+    fn reset_target(&mut self, reset_halt: bool) -> Result<(), SourceError> {
+        (**self).reset_target(reset_halt)
+    }
+
+    fn avail_buffer(&self) -> BufferStatus {
+        (**self).avail_buffer()
+    }
+
+    fn retain_bytes(&mut self) -> bool {
+        (**self).retain_bytes()
+    }
+
+    fn raw_bytes(&mut self) -> Option<api::RawBytes> {
+        (**self).raw_bytes()
+    }
+
+    fn host_dropped(&mut self) -> usize {
+        (**self).host_dropped()
+    }
+
+    fn truncated_by_stop(&self) -> bool {
+        (**self).truncated_by_stop()
+    }
+
+    fn finite(&self) -> bool {
+        (**self).finite()
+    }
+
+    fn describe(&self) -> String {
+        (**self).describe()
+    }
+}
+
+mod archive;
+pub use archive::ArchiveSource;
+
 mod file;
 pub use file::FileSource;
 
+mod truncation;
+pub use truncation::TrailingTruncation;
+
 pub mod tty;
 pub use tty::TTYSource;
 
 mod probe;
 pub use probe::ProbeSource;
 
+mod recorder;
+
+mod rtt;
+pub use rtt::RttSource;
+
+mod reader;
+pub use reader::{ByteStream, ReaderSource};
+
 mod raw_file;
 pub use raw_file::RawFileSource;
+
+mod stdin;
+pub use stdin::StdinSource;
+
+mod tcp;
+pub use tcp::TcpSource;
+
+mod tpiu;
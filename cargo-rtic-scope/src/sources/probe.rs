@@ -1,5 +1,9 @@
-//! Source which reads [`TraceData`] from a [`Session`].
+//! Source which reads [`TraceData`] from a [`Session`]. The probe
+//! captures the SWO output of the target, which is configured via
+//! probe-rs: no external tool (e.g. openocd) is required.
 use crate::manifest::ManifestProperties;
+use crate::sources::recorder::{ByteLog, Recorder};
+use crate::sources::tpiu::Deframer;
 use crate::sources::{Source, SourceError};
 use crate::TraceData;
 
@@ -8,9 +12,11 @@ use probe_rs::{
     architecture::arm::{SwoConfig, SwoReader},
     Session,
 };
+use rtic_scope_api as api;
 
 pub struct ProbeSource<'a> {
-    decoder: Timestamps<SwoReader<'a>>,
+    decoder: Timestamps<Deframer<Recorder<SwoReader<'a>>>>,
+    log: ByteLog,
     target_name: String,
 }
 
@@ -19,19 +25,24 @@ impl<'a> ProbeSource<'a> {
         // Configure probe and target for tracing
         let cfg = SwoConfig::new(opts.tpiu_freq)
             .set_baud(opts.tpiu_baud)
-            .set_continuous_formatting(false);
+            .set_continuous_formatting(opts.tpiu_source_id.is_some());
         session
             .setup_swv(0, &cfg)
-            .map_err(SourceError::ProbeError)?;
+            .map_err(|e| SourceError::SwoSetupError(e, opts.tpiu_freq, opts.tpiu_baud))?;
 
+        let target_name = session.target().name.clone();
+        let (reader, log) = Recorder::new(session.swo_reader()?);
+        let reader = Deframer::new(reader, opts.tpiu_source_id);
         Ok(Self {
-            target_name: session.target().name.clone(),
-            decoder: Decoder::new(session.swo_reader()?, DecoderOptions { ignore_eof: true })
-                .timestamps(TimestampsConfiguration {
-                    clock_frequency: opts.tpiu_freq,
+            target_name,
+            log,
+            decoder: Decoder::new(reader, DecoderOptions { ignore_eof: true }).timestamps(
+                TimestampsConfiguration {
+                    clock_frequency: opts.timestamp_clock(),
                     lts_prescaler: opts.lts_prescaler,
                     expect_malformed: opts.expect_malformed,
-                }),
+                },
+            ),
         })
     }
 }
@@ -47,6 +58,15 @@ impl<'a> Iterator for ProbeSource<'a> {
 }
 
 impl<'a> Source for ProbeSource<'a> {
+    fn retain_bytes(&mut self) -> bool {
+        self.log.enable();
+        true
+    }
+
+    fn raw_bytes(&mut self) -> Option<api::RawBytes> {
+        self.log.take()
+    }
+
     fn describe(&self) -> String {
         format!("probe (attached to {})", self.target_name)
     }
@@ -1,49 +1,78 @@
-//! Source which reads raw ITM packets from a file.
+//! Source which reads raw ITM packets from a file, e.g. a dump of a
+//! serial device:
+//! ```text
+//! $ cat /dev/ttyUSB0 > dump.bin
+//! $ cargo rtic-scope replay --raw-file dump.bin
+//! ```
+//!
+//! The dump is decoded as the stream of a live source would be. It is
+//! replayed as captured: a truncated final packet is reported as
+//! malformed.
 use crate::manifest::ManifestProperties;
-use crate::sources::{BufferStatus, Source, SourceError};
-use crate::TraceData;
+use crate::sources::ReaderSource;
 
 use std::fs;
 
-use itm::{Decoder, DecoderOptions, Timestamps, TimestampsConfiguration};
-
 /// Something data is deserialized from. Always a file.
-pub struct RawFileSource {
-    file_name: String,
-    decoder: Timestamps<std::fs::File>,
-}
+pub type RawFileSource = ReaderSource<fs::File>;
 
 impl RawFileSource {
     pub fn new(file: fs::File, opts: &ManifestProperties) -> Self {
-        Self {
-            file_name: format!("{:?}", file),
-            decoder: Decoder::new(file, DecoderOptions { ignore_eof: true }).timestamps(
-                TimestampsConfiguration {
-                    clock_frequency: opts.tpiu_freq,
-                    lts_prescaler: opts.lts_prescaler,
-                    expect_malformed: opts.expect_malformed,
-                },
-            ),
-        }
+        let name = format!("raw file ({:?})", file);
+        Self::from_reader(file, name, true, opts)
     }
 }
 
-impl Iterator for RawFileSource {
-    type Item = Result<TraceData, SourceError>;
+#[cfg(test)]
+mod test {
+    use super::*;
 
-    fn next(&mut self) -> Option<Self::Item> {
-        self.decoder
-            .next()
-            .map(|res| res.map_err(SourceError::DecodeError))
-    }
-}
+    use crate::manifest::test::properties as opts;
+    use crate::recovery::test::metadata;
+    use crate::sources::{Source, TrailingTruncation};
+    use crate::TraceData;
+
+    use itm::TracePacket;
+    use rtic_scope_api as api;
 
-impl Source for RawFileSource {
-    fn avail_buffer(&self) -> BufferStatus {
-        BufferStatus::NotApplicable
+    /// Ensure that a dump is decoded until EOF.
+    #[test]
+    fn dump_fixture() {
+        let dir = tempfile::tempdir().unwrap();
+        let dump = dir.path().join("dump.bin");
+        // Overflow, followed by a local timestamp (format 2) of 1.
+        fs::write(&dump, [0x70, 0x10]).unwrap();
+
+        let source = RawFileSource::new(fs::File::open(&dump).unwrap(), &opts());
+        assert!(source.finite() && !source.truncated_by_stop());
+        let packets: Vec<TracePacket> = source.flat_map(|data| data.unwrap().packets).collect();
+
+        assert!(packets.contains(&TracePacket::Overflow));
     }
 
-    fn describe(&self) -> String {
-        format!("raw file ({:?})", self.file_name)
+    /// Ensure that a dump that ends mid-packet is replayed to its end,
+    /// and that the truncated final packet is forwarded as malformed,
+    /// as it is when traced.
+    #[test]
+    fn truncated_dump() {
+        let dir = tempfile::tempdir().unwrap();
+        let dump = dir.path().join("dump.bin");
+        // Overflow, followed by a local timestamp (format 2) of 1, and
+        // an instrumentation packet of which only one of four payload
+        // bytes was captured.
+        fs::write(&dump, [0x70, 0x10, 0x03, 0xaa]).unwrap();
+
+        let source =
+            TrailingTruncation::new(RawFileSource::new(fs::File::open(&dump).unwrap(), &opts()));
+        let chunks: Vec<TraceData> = source.map(|data| data.unwrap()).collect();
+        assert!(chunks[0].packets.contains(&TracePacket::Overflow));
+        assert!(!chunks.last().unwrap().malformed_packets.is_empty());
+
+        let metadata = metadata();
+        let events: Vec<api::EventType> = chunks
+            .into_iter()
+            .flat_map(|data| metadata.build_event_chunk(data).events)
+            .collect();
+        assert!(matches!(events.last(), Some(api::EventType::Invalid(_))));
     }
 }
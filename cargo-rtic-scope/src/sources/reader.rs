@@ -0,0 +1,143 @@
+//! Source which reads raw ITM packets from a byte stream, e.g. a file
+//! dump, a pipe, a socket, or a serial device. The stream is decoded as
+//! that of a live source would be.
+use crate::manifest::ManifestProperties;
+use crate::sources::recorder::{ByteLog, Recorder};
+use crate::sources::tpiu::Deframer;
+use crate::sources::{BufferStatus, Source, SourceError};
+use crate::TraceData;
+
+use std::fs;
+use std::io::Read;
+use std::net::TcpStream;
+
+use itm::{Decoder, DecoderOptions, Timestamps, TimestampsConfiguration};
+use rtic_scope_api as api;
+
+/// A byte stream decoded by a [`ReaderSource`].
+pub trait ByteStream: Read {
+    /// Whether a read of zero bytes only means that no data is pending,
+    /// e.g. on a serial device, instead of the end of the stream.
+    fn transient_eof(&self) -> bool {
+        false
+    }
+
+    /// A query of the receive buffer of the stream. Taken before the
+    /// stream is handed to the decoder.
+    fn buffer_query(&self) -> Box<dyn Fn() -> BufferStatus + Send> {
+        Box::new(|| BufferStatus::NotApplicable)
+    }
+}
+
+impl ByteStream for fs::File {}
+impl ByteStream for TcpStream {}
+impl ByteStream for Box<dyn Read + Send> {}
+impl ByteStream for &[u8] {}
+
+pub struct ReaderSource<R: ByteStream> {
+    name: String,
+    finite: bool,
+    decoder: Timestamps<Deframer<Recorder<R>>>,
+    log: ByteLog,
+    buffer: Box<dyn Fn() -> BufferStatus + Send>,
+}
+
+impl<R: ByteStream> ReaderSource<R> {
+    /// Decodes the stream read from `reader`, described as `name`. A
+    /// `finite` stream is replayed as captured, e.g. a dump that does
+    /// not grow, instead of being received as it is written.
+    pub fn from_reader(reader: R, name: String, finite: bool, opts: &ManifestProperties) -> Self {
+        let buffer = reader.buffer_query();
+        // Unless transient, the stream ends at EOF, e.g. when the
+        // writing end of a pipe or the remote end of a socket is
+        // closed; do not wait for more data.
+        let ignore_eof = reader.transient_eof();
+        let (reader, log) = Recorder::new(reader);
+        let reader = Deframer::new(reader, opts.tpiu_source_id);
+        Self {
+            name,
+            finite,
+            log,
+            buffer,
+            decoder: Decoder::new(reader, DecoderOptions { ignore_eof }).timestamps(
+                TimestampsConfiguration {
+                    clock_frequency: opts.timestamp_clock(),
+                    lts_prescaler: opts.lts_prescaler,
+                    expect_malformed: opts.expect_malformed,
+                },
+            ),
+        }
+    }
+}
+
+impl<R: ByteStream> Iterator for ReaderSource<R> {
+    type Item = Result<TraceData, SourceError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.decoder
+            .next()
+            .map(|res| res.map_err(SourceError::DecodeError))
+    }
+}
+
+impl<R: ByteStream + Send> Source for ReaderSource<R> {
+    fn avail_buffer(&self) -> BufferStatus {
+        (self.buffer)()
+    }
+
+    fn retain_bytes(&mut self) -> bool {
+        self.log.enable();
+        true
+    }
+
+    fn raw_bytes(&mut self) -> Option<api::RawBytes> {
+        self.log.take()
+    }
+
+    fn finite(&self) -> bool {
+        self.finite
+    }
+
+    /// A finite stream is replayed as captured: a truncated final
+    /// packet is reported as malformed.
+    fn truncated_by_stop(&self) -> bool {
+        !self.finite
+    }
+
+    fn describe(&self) -> String {
+        self.name.clone()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::manifest::test::properties as opts;
+
+    use itm::TracePacket;
+
+    /// Ensure that the raw bytes of each chunk are those it was decoded
+    /// from.
+    #[test]
+    fn raw_byte_ranges() {
+        // Two overflows, each followed by a local timestamp (format 2)
+        // of 1 and 2, respectively.
+        let fixture: &'static [u8] = &[0x70, 0x10, 0x70, 0x20];
+        let mut source = ReaderSource::from_reader(fixture, "fixture".to_string(), false, &opts());
+        assert!(source.retain_bytes());
+
+        for range in [0..2, 2..4] {
+            let data = source.next().unwrap().unwrap();
+            assert!(data.packets.contains(&TracePacket::Overflow));
+            assert_eq!(
+                source.raw_bytes(),
+                Some(api::RawBytes {
+                    offset: range.start as u64,
+                    bytes: fixture[range].to_vec(),
+                })
+            );
+        }
+        assert!(source.next().is_none());
+    }
+}
@@ -0,0 +1,80 @@
+//! Retention of the raw ITM bytes from which [`crate::TraceData`] is
+//! decoded, e.g. for a frontend to present the bytes behind an event.
+use std::io::{self, BufReader, Read};
+use std::sync::{Arc, Mutex};
+
+use rtic_scope_api as api;
+
+#[derive(Default)]
+struct Log {
+    enabled: bool,
+    /// Offset of the first byte of `bytes` in the stream.
+    offset: u64,
+    bytes: Vec<u8>,
+}
+
+/// The bytes read through a [`Recorder`] since they were last taken.
+#[derive(Clone, Default)]
+pub struct ByteLog(Arc<Mutex<Log>>);
+
+impl ByteLog {
+    /// Starts recording. Must be called before the decoder reads from
+    /// the [`Recorder`]: bytes the decoder has already buffered cannot
+    /// be attributed.
+    pub fn enable(&self) {
+        self.0.lock().unwrap().enabled = true;
+    }
+
+    /// Takes the bytes read since the previous call, if enabled.
+    pub fn take(&self) -> Option<api::RawBytes> {
+        let mut log = self.0.lock().unwrap();
+        if !log.enabled {
+            return None;
+        }
+        let bytes = std::mem::take(&mut log.bytes);
+        let offset = log.offset;
+        log.offset += bytes.len() as u64;
+
+        Some(api::RawBytes { offset, bytes })
+    }
+}
+
+/// A reader which records the bytes read through it into a [`ByteLog`]
+/// once enabled.
+///
+/// While enabled, at most a single byte is read at a time such that the
+/// decoder reading from the recorder never reads beyond the packet it
+/// decodes: the bytes taken after a chunk is decoded are then exactly
+/// those the chunk was decoded from.
+pub struct Recorder<R: Read> {
+    reader: BufReader<R>,
+    log: ByteLog,
+}
+
+impl<R: Read> Recorder<R> {
+    pub fn new(reader: R) -> (Self, ByteLog) {
+        let log = ByteLog::default();
+        (
+            Self {
+                reader: BufReader::new(reader),
+                log: log.clone(),
+            },
+            log,
+        )
+    }
+}
+
+impl<R: Read> Read for Recorder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut log = self.log.0.lock().unwrap();
+        if !log.enabled {
+            let n = self.reader.read(buf)?;
+            log.offset += n as u64;
+            return Ok(n);
+        }
+
+        let n = self.reader.read(&mut buf[..buf.len().min(1)])?;
+        log.bytes.extend_from_slice(&buf[..n]);
+        Ok(n)
+    }
+}
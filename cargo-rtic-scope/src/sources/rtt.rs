@@ -0,0 +1,314 @@
+//! Source which reads software task events from an RTT up-channel of
+//! the target via a [`Session`], for targets that do not route SWO:
+//! ```text
+//! $ cargo rtic-scope trace --rtt
+//! ```
+//! The firmware writes the events in frames via the `rtt` feature of
+//! `cortex-m-rtic-trace` instead of to the DWT watch addresses. Each
+//! frame is [`FRAME_LEN`] bytes: the [`FrameKind`], the software task
+//! ID (`u16`), and the timestamp (`u32`) at the time of the event, both
+//! little-endian. The timestamp counts at the timestamp frequency of the
+//! manifest. Frames are decoded into the ITM packets the DWT would have
+//! emitted, such that the trace is otherwise handled as if received over
+//! SWO. Hardware tasks cannot be traced over RTT.
+use crate::manifest::ManifestProperties;
+use crate::sources::{Source, SourceError};
+use crate::TraceData;
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use itm::{MemoryAccessType, Timestamp, TimestampedTracePackets, TracePacket};
+use probe_rs::{config::MemoryRegion, Session};
+use probe_rs_rtt::{Rtt, UpChannel};
+
+/// Length of a frame in bytes.
+const FRAME_LEN: usize = 7;
+
+/// How long to wait for the firmware to set up the RTT control block
+/// after target reset.
+const ATTACH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long to wait before the up-channel is polled again when it is
+/// empty.
+const POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// What a frame reports. Mirrors `cortex_m_rtic_trace::rtt::FrameKind`.
+#[derive(Debug, PartialEq)]
+enum FrameKind {
+    /// A software task was entered.
+    Enter,
+    /// A software task exited.
+    Exit,
+    /// An async software task completed.
+    Returned,
+    /// The given number of frames were dropped by the firmware because
+    /// the up-channel was full.
+    Dropped,
+}
+
+impl FrameKind {
+    fn from(kind: u8) -> Option<Self> {
+        match kind {
+            0 => Some(Self::Enter),
+            1 => Some(Self::Exit),
+            2 => Some(Self::Returned),
+            3 => Some(Self::Dropped),
+            _ => None,
+        }
+    }
+}
+
+/// Decodes frames into [`TraceData`].
+struct FrameDecoder {
+    bytes: VecDeque<u8>,
+    /// Frequency at which the timestamp counts.
+    freq: u32,
+    /// Timestamp of the previous frame.
+    last_cycles: Option<u32>,
+    /// Counts since the first frame.
+    cycles: u64,
+    /// Frames dropped by the firmware before the frame most recently
+    /// decoded.
+    dropped: usize,
+    enter_id: u8,
+    exit_id: u8,
+    returned_id: Option<u8>,
+}
+
+impl FrameDecoder {
+    fn new(opts: &ManifestProperties) -> Self {
+        Self {
+            bytes: VecDeque::new(),
+            freq: opts.timestamp_clock(),
+            last_cycles: None,
+            cycles: 0,
+            dropped: 0,
+            enter_id: opts.dwt_enter_id as u8,
+            exit_id: opts.dwt_exit_id as u8,
+            returned_id: opts.dwt_returned_id.map(|id| id as u8),
+        }
+    }
+
+    fn push(&mut self, bytes: &[u8]) {
+        self.bytes.extend(bytes);
+    }
+
+    /// Decodes the next complete frame, if any. The timestamp is
+    /// assumed to wrap at most once between two frames.
+    fn next(&mut self) -> Option<Result<TraceData, SourceError>> {
+        loop {
+            if self.bytes.len() < FRAME_LEN {
+                return None;
+            }
+            let frame: Vec<u8> = self.bytes.drain(..FRAME_LEN).collect();
+            let id = [frame[1], frame[2]];
+            let cycles = u32::from_le_bytes([frame[3], frame[4], frame[5], frame[6]]);
+
+            if let Some(last) = self.last_cycles {
+                self.cycles += cycles.wrapping_sub(last) as u64;
+            }
+            self.last_cycles = Some(cycles);
+
+            let comparator = match FrameKind::from(frame[0]) {
+                Some(FrameKind::Enter) => self.enter_id,
+                Some(FrameKind::Exit) => self.exit_id,
+                Some(FrameKind::Returned) => match self.returned_id {
+                    Some(id) => id,
+                    // NOTE(continue) returns are only traced if the
+                    // comparator is declared, as over SWO
+                    None => continue,
+                },
+                // NOTE(Overflow) the dropped events are lost as if the
+                // ITM had overflowed
+                Some(FrameKind::Dropped) => {
+                    self.dropped += u16::from_le_bytes(id) as usize;
+                    return Some(Ok(self.chunk(TracePacket::Overflow)));
+                }
+                None => return Some(Err(SourceError::InvalidRttFrame(frame[0]))),
+            };
+            return Some(Ok(self.chunk(TracePacket::DataTraceValue {
+                comparator,
+                access_type: MemoryAccessType::Write,
+                value: id.to_vec(),
+            })));
+        }
+    }
+
+    /// A chunk of `packet` at the current offset.
+    fn chunk(&self, packet: TracePacket) -> TraceData {
+        let nanos = self.cycles as u128 * 1_000_000_000 / self.freq as u128;
+        TimestampedTracePackets {
+            timestamp: Timestamp::Sync(Duration::from_nanos(nanos as u64)),
+            packets: vec![packet],
+            malformed_packets: vec![],
+            consumed_packets: 1,
+        }
+    }
+}
+
+pub struct RttSource<'a> {
+    session: &'a mut Session,
+    memory_map: Vec<MemoryRegion>,
+    /// Number of the up-channel to read from.
+    number: usize,
+    /// The up-channel, once attached.
+    channel: Option<UpChannel>,
+    decoder: FrameDecoder,
+    target_name: String,
+}
+
+impl<'a> RttSource<'a> {
+    /// Reads from up-channel `number`. The source attaches to the
+    /// channel on the first read, after the target has been reset.
+    pub fn new(session: &'a mut Session, number: usize, opts: &ManifestProperties) -> Self {
+        Self {
+            memory_map: session.target().memory_map.clone(),
+            target_name: session.target().name.clone(),
+            session,
+            number,
+            channel: None,
+            decoder: FrameDecoder::new(opts),
+        }
+    }
+
+    /// Attaches to the up-channel, retrying until the firmware has set
+    /// up the RTT control block.
+    fn attach(&mut self) -> Result<UpChannel, SourceError> {
+        let start = Instant::now();
+        let mut rtt = loop {
+            let mut core = self.session.core(0)?;
+            match Rtt::attach(&mut core, &self.memory_map) {
+                Ok(rtt) => break rtt,
+                Err(_) if start.elapsed() < ATTACH_TIMEOUT => {
+                    std::thread::sleep(POLL_INTERVAL * 10);
+                }
+                Err(e) => return Err(SourceError::RttAttachError(e)),
+            }
+        };
+
+        rtt.up_channels()
+            .take(self.number)
+            .ok_or(SourceError::MissingRttChannel(self.number))
+    }
+
+    /// Reads the frames available in the up-channel into the decoder.
+    /// Returns the number of bytes read.
+    fn read(&mut self) -> Result<usize, SourceError> {
+        if self.channel.is_none() {
+            self.channel = Some(self.attach()?);
+        }
+
+        let mut buf = [0; 1024];
+        let mut core = self.session.core(0).map_err(SourceError::IterProbeError)?;
+        let n = self
+            .channel
+            .as_ref()
+            .unwrap()
+            .read(&mut core, &mut buf)
+            .map_err(SourceError::IterRttError)?;
+        self.decoder.push(&buf[..n]);
+
+        Ok(n)
+    }
+}
+
+impl<'a> Iterator for RttSource<'a> {
+    type Item = Result<TraceData, SourceError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(res) = self.decoder.next() {
+                return Some(res);
+            }
+            match self.read() {
+                Ok(0) => std::thread::sleep(POLL_INTERVAL),
+                Ok(_) => (),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+impl<'a> Source for RttSource<'a> {
+    fn host_dropped(&mut self) -> usize {
+        std::mem::take(&mut self.decoder.dropped)
+    }
+
+    /// Frames are read whole: a stop cannot truncate them.
+    fn truncated_by_stop(&self) -> bool {
+        false
+    }
+
+    fn describe(&self) -> String {
+        format!(
+            "RTT up-channel {} (attached to {})",
+            self.number, self.target_name
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::manifest::test::properties;
+
+    fn frame(kind: u8, id: u16, cycles: u32) -> Vec<u8> {
+        [&[kind][..], &id.to_le_bytes(), &cycles.to_le_bytes()].concat()
+    }
+
+    /// Ensure that frames are decoded into the packets of the DWT, at
+    /// the offset of their timestamp, also when it wraps, and that
+    /// dropped frames are counted.
+    #[test]
+    fn frames() {
+        let mut decoder = FrameDecoder::new(&properties());
+        let bytes = [
+            frame(0, 3, u32::MAX - 15),
+            frame(1, 3, 15),
+            frame(2, 3, 31), // no returned comparator
+            frame(3, 5, 47),
+        ]
+        .concat();
+
+        // frames are decoded once complete
+        decoder.push(&bytes[..FRAME_LEN - 1]);
+        assert!(decoder.next().is_none());
+        decoder.push(&bytes[FRAME_LEN - 1..]);
+
+        let mut chunks = vec![];
+        while let Some(res) = decoder.next() {
+            chunks.push(res.unwrap());
+        }
+        assert_eq!(chunks.len(), 3);
+        assert!(matches!(
+            chunks[0].packets.as_slice(),
+            [TracePacket::DataTraceValue { comparator: 1, value, .. }] if value == &[3, 0]
+        ));
+        assert!(matches!(
+            chunks[1].packets.as_slice(),
+            [TracePacket::DataTraceValue { comparator: 2, .. }]
+        ));
+        assert!(matches!(
+            chunks[2].packets.as_slice(),
+            [TracePacket::Overflow]
+        ));
+        assert_eq!(decoder.dropped, 5);
+
+        // 16 MHz: 1 cycle is 62.5 ns
+        assert!(matches!(
+            chunks[1].timestamp,
+            Timestamp::Sync(offset) if offset == Duration::from_nanos(1_937)
+        ));
+        assert!(matches!(
+            chunks[2].timestamp,
+            Timestamp::Sync(offset) if offset == Duration::from_nanos(3_937)
+        ));
+
+        decoder.push(&frame(9, 0, 0));
+        assert!(matches!(
+            decoder.next(),
+            Some(Err(SourceError::InvalidRttFrame(9)))
+        ));
+    }
+}
@@ -0,0 +1,17 @@
+//! Source which reads raw ITM packets from `stdin`, e.g. when piped
+//! from an external SWO tool:
+//! ```text
+//! $ openocd ... | cargo rtic-scope trace --stdin
+//! ```
+use crate::manifest::ManifestProperties;
+use crate::sources::ReaderSource;
+
+use std::io::Read;
+
+pub type StdinSource = ReaderSource<Box<dyn Read + Send>>;
+
+impl StdinSource {
+    pub fn new(opts: &ManifestProperties) -> Self {
+        Self::from_reader(Box::new(std::io::stdin()), "stdin".to_string(), false, opts)
+    }
+}
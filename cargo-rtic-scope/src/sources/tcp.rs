@@ -0,0 +1,61 @@
+//! Source which reads raw ITM packets from a TCP socket, e.g. the trace
+//! server of an openocd instance on a remote debug host:
+//! ```text
+//! $ cargo rtic-scope trace --source tcp://lab-host:6666
+//! ```
+use crate::manifest::ManifestProperties;
+use crate::sources::{ReaderSource, SourceError};
+
+use std::net::TcpStream;
+
+pub type TcpSource = ReaderSource<TcpStream>;
+
+impl TcpSource {
+    /// Connects to `addr`, given as `host:port`.
+    pub fn connect(addr: &str, opts: &ManifestProperties) -> Result<Self, SourceError> {
+        let stream = TcpStream::connect(addr).map_err(SourceError::SetupIOError)?;
+        let peer = stream
+            .peer_addr()
+            .map_or_else(|_| addr.to_string(), |peer| peer.to_string());
+
+        Ok(Self::from_reader(
+            stream,
+            format!("tcp://{}", peer),
+            false,
+            opts,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::manifest::test::properties as opts;
+    use crate::sources::Source;
+
+    use std::io::Write;
+    use std::net::TcpListener;
+
+    use itm::TracePacket;
+
+    /// Ensure that a stream served over TCP is decoded until the remote
+    /// closes the connection.
+    #[test]
+    fn served_fixture() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            // Overflow, followed by a local timestamp (format 2) of 1.
+            stream.write_all(&[0x70, 0x10]).unwrap();
+        });
+
+        let source = TcpSource::connect(&addr, &opts()).unwrap();
+        assert_eq!(source.describe(), format!("tcp://{}", addr));
+        let packets: Vec<TracePacket> = source.flat_map(|data| data.unwrap().packets).collect();
+        server.join().unwrap();
+
+        assert!(packets.contains(&TracePacket::Overflow));
+    }
+}
@@ -0,0 +1,158 @@
+//! Deframing of a trace stream formatted by the TPIU, which multiplexes
+//! the data of several trace sources (e.g. the ITM and the ETM) into
+//! 16-byte frames. Only the data of a single trace source, the ITM, is
+//! passed on to the ITM decoder.
+use std::collections::VecDeque;
+use std::io::{self, Read};
+
+/// Length of a TPIU frame in bytes.
+const FRAME_LEN: usize = 16;
+
+/// Full synchronization packet that may be inserted between frames.
+const FULL_SYNC: [u8; 4] = [0xff, 0xff, 0xff, 0x7f];
+
+/// A reader of the data of trace source `id` in a TPIU-framed stream.
+/// Passes the stream through as-is if no `id` is given, i.e. if the
+/// stream is not framed.
+///
+/// The stream must start at a frame boundary, e.g. after a full
+/// synchronization packet. The stream is read a frame at a time: the
+/// bytes recorded by a [`Recorder`](super::recorder::Recorder) that the
+/// stream is read from are those on the wire, in whole frames.
+pub struct Deframer<R: Read> {
+    inner: R,
+    id: Option<u8>,
+    /// Trace source of the data that follows in the current frame.
+    current: Option<u8>,
+    /// The bytes of the frame being read.
+    frame: Vec<u8>,
+    /// Deframed data of trace source `id` not yet read.
+    data: VecDeque<u8>,
+}
+
+impl<R: Read> Deframer<R> {
+    pub fn new(inner: R, id: Option<u8>) -> Self {
+        Self {
+            inner,
+            id,
+            current: None,
+            frame: Vec::with_capacity(FRAME_LEN),
+            data: VecDeque::new(),
+        }
+    }
+
+    /// Decodes a complete frame. Each even byte is either a data byte
+    /// (bit 0 clear), of which bit 0 is in the auxiliary byte, or
+    /// changes the trace source to the ID in bits [7:1] (bit 0 set). On
+    /// a change, the bit in the auxiliary byte is set if the following
+    /// odd byte still belongs to the previous trace source.
+    fn deframe(&mut self) {
+        let frame = std::mem::take(&mut self.frame);
+        let aux = frame[FRAME_LEN - 1];
+        for i in 0..FRAME_LEN / 2 {
+            let (even, aux_bit) = (frame[2 * i], (aux >> i) & 1);
+            // NOTE(odd) the last even byte is followed by the auxiliary
+            // byte
+            let odd = (i < FRAME_LEN / 2 - 1).then(|| frame[2 * i + 1]);
+
+            if even & 1 == 1 {
+                let id = Some(even >> 1);
+                if aux_bit == 1 {
+                    self.push(odd);
+                    self.current = id;
+                } else {
+                    self.current = id;
+                    self.push(odd);
+                }
+            } else {
+                self.push(Some(even | aux_bit));
+                self.push(odd);
+            }
+        }
+        self.frame = frame;
+        self.frame.clear();
+    }
+
+    fn push(&mut self, byte: Option<u8>) {
+        if let Some(byte) = byte {
+            if self.current.is_some() && self.current == self.id {
+                self.data.push_back(byte);
+            }
+        }
+    }
+}
+
+impl<R: Read> Read for Deframer<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.id.is_none() {
+            return self.inner.read(buf);
+        }
+
+        while self.data.is_empty() {
+            let mut bytes = [0; FRAME_LEN];
+            let n = self
+                .inner
+                .read(&mut bytes[..FRAME_LEN - self.frame.len()])?;
+            if n == 0 {
+                return Ok(0);
+            }
+            self.frame.extend_from_slice(&bytes[..n]);
+
+            if self.frame.len() >= FULL_SYNC.len() && self.frame[..FULL_SYNC.len()] == FULL_SYNC {
+                self.frame.drain(..FULL_SYNC.len());
+            } else if self.frame.len() == FRAME_LEN {
+                self.deframe();
+            }
+        }
+
+        let n = buf.len().min(self.data.len());
+        for (dst, src) in buf.iter_mut().zip(self.data.drain(..n)) {
+            *dst = src;
+        }
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Ensure that only the data of the given trace source is passed
+    /// on, across changes of the trace source within a frame and
+    /// synchronization packets between frames.
+    #[test]
+    fn deframe() {
+        let frame = [
+            0x03, 0xaa, // to ID 1, then data
+            0x10, 0xbb, // data (bit 0 set)
+            0x05, 0xcc, // to ID 2, after data of ID 1
+            0x12, 0xdd, // data of ID 2
+            0x03, 0xee, // to ID 1, then data
+            0x20, 0x11, // data
+            0x22, 0x33, // data
+            0x44, // data (bit 0 set)
+            0x86, // auxiliary byte
+        ];
+        let stream = [&FULL_SYNC[..], &frame, &FULL_SYNC, &frame].concat();
+
+        let mut data = vec![];
+        Deframer::new(&stream[..], Some(1))
+            .read_to_end(&mut data)
+            .unwrap();
+        let expected = [0xaa, 0x11, 0xbb, 0xcc, 0xee, 0x20, 0x11, 0x22, 0x33, 0x45];
+        assert_eq!(data, [expected, expected].concat());
+
+        let mut data = vec![];
+        Deframer::new(&stream[..], Some(2))
+            .read_to_end(&mut data)
+            .unwrap();
+        assert_eq!(data, [0x12, 0xdd, 0x12, 0xdd]);
+
+        // not framed
+        let mut data = vec![];
+        Deframer::new(&stream[..], None)
+            .read_to_end(&mut data)
+            .unwrap();
+        assert_eq!(data, stream);
+    }
+}
@@ -0,0 +1,200 @@
+//! Source adapter which discards a malformed packet at the very end of
+//! the trace stream.
+//!
+//! When a stream ends because its source is stopped, the last bytes
+//! may only form part of a packet which is then decoded as malformed.
+//! Such a packet is benign and should not be counted as a link error.
+//!
+//! The end of the stream is only known for [finite](Source::finite)
+//! sources, e.g. a recorded trace: the final chunk is then held back
+//! until the source ends. Live sources are passed through as-is, and
+//! sources that replay a byte stream as it was captured, e.g. a raw
+//! dump file, are not truncated by their stop: a malformed packet at
+//! their end is kept.
+use crate::sources::{BufferStatus, Source, SourceError};
+use crate::TraceData;
+
+use rtic_scope_api as api;
+
+pub struct TrailingTruncation<S> {
+    inner: S,
+    next: Option<Result<TraceData, SourceError>>,
+    /// The raw bytes of `next` and of the item most recently yielded,
+    /// respectively.
+    raw: (Option<api::RawBytes>, Option<api::RawBytes>),
+    /// The events dropped before `next` and before the item most
+    /// recently yielded, respectively.
+    host_dropped: (usize, usize),
+    started: bool,
+}
+
+impl<S: Source> TrailingTruncation<S> {
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            next: None,
+            raw: (None, None),
+            host_dropped: (0, 0),
+            started: false,
+        }
+    }
+}
+
+impl<S: Source> Iterator for TrailingTruncation<S> {
+    type Item = Result<TraceData, SourceError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if !self.inner.finite() || !self.inner.truncated_by_stop() {
+            let next = self.inner.next();
+            self.raw.1 = self.inner.raw_bytes();
+            self.host_dropped.1 = self.inner.host_dropped();
+            return next;
+        }
+
+        // Look one item ahead so that the last item of the
+        // stream can be identified.
+        if !self.started {
+            self.started = true;
+            self.next = self.inner.next();
+            self.raw.0 = self.inner.raw_bytes();
+            self.host_dropped.0 = self.inner.host_dropped();
+        }
+        let current = self.next.take()?;
+        self.next = self.inner.next();
+        self.raw = (self.inner.raw_bytes(), self.raw.0.take());
+        self.host_dropped = (self.inner.host_dropped(), self.host_dropped.0);
+
+        match current {
+            // Only a chunk without any valid packets is known to end in
+            // its last malformed packet.
+            Ok(mut data) if self.next.is_none() && data.packets.is_empty() => {
+                if data.malformed_packets.pop().is_some() {
+                    data.consumed_packets = data.consumed_packets.saturating_sub(1);
+                }
+                Some(Ok(data))
+            }
+            current => Some(current),
+        }
+    }
+}
+
+impl<S: Source> Source for TrailingTruncation<S> {
+    fn reset_target(&mut self, reset_halt: bool) -> Result<(), SourceError> {
+        self.inner.reset_target(reset_halt)
+    }
+
+    fn avail_buffer(&self) -> BufferStatus {
+        self.inner.avail_buffer()
+    }
+
+    fn retain_bytes(&mut self) -> bool {
+        self.inner.retain_bytes()
+    }
+
+    fn raw_bytes(&mut self) -> Option<api::RawBytes> {
+        self.raw.1.take()
+    }
+
+    fn host_dropped(&mut self) -> usize {
+        std::mem::take(&mut self.host_dropped.1)
+    }
+
+    fn truncated_by_stop(&self) -> bool {
+        self.inner.truncated_by_stop()
+    }
+
+    fn finite(&self) -> bool {
+        self.inner.finite()
+    }
+
+    fn describe(&self) -> String {
+        self.inner.describe()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::time::Duration;
+
+    use itm::{MalformedPacket, Timestamp, TracePacket};
+
+    /// A stream of the given items, truncated by its stop or not, and
+    /// finite or not.
+    struct Stream(
+        std::vec::IntoIter<Result<TraceData, SourceError>>,
+        bool,
+        bool,
+    );
+
+    impl Iterator for Stream {
+        type Item = Result<TraceData, SourceError>;
+
+        fn next(&mut self) -> Option<Self::Item> {
+            self.0.next()
+        }
+    }
+
+    impl Source for Stream {
+        fn truncated_by_stop(&self) -> bool {
+            self.1
+        }
+
+        fn finite(&self) -> bool {
+            self.2
+        }
+
+        fn describe(&self) -> String {
+            "stream".to_string()
+        }
+    }
+
+    fn data(packets: Vec<TracePacket>, malformed_packets: Vec<MalformedPacket>) -> TraceData {
+        TraceData {
+            timestamp: Timestamp::Sync(Duration::from_nanos(0)),
+            consumed_packets: packets.len() + malformed_packets.len(),
+            packets,
+            malformed_packets,
+        }
+    }
+
+    /// Ensure that a finite stream which ends mid-packet does not yield
+    /// a spurious malformed packet, while other malformed packets are
+    /// kept.
+    #[test]
+    fn truncated_final_packet() {
+        let malformed = |last: Vec<TracePacket>, truncated_by_stop, finite| -> Vec<usize> {
+            let invalid = || vec![MalformedPacket::InvalidHeader(0xff)];
+            let stream = vec![
+                Ok(data(vec![TracePacket::Overflow], invalid())),
+                Ok(data(vec![TracePacket::Overflow], vec![])),
+                Ok(data(last, invalid())),
+            ];
+            TrailingTruncation::new(Stream(stream.into_iter(), truncated_by_stop, finite))
+                .map(|d| d.unwrap().malformed_packets.len())
+                .collect()
+        };
+
+        assert_eq!(malformed(vec![], true, true), vec![1, 0, 0]);
+        // the malformed packet may precede the valid one
+        assert_eq!(
+            malformed(vec![TracePacket::Overflow], true, true),
+            vec![1, 0, 1]
+        );
+        // e.g. a raw dump file, of which the final packet is reported
+        assert_eq!(malformed(vec![], false, true), vec![1, 0, 1]);
+        // a live source, of which the end is not known
+        assert_eq!(malformed(vec![], true, false), vec![1, 0, 1]);
+    }
+
+    /// Ensure that a live source is not read ahead of what is yielded.
+    #[test]
+    fn live() {
+        let chunk = || Ok(data(vec![TracePacket::Overflow], vec![]));
+        let stream = vec![chunk(), chunk()];
+        let mut source = TrailingTruncation::new(Stream(stream.into_iter(), true, false));
+        assert!(source.next().is_some());
+        assert_eq!(source.inner.0.len(), 1);
+    }
+}
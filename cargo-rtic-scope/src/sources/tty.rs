@@ -2,13 +2,12 @@
 //! properly configuring it. Commonly used if `probe-rs` cannot read the
 //! target device.
 use crate::manifest::ManifestProperties;
-use crate::sources::{BufferStatus, Source, SourceError};
-use crate::TraceData;
+use crate::sources::{BufferStatus, ByteStream, ReaderSource, SourceError};
 
 use std::fs;
+use std::io::{self, Read};
 use std::os::unix::io::{AsRawFd, RawFd};
 
-use itm::{Decoder, DecoderOptions, Timestamps, TimestampsConfiguration};
 use nix::{
     libc,
     unistd::{sysconf, SysconfVar},
@@ -41,58 +40,55 @@ pub fn configure(device: &str, baud_rate: u32) -> Result<fs::File, SourceError>
     Ok(file)
 }
 
-pub struct TTYSource {
-    fd: RawFd,
-    decoder: Timestamps<fs::File>,
-}
+/// A configured serial device. Reads of zero bytes only mean that no
+/// data is pending.
+#[allow(clippy::upper_case_acronyms)]
+pub struct TTY(fs::File);
 
-impl TTYSource {
-    pub fn new(device: fs::File, opts: &ManifestProperties) -> Self {
-        Self {
-            fd: device.as_raw_fd(),
-            decoder: Decoder::new(device, DecoderOptions { ignore_eof: true }).timestamps(
-                TimestampsConfiguration {
-                    clock_frequency: opts.tpiu_freq,
-                    lts_prescaler: opts.lts_prescaler,
-                    expect_malformed: opts.expect_malformed,
-                },
-            ),
-        }
+impl Read for TTY {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
     }
 }
 
-impl Iterator for TTYSource {
-    type Item = Result<TraceData, SourceError>;
+impl ByteStream for TTY {
+    fn transient_eof(&self) -> bool {
+        true
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        self.decoder
-            .next()
-            .map(|res| res.map_err(SourceError::DecodeError))
+    fn buffer_query(&self) -> Box<dyn Fn() -> BufferStatus + Send> {
+        let fd = self.0.as_raw_fd();
+        Box::new(move || avail_buffer(fd))
     }
 }
 
-impl Source for TTYSource {
-    fn avail_buffer(&self) -> BufferStatus {
-        let avail_bytes = unsafe {
-            let mut fionread: libc::c_int = 0;
-            if ioctl::fionread(self.fd, &mut fionread).is_err() {
-                return BufferStatus::Unknown;
-            } else {
-                fionread as i64
-            }
-        };
-
-        if let Ok(Some(page_size)) = sysconf(SysconfVar::PAGE_SIZE) {
-            match page_size - avail_bytes {
-                n if n < page_size / 4 => BufferStatus::AvailWarn(n, page_size),
-                n => BufferStatus::Avail(n),
-            }
+/// Reports the available bytes in the input buffer of the device `fd`.
+fn avail_buffer(fd: RawFd) -> BufferStatus {
+    let avail_bytes = unsafe {
+        let mut fionread: libc::c_int = 0;
+        if ioctl::fionread(fd, &mut fionread).is_err() {
+            return BufferStatus::Unknown;
         } else {
-            BufferStatus::Unknown
+            fionread as i64
         }
+    };
+
+    if let Ok(Some(page_size)) = sysconf(SysconfVar::PAGE_SIZE) {
+        match page_size - avail_bytes {
+            n if n < page_size / 4 => BufferStatus::AvailWarn(n, page_size),
+            n => BufferStatus::Avail(n),
+        }
+    } else {
+        BufferStatus::Unknown
     }
+}
 
-    fn describe(&self) -> String {
-        format!("TTY (fd: {})", self.fd)
+/// Something data is received from. Always a serial device.
+pub type TTYSource = ReaderSource<TTY>;
+
+impl TTYSource {
+    pub fn new(device: fs::File, opts: &ManifestProperties) -> Self {
+        let name = format!("TTY (fd: {})", device.as_raw_fd());
+        Self::from_reader(TTY(device), name, false, opts)
     }
 }
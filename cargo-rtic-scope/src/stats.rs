@@ -0,0 +1,138 @@
+//! Incremental statistics over a trace, updated for each processed
+//! [`api::EventChunk`] and rendered in the status line of a live trace.
+use std::time::{Duration, Instant};
+
+use rtic_scope_api as api;
+
+#[derive(Default, Clone, Debug, PartialEq)]
+pub struct Stats {
+    /// How many ITM packets we have received from the source.
+    pub packets: usize,
+    /// How many events we have decoded from the received packets.
+    pub events: usize,
+    /// How many malformed ITM packets we have received from the source.
+    pub malformed: usize,
+    /// How many unmappable ITM packets we have received from the
+    /// source.
+    pub nonmappable: usize,
+    /// How many overflow packets we have received from the source.
+    pub overflows: usize,
+    /// How many sinks we started with, and how many that remained
+    /// functional until the end.
+    pub sinks: (usize, usize),
+}
+
+impl Stats {
+    /// Accounts for `chunk`, decoded from `consumed_packets` ITM
+    /// packets.
+    pub fn update(&mut self, consumed_packets: usize, chunk: &api::EventChunk) {
+        self.packets += consumed_packets;
+        self.events += chunk.events.len();
+        for event in chunk.events.iter() {
+            match event {
+                api::EventType::Unmappable(_, _) | api::EventType::Unknown(_) => {
+                    self.nonmappable += 1
+                }
+                api::EventType::Invalid(_) => self.malformed += 1,
+                api::EventType::Overflow => self.overflows += 1,
+                _ => (),
+            }
+        }
+    }
+}
+
+/// Rate-limits the rendering of [`Stats`] such that the terminal is not
+/// flooded, and tracks the event rate between renders.
+pub struct StatusLine {
+    interval: Duration,
+    last: Option<(Instant, usize)>,
+}
+
+impl StatusLine {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            last: None,
+        }
+    }
+
+    /// Returns the number of events per second since the previous
+    /// render if the status line is due to be rendered again.
+    pub fn poll(&mut self, stats: &Stats) -> Option<f32> {
+        let now = Instant::now();
+        match self.last {
+            Some((then, _)) if now.duration_since(then) < self.interval => None,
+            Some((then, events)) => {
+                self.last = Some((now, stats.events));
+                Some((stats.events - events) as f32 / now.duration_since(then).as_secs_f32())
+            }
+            None => {
+                self.last = Some((now, stats.events));
+                Some(0.0)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use itm::{MalformedPacket, TracePacket};
+
+    /// Ensure that the incrementally updated statistics match a full
+    /// fold over the same event stream.
+    #[test]
+    fn incremental_matches_fold() {
+        let chunks: Vec<(usize, api::EventChunk)> = (0..50)
+            .map(|i| {
+                let mut builder =
+                    api::ChunkBuilder::at(Duration::from_micros(i)).entered("app::foo");
+                if i % 3 == 0 {
+                    builder = builder.overflow();
+                }
+                if i % 7 == 0 {
+                    builder = builder.invalid(MalformedPacket::InvalidHeader(0));
+                }
+                if i % 11 == 0 {
+                    builder = builder.unknown(TracePacket::Sync);
+                }
+                (i as usize % 4 + 1, builder.build())
+            })
+            .collect();
+
+        let mut stats = Stats::default();
+        for (consumed, chunk) in chunks.iter() {
+            stats.update(*consumed, chunk);
+        }
+
+        let events = chunks.iter().flat_map(|(_, c)| c.events.iter());
+        let count = |f: fn(&api::EventType) -> bool| events.clone().filter(|e| f(e)).count();
+        assert_eq!(
+            stats,
+            Stats {
+                packets: chunks.iter().map(|(consumed, _)| consumed).sum(),
+                events: events.clone().count(),
+                malformed: count(|e| matches!(e, api::EventType::Invalid(_))),
+                nonmappable: count(|e| matches!(
+                    e,
+                    api::EventType::Unknown(_) | api::EventType::Unmappable(_, _)
+                )),
+                overflows: count(|e| matches!(e, api::EventType::Overflow)),
+                sinks: (0, 0),
+            }
+        );
+        assert_eq!(stats.overflows, 17);
+        assert_eq!(stats.malformed, 8);
+    }
+
+    /// Ensure that the status line is not rendered more often than
+    /// requested.
+    #[test]
+    fn rate_limited() {
+        let mut line = StatusLine::new(Duration::from_secs(60));
+        let stats = Stats::default();
+        assert!(line.poll(&stats).is_some());
+        assert!(line.poll(&stats).is_none());
+    }
+}
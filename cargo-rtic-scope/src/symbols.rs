@@ -0,0 +1,186 @@
+//! Function symbols of the traced application, read from its ELF, by
+//! which sampled program counters are resolved to the enclosing
+//! function, and interrupt handlers to their IRQ number.
+use std::fs;
+use std::path::Path;
+
+use indexmap::IndexMap;
+use object::{Object, ObjectSection, ObjectSymbol, SymbolKind};
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+struct Symbol {
+    address: u32,
+    size: u32,
+    name: String,
+}
+
+/// Function symbols in order of address.
+#[derive(Clone, Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct Symbols(Vec<Symbol>);
+
+impl Symbols {
+    /// Reads the function symbols of the ELF at `path`. Names are
+    /// demangled.
+    pub fn read(path: &Path) -> Result<Self, String> {
+        let data = fs::read(path).map_err(|e| e.to_string())?;
+        let elf = object::File::parse(&*data).map_err(|e| e.to_string())?;
+
+        Ok(elf
+            .symbols()
+            .filter(|sym| sym.kind() == SymbolKind::Text)
+            .filter_map(|sym| {
+                Some((
+                    u32::try_from(sym.address()).ok()?,
+                    u32::try_from(sym.size()).ok()?,
+                    sym.name().ok()?,
+                ))
+            })
+            .collect())
+    }
+
+    /// The name of the function that encloses `pc`, if any.
+    pub fn resolve(&self, pc: u32) -> Option<&str> {
+        let i = self
+            .0
+            .partition_point(|sym| sym.address <= pc)
+            .checked_sub(1)?;
+        let sym = &self.0[i];
+
+        // NOTE(size == 0) symbols of hand-written assembly may not be
+        // sized: they are assumed to extend to the next symbol.
+        if sym.size == 0 || pc - sym.address < sym.size {
+            Some(&sym.name)
+        } else {
+            None
+        }
+    }
+}
+
+/// Symbol of the device interrupt vector table, as exported by PACs
+/// generated by svd2rust.
+const INTERRUPTS: &str = "__INTERRUPTS";
+
+/// The IRQ numbers of the interrupt handlers in the vector table of the
+/// ELF at `path`, by handler name. RTIC exports the handler of a task
+/// under the name of the interrupt it is bound to, e.g. `EXTI0`.
+pub fn interrupt_handlers(path: &Path) -> Result<IndexMap<String, u16>, String> {
+    let data = fs::read(path).map_err(|e| e.to_string())?;
+    let elf = object::File::parse(&*data).map_err(|e| e.to_string())?;
+
+    let table = elf
+        .symbols()
+        .find(|sym| sym.name() == Ok(INTERRUPTS))
+        .ok_or_else(|| format!("{} not found", INTERRUPTS))?;
+    let vectors = table
+        .section_index()
+        .and_then(|i| elf.section_by_index(i).ok())
+        .and_then(|section| section.data_range(table.address(), table.size()).ok()?)
+        .ok_or_else(|| format!("{} has no data", INTERRUPTS))?;
+
+    let handlers: Vec<(u64, &str)> = elf
+        .symbols()
+        .filter(|sym| sym.kind() == SymbolKind::Text)
+        .filter_map(|sym| Some((sym.address(), sym.name().ok()?)))
+        .collect();
+
+    Ok(vector_table(vectors, &handlers))
+}
+
+/// Maps the `handlers` (address, name) referenced by the little-endian
+/// `vectors` to their index. Handlers referenced by multiple vectors,
+/// e.g. `DefaultHandler`, are omitted.
+fn vector_table(vectors: &[u8], handlers: &[(u64, &str)]) -> IndexMap<String, u16> {
+    let mut irqns: IndexMap<String, Option<u16>> = IndexMap::new();
+    for (irqn, vector) in vectors.chunks_exact(4).enumerate() {
+        // NOTE(!1) the address of a Thumb function has its least
+        // significant bit set. Reserved vectors are zero.
+        let address = u32::from_le_bytes(vector.try_into().unwrap()) as u64 & !1;
+        if address == 0 {
+            continue;
+        }
+        for (_, name) in handlers.iter().filter(|(a, _)| a & !1 == address) {
+            irqns
+                .entry(name.to_string())
+                .and_modify(|irqn| *irqn = None)
+                .or_insert(Some(irqn as u16));
+        }
+    }
+
+    irqns
+        .into_iter()
+        .filter_map(|(name, irqn)| Some((name, irqn?)))
+        .collect()
+}
+
+impl<'a> FromIterator<(u32, u32, &'a str)> for Symbols {
+    fn from_iter<I: IntoIterator<Item = (u32, u32, &'a str)>>(iter: I) -> Self {
+        let mut symbols: Vec<Symbol> = iter
+            .into_iter()
+            .map(|(address, size, name)| Symbol {
+                // NOTE(!1) the address of a Thumb function has its
+                // least significant bit set.
+                address: address & !1,
+                size,
+                name: format!("{:#}", rustc_demangle::demangle(name)),
+            })
+            .collect();
+        symbols.sort_by_key(|sym| sym.address);
+        symbols.dedup_by_key(|sym| sym.address);
+
+        Self(symbols)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Ensure that program counters resolve to the enclosing function,
+    /// if any.
+    #[test]
+    fn resolve() {
+        let symbols: Symbols = [
+            (0x0800_0101, 0x20, "_ZN4blinky3app3foo17h0123456789abcdefE"),
+            (0x0800_0041, 0x10, "main"),
+            (0x0800_0201, 0, "HardFaultTrampoline"),
+        ]
+        .into_iter()
+        .collect();
+
+        assert_eq!(symbols.resolve(0x0800_0000), None);
+        assert_eq!(symbols.resolve(0x0800_0040), Some("main"));
+        assert_eq!(symbols.resolve(0x0800_004f), Some("main"));
+        assert_eq!(symbols.resolve(0x0800_0050), None);
+        assert_eq!(symbols.resolve(0x0800_0110), Some("blinky::app::foo"));
+        assert_eq!(symbols.resolve(0x0800_0300), Some("HardFaultTrampoline"));
+    }
+
+    /// Ensure that handlers are mapped to their position in the vector
+    /// table, unless they are bound to multiple vectors.
+    #[test]
+    fn vectors() {
+        let vectors: Vec<u8> = [0x0800_0201u32, 0, 0x0800_0301, 0x0800_0101, 0x0800_0301]
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect();
+        let handlers = [
+            (0x0800_0101, "USART1"),
+            (0x0800_0201, "EXTI0"),
+            (0x0800_0301, "DefaultHandler"),
+        ];
+
+        assert_eq!(
+            vector_table(&vectors, &handlers),
+            IndexMap::from_iter([("EXTI0".to_string(), 0), ("USART1".to_string(), 3)])
+        );
+    }
+
+    /// Ensure that the function symbols of an ELF are read.
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn read_elf() {
+        let symbols = Symbols::read(&std::env::current_exe().unwrap()).unwrap();
+        assert!(!symbols.0.is_empty());
+    }
+}
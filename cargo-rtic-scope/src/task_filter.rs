@@ -0,0 +1,175 @@
+//! Selection of the tasks whose events are presented, by glob over
+//! their full paths, e.g. to focus on a few tasks of a busy system:
+//! ```text
+//! $ cargo rtic-scope --include 'app::sensor_*' --mask 'app::SysTick' replay
+//! ```
+//! Events other than task events are selected via `--event-kinds`.
+//! Tasks are selected after their events are nested: a task that is
+//! not selected still preempts the selected ones.
+use crate::recovery::TraceMetadata;
+
+use indexmap::IndexSet;
+use rtic_scope_api as api;
+
+/// Whether `name` matches `pattern`, in which `*` matches any sequence
+/// of characters, including none, and `?` matches any one character.
+fn glob(pattern: &str, name: &str) -> bool {
+    let (pattern, name): (Vec<char>, Vec<char>) =
+        (pattern.chars().collect(), name.chars().collect());
+    let (mut p, mut n) = (0, 0);
+    // Position after the last `*` and the position in `name` it has
+    // matched up until, to backtrack to on mismatch.
+    let mut star = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                p += 1;
+                star = Some((p, n));
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                Some((sp, sn)) => {
+                    p = sp;
+                    n = sn + 1;
+                    star = Some((sp, sn + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+pub struct TaskFilter {
+    include: Vec<String>,
+    mask: Vec<String>,
+}
+
+impl TaskFilter {
+    /// Selects the tasks that match any of `include`, or all tasks if
+    /// none are given, except those that match any of `mask`. Software
+    /// tasks may also be masked by ID, as resolved via `metadata`.
+    pub fn new(include: &[String], mask: &[String], metadata: &TraceMetadata) -> Self {
+        Self {
+            include: include.to_vec(),
+            mask: mask
+                .iter()
+                .map(|task| {
+                    task.parse()
+                        .ok()
+                        .and_then(|id| metadata.software_task(id))
+                        .unwrap_or_else(|| task.clone())
+                })
+                .collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.include.is_empty() && self.mask.is_empty()
+    }
+
+    pub fn selects(&self, task: &str) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|p| glob(p, task)))
+            && !self.mask.iter().any(|p| glob(p, task))
+    }
+
+    /// The masked tasks that match none of `tasks`.
+    pub fn unmatched_mask<'a>(
+        &'a self,
+        tasks: &'a IndexSet<String>,
+    ) -> impl Iterator<Item = &'a str> {
+        self.mask
+            .iter()
+            .filter(move |p| !tasks.iter().any(|task| glob(p, task)))
+            .map(String::as_str)
+    }
+
+    /// Drops the events of the tasks that are not selected from
+    /// `chunk`.
+    pub fn apply(&self, chunk: &mut api::EventChunk) {
+        chunk.events.retain(|event| match event {
+            api::EventType::Task { name, .. } => self.selects(name),
+            _ => true,
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::recovery::test::metadata;
+
+    use std::time::Duration;
+
+    /// Ensure that wildcards match within and across path segments.
+    #[test]
+    fn globs() {
+        assert!(glob("app::foo", "app::foo"));
+        assert!(!glob("app::foo", "app::foobar"));
+        assert!(glob("app::*", "app::sensors::poll"));
+        assert!(glob("app::*_task", "app::sensor_task"));
+        assert!(glob("*::SysTick", "app::SysTick"));
+        assert!(glob("app::?oo", "app::foo"));
+        assert!(glob("a*b*c", "aXbYbZc"));
+        assert!(!glob("a*b*c", "aXbYbZ"));
+    }
+
+    /// Ensure that only the task events of selected tasks are kept.
+    #[test]
+    fn include_mask() {
+        let filter = TaskFilter::new(
+            &["app::sensor_task".to_string(), "app::control_*".to_string()],
+            &["app::control_debug".to_string()],
+            &metadata(),
+        );
+        let mut chunk = api::ChunkBuilder::at(Duration::from_nanos(0))
+            .entered("app::SysTick")
+            .entered("app::sensor_task")
+            .overflow()
+            .entered("app::control_loop")
+            .entered("app::control_debug")
+            .build();
+        filter.apply(&mut chunk);
+
+        let events: Vec<String> = chunk
+            .events
+            .iter()
+            .map(|event| match event {
+                api::EventType::Task { name, .. } => name.clone(),
+                event => format!("{:?}", event.kind()),
+            })
+            .collect();
+        assert_eq!(
+            events,
+            ["app::sensor_task", "Overflow", "app::control_loop"]
+        );
+    }
+
+    /// Ensure that software tasks are masked by ID, and that masked
+    /// tasks that match no task are reported.
+    #[test]
+    fn mask_by_id() {
+        let metadata = metadata();
+        let filter = TaskFilter::new(
+            &[],
+            &[
+                "1".to_string(),
+                "app::l*".to_string(),
+                "app::nope".to_string(),
+            ],
+            &metadata,
+        );
+        assert!(!filter.selects("app::bar"));
+        assert!(!filter.selects("app::low"));
+        assert!(filter.selects("app::foo"));
+        assert_eq!(
+            filter.unmatched_mask(&metadata.tasks()).collect::<Vec<_>>(),
+            ["app::nope"]
+        );
+    }
+}
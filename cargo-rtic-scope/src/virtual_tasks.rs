@@ -0,0 +1,147 @@
+//! Aggregation of tasks that conceptually form one activity into a
+//! single virtual task, e.g. three DMA interrupts into a "transfer".
+//!
+//! A virtual task is entered when any of its member tasks is entered
+//! and exited when all entered members have exited. The events of the
+//! virtual task are emitted in addition to those of its members.
+use anyhow::Context;
+use indexmap::{IndexMap, IndexSet};
+use rtic_scope_api as api;
+
+struct VirtualTask {
+    /// Full paths of the member tasks.
+    members: IndexSet<String>,
+    /// How many member tasks are currently entered.
+    active: usize,
+}
+
+pub struct VirtualTasks(IndexMap<String, VirtualTask>);
+
+impl VirtualTasks {
+    /// Parses virtual tasks given as `<name>=<path>,<path>,...`.
+    pub fn parse(groups: &[String]) -> Result<Self, anyhow::Error> {
+        let mut tasks = IndexMap::new();
+        for group in groups {
+            let (name, members) = group.split_once('=').with_context(|| {
+                format!(
+                    "Invalid virtual task {}: expected <name>=<path>,<path>,...",
+                    group
+                )
+            })?;
+            tasks.insert(
+                name.to_string(),
+                VirtualTask {
+                    members: members
+                        .split(',')
+                        .map(|m| m.trim().to_string())
+                        .filter(|m| !m.is_empty())
+                        .collect(),
+                    active: 0,
+                },
+            );
+        }
+
+        Ok(Self(tasks))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Inserts the events of the virtual tasks after the events of
+    /// their members in `chunk`.
+    pub fn apply(&mut self, chunk: &mut api::EventChunk) {
+        let mut events = Vec::with_capacity(chunk.events.len());
+        for event in chunk.events.drain(..) {
+            let member = match &event {
+                api::EventType::Task {
+                    name,
+                    action: action @ (api::TaskAction::Entered | api::TaskAction::Exited),
+                    priority,
+                    depth,
+                } => Some((name.clone(), action.clone(), *priority, *depth)),
+                _ => None,
+            };
+            events.push(event);
+
+            let (name, action, priority, depth) = match member {
+                Some(member) => member,
+                None => continue,
+            };
+            for (vname, task) in self.0.iter_mut() {
+                if !task.members.contains(&name) {
+                    continue;
+                }
+                let transition = match action {
+                    api::TaskAction::Entered => {
+                        task.active += 1;
+                        task.active == 1
+                    }
+                    _ if task.active > 0 => {
+                        task.active -= 1;
+                        task.active == 0
+                    }
+                    // An exit of a member entered before the trace
+                    // started.
+                    _ => false,
+                };
+                if transition {
+                    events.push(api::EventType::Task {
+                        name: vname.clone(),
+                        action: action.clone(),
+                        priority,
+                        depth,
+                    });
+                }
+            }
+        }
+        chunk.events = events;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::time::Duration;
+
+    /// Ensure that overlapping executions of three member tasks yield a
+    /// single interval of the virtual task.
+    #[test]
+    fn single_interval() {
+        let mut tasks =
+            VirtualTasks::parse(&["transfer=app::dma1,app::dma2,app::dma3".to_string()]).unwrap();
+        let at = |us| api::ChunkBuilder::at(Duration::from_micros(us));
+        let mut chunks = vec![
+            at(0).entered("app::dma1").build(),
+            at(10).entered("app::dma2").build(),
+            at(20).exited("app::dma1").entered("app::other").build(),
+            at(30).entered("app::dma3").exited("app::other").build(),
+            at(40).exited("app::dma2").build(),
+            at(50).exited("app::dma3").build(),
+        ];
+
+        let mut intervals = vec![];
+        for chunk in chunks.iter_mut() {
+            tasks.apply(chunk);
+            let at = api::timestamp_offset(&chunk.timestamp);
+            intervals.extend(chunk.events.iter().filter_map(|event| match event {
+                api::EventType::Task { name, action, .. } if name == "transfer" => {
+                    Some((action.clone(), at))
+                }
+                _ => None,
+            }));
+        }
+
+        assert_eq!(
+            intervals,
+            vec![
+                (api::TaskAction::Entered, Duration::from_micros(0)),
+                (api::TaskAction::Exited, Duration::from_micros(50)),
+            ]
+        );
+        // Member events are kept.
+        assert_eq!(chunks[0].events.len(), 2);
+        assert_eq!(chunks[1].events.len(), 1);
+    }
+}
@@ -1,45 +1,278 @@
 extern crate proc_macro;
 use proc_macro::TokenStream;
 use quote::{quote, ToTokens};
-use syn::{self, parse_macro_input, ItemFn, LitInt, Stmt};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use syn::{self, parse_macro_input, AttributeArgs, ItemFn, Lit, LitInt, Meta, NestedMeta, Stmt};
 
-static mut TRACE_ID: usize = 0;
+/// The ID of the next traced function. Preserves state over multiple
+/// macro calls within the compilation of a crate.
+static TRACE_ID: AtomicUsize = AtomicUsize::new(0);
 
+/// The IDs assigned so far, explicitly or automatically.
+static ASSIGNED: Mutex<Vec<usize>> = Mutex::new(Vec::new());
+
+/// Traces the decorated function as a software task by writing its
+/// unique ID on entry and exit. The exit is written on all paths out
+/// of the function, e.g. an early `return` or `?`, and on unwinding.
+/// Methods in `impl` blocks are traced alike. The body of an `async fn`
+/// is that of its future, so the enter and exit are written on every
+/// poll: each exit but the last is a yield. The return is written once
+/// the body completes, which a future dropped before completion never
+/// does.
+/// IDs are assigned in expansion order, which RTIC Scope mirrors by
+/// walking the application source.
+///
+/// An ID can be requested explicitly via `#[trace(id = N)]`, e.g. to
+/// keep the IDs of recordings stable over reordered or conditionally
+/// compiled functions. Automatically assigned IDs skip the IDs that
+/// have been requested before; requesting an ID that is already
+/// assigned is an error.
+///
+/// This couples the IDs to the order in which rustc expands the
+/// attribute: top to bottom through the crate, modules in the order
+/// they are declared. rustc expands the attribute macros of a crate
+/// sequentially and anew on every (incremental) compilation, so the
+/// IDs of a build are deterministic. A long-lived expansion server
+/// (e.g. that of an IDE) does not restart the counter, but its
+/// expansions are not compiled into the firmware.
+///
+/// Helper attribute macros that expand to functions carrying `#[trace]`
+/// are not seen in the source. Declare them, with the number of
+/// `#[trace]` attributes they emit per function, via
+/// `trace_helpers = { traced_task = 1 }` in
+/// `[package.metadata.rtic-scope]`, or the IDs of all subsequently
+/// traced functions are off.
 #[proc_macro_attribute]
-pub fn trace(_attrs: TokenStream, item: TokenStream) -> TokenStream {
-    let mut fun = parse_macro_input!(item as ItemFn);
-    fun.block.stmts = {
-        // Generate a unique (software) task ID by strictly increasing a
-        // variable that preserves state over multiple macro calls.
-        let task_id = syn::parse_str::<LitInt>(
-            format!("{}", unsafe {
-                let id = TRACE_ID;
-                TRACE_ID += 1;
-                if TRACE_ID > u8::MAX.into() {
-                    panic!("255 software tasks are supported at maximum");
-                }
-                id
-            })
-            .as_str(),
-        )
-        .unwrap();
+pub fn trace(attrs: TokenStream, item: TokenStream) -> TokenStream {
+    let args = parse_macro_input!(attrs as AttributeArgs);
+    let fun = parse_macro_input!(item as ItemFn);
 
-        // Insert a statement at the start and end of the given function
-        // that writes the unique task ID to the respecpive watchpoint
-        // address.
-        let prologue = syn::parse2::<Stmt>(quote!(
-            ::cortex_m_rtic_trace::__write_enter_id(#task_id);
-        ))
-        .unwrap();
-        let epilogue = syn::parse2::<Stmt>(quote!(
-            ::cortex_m_rtic_trace::__write_exit_id(#task_id);
-        ))
-        .unwrap();
-        let mut stmts = vec![prologue];
-        stmts.append(&mut fun.block.stmts);
-        stmts.push(epilogue);
-        stmts
+    let id = requested_id(&args).and_then(|requested| {
+        assign(&ASSIGNED, &TRACE_ID, requested)
+            .map_err(|msg| syn::Error::new_spanned(&fun.sig.ident, msg))
+    });
+    match id {
+        Ok(id) => expand(fun, id).into_token_stream().into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+/// Parses the ID requested via `#[trace(id = N)]`, if any.
+fn requested_id(args: &[NestedMeta]) -> syn::Result<Option<usize>> {
+    let mut id = None;
+    for arg in args {
+        match arg {
+            NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("id") => match &nv.lit {
+                Lit::Int(lit) => match lit.base10_parse::<u16>()? {
+                    u16::MAX => {
+                        return Err(syn::Error::new_spanned(
+                            lit,
+                            "software task IDs must be less than 65535",
+                        ))
+                    }
+                    requested => id = Some(requested.into()),
+                },
+                lit => return Err(syn::Error::new_spanned(lit, "expected an integer ID")),
+            },
+            arg => return Err(syn::Error::new_spanned(arg, "expected `id = <integer>`")),
+        }
+    }
+
+    Ok(id)
+}
+
+/// Assigns the `requested` ID, or otherwise the next ID of `counter`
+/// that has not been assigned, and records it in `assigned`.
+fn assign(
+    assigned: &Mutex<Vec<usize>>,
+    counter: &AtomicUsize,
+    requested: Option<usize>,
+) -> Result<usize, String> {
+    let mut assigned = assigned.lock().unwrap();
+    let id = match requested {
+        Some(id) if assigned.contains(&id) => {
+            return Err(format!("software task ID {} is already assigned", id))
+        }
+        Some(id) => id,
+        None => loop {
+            let id = next_id(counter);
+            if !assigned.contains(&id) {
+                break id;
+            }
+        },
     };
+    assigned.push(id);
+
+    Ok(id)
+}
+
+/// Generates a unique (software) task ID by strictly increasing
+/// `counter`.
+fn next_id(counter: &AtomicUsize) -> usize {
+    let id = counter.fetch_add(1, Ordering::SeqCst);
+    if id >= u16::MAX.into() {
+        panic!("65535 software tasks are supported at maximum");
+    }
+    id
+}
+
+/// Inserts statements at the start of `fun` that write `task_id` to
+/// the respective watchpoint address on entry, and on exit by dropping
+/// a guard. The guard is dropped after the value of the function has
+/// been evaluated, on any path out of the function. The body of an
+/// `async fn` is instead wrapped in a future that does so on every
+/// poll, and writes the return on completion.
+fn expand(mut fun: ItemFn, task_id: usize) -> ItemFn {
+    let task_id = syn::parse_str::<LitInt>(format!("{}", task_id).as_str()).unwrap();
+
+    if fun.sig.asyncness.is_some() {
+        let block = &fun.block;
+        fun.block = syn::parse2(quote!({
+            ::cortex_m_rtic_trace::__Traced::new(#task_id, async move #block).await
+        }))
+        .unwrap();
+        return fun;
+    }
+
+    let prologue = syn::parse2::<Stmt>(quote!(
+        ::cortex_m_rtic_trace::__write_enter_id(#task_id);
+    ))
+    .unwrap();
+    let guard = syn::parse2::<Stmt>(quote!(
+        let __rtic_trace_exit_guard = ::cortex_m_rtic_trace::__ExitGuard(#task_id);
+    ))
+    .unwrap();
+    let mut stmts = vec![prologue, guard];
+    stmts.append(&mut fun.block.stmts);
+    fun.block.stmts = stmts;
+
+    fun
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Ensure that functions traced in sequence are assigned
+    /// monotonic IDs without gaps.
+    #[test]
+    fn sequential_ids() {
+        let assigned = Mutex::new(vec![]);
+        let counter = AtomicUsize::new(0);
+        let ids: Vec<String> = ["foo", "bar", "baz"]
+            .iter()
+            .map(|name| {
+                let fun =
+                    syn::parse_str::<ItemFn>(&format!("fn {}() {{ work(); }}", name)).unwrap();
+                let fun = expand(fun, assign(&assigned, &counter, None).unwrap());
+                // The prologue and the exit guard precede the body.
+                assert_eq!(fun.block.stmts.len(), 3);
+                fun.block.stmts[..2]
+                    .iter()
+                    .map(|stmt| stmt.to_token_stream().to_string().replace(' ', ""))
+                    .collect::<String>()
+            })
+            .collect();
+
+        for (id, stmts) in ids.iter().enumerate() {
+            assert!(
+                stmts.contains(&format!("__write_enter_id({});", id)),
+                "{}",
+                stmts
+            );
+            assert!(
+                stmts.ends_with(&format!(
+                    "let__rtic_trace_exit_guard=::cortex_m_rtic_trace::__ExitGuard({});",
+                    id
+                )),
+                "{}",
+                stmts
+            );
+        }
+        assert_eq!(counter.load(Ordering::SeqCst), 3);
+    }
+
+    /// Ensure that the exit is guarded rather than written by a trailing
+    /// statement that an early return would skip.
+    #[test]
+    fn guarded_exit() {
+        let fun =
+            syn::parse_str::<ItemFn>("fn early(x: bool) -> u8 { if x { return 1; } work(); 2 }")
+                .unwrap();
+        let stmts: Vec<String> = expand(fun, 7)
+            .block
+            .stmts
+            .iter()
+            .map(|stmt| stmt.to_token_stream().to_string().replace(' ', ""))
+            .collect();
+
+        assert!(stmts[1].ends_with("__ExitGuard(7);"), "{}", stmts[1]);
+        assert!(stmts.iter().all(|stmt| !stmt.contains("__write_exit_id")));
+        // the value of the function remains its tail expression
+        assert_eq!(stmts.last().unwrap(), "2");
+    }
+
+    /// Ensure that the body of async methods is wrapped in a future
+    /// that traces every poll.
+    #[test]
+    fn async_method() {
+        let fun =
+            syn::parse_str::<ItemFn>("async fn method(&self) -> u8 { work().await; 1 }").unwrap();
+        let fun = expand(fun, 3);
+
+        assert!(fun.sig.asyncness.is_some());
+        assert!(matches!(
+            fun.sig.inputs.first(),
+            Some(syn::FnArg::Receiver(_))
+        ));
+        assert_eq!(fun.block.stmts.len(), 1);
+        let body = fun.block.stmts[0]
+            .to_token_stream()
+            .to_string()
+            .replace(' ', "");
+        assert!(
+            body.starts_with("::cortex_m_rtic_trace::__Traced::new(3,asyncmove{work().await;1})"),
+            "{}",
+            body
+        );
+        assert!(body.ends_with(".await"), "{}", body);
+    }
+
+    /// Ensure that requested IDs are assigned once, and are skipped by
+    /// automatically assigned IDs.
+    #[test]
+    fn requested_ids() {
+        let args = |args: &str| -> Vec<NestedMeta> {
+            syn::parse_str::<syn::MetaList>(&format!("trace({})", args))
+                .unwrap()
+                .nested
+                .into_iter()
+                .collect()
+        };
+        assert_eq!(requested_id(&args("")).unwrap(), None);
+        assert_eq!(requested_id(&args("id = 12")).unwrap(), Some(12));
+        assert!(requested_id(&args("id = \"12\"")).is_err());
+        assert!(requested_id(&args("id = 65535")).is_err());
+        assert!(requested_id(&args("name = 12")).is_err());
+
+        let assigned = Mutex::new(vec![]);
+        let counter = AtomicUsize::new(0);
+        let assign = |requested| assign(&assigned, &counter, requested);
+        assert_eq!(assign(None), Ok(0));
+        assert_eq!(assign(Some(1)), Ok(1));
+        assert_eq!(assign(None), Ok(2));
+        assert!(assign(Some(2)).is_err());
+        assert!(assign(Some(1)).is_err());
+        assert_eq!(assign(Some(10)), Ok(10));
+        assert_eq!(assign(None), Ok(3));
+    }
 
-    fun.into_token_stream().into()
+    /// Ensure that IDs beyond the range of a `u16` are refused.
+    #[test]
+    #[should_panic(expected = "65535 software tasks")]
+    fn too_many_ids() {
+        let counter = AtomicUsize::new(u16::MAX.into());
+        next_id(&counter);
+    }
 }
@@ -1,7 +1,15 @@
 #![allow(rustdoc::bare_urls)]
 //! Auxilliary target-side crate for RTIC Scope configuration.
 #![doc = include_str!("../../docs/profile/README.md")]
-#![no_std]
+#![cfg_attr(not(test), no_std)]
+
+// NOTE(self) #[trace] refers to this crate by name, also in its tests.
+#[cfg(test)]
+extern crate self as cortex_m_rtic_trace;
+
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll};
 
 use cortex_m::peripheral::{
     self as Core,
@@ -13,10 +21,15 @@ pub use cortex_m::peripheral::{
     tpiu::TraceProtocol,
 };
 
-/// The tracing macro. Takes no arguments and should be placed on a
-/// function. Refer to crate example usage.
+/// The tracing macro. Should be placed on a function, `async fn`, or
+/// method. Refer to crate example usage.
 pub use rtic_trace_macros::trace;
 
+#[cfg(feature = "rtt")]
+mod rtt;
+#[cfg(feature = "rtt")]
+pub use rtt::{configure_rtt, RttTimestamp, RttWriter};
+
 /// Trace configuration to apply via [`configure`].
 #[derive(Debug, Eq, PartialEq, Copy, Clone)]
 pub struct TraceConfiguration {
@@ -47,6 +60,9 @@ pub enum TraceConfigurationError {
     TPIUConfig,
     /// The ITM configuration failed to apply.
     ITMConfig(Core::itm::ITMConfigurationError),
+    /// A watch address given to [`set_watch_addresses`] is not
+    /// word-aligned.
+    WatchAddress,
 }
 
 impl From<Core::itm::ITMConfigurationError> for TraceConfigurationError {
@@ -63,13 +79,73 @@ impl From<Core::itm::ITMConfigurationError> for TraceConfigurationError {
 #[repr(align(4))]
 struct WatchVariable {
     /// ID of the software task that was entered or exited.
-    pub id: u8,
+    pub id: u16,
 }
 
 /// Watch variable to which the just entered software task ID is written to. Aligned to 32-bit.
 static mut WATCH_VARIABLE_ENTER: WatchVariable = WatchVariable { id: 0 };
 /// Watch variable to which the just exited software task ID is written to. Aligned to 32-bit.
 static mut WATCH_VARIABLE_EXIT: WatchVariable = WatchVariable { id: 0 };
+/// Watch variable to which the ID of the just completed async software task is written to. Aligned to 32-bit.
+static mut WATCH_VARIABLE_RETURNED: WatchVariable = WatchVariable { id: 0 };
+/// Watch variable to which the counter of the RTIC monotonic is written to.
+static mut WATCH_VARIABLE_MONOTONIC: u32 = 0;
+
+/// Locations of the variables to which the IDs of software tasks are
+/// written and which the DWT comparators watch, e.g. in a RAM region
+/// that is not zeroed on reset. Each address must be word-aligned, as
+/// the default watch variables are: the comparators match the exact
+/// address (no mask) of the halfword write of the ID, which is then
+/// emitted. Each variable must be at least a halfword in size and
+/// must not be written to by anything else.
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub struct WatchAddresses {
+    /// Of the ID of the just entered software task.
+    pub enter: *mut u16,
+    /// Of the ID of the software task about to exit.
+    pub exit: *mut u16,
+    /// Of the ID of the just completed async software task.
+    pub returned: *mut u16,
+}
+
+/// Watch addresses that replace the default watch variables, if set
+/// via [`set_watch_addresses`].
+static mut WATCH_ADDRESSES: Option<WatchAddresses> = None;
+
+/// Replaces the default watch variables with the variables at the given
+/// addresses. Must be called before [`configure`] and
+/// [`configure_returned`], and before any software task is traced.
+///
+/// # Safety
+///
+/// The addresses must be valid for writes of a `u16` for the lifetime
+/// of the program.
+pub unsafe fn set_watch_addresses(
+    addresses: WatchAddresses,
+) -> Result<(), TraceConfigurationError> {
+    if [addresses.enter, addresses.exit, addresses.returned]
+        .iter()
+        .any(|addr| *addr as usize % 4 != 0)
+    {
+        return Err(TraceConfigurationError::WatchAddress);
+    }
+    WATCH_ADDRESSES = Some(addresses);
+
+    Ok(())
+}
+
+/// The watch addresses set via [`set_watch_addresses`], or those of the
+/// default watch variables.
+#[inline]
+fn watch_addresses() -> WatchAddresses {
+    unsafe {
+        WATCH_ADDRESSES.unwrap_or(WatchAddresses {
+            enter: core::ptr::addr_of_mut!(WATCH_VARIABLE_ENTER.id),
+            exit: core::ptr::addr_of_mut!(WATCH_VARIABLE_EXIT.id),
+            returned: core::ptr::addr_of_mut!(WATCH_VARIABLE_RETURNED.id),
+        })
+    }
+}
 
 /// Configures the ARMv7-M peripherals for RTIC hardware and software
 /// task tracing. Fails if the configuration cannot be applied.
@@ -126,32 +202,73 @@ pub fn configure(
     dwt.enable_exception_tracing();
 
     // Configure DWT comparators for software task tracing.
-    let enter_addr: u32 = unsafe { &WATCH_VARIABLE_ENTER.id as *const _ } as u32;
-    let exit_addr: u32 = unsafe { &WATCH_VARIABLE_EXIT.id as *const _ } as u32;
-    for (dwt, addr) in [
-        (&dwt.c[enter_dwt_idx], enter_addr),
-        (&dwt.c[exit_dwt_idx], exit_addr),
-    ] {
-        // TODO do we need to clear the MATCHED, bit[24] after every match?
-        dwt.configure(ComparatorFunction::Address(ComparatorAddressSettings {
-            address: addr,
+    let addresses = watch_addresses();
+    watch(&dwt.c[enter_dwt_idx], addresses.enter);
+    watch(&dwt.c[exit_dwt_idx], addresses.exit);
+
+    Ok(())
+}
+
+/// Configures a DWT comparator to trace the completion of traced
+/// `async fn`s, in addition to [`configure`]. An async software task
+/// is entered and exited on every poll: each exit but the last is a
+/// yield. Its completion is reported as a return.
+///
+/// Optional: the comparator must be declared via `dwt_returned_id` in
+/// `[package.metadata.rtic-scope]`.
+pub fn configure_returned(dwt: &mut Core::DWT, returned_dwt_idx: usize) {
+    watch(&dwt.c[returned_dwt_idx], watch_addresses().returned);
+}
+
+/// Configures a DWT comparator to trace samples of the RTIC monotonic,
+/// written via [`write_monotonic`], in addition to [`configure`]. The
+/// samples correlate the ticks of the monotonic with the trace
+/// timeline.
+///
+/// Optional: the comparator must be declared via `dwt_monotonic_id` in
+/// `[package.metadata.rtic-scope]`.
+pub fn configure_monotonic(dwt: &mut Core::DWT, monotonic_dwt_idx: usize) {
+    watch(
+        &dwt.c[monotonic_dwt_idx],
+        core::ptr::addr_of!(WATCH_VARIABLE_MONOTONIC),
+    );
+}
+
+/// Writes a sample of the counter of the RTIC monotonic, e.g.
+/// periodically from a task, to the watch address configured via
+/// [`configure_monotonic`]. A 64-bit counter is truncated: the host
+/// extends it as long as it wraps at most once between two samples.
+#[inline]
+pub fn write_monotonic(ticks: u32) {
+    unsafe {
+        core::ptr::write_volatile(core::ptr::addr_of_mut!(WATCH_VARIABLE_MONOTONIC), ticks);
+    }
+}
+
+/// Configures `comparator` to emit the data written to the watch
+/// variable at `addr`.
+fn watch<T>(comparator: &Core::dwt::Comparator, addr: *const T) {
+    // TODO do we need to clear the MATCHED, bit[24] after every match?
+    comparator
+        .configure(ComparatorFunction::Address(ComparatorAddressSettings {
+            address: addr as u32,
             mask: 0,
             emit: EmitOption::Data,
             access_type: AccessType::WriteOnly,
         }))
         .unwrap(); // NOTE safe: valid (emit, access_type) used
-    }
-
-    Ok(())
 }
 
 /// Function utilized by [`#[trace]`](trace) to write the unique ID of
 /// the just entered software task to its associated watch address. Only
 /// use this function via [`#[trace]`](trace).
 #[inline]
-pub fn __write_enter_id(id: u8) {
+pub fn __write_enter_id(id: u16) {
+    #[cfg(feature = "rtt")]
+    rtt::write(rtt::FrameKind::Enter, id);
+    #[cfg(not(feature = "rtt"))]
     unsafe {
-        core::ptr::write_volatile(&mut WATCH_VARIABLE_ENTER.id, id);
+        core::ptr::write_volatile(watch_addresses().enter, id);
     }
 }
 
@@ -159,8 +276,243 @@ pub fn __write_enter_id(id: u8) {
 /// the software task about to exit to its associated watch address.
 /// Only use this function via [`#[trace]`](trace).
 #[inline]
-pub fn __write_exit_id(id: u8) {
+pub fn __write_exit_id(id: u16) {
+    #[cfg(feature = "rtt")]
+    rtt::write(rtt::FrameKind::Exit, id);
+    #[cfg(not(feature = "rtt"))]
     unsafe {
-        core::ptr::write_volatile(&mut WATCH_VARIABLE_EXIT.id, id);
+        core::ptr::write_volatile(watch_addresses().exit, id);
+    }
+}
+
+/// Writes the ID of the software task about to exit to its associated
+/// watch address when dropped, such that the exit is written on all
+/// paths out of a function traced by [`#[trace]`](trace). Only use this
+/// type via [`#[trace]`](trace).
+#[doc(hidden)]
+pub struct __ExitGuard(pub u16);
+
+impl Drop for __ExitGuard {
+    #[inline]
+    fn drop(&mut self) {
+        __write_exit_id(self.0);
+    }
+}
+
+/// Function utilized by [`#[trace]`](trace) to write the unique ID of
+/// the just completed async software task to its associated watch
+/// address. Only use this function via [`#[trace]`](trace).
+#[inline]
+pub fn __write_returned_id(id: u16) {
+    #[cfg(feature = "rtt")]
+    rtt::write(rtt::FrameKind::Returned, id);
+    #[cfg(not(feature = "rtt"))]
+    unsafe {
+        core::ptr::write_volatile(watch_addresses().returned, id);
+    }
+}
+
+/// Wraps the body of an `async fn` traced by [`#[trace]`](trace): the
+/// enter and exit are written on every poll, and the return once the
+/// body completes. Only use this type via [`#[trace]`](trace).
+#[doc(hidden)]
+pub struct __Traced<F> {
+    id: u16,
+    fut: F,
+}
+
+impl<F> __Traced<F> {
+    #[inline]
+    pub fn new(id: u16, fut: F) -> Self {
+        Self { id, fut }
+    }
+}
+
+impl<F: Future> Future for __Traced<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // SAFETY: `fut` is structurally pinned; it is never moved out
+        // of `self`.
+        let (id, fut) = unsafe {
+            let this = self.get_unchecked_mut();
+            (this.id, Pin::new_unchecked(&mut this.fut))
+        };
+
+        __write_enter_id(id);
+        let poll = {
+            let _exit = __ExitGuard(id);
+            fut.poll(cx)
+        };
+        if poll.is_ready() {
+            __write_returned_id(id);
+        }
+        poll
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::Mutex;
+    use std::task::Waker;
+
+    /// Serializes the tests, which share the watch variables.
+    static SERIAL: Mutex<()> = Mutex::new(());
+
+    /// The IDs last written to the enter and exit watch variables.
+    fn watched() -> (u16, u16) {
+        unsafe {
+            (
+                core::ptr::read_volatile(&WATCH_VARIABLE_ENTER.id),
+                core::ptr::read_volatile(&WATCH_VARIABLE_EXIT.id),
+            )
+        }
+    }
+
+    /// The ID last written to the returned watch variable.
+    fn returned() -> u16 {
+        unsafe { core::ptr::read_volatile(&WATCH_VARIABLE_RETURNED.id) }
+    }
+
+    fn reset() {
+        __write_enter_id(u16::MAX);
+        __write_exit_id(u16::MAX);
+        __write_returned_id(u16::MAX);
+    }
+
+    /// Ensure that IDs are written to the watch addresses that replace
+    /// the default watch variables, which must be word-aligned.
+    #[test]
+    fn custom_watch_addresses() {
+        let _serial = SERIAL.lock().unwrap();
+
+        #[repr(align(4))]
+        struct Variables([u16; 6]);
+        let mut variables = Variables([0; 6]);
+        let base = variables.0.as_mut_ptr();
+
+        unsafe {
+            assert_eq!(
+                set_watch_addresses(WatchAddresses {
+                    enter: base,
+                    exit: base.add(1),
+                    returned: base.add(4),
+                }),
+                Err(TraceConfigurationError::WatchAddress)
+            );
+            set_watch_addresses(WatchAddresses {
+                enter: base,
+                exit: base.add(2),
+                returned: base.add(4),
+            })
+            .unwrap();
+        }
+        __write_enter_id(1);
+        __write_exit_id(2);
+        __write_returned_id(3);
+        unsafe {
+            WATCH_ADDRESSES = None;
+            assert_eq!(
+                [0, 2, 4].map(|i| core::ptr::read_volatile(base.add(i))),
+                [1, 2, 3]
+            );
+        }
+    }
+
+    /// Ensure that the exit of a traced function is written after its
+    /// body, also when it returns early.
+    #[test]
+    fn early_return() {
+        let _serial = SERIAL.lock().unwrap();
+
+        #[trace]
+        fn traced(early: bool) -> Result<u8, ()> {
+            if early {
+                return Err(());
+            }
+            // not yet exited
+            assert_eq!(watched().1, u16::MAX);
+            Ok(1)
+        }
+
+        for early in [false, true] {
+            reset();
+            assert_eq!(traced(early).is_err(), early);
+            let (enter, exit) = watched();
+            assert_ne!(enter, u16::MAX);
+            assert_eq!(enter, exit);
+        }
+    }
+
+    /// Ensure that the enter and exit of a traced async method are
+    /// written when its future is polled, not when it is constructed.
+    #[test]
+    fn async_method() {
+        let _serial = SERIAL.lock().unwrap();
+
+        struct Shared(u8);
+        impl Shared {
+            #[trace]
+            async fn method(&self) -> u8 {
+                // not yet exited
+                assert_eq!(watched().1, u16::MAX);
+                self.0
+            }
+        }
+
+        reset();
+        let shared = Shared(1);
+        let mut fut = Box::pin(shared.method());
+        assert_eq!(watched(), (u16::MAX, u16::MAX));
+
+        let mut cx = Context::from_waker(Waker::noop());
+        assert_eq!(fut.as_mut().poll(&mut cx), Poll::Ready(1));
+        let (enter, exit) = watched();
+        assert_ne!(enter, u16::MAX);
+        assert_eq!(enter, exit);
+        assert_eq!(returned(), enter);
+    }
+
+    /// Ensure that a traced async function is exited on every yield,
+    /// and returns once it completes.
+    #[test]
+    fn async_yield() {
+        let _serial = SERIAL.lock().unwrap();
+
+        /// Yields once before it completes.
+        struct YieldOnce(bool);
+        impl Future for YieldOnce {
+            type Output = ();
+
+            fn poll(mut self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<()> {
+                if self.0 {
+                    Poll::Ready(())
+                } else {
+                    self.0 = true;
+                    Poll::Pending
+                }
+            }
+        }
+
+        #[trace]
+        async fn traced() -> u8 {
+            YieldOnce(false).await;
+            2
+        }
+
+        reset();
+        let mut fut = Box::pin(traced());
+        let mut cx = Context::from_waker(Waker::noop());
+
+        assert_eq!(fut.as_mut().poll(&mut cx), Poll::Pending);
+        let (enter, exit) = watched();
+        assert_ne!(enter, u16::MAX);
+        assert_eq!(enter, exit);
+        assert_eq!(returned(), u16::MAX);
+
+        assert_eq!(fut.as_mut().poll(&mut cx), Poll::Ready(2));
+        assert_eq!(watched(), (enter, enter));
+        assert_eq!(returned(), enter);
     }
 }
@@ -0,0 +1,100 @@
+//! Software task tracing over an RTT up-channel instead of the DWT, for
+//! targets that do not route SWO. Enabled by the `rtt` feature: the
+//! enter, exit, and return of software tasks are then written in frames
+//! to the up-channel set via [`configure_rtt`], and traced via
+//! `cargo rtic-scope trace --rtt`. Hardware tasks cannot be traced over
+//! RTT.
+//!
+//! A frame is [`FRAME_LEN`] bytes: the [`FrameKind`], the software task
+//! ID (`u16`), and the [`RttTimestamp`] (`u32`) at the time of the
+//! event, both little-endian.
+
+/// Writes the given bytes to an RTT up-channel and returns the number
+/// of bytes written. The channel must write frames whole or not at all
+/// (e.g. `rtt_target::ChannelMode::NoBlockSkip`) and must not be
+/// written to by anything else.
+pub type RttWriter = fn(&[u8]) -> usize;
+
+/// Reads a free-running counter that timestamps the frames. The counter
+/// must count up and wrap at `u32::MAX`, at the frequency given as
+/// `timestamp_freq` in `[package.metadata.rtic-scope]`, and must not
+/// wrap more than once between two frames. On ARMv7-M, this is the DWT
+/// cycle counter; ARMv6-M targets, which lack one, must provide a
+/// 32-bit timer instead.
+pub type RttTimestamp = fn() -> u32;
+
+/// Length of a frame in bytes.
+const FRAME_LEN: usize = 7;
+
+/// What a frame reports.
+#[derive(Clone, Copy)]
+#[repr(u8)]
+pub(crate) enum FrameKind {
+    /// A software task was entered.
+    Enter = 0,
+    /// A software task exited.
+    Exit = 1,
+    /// An async software task completed.
+    Returned = 2,
+    /// The given number of frames were dropped because the up-channel
+    /// was full.
+    Dropped = 3,
+}
+
+/// Writer of the up-channel to which frames are written, and the
+/// timestamp of the frames.
+static mut WRITER: Option<(RttWriter, RttTimestamp)> = None;
+/// Number of frames dropped since the last frame was written.
+static mut DROPPED: u16 = 0;
+
+/// Configures software task tracing over RTT: frames are written with
+/// `writer` and timestamped with `timestamp`, e.g. on ARMv7-M
+///
+/// ```ignore
+/// let channels = rtt_target::rtt_init! { up: { 0: { size: 1024 name: "trace" } } };
+/// static mut TRACE: Option<rtt_target::UpChannel> = None;
+/// unsafe { TRACE = Some(channels.up.0) };
+/// dcb.enable_trace();
+/// dwt.enable_cycle_counter();
+/// cortex_m_rtic_trace::configure_rtt(
+///     |bytes| unsafe { TRACE.as_mut().unwrap().write(bytes) },
+///     cortex_m::peripheral::DWT::cycle_count,
+/// );
+/// ```
+///
+/// where `timestamp_freq` is the frequency of the processor clock.
+pub fn configure_rtt(writer: RttWriter, timestamp: RttTimestamp) {
+    cortex_m::interrupt::free(|_| unsafe {
+        WRITER = Some((writer, timestamp));
+    });
+}
+
+/// Writes a frame of `kind` for the software task `id`, preceded by a
+/// frame that reports the number of dropped frames, if any. Frames are
+/// dropped if the up-channel is full, or if [`configure_rtt`] has not
+/// been called.
+pub(crate) fn write(kind: FrameKind, id: u16) {
+    cortex_m::interrupt::free(|_| unsafe {
+        let (writer, timestamp) = match WRITER {
+            Some(writer) => writer,
+            None => return,
+        };
+
+        if DROPPED > 0 {
+            if writer(&frame(FrameKind::Dropped, DROPPED, timestamp())) < FRAME_LEN {
+                DROPPED = DROPPED.saturating_add(1);
+                return;
+            }
+            DROPPED = 0;
+        }
+        if writer(&frame(kind, id, timestamp())) < FRAME_LEN {
+            DROPPED = DROPPED.saturating_add(1);
+        }
+    });
+}
+
+fn frame(kind: FrameKind, id: u16, timestamp: u32) -> [u8; FRAME_LEN] {
+    let [i0, i1] = id.to_le_bytes();
+    let [c0, c1, c2, c3] = timestamp.to_le_bytes();
+    [kind as u8, i0, i1, c0, c1, c2, c3]
+}
@@ -2,22 +2,215 @@
 //! API used between RTIC Scope front- and backends.
 #![doc = include_str!("../../docs/profile/README.md")]
 
-pub use itm::Timestamp;
-use itm::{ExceptionAction, MalformedPacket, TracePacket};
+use itm::{MalformedPacket, TracePacket};
+pub use itm::{MemoryAccessType, Timestamp};
 use serde::{Deserialize, Serialize};
 
-/// [RTIC](https://rtic.rs) nomenclature alias.
-pub type TaskAction = ExceptionAction;
+/// What an [RTIC](https://rtic.rs) task did. Hardware tasks are
+/// entered, exited, and returned to as the exceptions they are bound
+/// to.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum TaskAction {
+    /// The task started executing. An async software task is entered
+    /// on every poll.
+    Entered,
+    /// The task stopped executing. An async software task is exited on
+    /// every poll, also when it yields.
+    Exited,
+    /// The task resumed executing after it was preempted.
+    Returned,
+    /// An async software task completed, after its last poll exited.
+    Completed,
+}
+
+/// Returns the offset since target reset denoted by `timestamp`,
+/// regardless of its quality. For timestamps of unknown delay, the
+/// latest possible offset is returned.
+pub fn timestamp_offset(timestamp: &Timestamp) -> std::time::Duration {
+    match timestamp {
+        Timestamp::Sync(offset) | Timestamp::AssocEventDelay(offset) => *offset,
+        Timestamp::UnknownDelay { prev: _, curr }
+        | Timestamp::UnknownAssocEventDelay { prev: _, curr } => *curr,
+    }
+}
+
+/// Version of the schema of the messages sent to frontends: a
+/// [`Handshake`] followed by [`EventChunk`]s. Bumped once per release
+/// that makes any incompatible change to these types, e.g. a new
+/// [`EventType`] variant.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// The first message sent to a frontend, before any [`EventChunk`].
+/// A frontend should check [`Handshake::is_compatible`] and fail fast
+/// otherwise.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Handshake {
+    /// Version of the backend, e.g. `cargo-rtic-scope`.
+    pub version: String,
+
+    /// The [`SCHEMA_VERSION`] of the backend.
+    pub schema: u32,
+
+    /// Metadata of the trace, as recovered by the backend. The
+    /// `hardware_tasks` and `software_tasks` keys list the full paths
+    /// of all tasks, so that a frontend can set up its tracks before
+    /// the first event arrives.
+    pub metadata: serde_json::Value,
+}
+
+impl Handshake {
+    pub fn new(version: &str, metadata: serde_json::Value) -> Self {
+        Self {
+            version: version.to_string(),
+            schema: SCHEMA_VERSION,
+            metadata,
+        }
+    }
+
+    /// Whether the backend speaks the schema of this crate.
+    pub fn is_compatible(&self) -> bool {
+        self.schema == SCHEMA_VERSION
+    }
+}
 
 /// A set of events that occurred at a certain timepoint during target
 /// execution.
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct EventChunk {
     /// Collective timestamp for the chunk of [`EventChunk::events`].
+    ///
+    /// Offsets are re-anchored to the last global timestamp of the
+    /// trace, if any, which corrects the drift of the accumulated local
+    /// timestamps. The variant, i.e. whether the timestamp is in sync
+    /// and whether it relates to the events or to the data of the
+    /// chunk, is that of the local timestamp: a global timestamp
+    /// corrects the offset, but does not undo a divergence after an
+    /// overflow for the chunks in between.
     pub timestamp: Timestamp,
 
     /// Set of events that occured during [`EventChunk::timestamp`].
     pub events: Vec<EventType>,
+
+    /// The raw ITM bytes from which the chunk was decoded, if
+    /// requested and available from the trace source.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub raw: Option<RawBytes>,
+
+    /// Ticks of the RTIC monotonic at [`EventChunk::timestamp`], if the
+    /// firmware samples the monotonic to a DWT comparator. Exact for
+    /// the chunk in which a sample is received, and otherwise
+    /// extrapolated from the last two samples.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub monotonic_ticks: Option<u64>,
+}
+
+/// A contiguous range of the raw ITM byte stream of a trace.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RawBytes {
+    /// Offset of the first byte in the stream.
+    pub offset: u64,
+
+    pub bytes: Vec<u8>,
+}
+
+impl EventChunk {
+    /// Drops all events that are not of the given `kinds`, e.g. to
+    /// scope an export to what the target format expects.
+    pub fn retain_kinds(&mut self, kinds: &[EventKind]) {
+        self.events.retain(|event| kinds.contains(&event.kind()));
+    }
+
+    /// Splits the chunk into chunks of at most `max` events that share
+    /// its timestamp, preserving the order of events, e.g. to bound the
+    /// latency of a frontend under bursts. A task that is entered and
+    /// exited within the chunk is never split from its exit; a split
+    /// chunk exceeds `max` events if that is unavoidable. The raw bytes
+    /// are attached to the first split chunk.
+    pub fn split(self, max: usize) -> Vec<EventChunk> {
+        let max = max.max(1);
+        let len = self.events.len();
+        if len <= max {
+            return vec![self];
+        }
+
+        // For each event, the index after the exit of the task it
+        // enters, if that exit is within the chunk.
+        let mut exits = vec![0; len];
+        {
+            let mut entered = std::collections::HashMap::<&str, Vec<usize>>::new();
+            for (i, event) in self.events.iter().enumerate() {
+                match event {
+                    EventType::Task {
+                        name,
+                        action: TaskAction::Entered,
+                        ..
+                    } => entered.entry(name).or_default().push(i),
+                    EventType::Task {
+                        name,
+                        action: TaskAction::Exited,
+                        ..
+                    } => {
+                        if let Some(j) = entered.get_mut(name.as_str()).and_then(Vec::pop) {
+                            exits[j] = i + 1;
+                        }
+                    }
+                    _ => (),
+                }
+            }
+        }
+
+        // Split at the latest boundary within `max` events that no
+        // task is entered and exited across, or otherwise at the
+        // earliest boundary after it.
+        let mut cuts = vec![];
+        let mut start = 0;
+        while len - start > max {
+            let mut reach = start;
+            let mut cut = None;
+            for end in start + 1..=len {
+                reach = reach.max(exits[end - 1]);
+                if end > start + max && cut.is_some() {
+                    break;
+                }
+                if reach <= end {
+                    cut = Some(end);
+                }
+            }
+            let cut = match cut {
+                Some(cut) if cut < len => cut,
+                _ => break,
+            };
+            cuts.push(cut);
+            start = cut;
+        }
+
+        let mut events = self.events.into_iter();
+        let mut raw = self.raw;
+        let mut prev = 0;
+        cuts.into_iter()
+            .chain(std::iter::once(len))
+            .map(|end| {
+                let chunk = EventChunk {
+                    timestamp: self.timestamp.clone(),
+                    events: events.by_ref().take(end - prev).collect(),
+                    raw: raw.take(),
+                    monotonic_ticks: self.monotonic_ticks,
+                };
+                prev = end;
+                chunk
+            })
+            .collect()
+    }
+
+    /// Maps the name of every task event through `rename`, e.g. to
+    /// shorten `app::sensors::i2c::poll` to `i2c_poll` for presentation.
+    pub fn rename_tasks(&mut self, mut rename: impl FnMut(&str) -> String) {
+        for event in self.events.iter_mut() {
+            if let EventType::Task { name, .. } = event {
+                *name = rename(name);
+            }
+        }
+    }
 }
 
 /// Derivative of [`TracePacket`], where RTIC task information has
@@ -36,8 +229,41 @@ pub enum EventType {
         /// `"app::some_task"`.
         name: String,
 
-        /// What did the task do?
+        /// What did the task do? An async software task is entered
+        /// and exited on every poll, and completes once done, if its
+        /// comparator is configured.
         action: TaskAction,
+
+        /// Priority of the task, if known. For
+        /// [`TaskAction::Returned`], this is the priority level that
+        /// execution resumes on.
+        priority: Option<u8>,
+
+        /// Number of tasks that are active at the event, including
+        /// this task: a task that preempts another is one deeper. The
+        /// `#[idle]` task is at depth 0. Allows timelines to stack
+        /// tasks as they were nested on the target.
+        #[serde(default)]
+        depth: u8,
+    },
+
+    /// A DWT comparator that is not used for software task tracing
+    /// matched a data access of a watched variable.
+    DataTrace {
+        /// Index of the DWT comparator that matched. Distinguishes
+        /// multiple watched variables.
+        comparator: u8,
+
+        /// Whether the variable was read or written.
+        access_type: MemoryAccessType,
+
+        /// The data value that was read or written.
+        value: Vec<u8>,
+
+        /// Name of the watched variable, if the comparator is
+        /// configured as a named watchpoint.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        name: Option<String>,
     },
 
     /// RTIC Scope does not know how to map this packet.
@@ -49,4 +275,496 @@ pub enum EventType {
 
     /// Packet could not be decoded.
     Invalid(MalformedPacket),
+
+    /// The given number of preceding chunks were dropped on the host
+    /// because the consumer could not keep up. Unlike
+    /// [`EventType::Overflow`], no packets were dropped by the target,
+    /// except over RTT: the firmware then drops the given number of
+    /// events if the host does not read them in time, which is also
+    /// reported as an [`EventType::Overflow`].
+    HostDropped(usize),
+
+    /// The program counter was sampled, e.g. for statistical profiling.
+    PcSample {
+        /// The sampled program counter, or `None` if the core was
+        /// sleeping.
+        pc: Option<u32>,
+
+        /// Name of the function that encloses `pc`, if it could be
+        /// resolved from the symbols of the traced application.
+        symbol: Option<String>,
+    },
+
+    /// The given number of packets, valid or not, were dropped after a
+    /// sustained run of malformed packets until the decoder
+    /// resynchronized on a [`TracePacket::Sync`], e.g. on a noisy
+    /// physical link. Replaces the [`EventType::Invalid`] events of the
+    /// dropped packets.
+    Resynced(usize),
+
+    /// A line of text written by the firmware to an ITM stimulus port
+    /// that is configured as a log port, e.g. ad-hoc logging, without
+    /// its line terminator.
+    Log {
+        /// The stimulus port the line was written to.
+        port: u8,
+
+        /// The line, lossily decoded as UTF-8.
+        message: String,
+    },
+}
+
+impl EventType {
+    pub fn kind(&self) -> EventKind {
+        match self {
+            EventType::Overflow => EventKind::Overflow,
+            EventType::Task { .. } => EventKind::Task,
+            EventType::DataTrace { .. } => EventKind::DataTrace,
+            EventType::Unknown(_) => EventKind::Unknown,
+            EventType::Unmappable(_, _) => EventKind::Unmappable,
+            EventType::Invalid(_) => EventKind::Invalid,
+            EventType::HostDropped(_) => EventKind::HostDropped,
+            EventType::PcSample { .. } => EventKind::PcSample,
+            EventType::Resynced(_) => EventKind::Resynced,
+            EventType::Log { .. } => EventKind::Log,
+        }
+    }
+}
+
+/// The variant of an [`EventType`], without its data.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EventKind {
+    Overflow,
+    Task,
+    DataTrace,
+    Unknown,
+    Unmappable,
+    Invalid,
+    HostDropped,
+    PcSample,
+    Resynced,
+    Log,
+}
+
+impl std::str::FromStr for EventKind {
+    type Err = String;
+
+    /// Parses the snake-case name of a kind, e.g. `data_trace`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "overflow" => Ok(EventKind::Overflow),
+            "task" => Ok(EventKind::Task),
+            "data_trace" => Ok(EventKind::DataTrace),
+            "unknown" => Ok(EventKind::Unknown),
+            "unmappable" => Ok(EventKind::Unmappable),
+            "invalid" => Ok(EventKind::Invalid),
+            "host_dropped" => Ok(EventKind::HostDropped),
+            "pc_sample" => Ok(EventKind::PcSample),
+            "resynced" => Ok(EventKind::Resynced),
+            "log" => Ok(EventKind::Log),
+            _ => Err(format!(
+                "unknown event kind {}; expected one of overflow, task, data_trace, unknown, unmappable, invalid, host_dropped, pc_sample, resynced, log",
+                s
+            )),
+        }
+    }
+}
+
+/// Programmatic construction of [`EventChunk`]s, e.g. to generate
+/// deterministic event streams for testing frontends without target
+/// hardware.
+///
+/// ```
+/// use rtic_scope_api::{ChunkBuilder, EventType};
+/// use std::time::Duration;
+///
+/// let chunk = ChunkBuilder::at(Duration::from_micros(10))
+///     .entered("app::some_task")
+///     .overflow()
+///     .build();
+/// assert!(matches!(chunk.events[1], EventType::Overflow));
+/// ```
+#[derive(Debug, Clone)]
+pub struct ChunkBuilder {
+    timestamp: Timestamp,
+    events: Vec<EventType>,
+}
+
+impl ChunkBuilder {
+    /// A chunk without events, synchronous to `offset` since target
+    /// reset.
+    pub fn at(offset: std::time::Duration) -> Self {
+        Self {
+            timestamp: Timestamp::Sync(offset),
+            events: vec![],
+        }
+    }
+
+    /// Overrides the timestamp of the chunk, e.g. to generate
+    /// timestamps of worse quality.
+    pub fn timestamp(mut self, timestamp: Timestamp) -> Self {
+        self.timestamp = timestamp;
+        self
+    }
+
+    /// Appends an arbitrary event.
+    pub fn event(mut self, event: EventType) -> Self {
+        self.events.push(event);
+        self
+    }
+
+    /// Appends an [`EventType::Task`] of unknown priority.
+    pub fn task(self, name: &str, action: TaskAction) -> Self {
+        self.event(EventType::Task {
+            name: name.to_string(),
+            action,
+            priority: None,
+            depth: 0,
+        })
+    }
+
+    /// Appends an [`EventType::Task`] of the given priority.
+    pub fn task_with_priority(self, name: &str, action: TaskAction, priority: u8) -> Self {
+        self.event(EventType::Task {
+            name: name.to_string(),
+            action,
+            priority: Some(priority),
+            depth: 0,
+        })
+    }
+
+    /// Appends a [`TaskAction::Entered`] of the named task.
+    pub fn entered(self, name: &str) -> Self {
+        self.task(name, TaskAction::Entered)
+    }
+
+    /// Appends a [`TaskAction::Exited`] of the named task.
+    pub fn exited(self, name: &str) -> Self {
+        self.task(name, TaskAction::Exited)
+    }
+
+    /// Appends a [`TaskAction::Returned`] of the named task.
+    pub fn returned(self, name: &str) -> Self {
+        self.task(name, TaskAction::Returned)
+    }
+
+    /// Appends a [`TaskAction::Completed`] of the named task.
+    pub fn completed(self, name: &str) -> Self {
+        self.task(name, TaskAction::Completed)
+    }
+
+    /// Appends an [`EventType::Overflow`].
+    pub fn overflow(self) -> Self {
+        self.event(EventType::Overflow)
+    }
+
+    /// Appends an [`EventType::DataTrace`].
+    pub fn data_trace(self, comparator: u8, access_type: MemoryAccessType, value: &[u8]) -> Self {
+        self.event(EventType::DataTrace {
+            comparator,
+            access_type,
+            value: value.to_vec(),
+            name: None,
+        })
+    }
+
+    /// Appends an [`EventType::DataTrace`] of the named watched
+    /// variable.
+    pub fn named_data_trace(
+        self,
+        comparator: u8,
+        name: &str,
+        access_type: MemoryAccessType,
+        value: &[u8],
+    ) -> Self {
+        self.event(EventType::DataTrace {
+            comparator,
+            access_type,
+            value: value.to_vec(),
+            name: Some(name.to_string()),
+        })
+    }
+
+    /// Appends an [`EventType::Unknown`].
+    pub fn unknown(self, packet: TracePacket) -> Self {
+        self.event(EventType::Unknown(packet))
+    }
+
+    /// Appends an [`EventType::Unmappable`].
+    pub fn unmappable(self, packet: TracePacket, reason: &str) -> Self {
+        self.event(EventType::Unmappable(packet, reason.to_string()))
+    }
+
+    /// Appends an [`EventType::Invalid`].
+    pub fn invalid(self, malformed: MalformedPacket) -> Self {
+        self.event(EventType::Invalid(malformed))
+    }
+
+    /// Appends an [`EventType::PcSample`].
+    pub fn pc_sample(self, pc: Option<u32>, symbol: Option<&str>) -> Self {
+        self.event(EventType::PcSample {
+            pc,
+            symbol: symbol.map(str::to_string),
+        })
+    }
+
+    /// Appends an [`EventType::HostDropped`] of `chunks` chunks.
+    pub fn host_dropped(self, chunks: usize) -> Self {
+        self.event(EventType::HostDropped(chunks))
+    }
+
+    /// Appends an [`EventType::Resynced`] of `packets` dropped packets.
+    pub fn resynced(self, packets: usize) -> Self {
+        self.event(EventType::Resynced(packets))
+    }
+
+    /// Appends an [`EventType::Log`] of the given line.
+    pub fn log(self, port: u8, message: &str) -> Self {
+        self.event(EventType::Log {
+            port,
+            message: message.to_string(),
+        })
+    }
+
+    pub fn build(self) -> EventChunk {
+        EventChunk {
+            timestamp: self.timestamp,
+            events: self.events,
+            raw: None,
+            monotonic_ticks: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::time::Duration;
+
+    /// Ensure that a representative stream of a software task preempted
+    /// by a hardware task can be generated.
+    #[test]
+    fn build_stream() {
+        let stream = vec![
+            ChunkBuilder::at(Duration::from_micros(0))
+                .task_with_priority("app::foo", TaskAction::Entered, 1)
+                .build(),
+            ChunkBuilder::at(Duration::from_micros(5))
+                .timestamp(Timestamp::AssocEventDelay(Duration::from_micros(5)))
+                .task_with_priority("app::hw", TaskAction::Entered, 2)
+                .build(),
+            ChunkBuilder::at(Duration::from_micros(8))
+                .exited("app::hw")
+                .returned("app::foo")
+                .build(),
+            ChunkBuilder::at(Duration::from_micros(12))
+                .timestamp(Timestamp::UnknownDelay {
+                    prev: Duration::from_micros(8),
+                    curr: Duration::from_micros(12),
+                })
+                .overflow()
+                .exited("app::foo")
+                .build(),
+        ];
+
+        let offsets: Vec<u128> = stream
+            .iter()
+            .map(|c| timestamp_offset(&c.timestamp).as_micros())
+            .collect();
+        assert_eq!(offsets, vec![0, 5, 8, 12]);
+        assert_eq!(stream.iter().map(|c| c.events.len()).sum::<usize>(), 6);
+        assert!(matches!(
+            &stream[2].events[..],
+            [
+                EventType::Task {
+                    action: TaskAction::Exited,
+                    ..
+                },
+                EventType::Task {
+                    action: TaskAction::Returned,
+                    priority: None,
+                    ..
+                },
+            ]
+        ));
+    }
+
+    /// Ensure that only events of the allowed kinds are retained.
+    #[test]
+    fn retain_task_kinds() {
+        let mut chunk = ChunkBuilder::at(Duration::from_nanos(100))
+            .entered("app::foo")
+            .overflow()
+            .data_trace(3, MemoryAccessType::Write, &[42])
+            .exited("app::foo")
+            .unknown(TracePacket::Sync)
+            .invalid(MalformedPacket::InvalidHeader(0xff))
+            .build();
+        chunk.retain_kinds(&["task".parse().unwrap()]);
+
+        assert!(matches!(
+            &chunk.events[..],
+            [
+                EventType::Task {
+                    action: TaskAction::Entered,
+                    ..
+                },
+                EventType::Task {
+                    action: TaskAction::Exited,
+                    ..
+                },
+            ]
+        ));
+        assert_eq!("log".parse(), Ok(EventKind::Log));
+        assert!("stimulus".parse::<EventKind>().unwrap_err().contains("log"));
+    }
+
+    /// Ensure that a large chunk is split at the configured boundary
+    /// in order, without splitting a task from its exit.
+    #[test]
+    fn split_chunk() {
+        let mut builder = ChunkBuilder::at(Duration::from_nanos(100));
+        for i in 0..10 {
+            builder = builder.data_trace(0, MemoryAccessType::Write, &[i]);
+        }
+        let mut chunk = builder.build();
+        chunk.raw = Some(RawBytes {
+            offset: 0,
+            bytes: vec![1, 2, 3],
+        });
+
+        let chunks = chunk.split(4);
+        assert_eq!(
+            chunks.iter().map(|c| c.events.len()).collect::<Vec<_>>(),
+            [4, 4, 2]
+        );
+        let values: Vec<u8> = chunks
+            .iter()
+            .flat_map(|c| c.events.iter())
+            .map(|event| match event {
+                EventType::DataTrace { value, .. } => value[0],
+                _ => unreachable!(),
+            })
+            .collect();
+        assert_eq!(values, (0..10).collect::<Vec<_>>());
+        assert!(chunks
+            .iter()
+            .all(|c| timestamp_offset(&c.timestamp) == Duration::from_nanos(100)));
+        assert!(chunks[0].raw.is_some() && chunks[1..].iter().all(|c| c.raw.is_none()));
+
+        // `app::bar` is entered before the first boundary and exited
+        // after it: the second chunk is extended to its exit.
+        let chunk = ChunkBuilder::at(Duration::from_nanos(100))
+            .entered("app::foo")
+            .exited("app::foo")
+            .entered("app::bar")
+            .entered("app::baz")
+            .exited("app::baz")
+            .exited("app::bar")
+            .overflow()
+            .overflow()
+            .build();
+        let names = |chunk: &EventChunk| -> Vec<String> {
+            chunk
+                .events
+                .iter()
+                .map(|event| match event {
+                    EventType::Task { name, action, .. } => format!("{:?} {}", action, name),
+                    _ => "overflow".to_string(),
+                })
+                .collect()
+        };
+        let chunks: Vec<Vec<String>> = chunk.split(3).iter().map(names).collect();
+        assert_eq!(
+            chunks,
+            [
+                vec!["Entered app::foo", "Exited app::foo"],
+                vec![
+                    "Entered app::bar",
+                    "Entered app::baz",
+                    "Exited app::baz",
+                    "Exited app::bar"
+                ],
+                vec!["overflow", "overflow"],
+            ]
+        );
+    }
+
+    /// Ensure that the renaming hook is applied to all task events, and
+    /// only those.
+    #[test]
+    fn rename_tasks() {
+        let mut chunk = ChunkBuilder::at(Duration::from_nanos(100))
+            .entered("app::sensors::i2c::poll")
+            .overflow()
+            .exited("app::sensors::i2c::poll")
+            .returned("app::idle")
+            .build();
+
+        let mut calls = 0;
+        chunk.rename_tasks(|name| {
+            calls += 1;
+            name.rsplit("::").next().unwrap().to_string()
+        });
+
+        assert_eq!(calls, 3);
+        let names: Vec<_> = chunk
+            .events
+            .iter()
+            .filter_map(|event| match event {
+                EventType::Task { name, .. } => Some(name.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(names, ["poll", "poll", "idle"]);
+        assert!(matches!(chunk.events[1], EventType::Overflow));
+    }
+
+    /// Ensure that all event types can be generated.
+    #[test]
+    fn build_all_event_types() {
+        let chunk = ChunkBuilder::at(Duration::from_nanos(100))
+            .entered("app::foo")
+            .overflow()
+            .data_trace(3, MemoryAccessType::Read, &[42])
+            .unknown(TracePacket::Sync)
+            .unmappable(TracePacket::Overflow, "no reason")
+            .invalid(MalformedPacket::InvalidHeader(0xff))
+            .host_dropped(2)
+            .pc_sample(Some(0x0800_0100), Some("app::foo"))
+            .resynced(7)
+            .log(1, "hello")
+            .named_data_trace(1, "counter", MemoryAccessType::Write, &[1, 0])
+            .build();
+
+        assert!(matches!(
+            &chunk.events[..],
+            [
+                EventType::Task { .. },
+                EventType::Overflow,
+                EventType::DataTrace {
+                    comparator: 3,
+                    name: None,
+                    ..
+                },
+                EventType::Unknown(_),
+                EventType::Unmappable(_, _),
+                EventType::Invalid(_),
+                EventType::HostDropped(2),
+                EventType::PcSample {
+                    pc: Some(0x0800_0100),
+                    symbol: Some(_),
+                },
+                EventType::Resynced(7),
+                EventType::Log { port: 1, message },
+                EventType::DataTrace {
+                    comparator: 1,
+                    name: Some(name),
+                    access_type: MemoryAccessType::Write,
+                    ..
+                },
+            ] if message == "hello" && name == "counter"
+        ));
+    }
 }
@@ -2,9 +2,10 @@
 //! Reference frontend implementation for RTIC Scope.
 #![doc = include_str!("../../docs/profile/README.md")]
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use rtic_scope_api as api;
 use serde_json::Deserializer;
+use std::io::{BufRead, BufReader};
 
 fn main() -> Result<()> {
     // Create frontend socket in a temporary directory, print it for the parent backend.
@@ -18,10 +19,31 @@ fn main() -> Result<()> {
     // Deserialize api::EventChunks from socket and print events to
     // stderr along with nanoseconds timestamp.
     let (socket, _addr) = listener.accept().context("Failed to accept()")?;
+    let mut socket = BufReader::new(socket);
+
+    // The first message is a handshake; refuse to misparse a schema
+    // we do not know.
+    let mut handshake = String::new();
+    socket
+        .read_line(&mut handshake)
+        .context("Failed to read handshake")?;
+    let handshake: api::Handshake =
+        serde_json::from_str(&handshake).context("Failed to deserialize handshake")?;
+    if !handshake.is_compatible() {
+        bail!(
+            "Backend {} speaks schema version {}, but this frontend only speaks version {}",
+            handshake.version,
+            handshake.schema,
+            api::SCHEMA_VERSION
+        );
+    }
+
     let stream = Deserializer::from_reader(socket).into_iter::<api::EventChunk>();
     let mut prev_nanos = 0;
     for chunk in stream {
-        let api::EventChunk { timestamp, events } = chunk.context("Failed to deserialize chunk")?;
+        let api::EventChunk {
+            timestamp, events, ..
+        } = chunk.context("Failed to deserialize chunk")?;
         let (quality, nanos) = match timestamp {
             api::Timestamp::Sync(offset) | api::Timestamp::AssocEventDelay(offset) => {
                 ("good", offset.as_nanos())